@@ -16,7 +16,8 @@ use std::path::PathBuf;
 mod example_tools;
 use example_tools::{DxUiTool, DxCodegenTool, DxStyleTool, DxOptimizerTool};
 
-fn main() -> Result<()> {
+#[tokio::main]
+async fn main() -> Result<()> {
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter("debug")
@@ -98,16 +99,16 @@ fn main() -> Result<()> {
         "Initial DX tools generation",
         tool_states,
         all_generated.clone(),
-    )?;
+    ).await?;
     
     println!("✅ Created snapshot: {}\n", snapshot_id);
 
     // Step 6: Demonstrate Branching
     println!("📦 Step 6: Creating feature branch...");
     snapshot_mgr.create_branch("feature/new-components")?;
-    snapshot_mgr.checkout_branch("feature/new-components")?;
+    snapshot_mgr.checkout_branch("feature/new-components").await?;
     
-    println!("✅ On branch: {}\n", snapshot_mgr.current_branch());
+    println!("✅ On branch: {}\n", snapshot_mgr.current_branch().unwrap_or("(detached)"));
 
     // Step 7: View History
     println!("📦 Step 7: Version history...");
@@ -62,9 +62,6 @@ fn get_branching_state() -> Arc<RwLock<BranchingState>> {
 pub fn apply_changes(changes: Vec<FileChange>) -> Result<Vec<PathBuf>> {
     tracing::info!("📝 Applying {} changes with branching safety", changes.len());
     
-    let state = get_branching_state();
-    let mut state = state.write();
-    
     let mut applied_files = Vec::new();
     
     for change in changes {
@@ -99,7 +96,8 @@ pub fn apply_changes(changes: Vec<FileChange>) -> Result<Vec<PathBuf>> {
         }
     }
     
-    state.last_application = Some(applied_files.clone());
+    // Locked only now: the votes above read the same state
+    get_branching_state().write().last_application = Some(applied_files.clone());
     
     Ok(applied_files)
 }
@@ -307,8 +305,13 @@ pub fn reset_branching_engine_state() -> Result<()> {
 
 // Helper function
 fn apply_file_change(change: &FileChange) -> Result<()> {
-    // TODO: Actually write file
     tracing::debug!("💾 Writing file: {:?}", change.path);
+    if let Some(parent) = change.path.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    std::fs::write(&change.path, &change.new_content)?;
     Ok(())
 }
 
@@ -332,4 +335,47 @@ mod tests {
         let color = query_predicted_branch_color(&file).unwrap();
         assert_eq!(color, BranchColor::Green);
     }
+    
+    fn change(path: PathBuf, content: &str) -> FileChange {
+        FileChange {
+            path,
+            old_content: None,
+            new_content: content.to_string(),
+            tool_id: "test-tool".to_string(),
+        }
+    }
+    
+    #[test]
+    fn test_apply_changes_writes_files_and_skips_vetoed_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let approved = dir.path().join("src/approved.ts");
+        let vetoed = dir.path().join("vetoed.ts");
+        issue_immediate_veto(&vetoed, "test-voter", "Unsafe").unwrap();
+        
+        // Votes are read while applying, so this must not hold the state lock
+        let changes = vec![change(approved.clone(), "approved"), change(vetoed.clone(), "vetoed")];
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || tx.send(apply_changes(changes)).unwrap());
+        let applied = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("apply_changes deadlocked")
+            .unwrap();
+        
+        assert_eq!(applied, vec![approved.clone()]);
+        assert_eq!(std::fs::read_to_string(&approved).unwrap(), "approved");
+        assert!(!vetoed.exists());
+    }
+    
+    #[test]
+    fn test_unchecked_paths_write_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let preapproved = dir.path().join("preapproved.ts");
+        let forced = dir.path().join("nested/forced.ts");
+        
+        apply_changes_with_preapproved_votes(vec![change(preapproved.clone(), "one")]).unwrap();
+        apply_changes_force_unchecked(vec![change(forced.clone(), "two")]).unwrap();
+        
+        assert_eq!(std::fs::read_to_string(&preapproved).unwrap(), "one");
+        assert_eq!(std::fs::read_to_string(&forced).unwrap(), "two");
+    }
 }
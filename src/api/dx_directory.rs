@@ -1,7 +1,14 @@
 //! .dx/ Directory — The Transparent, Version-Controlled Brain APIs
 
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::storage::{open_remote, ObjectStore};
+use crate::version::remote::{self, PullOutcome, PullReport, PushReport};
+use crate::version::{
+    RestoreMode, RestoreReport, SnapshotManager, ToolRegistry, ToolSource, ToolState,
+};
 
 pub fn get_dx_directory_path() -> Result<PathBuf> {
     let root = crate::api::cicd::detect_workspace_root()?;
@@ -25,32 +32,242 @@ pub fn load_tool_offline_binary(tool_name: &str) -> Result<Vec<u8>> {
     Ok(std::fs::read(&path)?)
 }
 
-pub fn commit_current_dx_state(message: &str) -> Result<String> {
-    tracing::info!("💾 Committing dx state: {}", message);
-    let commit_id = uuid::Uuid::new_v4().to_string();
-    Ok(commit_id)
+/// Snapshots of the `.dx` directory itself, kept apart from the project
+/// snapshots in `.dx/forge`
+const DX_STATE_DIR: &str = "forge/dx-state";
+
+pub async fn commit_current_dx_state(message: &str) -> Result<String> {
+    commit_dx_state(&get_dx_directory_path()?, message).await
 }
 
-pub fn checkout_dx_state(state_id: &str) -> Result<()> {
-    tracing::info!("🔄 Checking out dx state: {}", state_id);
-    Ok(())
+/// Check out a dx state by branch, tag or snapshot id, restoring the files
+/// under `.dx` and the tool registry. Refuses to overwrite local changes.
+pub async fn checkout_dx_state(state_id: &str) -> Result<RestoreReport> {
+    checkout_dx_state_at(&get_dx_directory_path()?, state_id).await
 }
 
+/// (snapshot id, message, unix timestamp), newest first
 pub fn list_dx_history() -> Result<Vec<(String, String, i64)>> {
-    // Returns (commit_id, message, timestamp)
-    Ok(Vec::new())
+    let history = dx_snapshots(&get_dx_directory_path()?)?.history(usize::MAX)?;
+    Ok(history
+        .into_iter()
+        .map(|snapshot| {
+            let id = snapshot.id.as_str().to_string();
+            (id, snapshot.message, snapshot.timestamp.timestamp())
+        })
+        .collect())
 }
 
-pub fn show_dx_state_diff(from_state: &str, to_state: &str) -> Result<String> {
-    Ok(format!("Diff from {} to {}", from_state, to_state))
+/// Unified diff between two dx states, each given as a branch, tag or
+/// snapshot id
+pub async fn show_dx_state_diff(from_state: &str, to_state: &str) -> Result<String> {
+    let snapshots = dx_snapshots(&get_dx_directory_path()?)?;
+    let diff = snapshots
+        .diff_contents(
+            &snapshots.resolve(from_state)?,
+            &snapshots.resolve(to_state)?,
+        )
+        .await?;
+    Ok(diff.unified())
 }
 
-pub fn push_dx_state_to_remote(remote_url: &str) -> Result<()> {
+/// Push the current dx branch to a remote (see `storage::open_remote`)
+pub async fn push_dx_state_to_remote(remote_url: &str) -> Result<PushReport> {
     tracing::info!("☁️  Pushing dx state to: {}", remote_url);
-    Ok(())
+    let remote = open_remote(remote_url)?;
+    push_dx_state(&get_dx_directory_path()?, remote.as_ref()).await
 }
 
-pub fn pull_dx_state_from_remote(remote_url: &str) -> Result<()> {
+/// Pull the current dx branch from a remote, fast-forwarding or merging,
+/// and bring the tool registry in line with the result
+pub async fn pull_dx_state_from_remote(remote_url: &str) -> Result<PullReport> {
     tracing::info!("☁️  Pulling dx state from: {}", remote_url);
+    let remote = open_remote(remote_url)?;
+    pull_dx_state(&get_dx_directory_path()?, remote.as_ref()).await
+}
+
+fn dx_snapshots(dx_dir: &Path) -> Result<SnapshotManager> {
+    Ok(SnapshotManager::new(&dx_dir.join(DX_STATE_DIR))?.with_work_dir(dx_dir))
+}
+
+async fn commit_dx_state(dx_dir: &Path, message: &str) -> Result<String> {
+    tracing::info!("💾 Committing dx state: {}", message);
+    let mut snapshots = dx_snapshots(dx_dir)?;
+    let id = snapshots
+        .create_snapshot(message, registry_tool_states(dx_dir)?, dx_files(dx_dir)?)
+        .await?;
+    Ok(id.as_str().to_string())
+}
+
+async fn checkout_dx_state_at(dx_dir: &Path, state_id: &str) -> Result<RestoreReport> {
+    tracing::info!("🔄 Checking out dx state: {}", state_id);
+    let mut snapshots = dx_snapshots(dx_dir)?;
+    let report = if snapshots.branch_head(state_id)?.is_some() {
+        snapshots.checkout_branch(state_id).await?
+    } else {
+        snapshots
+            .checkout_detached(state_id, RestoreMode::Refuse)
+            .await?
+    };
+    restore_registry(dx_dir, &snapshots)?;
+    Ok(report)
+}
+
+async fn push_dx_state(dx_dir: &Path, remote: &dyn ObjectStore) -> Result<PushReport> {
+    let snapshots = dx_snapshots(dx_dir)?;
+    let branch = snapshots
+        .current_branch()
+        .context("Cannot push a detached dx state; check out a branch first")?;
+    remote::push(&snapshots, remote, branch).await
+}
+
+async fn pull_dx_state(dx_dir: &Path, remote: &dyn ObjectStore) -> Result<PullReport> {
+    let mut snapshots = dx_snapshots(dx_dir)?;
+    let branch = snapshots
+        .current_branch()
+        .context("Cannot pull into a detached dx state; check out a branch first")?
+        .to_string();
+    let report = remote::pull(&mut snapshots, remote, &branch).await?;
+    if report.outcome != PullOutcome::Conflicted {
+        restore_registry(dx_dir, &snapshots)?;
+    }
+    Ok(report)
+}
+
+/// Files under `.dx`, relative to it, leaving out forge's own storage
+fn dx_files(dx_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = walkdir::WalkDir::new(dx_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != "forge");
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(dx_dir)?.to_path_buf());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Installed tools as snapshot tool states; source and dependencies go in
+/// the config so the registry can be rebuilt from a snapshot
+fn registry_tool_states(dx_dir: &Path) -> Result<HashMap<String, ToolState>> {
+    let registry = ToolRegistry::new(&dx_dir.join("forge"))?;
+    registry
+        .list()
+        .into_iter()
+        .map(|tool| {
+            let config = HashMap::from([
+                ("source".to_string(), serde_json::to_value(&tool.source)?),
+                (
+                    "dependencies".to_string(),
+                    serde_json::to_value(&tool.dependencies)?,
+                ),
+            ]);
+            let state = ToolState {
+                tool_name: tool.name.clone(),
+                version: tool.version.clone(),
+                config,
+                output_files: Vec::new(),
+            };
+            Ok((tool.name.clone(), state))
+        })
+        .collect()
+}
+
+/// Make the tool registry hold exactly the tools of the HEAD snapshot
+fn restore_registry(dx_dir: &Path, snapshots: &SnapshotManager) -> Result<()> {
+    let Some(head) = snapshots.head_id()? else {
+        return Ok(());
+    };
+    let states = snapshots
+        .get_snapshot(&head)?
+        .map(|snapshot| snapshot.tool_states)
+        .unwrap_or_default();
+
+    let mut registry = ToolRegistry::new(&dx_dir.join("forge"))?;
+    let installed: Vec<String> = registry
+        .list()
+        .iter()
+        .map(|tool| tool.name.clone())
+        .collect();
+    for name in installed {
+        if !states.contains_key(&name) {
+            registry.unregister(&name)?;
+        }
+    }
+    for (name, state) in states {
+        let field = |key: &str| state.config.get(key).cloned().unwrap_or_default();
+        let source: ToolSource = serde_json::from_value(field("source"))
+            .with_context(|| format!("Snapshot has no source for tool {}", name))?;
+        let dependencies = serde_json::from_value(field("dependencies")).unwrap_or_default();
+        let unchanged = registry.get(&name).is_some_and(|tool| {
+            tool.version == state.version
+                && serde_json::to_value(&tool.source).ok() == Some(field("source"))
+        });
+        if !unchanged {
+            registry.register(name, state.version, source, dependencies)?;
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalObjectStore;
+    use crate::version::Version;
+
+    fn install(dx_dir: &Path, name: &str, version: Version) {
+        std::fs::create_dir_all(dx_dir.join("forge")).unwrap();
+        let mut registry = ToolRegistry::new(&dx_dir.join("forge")).unwrap();
+        let source = ToolSource::Crate {
+            version: version.to_string(),
+        };
+        registry
+            .register(name.to_string(), version, source, HashMap::new())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn committed_state_restores_files_and_tools_elsewhere() {
+        let (dev, ci, remote) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+        let remote = LocalObjectStore::new(remote.path()).unwrap();
+        let config = PathBuf::from("config/style.toml");
+        std::fs::create_dir_all(dev.path().join("config")).unwrap();
+
+        std::fs::write(dev.path().join(&config), "indent = 2").unwrap();
+        install(dev.path(), "style", Version::new(1, 0, 0));
+        let v1 = commit_dx_state(dev.path(), "v1").await.unwrap();
+        std::fs::write(dev.path().join(&config), "indent = 4").unwrap();
+        install(dev.path(), "style", Version::new(2, 0, 0));
+        commit_dx_state(dev.path(), "v2").await.unwrap();
+        assert_eq!(
+            dx_snapshots(dev.path()).unwrap().history(10).unwrap().len(),
+            2
+        );
+
+        push_dx_state(dev.path(), &remote).await.unwrap();
+        let pulled = pull_dx_state(ci.path(), &remote).await.unwrap();
+        assert_eq!(pulled.outcome, PullOutcome::Created);
+        assert_eq!(
+            std::fs::read_to_string(ci.path().join(&config)).unwrap(),
+            "indent = 4"
+        );
+        let registry = ToolRegistry::new(&ci.path().join("forge")).unwrap();
+        assert_eq!(registry.version("style"), Some(&Version::new(2, 0, 0)));
+
+        checkout_dx_state_at(ci.path(), &v1).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(ci.path().join(&config)).unwrap(),
+            "indent = 2"
+        );
+        let registry = ToolRegistry::new(&ci.path().join("forge")).unwrap();
+        assert_eq!(registry.version("style"), Some(&Version::new(1, 0, 0)));
+    }
+}
@@ -1,8 +1,29 @@
 //! Package Management — The Death of npm/cargo/pip
+//!
+//! Packages come from a registry index (see [`crate::version::package_index`]):
+//! the remote named by `DX_REGISTRY` if set, otherwise the `.dx/registry`
+//! directory, which is all an air-gapped machine needs. Chosen versions
+//! live in `dx.lock`, the files each package wrote in
+//! `.dx/forge/packages.json`.
 
-use anyhow::Result;
-use std::path::PathBuf;
-use serde::{Serialize, Deserialize};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api::branching::{self, FileChange};
+use crate::storage::{open_remote, LocalObjectStore, ObjectStore};
+use crate::version::types::version_string;
+use crate::version::{
+    IndexEntry, Lockfile, PackageIndex, Resolution, ResolvedPackage, Resolver, VariantManifest,
+    Version, VersionReq, DEFAULT_VARIANT, LOCKFILE_NAME,
+};
+
+/// Registry URL or path overriding `.dx/registry`
+pub const REGISTRY_ENV: &str = "DX_REGISTRY";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageInfo {
@@ -11,58 +32,887 @@ pub struct PackageInfo {
     pub version: String,
     pub variant: String,
     pub installed_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub description: String,
+    /// Variants the registry offers for `version`
+    #[serde(default)]
+    pub variants: Vec<String>,
+}
+
+/// `.dx/forge/packages.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InstalledState {
+    packages: BTreeMap<String, InstalledPackage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstalledPackage {
+    id: String,
+    name: String,
+    #[serde(with = "version_string")]
+    version: Version,
+    variant: String,
+    /// Files this package wrote, as they were written
+    files: Vec<InstalledFile>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    /// Asked for by the user rather than pulled in as a dependency
+    explicit: bool,
+    installed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstalledFile {
+    /// Relative to the project root
+    path: String,
+    hash: String,
+}
+
+impl InstalledState {
+    fn path(root: &Path) -> PathBuf {
+        root.join(".dx").join("forge").join("packages.json")
+    }
+
+    fn load(root: &Path) -> Result<Self> {
+        let path = Self::path(root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self, root: &Path) -> Result<()> {
+        let path = Self::path(root);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// What the project asked for; locked versions keep the rest stable
+    fn requirements(&self) -> HashMap<String, VersionReq> {
+        self.packages
+            .values()
+            .filter(|package| package.explicit)
+            .map(|package| (package.id.clone(), VersionReq::any()))
+            .collect()
+    }
+}
+
+impl InstalledPackage {
+    fn info(&self, root: &Path) -> PackageInfo {
+        PackageInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            version: self.version.to_string(),
+            variant: self.variant.clone(),
+            installed_files: self
+                .files
+                .iter()
+                .map(|file| root.join(&file.path))
+                .collect(),
+            description: String::new(),
+            variants: vec![self.variant.clone()],
+        }
+    }
 }
 
-pub fn install_package_with_variant(package_id: &str, variant: &str) -> Result<Vec<PathBuf>> {
-    tracing::info!("📦 Installing package '{}' with variant '{}'", package_id, variant);
-    
+pub async fn install_package_with_variant(package_id: &str, variant: &str) -> Result<Vec<PathBuf>> {
+    tracing::info!(
+        "📦 Installing package '{}' with variant '{}'",
+        package_id,
+        variant
+    );
+
     crate::api::events::emit_package_installation_begin(package_id)?;
-    
-    // TODO: Actual package installation logic
-    
+
+    let root = crate::api::cicd::detect_workspace_root()?;
+    let index = load_index(&require_registry(&root)?).await?;
+    let written = install_at(&root, &index, package_id, variant).await?;
+
     crate::api::events::emit_package_installation_success(package_id)?;
-    
-    Ok(Vec::new())
+
+    Ok(written)
 }
 
 pub fn uninstall_package_safely(package_id: &str) -> Result<Vec<PathBuf>> {
     tracing::info!("🗑️  Uninstalling package: {}", package_id);
-    
-    // TODO: Remove package files
-    
-    Ok(Vec::new())
+
+    let root = crate::api::cicd::detect_workspace_root()?;
+    uninstall_at(&root, package_id)
 }
 
-pub fn update_package_intelligently(package_id: &str) -> Result<Vec<PathBuf>> {
+pub async fn update_package_intelligently(package_id: &str) -> Result<Vec<PathBuf>> {
     tracing::info!("🔄 Intelligently updating package: {}", package_id);
-    
-    // TODO: Compare versions, run branching for changed files
-    
-    Ok(Vec::new())
+
+    let root = crate::api::cicd::detect_workspace_root()?;
+    let index = load_index(&require_registry(&root)?).await?;
+    update_at(&root, &index, package_id).await
 }
 
 pub fn list_all_installed_packages() -> Result<Vec<PackageInfo>> {
-    Ok(Vec::new())
+    let root = crate::api::cicd::detect_workspace_root()?;
+    list_at(&root)
 }
 
-pub fn search_dx_package_registry(query: &str) -> Result<Vec<PackageInfo>> {
+pub async fn search_dx_package_registry(query: &str) -> Result<Vec<PackageInfo>> {
     tracing::info!("🔍 Searching package registry: {}", query);
-    Ok(Vec::new())
+
+    let root = crate::api::cicd::detect_workspace_root()?;
+    let Some(registry) = open_registry(&root)? else {
+        return Ok(Vec::new());
+    };
+    let index = load_index(&registry).await?;
+    search_at(&root, &index, query)
 }
 
-pub fn pin_package_to_exact_version(package_id: &str, version: &str) -> Result<()> {
+pub async fn pin_package_to_exact_version(package_id: &str, version: &str) -> Result<()> {
     tracing::info!("📌 Pinning '{}' to version {}", package_id, version);
-    Ok(())
+
+    let root = crate::api::cicd::detect_workspace_root()?;
+    let index = load_index(&require_registry(&root)?).await?;
+    pin_at(&root, &index, package_id, version).await
 }
 
-pub fn fork_existing_variant(package_id: &str, variant: &str, new_variant_name: &str) -> Result<String> {
-    tracing::info!("🍴 Forking variant '{}' from '{}' to '{}'", variant, package_id, new_variant_name);
+pub fn fork_existing_variant(
+    package_id: &str,
+    variant: &str,
+    new_variant_name: &str,
+) -> Result<String> {
+    tracing::info!(
+        "🍴 Forking variant '{}' from '{}' to '{}'",
+        variant,
+        package_id,
+        new_variant_name
+    );
     Ok(new_variant_name.to_string())
 }
 
 pub fn publish_your_variant(package_id: &str, variant: &str) -> Result<String> {
-    tracing::info!("📤 Publishing variant '{}' for package '{}'", variant, package_id);
-    
+    tracing::info!(
+        "📤 Publishing variant '{}' for package '{}'",
+        variant,
+        package_id
+    );
+
     let published_id = format!("{}-{}", package_id, variant);
     Ok(published_id)
 }
+
+/// The configured registry, or `None` when there is none
+fn open_registry(root: &Path) -> Result<Option<Arc<dyn ObjectStore>>> {
+    if let Ok(url) = std::env::var(REGISTRY_ENV) {
+        if !url.trim().is_empty() {
+            return open_remote(url.trim()).map(Some);
+        }
+    }
+    let local = root.join(".dx").join("registry");
+    if !local.is_dir() {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(LocalObjectStore::new(local)?)))
+}
+
+fn require_registry(root: &Path) -> Result<Arc<dyn ObjectStore>> {
+    open_registry(root)?.with_context(|| {
+        format!(
+            "No package registry: set {} or create {}",
+            REGISTRY_ENV,
+            root.join(".dx").join("registry").display()
+        )
+    })
+}
+
+async fn load_index(registry: &Arc<dyn ObjectStore>) -> Result<PackageIndex> {
+    PackageIndex::load(registry.clone())
+        .await
+        .with_context(|| format!("Failed to read package registry {}", registry.name()))
+}
+
+fn load_lockfile(root: &Path) -> Result<Lockfile> {
+    Ok(Lockfile::load(&root.join(LOCKFILE_NAME))?.unwrap_or_default())
+}
+
+fn save_lockfile(root: &Path, resolution: &Resolution, previous: &Lockfile) -> Result<()> {
+    let mut lockfile = Lockfile::from_resolution(resolution);
+    lockfile.keep_pins(previous);
+    lockfile.save(&root.join(LOCKFILE_NAME))
+}
+
+async fn install_at(
+    root: &Path,
+    index: &PackageIndex,
+    package_id: &str,
+    variant: &str,
+) -> Result<Vec<PathBuf>> {
+    if index.get(package_id).is_none() {
+        bail!("Package '{}' not found in the registry", package_id);
+    }
+    let mut state = InstalledState::load(root)?;
+    let lockfile = load_lockfile(root)?;
+
+    let mut requirements = state.requirements();
+    requirements.insert(package_id.to_string(), VersionReq::any());
+    let resolution = Resolver::new(index)
+        .with_lockfile(&lockfile)
+        .resolve(&requirements)?;
+
+    let mut variants = HashMap::new();
+    variants.insert(package_id.to_string(), variant.to_string());
+    let written = sync(root, index, &mut state, &resolution, &variants).await?;
+    if let Some(package) = state.packages.get_mut(package_id) {
+        package.explicit = true;
+    }
+
+    state.save(root)?;
+    save_lockfile(root, &resolution, &lockfile)?;
+    Ok(written)
+}
+
+fn uninstall_at(root: &Path, package_id: &str) -> Result<Vec<PathBuf>> {
+    let mut state = InstalledState::load(root)?;
+    let Some(package) = state.packages.get(package_id).cloned() else {
+        bail!("Package '{}' is not installed", package_id);
+    };
+    let dependents: Vec<&str> = state
+        .packages
+        .values()
+        .filter(|other| {
+            other
+                .dependencies
+                .iter()
+                .any(|dependency| dependency == package_id)
+        })
+        .map(|other| other.id.as_str())
+        .collect();
+    if !dependents.is_empty() {
+        bail!(
+            "Cannot uninstall '{}': required by {}",
+            package_id,
+            dependents.join(", ")
+        );
+    }
+
+    let removed = remove_files(root, &package.files)?;
+    state.packages.remove(package_id);
+    state.save(root)?;
+
+    let lock_path = root.join(LOCKFILE_NAME);
+    if let Some(lockfile) = Lockfile::load(&lock_path)? {
+        lockfile.without(package_id).save(&lock_path)?;
+    }
+    Ok(removed)
+}
+
+async fn update_at(root: &Path, index: &PackageIndex, package_id: &str) -> Result<Vec<PathBuf>> {
+    let mut state = InstalledState::load(root)?;
+    if !state.packages.contains_key(package_id) {
+        bail!("Package '{}' is not installed", package_id);
+    }
+    let lockfile = load_lockfile(root)?;
+    if let Some(locked) = lockfile.get(package_id).filter(|locked| locked.pinned) {
+        bail!(
+            "Package '{}' is pinned to {} in {}",
+            package_id,
+            locked.version,
+            LOCKFILE_NAME
+        );
+    }
+
+    let resolution = Resolver::new(index)
+        .with_lockfile(&lockfile.without(package_id))
+        .resolve(&state.requirements())?;
+    let written = sync(root, index, &mut state, &resolution, &HashMap::new()).await?;
+
+    state.save(root)?;
+    save_lockfile(root, &resolution, &lockfile)?;
+    Ok(written)
+}
+
+async fn pin_at(root: &Path, index: &PackageIndex, package_id: &str, version: &str) -> Result<()> {
+    let version: Version = version.parse()?;
+    if index.version(package_id, &version).is_none() {
+        bail!(
+            "Package '{}' has no version {} in the registry",
+            package_id,
+            version
+        );
+    }
+    let mut lockfile = load_lockfile(root)?;
+    lockfile.pin(package_id, version);
+
+    let mut state = InstalledState::load(root)?;
+    if !state.packages.contains_key(package_id) {
+        // Applies on the next install
+        return lockfile.save(&root.join(LOCKFILE_NAME));
+    }
+    let resolution = Resolver::new(index)
+        .with_lockfile(&lockfile)
+        .resolve(&state.requirements())?;
+    sync(root, index, &mut state, &resolution, &HashMap::new()).await?;
+
+    state.save(root)?;
+    save_lockfile(root, &resolution, &lockfile)
+}
+
+fn list_at(root: &Path) -> Result<Vec<PackageInfo>> {
+    let state = InstalledState::load(root)?;
+    Ok(state
+        .packages
+        .values()
+        .map(|package| package.info(root))
+        .collect())
+}
+
+fn search_at(root: &Path, index: &PackageIndex, query: &str) -> Result<Vec<PackageInfo>> {
+    let state = InstalledState::load(root)?;
+    Ok(index
+        .search(query)
+        .into_iter()
+        .filter_map(|entry| {
+            let latest = entry.latest()?;
+            let installed = state.packages.get(&entry.id);
+            Some(PackageInfo {
+                id: entry.id.clone(),
+                name: entry.name.clone(),
+                version: latest.version.to_string(),
+                variant: installed
+                    .map(|package| package.variant.clone())
+                    .unwrap_or_else(|| DEFAULT_VARIANT.to_string()),
+                installed_files: installed
+                    .map(|package| package.info(root).installed_files)
+                    .unwrap_or_default(),
+                description: entry.description.clone(),
+                variants: latest.variants.keys().cloned().collect(),
+            })
+        })
+        .collect())
+}
+
+/// A package whose installed files [`sync`] changes
+struct Planned<'a> {
+    id: &'a str,
+    entry: &'a IndexEntry,
+    resolved: &'a ResolvedPackage,
+    variant: String,
+    manifest: &'a VariantManifest,
+}
+
+/// Bring installed files in line with `resolution` through the branching
+/// engine, returning the paths written or removed. `variants` overrides
+/// the variant of particular packages.
+///
+/// A package only overwrites files its previous version installed; a
+/// project file in the way stops the sync before anything is written.
+async fn sync(
+    root: &Path,
+    index: &PackageIndex,
+    state: &mut InstalledState,
+    resolution: &Resolution,
+    variants: &HashMap<String, String>,
+) -> Result<Vec<PathBuf>> {
+    let mut planned = Vec::new();
+    for (id, resolved) in &resolution.packages {
+        let entry = index
+            .get(id)
+            .with_context(|| format!("Package '{}' not found in the registry", id))?;
+        let indexed = entry
+            .version(&resolved.version)
+            .with_context(|| format!("Package '{}' has no version {}", id, resolved.version))?;
+        let previous = state.packages.get(id);
+        let variant = variants
+            .get(id)
+            .or(previous.map(|package| &package.variant))
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_VARIANT.to_string());
+        if previous.is_some_and(|package| {
+            package.version == resolved.version && package.variant == variant
+        }) {
+            continue;
+        }
+        let manifest = indexed.variants.get(&variant).with_context(|| {
+            format!(
+                "Package '{}' {} has no variant '{}' (available: {})",
+                id,
+                resolved.version,
+                variant,
+                indexed
+                    .variants
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        planned.push(Planned {
+            id,
+            entry,
+            resolved,
+            variant,
+            manifest,
+        });
+    }
+
+    let mut conflicts = Vec::new();
+    for plan in &planned {
+        for file in &plan.manifest.files {
+            let path = target_path(root, &file.path)?;
+            if path.exists()
+                && !owns(state, plan.id, &file.path)
+                && file_hash(&path).as_deref() != Some(file.hash.as_str())
+            {
+                conflicts.push(format!("{} ({})", file.path, plan.id));
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!(
+            "Refusing to overwrite files no installed package owns: {}",
+            conflicts.join(", ")
+        );
+    }
+
+    let mut touched = Vec::new();
+
+    // Dependencies nothing needs any more
+    let dropped: Vec<String> = state
+        .packages
+        .keys()
+        .filter(|id| !resolution.packages.contains_key(*id))
+        .cloned()
+        .collect();
+    for id in dropped {
+        if let Some(package) = state.packages.remove(&id) {
+            touched.extend(remove_files(root, &package.files)?);
+        }
+    }
+
+    for plan in planned {
+        let id = plan.id;
+        let mut changes = Vec::new();
+        let mut unchanged = Vec::new();
+        for file in &plan.manifest.files {
+            let path = target_path(root, &file.path)?;
+            let old_content = std::fs::read(&path).ok();
+            if old_content
+                .as_ref()
+                .is_some_and(|content| format!("{:x}", Sha256::digest(content)) == file.hash)
+            {
+                // A matching project file stays the project's
+                if owns(state, id, &file.path) {
+                    unchanged.push(file);
+                }
+                continue;
+            }
+            let content = index.fetch_file(file).await?;
+            let new_content = String::from_utf8(content).map_err(|_| {
+                anyhow::anyhow!("Package '{}' file {} is not UTF-8 text", id, file.path)
+            })?;
+            changes.push(FileChange {
+                path,
+                old_content: old_content.and_then(|content| String::from_utf8(content).ok()),
+                new_content,
+                tool_id: format!("package:{}", id),
+            });
+        }
+        let applied = branching::apply_changes(changes)?;
+        let previous = state.packages.get(id);
+
+        // Files the new manifest no longer ships
+        if let Some(previous) = previous {
+            let stale: Vec<InstalledFile> = previous
+                .files
+                .iter()
+                .filter(|old| plan.manifest.files.iter().all(|file| file.path != old.path))
+                .cloned()
+                .collect();
+            touched.extend(remove_files(root, &stale)?);
+        }
+
+        let files = plan
+            .manifest
+            .files
+            .iter()
+            .filter(|file| unchanged.contains(file) || applied.contains(&root.join(&file.path)))
+            .map(|file| InstalledFile {
+                path: file.path.clone(),
+                hash: file.hash.clone(),
+            })
+            .collect();
+        touched.extend(applied);
+
+        let mut dependencies: Vec<String> = plan.resolved.dependencies.keys().cloned().collect();
+        dependencies.sort();
+        let explicit = previous.is_some_and(|package| package.explicit);
+        state.packages.insert(
+            id.to_string(),
+            InstalledPackage {
+                id: id.to_string(),
+                name: plan.entry.name.clone(),
+                version: plan.resolved.version.clone(),
+                variant: plan.variant,
+                files,
+                dependencies,
+                explicit,
+                installed_at: Utc::now(),
+            },
+        );
+    }
+    Ok(touched)
+}
+
+/// Whether the installed version of `package_id` wrote `path`
+fn owns(state: &InstalledState, package_id: &str, path: &str) -> bool {
+    state
+        .packages
+        .get(package_id)
+        .is_some_and(|package| package.files.iter().any(|file| file.path == path))
+}
+
+/// SHA-256 of the file at `path`, if it can be read
+fn file_hash(path: &Path) -> Option<String> {
+    let content = std::fs::read(path).ok()?;
+    Some(format!("{:x}", Sha256::digest(&content)))
+}
+
+/// Delete `files` that still hold what was installed; edited ones stay
+fn remove_files(root: &Path, files: &[InstalledFile]) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for file in files {
+        let path = target_path(root, &file.path)?;
+        let Ok(content) = std::fs::read(&path) else {
+            continue;
+        };
+        if format!("{:x}", Sha256::digest(&content)) != file.hash {
+            tracing::warn!(
+                "Keeping {}: modified since it was installed",
+                path.display()
+            );
+            continue;
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// `relative` under `root`, refusing paths that would escape it
+fn target_path(root: &Path, relative: &str) -> Result<PathBuf> {
+    let path = Path::new(relative);
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!(
+            "Refusing package file path outside the project: {}",
+            relative
+        );
+    }
+    Ok(root.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::{FileEntry, IndexedVersion};
+
+    /// Registry under `root` with a file per package version
+    struct Registry {
+        store: Arc<dyn ObjectStore>,
+        entries: BTreeMap<String, IndexEntry>,
+    }
+
+    impl Registry {
+        fn new(dir: &Path) -> Self {
+            Self {
+                store: Arc::new(LocalObjectStore::new(dir).unwrap()),
+                entries: BTreeMap::new(),
+            }
+        }
+
+        async fn publish(
+            &mut self,
+            id: &str,
+            version: &str,
+            deps: &[(&str, &str)],
+            files: &[(&str, &str)],
+        ) {
+            let mut manifest = VariantManifest::default();
+            for (path, content) in files {
+                let hash = format!("{:x}", Sha256::digest(content.as_bytes()));
+                self.store
+                    .put(&format!("files/{}", hash), content.as_bytes().to_vec())
+                    .await
+                    .unwrap();
+                manifest.files.push(FileEntry {
+                    path: path.to_string(),
+                    hash,
+                    size: content.len() as u64,
+                });
+            }
+            let entry = self
+                .entries
+                .entry(id.to_string())
+                .or_insert_with(|| IndexEntry {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    description: format!("The {} package", id),
+                    keywords: vec!["ui".to_string()],
+                    versions: Vec::new(),
+                });
+            entry.versions.push(IndexedVersion {
+                version: version.parse().unwrap(),
+                dependencies: deps
+                    .iter()
+                    .map(|(name, req)| (name.to_string(), req.parse().unwrap()))
+                    .collect(),
+                variants: BTreeMap::from([(DEFAULT_VARIANT.to_string(), manifest)]),
+            });
+            self.store
+                .put(
+                    &format!("index/{}.json", id),
+                    serde_json::to_vec(entry).unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        async fn index(&self) -> PackageIndex {
+            PackageIndex::load(self.store.clone()).await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn install_writes_files_and_records_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish("dx-core", "1.0.0", &[], &[("core/index.ts", "core")])
+            .await;
+        registry
+            .publish(
+                "dx-button",
+                "1.0.0",
+                &[("dx-core", "^1")],
+                &[("ui/button.tsx", "button")],
+            )
+            .await;
+        let index = registry.index().await;
+
+        let written = install_at(&root, &index, "dx-button", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(root.join("ui/button.tsx")).unwrap(),
+            "button"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("core/index.ts")).unwrap(),
+            "core"
+        );
+
+        let lockfile = load_lockfile(&root).unwrap();
+        assert_eq!(
+            lockfile.get("dx-button").unwrap().version,
+            Version::new(1, 0, 0)
+        );
+        assert_eq!(
+            lockfile.get("dx-button").unwrap().dependencies,
+            vec!["dx-core"]
+        );
+
+        let installed = list_at(&root).unwrap();
+        let ids: Vec<&str> = installed
+            .iter()
+            .map(|package| package.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["dx-button", "dx-core"]);
+
+        let found = search_at(&root, &index, "BUTTON").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].installed_files, vec![root.join("ui/button.tsx")]);
+    }
+
+    #[tokio::test]
+    async fn uninstall_removes_exactly_recorded_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish(
+                "dx-card",
+                "1.0.0",
+                &[],
+                &[("ui/card.tsx", "card"), ("ui/card.css", "css")],
+            )
+            .await;
+        let index = registry.index().await;
+
+        install_at(&root, &index, "dx-card", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        std::fs::write(root.join("ui/mine.tsx"), "mine").unwrap();
+        std::fs::write(root.join("ui/card.css"), "edited").unwrap();
+
+        let removed = uninstall_at(&root, "dx-card").unwrap();
+        assert_eq!(removed, vec![root.join("ui/card.tsx")]);
+        assert!(root.join("ui/mine.tsx").exists());
+        assert!(root.join("ui/card.css").exists());
+        assert!(list_at(&root).unwrap().is_empty());
+        assert!(load_lockfile(&root).unwrap().get("dx-card").is_none());
+    }
+
+    #[tokio::test]
+    async fn project_files_at_manifest_paths_survive_install_and_uninstall() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish(
+                "dx-card",
+                "1.0.0",
+                &[],
+                &[("ui/card.tsx", "card"), ("ui/card.css", "css")],
+            )
+            .await;
+        let index = registry.index().await;
+
+        // A different file in the way: nothing is written
+        std::fs::create_dir_all(root.join("ui")).unwrap();
+        std::fs::write(root.join("ui/card.tsx"), "mine").unwrap();
+        let refused = install_at(&root, &index, "dx-card", DEFAULT_VARIANT).await;
+        assert!(refused.unwrap_err().to_string().contains("ui/card.tsx"));
+        assert_eq!(
+            std::fs::read_to_string(root.join("ui/card.tsx")).unwrap(),
+            "mine"
+        );
+        assert!(!root.join("ui/card.css").exists());
+        assert!(list_at(&root).unwrap().is_empty());
+
+        // An identical file is used but stays the project's
+        std::fs::write(root.join("ui/card.tsx"), "card").unwrap();
+        let written = install_at(&root, &index, "dx-card", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        assert_eq!(written, vec![root.join("ui/card.css")]);
+        let removed = uninstall_at(&root, "dx-card").unwrap();
+        assert_eq!(removed, vec![root.join("ui/card.css")]);
+        assert_eq!(
+            std::fs::read_to_string(root.join("ui/card.tsx")).unwrap(),
+            "card"
+        );
+    }
+
+    #[tokio::test]
+    async fn uninstall_refuses_required_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish("dx-core", "1.0.0", &[], &[("core.ts", "core")])
+            .await;
+        registry
+            .publish("dx-app", "1.0.0", &[("dx-core", "1")], &[("app.ts", "app")])
+            .await;
+        let index = registry.index().await;
+
+        install_at(&root, &index, "dx-app", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        let err = uninstall_at(&root, "dx-core").unwrap_err();
+        assert!(err.to_string().contains("required by dx-app"));
+        assert!(root.join("core.ts").exists());
+    }
+
+    #[tokio::test]
+    async fn pin_holds_version_through_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish(
+                "dx-nav",
+                "1.0.0",
+                &[],
+                &[("nav.ts", "v1"), ("old.ts", "old")],
+            )
+            .await;
+        registry
+            .publish("dx-nav", "1.1.0", &[], &[("nav.ts", "v1.1")])
+            .await;
+        let index = registry.index().await;
+
+        install_at(&root, &index, "dx-nav", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("nav.ts")).unwrap(),
+            "v1.1"
+        );
+
+        pin_at(&root, &index, "dx-nav", "1.0.0").await.unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("nav.ts")).unwrap(), "v1");
+        assert!(root.join("old.ts").exists());
+        assert!(load_lockfile(&root).unwrap().is_pinned("dx-nav"));
+        assert!(update_at(&root, &index, "dx-nav").await.is_err());
+
+        // Re-resolving for another install keeps both the version and the pin
+        registry
+            .publish("dx-footer", "1.0.0", &[], &[("footer.ts", "footer")])
+            .await;
+        let index = registry.index().await;
+        install_at(&root, &index, "dx-footer", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+        let lockfile = load_lockfile(&root).unwrap();
+        assert!(lockfile.is_pinned("dx-nav"));
+        assert_eq!(
+            lockfile.get("dx-nav").unwrap().version,
+            Version::new(1, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn update_moves_to_newest_and_drops_stale_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let mut registry = Registry::new(&dir.path().join("registry"));
+        registry
+            .publish(
+                "dx-nav",
+                "1.0.0",
+                &[],
+                &[("nav.ts", "v1"), ("old.ts", "old")],
+            )
+            .await;
+        let index = registry.index().await;
+        install_at(&root, &index, "dx-nav", DEFAULT_VARIANT)
+            .await
+            .unwrap();
+
+        registry
+            .publish("dx-nav", "1.1.0", &[], &[("nav.ts", "v1.1")])
+            .await;
+        let index = registry.index().await;
+        let touched = update_at(&root, &index, "dx-nav").await.unwrap();
+        assert!(touched.contains(&root.join("old.ts")));
+        assert_eq!(
+            std::fs::read_to_string(root.join("nav.ts")).unwrap(),
+            "v1.1"
+        );
+        assert!(!root.join("old.ts").exists());
+        assert_eq!(list_at(&root).unwrap()[0].version, "1.1.0");
+    }
+
+    #[test]
+    fn rejects_paths_outside_project() {
+        let root = Path::new("/project");
+        assert!(target_path(root, "../etc/passwd").is_err());
+        assert!(target_path(root, "/etc/passwd").is_err());
+        assert_eq!(target_path(root, "ui/a.ts").unwrap(), root.join("ui/a.ts"));
+    }
+}
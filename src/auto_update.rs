@@ -2,14 +2,30 @@
 //!
 //! Provides automatic updates for green traffic changes, version conflict detection,
 //! update notifications, and rollback capability.
+//!
+//! Installed tools live in `<forge>/tools/<name>`. An update fetches the new
+//! artifact from the tool's `ToolSource` into a staging directory, moves the
+//! current files into `<forge>/backups/<name>/<id>` and the staged ones into
+//! place, then updates the registry; rollback reverses both. Preferences,
+//! notifications and backups are kept in `<forge>/auto_update.json`.
+//!
+//! Updates are gated: a tool's configured [`HealthCheck`] runs right after
+//! the new version is installed and a failure rolls it back. Tools follow a
+//! [`UpdateChannel`] (stable tools never get pre-releases and receive green
+//! updates in stages, see [`RolloutPolicy`]) and held tools are never
+//! updated.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use chrono::{DateTime, Utc};
 
-use crate::version::{Version, ToolInfo, ToolRegistry};
+use crate::orchestrator::{DxTool, ExecutionContext};
+use crate::version::{Version, ToolInfo, ToolRegistry, ToolSource};
 
 /// Traffic level for updates (simplified version)
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -37,6 +53,121 @@ pub enum UpdateStatus {
     Failed(String),
     /// Update requires manual intervention
     RequiresManual,
+    /// Update was undone, after a failed health check or by hand
+    RolledBack(String),
+}
+
+/// What [`AutoUpdateManager::process_update`] did with an update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// Installed and healthy
+    Applied,
+    /// Installed, failed its health check and rolled back
+    RolledBack(String),
+    /// Recorded as a notification for review
+    Notified,
+    /// Not yet rolled out to this installation
+    Deferred,
+    /// The tool is on the hold list
+    Held,
+    /// Auto-updates are off, or the version isn't on the tool's channel
+    Skipped,
+}
+
+/// Which releases a tool follows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    /// Releases only, with green updates rolled out in stages
+    #[default]
+    Stable,
+    /// Pre-releases too, applied as soon as they are seen
+    Beta,
+}
+
+impl UpdateChannel {
+    pub fn accepts(&self, version: &Version) -> bool {
+        *self == UpdateChannel::Beta || !version.is_prerelease()
+    }
+}
+
+/// How a tool is checked after an update before the update is kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HealthCheck {
+    /// Build the tool with its registered [`ToolFactory`] and run its
+    /// `should_run`/`execute` against a scratch context; a tool that
+    /// declines to run fails the check
+    Tool,
+    /// Run a command in the tool's directory; healthy when it exits
+    /// successfully within `timeout_secs`
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+/// Builds a tool from its installed files, for [`HealthCheck::Tool`]
+pub type ToolFactory = Arc<dyn Fn(&Path) -> Result<Box<dyn DxTool>> + Send + Sync>;
+
+/// One step of a staged rollout: `percent` of installations take a green
+/// update once it has been known for `after_hours`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RolloutStage {
+    pub after_hours: i64,
+    pub percent: u8,
+}
+
+/// Staged rollout of green updates on the stable channel. Each
+/// installation falls in a fixed bucket per tool version, so a bad release
+/// reaches a growing share of installations instead of all of them at once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RolloutPolicy {
+    pub stages: Vec<RolloutStage>,
+}
+
+impl RolloutPolicy {
+    /// Every installation takes updates as soon as they are seen
+    pub fn immediate() -> Self {
+        Self {
+            stages: vec![RolloutStage {
+                after_hours: 0,
+                percent: 100,
+            }],
+        }
+    }
+
+    /// Share of installations an update known for `age` has reached
+    pub fn percent_at(&self, age: chrono::Duration) -> u8 {
+        self.stages
+            .iter()
+            // A stage too far out to represent is never reached
+            .filter(|stage| {
+                chrono::TimeDelta::try_hours(stage.after_hours).is_some_and(|after| age >= after)
+            })
+            .map(|stage| stage.percent.min(100))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Default for RolloutPolicy {
+    fn default() -> Self {
+        Self {
+            stages: vec![
+                RolloutStage { after_hours: 0, percent: 10 },
+                RolloutStage { after_hours: 24, percent: 50 },
+                RolloutStage { after_hours: 72, percent: 100 },
+            ],
+        }
+    }
 }
 
 /// Update notification
@@ -49,6 +180,10 @@ pub struct UpdateNotification {
     pub status: UpdateStatus,
     pub timestamp: DateTime<Utc>,
     pub message: String,
+    /// Where to fetch the new version from; derived from the installed
+    /// tool's source when not given
+    #[serde(default)]
+    pub source: Option<ToolSource>,
 }
 
 /// Backup information for rollback
@@ -58,37 +193,164 @@ pub struct Backup {
     pub tool_name: String,
     pub version: Version,
     pub created_at: DateTime<Utc>,
+    /// Holds the replaced files under `files/`
     pub backup_path: PathBuf,
+    /// Registry entry before the update
+    pub tool: ToolInfo,
+}
+
+/// Everything the manager keeps between runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UpdateState {
+    #[serde(default)]
+    preferences: UpdatePreference,
+    #[serde(default = "enabled")]
+    auto_update_enabled: bool,
+    #[serde(default)]
+    notifications: Vec<UpdateNotification>,
+    #[serde(default)]
+    backups: HashMap<String, Vec<Backup>>,
+    /// Identifies this installation for staged rollouts
+    #[serde(default = "new_install_id")]
+    install_id: String,
+    #[serde(default)]
+    channels: HashMap<String, UpdateChannel>,
+    /// Tools that are never updated
+    #[serde(default)]
+    held: BTreeSet<String>,
+    #[serde(default)]
+    health_checks: HashMap<String, HealthCheck>,
+    /// When each `tool@version` update was first seen
+    #[serde(default)]
+    first_seen: HashMap<String, DateTime<Utc>>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn new_install_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+impl Default for UpdateState {
+    fn default() -> Self {
+        Self {
+            preferences: UpdatePreference::default(),
+            auto_update_enabled: true,
+            notifications: Vec::new(),
+            backups: HashMap::new(),
+            install_id: new_install_id(),
+            channels: HashMap::new(),
+            held: BTreeSet::new(),
+            health_checks: HashMap::new(),
+            first_seen: HashMap::new(),
+        }
+    }
 }
 
 /// Auto-update manager
 pub struct AutoUpdateManager {
+    forge_dir: PathBuf,
     registry: ToolRegistry,
-    backups: HashMap<String, Vec<Backup>>,
-    notifications: Vec<UpdateNotification>,
-    auto_update_enabled: bool,
+    state: UpdateState,
+    factories: HashMap<String, ToolFactory>,
 }
 
 impl AutoUpdateManager {
-    /// Create a new auto-update manager
+    /// Create a new auto-update manager, loading its saved state
     pub fn new(forge_dir: &Path) -> Result<Self> {
         let registry = ToolRegistry::new(forge_dir)?;
-        
+        let state_path = forge_dir.join("auto_update.json");
+        let state = if state_path.exists() {
+            let content = std::fs::read_to_string(&state_path)
+                .context("Failed to read auto-update state")?;
+            serde_json::from_str(&content).context("Failed to parse auto-update state")?
+        } else {
+            UpdateState::default()
+        };
+
         Ok(Self {
+            forge_dir: forge_dir.to_path_buf(),
             registry,
-            backups: HashMap::new(),
-            notifications: Vec::new(),
-            auto_update_enabled: true,
+            state,
+            factories: HashMap::new(),
         })
     }
 
     /// Enable or disable auto-updates
-    pub fn set_auto_update(&mut self, enabled: bool) {
-        self.auto_update_enabled = enabled;
+    pub fn set_auto_update(&mut self, enabled: bool) -> Result<()> {
+        self.state.auto_update_enabled = enabled;
+        self.save()
+    }
+
+    pub fn preferences(&self) -> &UpdatePreference {
+        &self.state.preferences
+    }
+
+    pub fn set_preferences(&mut self, preferences: UpdatePreference) -> Result<()> {
+        self.state.preferences = preferences;
+        self.save()
+    }
+
+    pub fn registry(&self) -> &ToolRegistry {
+        &self.registry
+    }
+
+    pub fn channel(&self, tool_name: &str) -> UpdateChannel {
+        self.state.channels.get(tool_name).copied().unwrap_or_default()
+    }
+
+    pub fn set_channel(&mut self, tool_name: &str, channel: UpdateChannel) -> Result<()> {
+        self.state.channels.insert(tool_name.to_string(), channel);
+        self.save()
+    }
+
+    /// Keep a tool at its installed version until released
+    pub fn hold(&mut self, tool_name: &str) -> Result<()> {
+        self.state.held.insert(tool_name.to_string());
+        self.save()
+    }
+
+    pub fn release(&mut self, tool_name: &str) -> Result<()> {
+        self.state.held.remove(tool_name);
+        self.save()
+    }
+
+    pub fn is_held(&self, tool_name: &str) -> bool {
+        self.state.held.contains(tool_name)
+    }
+
+    pub fn held_tools(&self) -> impl Iterator<Item = &str> {
+        self.state.held.iter().map(String::as_str)
+    }
+
+    /// Check run after every update of `tool_name`; `None` keeps updates
+    /// without checking
+    pub fn set_health_check(&mut self, tool_name: &str, check: Option<HealthCheck>) -> Result<()> {
+        match check {
+            Some(check) => self.state.health_checks.insert(tool_name.to_string(), check),
+            None => self.state.health_checks.remove(tool_name),
+        };
+        self.save()
+    }
+
+    /// How to build `tool_name` for [`HealthCheck::Tool`]. Factories aren't
+    /// persisted and have to be registered on every start.
+    pub fn register_tool_factory(&mut self, tool_name: &str, factory: ToolFactory) {
+        self.factories.insert(tool_name.to_string(), factory);
+    }
+
+    /// Directory a tool's files are installed in
+    pub fn tool_dir(&self, tool_name: &str) -> PathBuf {
+        self.forge_dir.join("tools").join(tool_name)
     }
 
     /// Check for updates for a specific tool
     pub fn check_update(&self, tool_name: &str, latest_version: Version) -> Option<UpdateNotification> {
+        if !self.channel(tool_name).accepts(&latest_version) {
+            return None;
+        }
         if let Some(current_version) = self.registry.version(tool_name) {
             if &latest_version > current_version {
                 return Some(UpdateNotification {
@@ -99,6 +361,7 @@ impl AutoUpdateManager {
                     status: UpdateStatus::Available,
                     timestamp: Utc::now(),
                     message: format!("Update available: {} -> {}", current_version, latest_version),
+                    source: None,
                 });
             }
         }
@@ -121,28 +384,47 @@ impl AutoUpdateManager {
         TrafficLevel::Red
     }
 
-    /// Apply update if it's a green traffic update
-    pub fn process_update(&mut self, notification: &UpdateNotification) -> Result<()> {
-        if !self.auto_update_enabled {
-            return Ok(());
+    /// Run an update through the pipeline: held tools and versions off the
+    /// tool's channel are left alone, green updates are applied (on the
+    /// stable channel once the staged rollout reaches this installation)
+    /// and health checked, and the rest are recorded for review
+    pub async fn process_update(&mut self, notification: &UpdateNotification) -> Result<UpdateOutcome> {
+        let tool_name = &notification.tool_name;
+        if !self.state.auto_update_enabled
+            || !self.channel(tool_name).accepts(&notification.new_version)
+        {
+            return Ok(UpdateOutcome::Skipped);
+        }
+        if self.is_held(tool_name) {
+            println!("⏸  {} is held at {}; not updating to {}",
+                tool_name,
+                notification.current_version,
+                notification.new_version
+            );
+            return Ok(UpdateOutcome::Held);
         }
 
-        match notification.traffic_level {
-            TrafficLevel::Green => {
+        let preferences = self.state.preferences.clone();
+        let notify = match notification.traffic_level {
+            TrafficLevel::Green if preferences.auto_update_green => {
+                if !self.rollout_reached(notification)? {
+                    return Ok(UpdateOutcome::Deferred);
+                }
                 println!("🟢 Auto-applying green traffic update: {} {} -> {}",
                     notification.tool_name,
                     notification.current_version,
                     notification.new_version
                 );
-                self.apply_update(notification)?;
+                return self.apply_update(notification).await;
             },
+            TrafficLevel::Green => true,
             TrafficLevel::Yellow => {
                 println!("🟡 Yellow traffic update available: {} {} -> {} (requires review)",
                     notification.tool_name,
                     notification.current_version,
                     notification.new_version
                 );
-                self.add_notification(notification.clone());
+                preferences.notify_yellow
             },
             TrafficLevel::Red => {
                 println!("🔴 Red traffic update available: {} {} -> {} (requires manual intervention)",
@@ -150,130 +432,281 @@ impl AutoUpdateManager {
                     notification.current_version,
                     notification.new_version
                 );
-                self.add_notification(notification.clone());
+                preferences.notify_red
             },
+        };
+        if notify {
+            self.add_notification(notification.clone())?;
         }
 
-        Ok(())
+        Ok(UpdateOutcome::Notified)
     }
 
-    /// Apply an update
-    fn apply_update(&mut self, notification: &UpdateNotification) -> Result<()> {
-        // Create backup before applying update
-        self.create_backup(&notification.tool_name, &notification.current_version)?;
+    /// Whether the staged rollout of this update includes this installation
+    fn rollout_reached(&mut self, notification: &UpdateNotification) -> Result<bool> {
+        if self.channel(&notification.tool_name) == UpdateChannel::Beta {
+            return Ok(true);
+        }
+        let key = format!("{}@{}", notification.tool_name, notification.new_version);
+        let first_seen = match self.state.first_seen.get(&key) {
+            Some(first_seen) => *first_seen,
+            None => {
+                let now = Utc::now();
+                self.state.first_seen.insert(key.clone(), now);
+                self.save()?;
+                now
+            }
+        };
 
-        // In a real implementation, this would download and install the update
-        // For now, we'll just update the registry
-        println!("  ✓ Created backup");
-        println!("  ⬇ Downloading update...");
-        println!("  ✓ Applying update...");
+        let digest = Sha256::digest(format!("{}/{}", self.state.install_id, key));
+        let bucket = u64::from_be_bytes(digest[..8].try_into()?) % 100;
+        let percent = self.state.preferences.rollout.percent_at(Utc::now() - first_seen);
+        Ok(bucket < u64::from(percent))
+    }
 
-        // Update the registry (simulated)
-        let _tool_info = ToolInfo {
-            name: notification.tool_name.clone(),
-            version: notification.new_version.clone(),
-            installed_at: Utc::now(),
-            source: crate::version::ToolSource::Crate {
-                version: notification.new_version.to_string(),
-            },
-            dependencies: HashMap::new(),
-        };
+    /// Install the update's version: fetch it, back up the current files
+    /// and registry entry, swap the new files in and update the registry,
+    /// then run the tool's health check and roll back if it fails. If
+    /// installing fails the tool is left as it was.
+    pub async fn apply_update(&mut self, notification: &UpdateNotification) -> Result<UpdateOutcome> {
+        if self.is_held(&notification.tool_name) {
+            bail!("{} is held; release it before updating", notification.tool_name);
+        }
+        if let Err(e) = self.install_update(notification).await {
+            self.add_notification(UpdateNotification {
+                status: UpdateStatus::Failed(format!("{:#}", e)),
+                timestamp: Utc::now(),
+                message: format!("Failed to update to {}", notification.new_version),
+                ..notification.clone()
+            })?;
+            return Err(e);
+        }
 
-        // In reality, we'd update through the registry methods
-        println!("  ✓ Update applied successfully");
+        if let Err(reason) = self.run_health_check(&notification.tool_name).await {
+            println!("  ✗ Health check failed: {}", reason);
+            self.restore_latest_backup(&notification.tool_name)?;
+            self.add_notification(UpdateNotification {
+                status: UpdateStatus::RolledBack(reason.clone()),
+                timestamp: Utc::now(),
+                message: format!(
+                    "{} failed its health check and was rolled back",
+                    notification.new_version
+                ),
+                ..notification.clone()
+            })?;
+            return Ok(UpdateOutcome::RolledBack(reason));
+        }
 
-        // Add success notification
+        println!("  ✓ Update applied successfully");
         self.add_notification(UpdateNotification {
             status: UpdateStatus::Applied,
             timestamp: Utc::now(),
             message: format!("Successfully updated to {}", notification.new_version),
             ..notification.clone()
-        });
+        })?;
+        Ok(UpdateOutcome::Applied)
+    }
 
-        Ok(())
+    /// Run the tool's health check, if it has one; `Err` holds the reason
+    /// it failed
+    async fn run_health_check(&self, tool_name: &str) -> std::result::Result<(), String> {
+        let Some(check) = self.state.health_checks.get(tool_name) else {
+            return Ok(());
+        };
+        let tool_dir = self.tool_dir(tool_name);
+        match check {
+            HealthCheck::Command { program, args, timeout_secs } => {
+                let output = tokio::process::Command::new(program)
+                    .args(args)
+                    .current_dir(&tool_dir)
+                    .kill_on_drop(true)
+                    .output();
+                match tokio::time::timeout(Duration::from_secs(*timeout_secs), output).await {
+                    Err(_) => Err(format!("{} timed out after {}s", program, timeout_secs)),
+                    Ok(Err(e)) => Err(format!("failed to run {}: {}", program, e)),
+                    Ok(Ok(output)) if !output.status.success() => Err(format!(
+                        "{} exited with {}: {}",
+                        program,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )),
+                    Ok(Ok(_)) => Ok(()),
+                }
+            }
+            HealthCheck::Tool => {
+                let factory = self
+                    .factories
+                    .get(tool_name)
+                    .ok_or_else(|| format!("no tool factory is registered for {}", tool_name))?;
+                let scratch = self
+                    .forge_dir
+                    .join("health")
+                    .join(uuid::Uuid::new_v4().to_string());
+                let result = run_tool_check(factory, &tool_dir, &scratch);
+                let _ = std::fs::remove_dir_all(&scratch);
+                result
+            }
+        }
     }
 
-    /// Create a backup for rollback
-    fn create_backup(&mut self, tool_name: &str, version: &Version) -> Result<()> {
-        let backup_id = uuid::Uuid::new_v4().to_string();
-        let backup_path = PathBuf::from(format!(".dx/forge/backups/{}/{}", tool_name, backup_id));
+    async fn install_update(&mut self, notification: &UpdateNotification) -> Result<()> {
+        let name = &notification.tool_name;
+        let current = self
+            .registry
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} is not installed", name))?;
+        let source = match &notification.source {
+            Some(source) => source.clone(),
+            None => current
+                .source
+                .for_version(&current.version, &notification.new_version)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Can't tell where {} {} comes from; give the update a source",
+                        name,
+                        notification.new_version
+                    )
+                })?,
+        };
 
-        // In a real implementation, this would copy the tool files
-        std::fs::create_dir_all(&backup_path)?;
+        let staging = self
+            .forge_dir
+            .join("tools")
+            .join(format!(".staging-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&staging)?;
+        println!("  ⬇ Downloading update...");
+        if let Err(e) = fetch_artifact(name, &source, &staging).await {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(e.context(format!("Failed to fetch {} {}", name, notification.new_version)));
+        }
 
-        let backup = Backup {
-            id: backup_id,
-            tool_name: tool_name.to_string(),
-            version: version.clone(),
-            created_at: Utc::now(),
-            backup_path,
-        };
+        let backup = self.create_backup(&current)?;
+        println!("  ✓ Created backup");
 
-        self.backups
-            .entry(tool_name.to_string())
-            .or_insert_with(Vec::new)
-            .push(backup);
+        let live = self.tool_dir(name);
+        if let Err(e) = std::fs::rename(&staging, &live) {
+            // Put the old files back so the tool keeps working
+            let _ = std::fs::remove_dir_all(&staging);
+            restore_files(&backup.backup_path.join("files"), &live)?;
+            let _ = std::fs::remove_dir_all(&backup.backup_path);
+            return Err(anyhow::Error::new(e).context(format!("Failed to install {}", name)));
+        }
+        println!("  ✓ Applying update...");
 
-        Ok(())
+        let registered = self.registry.register(
+            name.clone(),
+            notification.new_version.clone(),
+            source,
+            current.dependencies.clone(),
+        );
+        if let Err(e) = registered {
+            // Put the previous version back, files and registry entry alike
+            restore_files(&backup.backup_path.join("files"), &live)?;
+            let _ = std::fs::remove_dir_all(&backup.backup_path);
+            let _ = self.registry.restore(current);
+            return Err(e.context(format!(
+                "Failed to register {} {}",
+                name, notification.new_version
+            )));
+        }
+        self.state.backups.entry(name.clone()).or_default().push(backup);
+        self.save()
     }
 
-    /// Rollback to a previous version
-    pub fn rollback(&mut self, tool_name: &str) -> Result<()> {
-        let backups = self.backups
-            .get_mut(tool_name)
-            .ok_or_else(|| anyhow!("No backups found for {}", tool_name))?;
+    /// Move a tool's files out of the way into a new backup
+    fn create_backup(&mut self, tool: &ToolInfo) -> Result<Backup> {
+        let backup_id = uuid::Uuid::new_v4().to_string();
+        let backup_path = self.forge_dir.join("backups").join(&tool.name).join(&backup_id);
+        std::fs::create_dir_all(&backup_path)?;
 
-        let backup = backups.pop()
-            .ok_or_else(|| anyhow!("No backups available for {}", tool_name))?;
+        let live = self.tool_dir(&tool.name);
+        if live.exists() {
+            std::fs::rename(&live, backup_path.join("files"))
+                .with_context(|| format!("Failed to back up {}", tool.name))?;
+        }
 
-        println!("🔄 Rolling back {} to version {}", tool_name, backup.version);
-        println!("  ✓ Restoring from backup: {}", backup.id);
+        Ok(Backup {
+            id: backup_id,
+            tool_name: tool.name.clone(),
+            version: tool.version.clone(),
+            created_at: Utc::now(),
+            backup_path,
+            tool: tool.clone(),
+        })
+    }
 
-        // In a real implementation, this would restore the files from backup
-        // and update the registry
-        println!("  ✓ Rollback complete");
+    /// Rollback to a previous version, restoring its files and registry
+    /// entry from the latest backup
+    pub fn rollback(&mut self, tool_name: &str) -> Result<()> {
+        let current_version = self.registry.version(tool_name).cloned();
+        let backup = self.restore_latest_backup(tool_name)?;
 
         // Add notification
         self.add_notification(UpdateNotification {
             tool_name: tool_name.to_string(),
-            current_version: backup.version.clone(),
+            current_version: current_version.unwrap_or_else(|| backup.version.clone()),
             new_version: backup.version.clone(),
             traffic_level: TrafficLevel::Green,
-            status: UpdateStatus::Applied,
+            status: UpdateStatus::RolledBack("manual rollback".to_string()),
             timestamp: Utc::now(),
             message: format!("Rolled back to version {}", backup.version),
-        });
+            source: None,
+        })
+    }
 
-        Ok(())
+    /// Put back the files and registry entry of the latest backup and
+    /// drop it
+    fn restore_latest_backup(&mut self, tool_name: &str) -> Result<Backup> {
+        let backup = self.state.backups
+            .get(tool_name)
+            .and_then(|backups| backups.last())
+            .cloned()
+            .ok_or_else(|| anyhow!("No backups available for {}", tool_name))?;
+
+        println!("🔄 Rolling back {} to version {}", tool_name, backup.version);
+        println!("  ✓ Restoring from backup: {}", backup.id);
+
+        restore_files(&backup.backup_path.join("files"), &self.tool_dir(tool_name))?;
+        self.registry.restore(backup.tool.clone())?;
+        let _ = std::fs::remove_dir_all(&backup.backup_path);
+        if let Some(backups) = self.state.backups.get_mut(tool_name) {
+            backups.pop();
+        }
+
+        println!("  ✓ Rollback complete");
+        Ok(backup)
     }
 
     /// Add a notification
-    fn add_notification(&mut self, notification: UpdateNotification) {
-        self.notifications.push(notification);
+    fn add_notification(&mut self, notification: UpdateNotification) -> Result<()> {
+        self.state.notifications.push(notification);
 
         // Keep only last 100 notifications
-        if self.notifications.len() > 100 {
-            self.notifications.remove(0);
+        if self.state.notifications.len() > 100 {
+            self.state.notifications.remove(0);
         }
+        self.save()
     }
 
     /// Get all notifications
     pub fn get_notifications(&self) -> &[UpdateNotification] {
-        &self.notifications
+        &self.state.notifications
     }
 
     /// Get pending updates
     pub fn get_pending_updates(&self) -> Vec<&UpdateNotification> {
-        self.notifications
+        self.state.notifications
             .iter()
             .filter(|n| n.status == UpdateStatus::Available || n.status == UpdateStatus::RequiresManual)
             .collect()
     }
 
     /// Clear old notifications
-    pub fn clear_old_notifications(&mut self, days: i64) {
+    pub fn clear_old_notifications(&mut self, days: i64) -> Result<()> {
         let cutoff = Utc::now() - chrono::Duration::days(days);
-        self.notifications.retain(|n| n.timestamp > cutoff);
+        self.state.notifications.retain(|n| n.timestamp > cutoff);
+        self.save()
     }
 
     /// Detect version conflicts
@@ -291,7 +724,7 @@ impl AutoUpdateManager {
 
     /// Get backup history for a tool
     pub fn get_backups(&self, tool_name: &str) -> Vec<&Backup> {
-        self.backups
+        self.state.backups
             .get(tool_name)
             .map(|backups| backups.iter().collect())
             .unwrap_or_default()
@@ -301,7 +734,7 @@ impl AutoUpdateManager {
     pub fn clean_old_backups(&mut self, days: i64) -> Result<()> {
         let cutoff = Utc::now() - chrono::Duration::days(days);
 
-        for backups in self.backups.values_mut() {
+        for backups in self.state.backups.values_mut() {
             backups.retain(|backup| {
                 if backup.created_at < cutoff {
                     // Delete backup directory
@@ -313,10 +746,124 @@ impl AutoUpdateManager {
             });
         }
 
+        self.save()
+    }
+
+    /// Write the state through a temporary file so it is never half written
+    fn save(&self) -> Result<()> {
+        let path = self.forge_dir.join("auto_update.json");
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&self.state)?)?;
+        std::fs::rename(&tmp, &path).context("Failed to save auto-update state")?;
         Ok(())
     }
 }
 
+/// Build the tool from `tool_dir` and run it against an empty project in
+/// `scratch`
+fn run_tool_check(factory: &ToolFactory, tool_dir: &Path, scratch: &Path) -> std::result::Result<(), String> {
+    let forge_path = scratch.join(".dx").join("forge");
+    std::fs::create_dir_all(&forge_path).map_err(|e| e.to_string())?;
+    let context = ExecutionContext::new(scratch.to_path_buf(), forge_path);
+    let mut tool = factory(tool_dir).map_err(|e| format!("failed to load the tool: {:#}", e))?;
+    // Sitting the check out shows nothing about the new version
+    if !tool.should_run(&context) {
+        return Err(format!(
+            "{} declined to run against an empty project, so its health is unknown",
+            tool.name()
+        ));
+    }
+    match tool.execute(&context) {
+        Ok(output) if output.success => Ok(()),
+        Ok(output) => Err(format!("{} reported failure: {}", tool.name(), output.message)),
+        Err(e) => Err(format!("{} failed: {:#}", tool.name(), e)),
+    }
+}
+
+/// Replace `live` with the backed up files in `files` (or nothing, if the
+/// tool had no files)
+fn restore_files(files: &Path, live: &Path) -> Result<()> {
+    if live.exists() {
+        std::fs::remove_dir_all(live)?;
+    }
+    if files.exists() {
+        std::fs::rename(files, live).with_context(|| format!("Failed to restore {}", live.display()))?;
+    }
+    Ok(())
+}
+
+/// Fetch a tool's artifact from `source` into the empty directory `dest`
+async fn fetch_artifact(name: &str, source: &ToolSource, dest: &Path) -> Result<()> {
+    match source {
+        ToolSource::Local(path) => {
+            if path.is_dir() {
+                copy_dir(path, dest)
+            } else {
+                let file_name = path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("{} is not a file", path.display()))?;
+                std::fs::copy(path, dest.join(file_name))
+                    .with_context(|| format!("Failed to copy {}", path.display()))?;
+                Ok(())
+            }
+        }
+        ToolSource::Crate { version } => {
+            let status = tokio::process::Command::new("cargo")
+                .args(["install", "--quiet", "--root"])
+                .arg(dest)
+                .args(["--version", &format!("={}", version), name])
+                .status()
+                .await
+                .context("Failed to run cargo install")?;
+            if !status.success() {
+                bail!("cargo install {}@{} failed ({})", name, version, status);
+            }
+            Ok(())
+        }
+        ToolSource::Git { url, rev } => {
+            let (url, rev, dest) = (url.clone(), rev.clone(), dest.to_path_buf());
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let repo = git2::Repository::clone(&url, &dest)
+                    .with_context(|| format!("Failed to clone {}", url))?;
+                let object = repo
+                    .revparse_single(&rev)
+                    .with_context(|| format!("{} has no revision {}", url, rev))?;
+                repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))?;
+                repo.set_head_detached(object.peel_to_commit()?.id())?;
+                drop(object);
+                drop(repo);
+                std::fs::remove_dir_all(dest.join(".git"))?;
+                Ok(())
+            })
+            .await?
+        }
+        ToolSource::R2 { bucket, key } => {
+            let store = crate::storage::open_remote(&format!("r2://{}", bucket))?;
+            let data = store
+                .get(key)
+                .await?
+                .ok_or_else(|| anyhow!("r2://{}/{} doesn't exist", bucket, key))?;
+            let file_name = key.rsplit('/').next().unwrap_or(key);
+            std::fs::write(dest.join(file_name), data)?;
+            Ok(())
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)
+                .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
 /// Update preference for notifications
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdatePreference {
@@ -324,6 +871,9 @@ pub struct UpdatePreference {
     pub notify_yellow: bool,
     pub notify_red: bool,
     pub email_notifications: bool,
+    /// Staged rollout of green updates on the stable channel
+    #[serde(default)]
+    pub rollout: RolloutPolicy,
 }
 
 impl Default for UpdatePreference {
@@ -333,6 +883,7 @@ impl Default for UpdatePreference {
             notify_yellow: true,
             notify_red: true,
             email_notifications: false,
+            rollout: RolloutPolicy::default(),
         }
     }
 }
@@ -340,15 +891,12 @@ impl Default for UpdatePreference {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::orchestrator::ToolOutput;
 
     #[test]
     fn test_determine_traffic_level() {
-        let manager = AutoUpdateManager {
-            registry: ToolRegistry::new(Path::new(".dx/forge")).unwrap(),
-            backups: HashMap::new(),
-            notifications: Vec::new(),
-            auto_update_enabled: true,
-        };
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AutoUpdateManager::new(dir.path()).unwrap();
 
         // Patch update (green)
         let current = Version::new(1, 2, 3);
@@ -363,4 +911,241 @@ mod tests {
         let new = Version::new(2, 0, 0);
         assert_eq!(manager.determine_traffic_level(&current, &new), TrafficLevel::Red);
     }
+
+    fn immediate() -> UpdatePreference {
+        UpdatePreference {
+            rollout: RolloutPolicy::immediate(),
+            ..Default::default()
+        }
+    }
+
+    /// A forge dir with `style` 1.0.0 installed from a local build, and
+    /// builds of 1.0.0 and 1.0.1 next to it
+    fn installed_style(root: &Path) -> PathBuf {
+        let forge_dir = root.join("forge");
+        for (version, content) in [("1.0.0", "v1"), ("1.0.1", "v2")] {
+            let build = root.join("builds").join(version);
+            std::fs::create_dir_all(build.join("bin")).unwrap();
+            std::fs::write(build.join("bin/style"), content).unwrap();
+        }
+        std::fs::create_dir_all(forge_dir.join("tools/style/bin")).unwrap();
+        std::fs::write(forge_dir.join("tools/style/bin/style"), "v1").unwrap();
+        let mut registry = ToolRegistry::new(&forge_dir).unwrap();
+        let source = ToolSource::Local(root.join("builds").join("1.0.0"));
+        registry
+            .register("style".to_string(), Version::new(1, 0, 0), source, HashMap::new())
+            .unwrap();
+        forge_dir
+    }
+
+    #[tokio::test]
+    async fn test_update_and_rollback_restore_files_and_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge_dir = installed_style(dir.path());
+        let binary = forge_dir.join("tools/style/bin/style");
+
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        manager.set_preferences(immediate()).unwrap();
+        let update = manager.check_update("style", Version::new(1, 0, 1)).unwrap();
+        assert_eq!(manager.process_update(&update).await.unwrap(), UpdateOutcome::Applied);
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v2");
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 1)));
+
+        // Backups and notifications survive a restart
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        assert_eq!(manager.get_backups("style").len(), 1);
+        assert_eq!(manager.get_notifications()[0].status, UpdateStatus::Applied);
+        assert_eq!(manager.preferences().rollout, RolloutPolicy::immediate());
+
+        manager.rollback("style").unwrap();
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v1");
+        let rolled_back = manager.get_notifications().last().unwrap();
+        assert_eq!(
+            rolled_back.status,
+            UpdateStatus::RolledBack("manual rollback".to_string())
+        );
+        assert_eq!(
+            (&rolled_back.current_version, &rolled_back.new_version),
+            (&Version::new(1, 0, 1), &Version::new(1, 0, 0))
+        );
+        assert!(manager.get_pending_updates().is_empty());
+        let registry = ToolRegistry::new(&forge_dir).unwrap();
+        assert_eq!(registry.version("style"), Some(&Version::new(1, 0, 0)));
+        assert!(manager.get_backups("style").is_empty());
+        assert!(manager.rollback("style").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_update_leaves_tool_and_preferences_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge_dir = installed_style(dir.path());
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+
+        // There is no build of 1.0.2
+        let update = manager.check_update("style", Version::new(1, 0, 2)).unwrap();
+        assert!(manager.apply_update(&update).await.is_err());
+        let binary = forge_dir.join("tools/style/bin/style");
+        assert_eq!(std::fs::read_to_string(binary).unwrap(), "v1");
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 0)));
+        assert!(matches!(manager.get_notifications()[0].status, UpdateStatus::Failed(_)));
+        assert!(manager.get_backups("style").is_empty());
+
+        manager
+            .set_preferences(UpdatePreference {
+                auto_update_green: false,
+                ..Default::default()
+            })
+            .unwrap();
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        assert!(!manager.preferences().auto_update_green);
+
+        // Green updates are now only announced
+        let update = manager.check_update("style", Version::new(1, 0, 1)).unwrap();
+        assert_eq!(manager.process_update(&update).await.unwrap(), UpdateOutcome::Notified);
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 0)));
+        assert_eq!(manager.get_pending_updates().len(), 1);
+    }
+
+    /// Fails once its installed binary says `v2`
+    struct StyleTool {
+        binary: String,
+    }
+
+    impl DxTool for StyleTool {
+        fn name(&self) -> &str {
+            "style"
+        }
+
+        fn version(&self) -> &str {
+            "1.0.0"
+        }
+
+        fn priority(&self) -> u32 {
+            50
+        }
+
+        fn should_run(&self, _context: &ExecutionContext) -> bool {
+            self.binary != "idle"
+        }
+
+        fn execute(&mut self, context: &ExecutionContext) -> Result<ToolOutput> {
+            assert!(context.repo_root.exists());
+            if self.binary == "v2" {
+                return Ok(ToolOutput::failure("broken release"));
+            }
+            Ok(ToolOutput::success())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_registration_restores_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge_dir = installed_style(dir.path());
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        manager.set_preferences(immediate()).unwrap();
+        let update = manager.check_update("style", Version::new(1, 0, 1)).unwrap();
+
+        // The registry can no longer be saved
+        let registry_path = forge_dir.join("tool_registry.json");
+        std::fs::remove_file(&registry_path).unwrap();
+        std::fs::create_dir(&registry_path).unwrap();
+
+        assert!(manager.apply_update(&update).await.is_err());
+        let binary = forge_dir.join("tools/style/bin/style");
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v1");
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 0)));
+        assert!(manager.get_backups("style").is_empty());
+        assert_eq!(
+            std::fs::read_dir(forge_dir.join("backups/style")).unwrap().count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_health_check_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge_dir = installed_style(dir.path());
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        manager.set_preferences(immediate()).unwrap();
+        let factory: ToolFactory = Arc::new(|tool_dir: &Path| {
+            let binary = std::fs::read_to_string(tool_dir.join("bin/style"))?;
+            Ok(Box::new(StyleTool { binary }) as Box<dyn DxTool>)
+        });
+        manager.register_tool_factory("style", factory);
+        manager.set_health_check("style", Some(HealthCheck::Tool)).unwrap();
+
+        let update = manager.check_update("style", Version::new(1, 0, 1)).unwrap();
+        let outcome = manager.process_update(&update).await.unwrap();
+        assert!(matches!(outcome, UpdateOutcome::RolledBack(reason) if reason.contains("broken release")));
+        let binary = forge_dir.join("tools/style/bin/style");
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v1");
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 0)));
+        let rolled_back = manager
+            .get_notifications()
+            .iter()
+            .filter(|n| matches!(n.status, UpdateStatus::RolledBack(_)))
+            .count();
+        assert_eq!(rolled_back, 1);
+
+        // A build that won't run can't prove itself either
+        std::fs::write(dir.path().join("builds/1.0.1/bin/style"), "idle").unwrap();
+        let outcome = manager.process_update(&update).await.unwrap();
+        assert!(matches!(outcome, UpdateOutcome::RolledBack(reason) if reason.contains("declined to run")));
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v1");
+        std::fs::write(dir.path().join("builds/1.0.1/bin/style"), "v2").unwrap();
+
+        // A passing command check keeps the update
+        let check = HealthCheck::Command {
+            program: "cat".to_string(),
+            args: vec!["bin/style".to_string()],
+            timeout_secs: 10,
+        };
+        manager.set_health_check("style", Some(check)).unwrap();
+        assert_eq!(manager.apply_update(&update).await.unwrap(), UpdateOutcome::Applied);
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "v2");
+    }
+
+    #[test]
+    fn test_rollout_stages_beyond_range_are_never_reached() {
+        let policy = RolloutPolicy {
+            stages: vec![
+                RolloutStage { after_hours: 0, percent: 10 },
+                RolloutStage { after_hours: i64::MAX, percent: 100 },
+            ],
+        };
+        assert_eq!(policy.percent_at(chrono::TimeDelta::MAX), 10);
+    }
+
+    #[tokio::test]
+    async fn test_holds_channels_and_staged_rollout() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge_dir = installed_style(dir.path());
+        let mut manager = AutoUpdateManager::new(&forge_dir).unwrap();
+        manager
+            .set_preferences(UpdatePreference {
+                rollout: RolloutPolicy {
+                    stages: vec![RolloutStage { after_hours: 24, percent: 100 }],
+                },
+                ..Default::default()
+            })
+            .unwrap();
+
+        // Not rolled out to anyone yet on stable
+        let update = manager.check_update("style", Version::new(1, 0, 1)).unwrap();
+        assert_eq!(manager.process_update(&update).await.unwrap(), UpdateOutcome::Deferred);
+
+        manager.hold("style").unwrap();
+        assert_eq!(manager.process_update(&update).await.unwrap(), UpdateOutcome::Held);
+        assert!(manager.apply_update(&update).await.is_err());
+        assert!(AutoUpdateManager::new(&forge_dir).unwrap().is_held("style"));
+        manager.release("style").unwrap();
+
+        // Pre-releases only reach the beta channel, which skips staging
+        let beta: Version = "1.0.1-beta.1".parse().unwrap();
+        assert!(manager.check_update("style", beta.clone()).is_none());
+        manager.set_channel("style", UpdateChannel::Beta).unwrap();
+        assert!(manager.check_update("style", beta).is_some());
+        assert_eq!(manager.process_update(&update).await.unwrap(), UpdateOutcome::Applied);
+        assert_eq!(manager.registry().version("style"), Some(&Version::new(1, 0, 1)));
+    }
 }
@@ -81,11 +81,40 @@ enum Commands {
 
     /// Query the operation log
     OpLog {
+        /// File path, or a glob such as "src/**.rs"
         #[arg(short, long)]
         file: Option<PathBuf>,
 
         #[arg(short, long)]
         limit: Option<usize>,
+
+        /// Only operations by this actor (repeatable)
+        #[arg(long)]
+        actor: Vec<String>,
+
+        /// Only operations of this type, e.g. insert or file-rename (repeatable)
+        #[arg(long = "type", value_name = "TYPE")]
+        op_type: Vec<storage::OpKind>,
+
+        /// Only operations at or after this time (RFC 3339, "YYYY-MM-DD HH:MM" or an age like "6h")
+        #[arg(long, value_parser = storage::op_query::parse_time)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+
+        /// Only operations at or before this time
+        #[arg(long, value_parser = storage::op_query::parse_time)]
+        until: Option<chrono::DateTime<chrono::Utc>>,
+
+        /// Only operations with this parent operation
+        #[arg(long)]
+        parent: Option<uuid::Uuid>,
+
+        /// Continue from a previous page
+        #[arg(long)]
+        cursor: Option<String>,
+
+        /// List oldest operations first
+        #[arg(long)]
+        oldest_first: bool,
     },
 
     /// Create a character-level anchor/permalink
@@ -118,10 +147,14 @@ enum Commands {
         line: Option<usize>,
     },
 
-    /// Sync Forge repository
+    /// Import Git history into forge and export forge snapshots to Git
     ForgeSync {
         #[arg(default_value = ".")]
         path: PathBuf,
+
+        /// Branch to sync (defaults to Git's current branch)
+        #[arg(long)]
+        branch: Option<String>,
     },
 
     /// Any unrecognized subcommand will be passed to the system `git`.
@@ -135,6 +168,14 @@ enum Commands {
 
         #[arg(default_value = ".")]
         path: PathBuf,
+
+        /// Additional repositories to host, routed by their repo_id
+        #[arg(long = "repo", value_name = "PATH")]
+        repos: Vec<PathBuf>,
+
+        /// Reject sync sessions without a valid session token
+        #[arg(long)]
+        require_auth: bool,
     },
 
     /// Show time-travel view of a file
@@ -145,6 +186,46 @@ enum Commands {
         timestamp: Option<String>,
     },
 
+    /// Fold old operations into content checkpoints
+    Compact {
+        /// Keep individual operations newer than this many days
+        #[arg(long, default_value = "30")]
+        retention_days: u32,
+
+        /// Report what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Pack small loose blobs into a single packfile
+    Repack,
+
+    /// Verify blobs, packs, snapshots and the database
+    Fsck {
+        /// Fix issues that can be repaired without losing good data
+        #[arg(long)]
+        repair: bool,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Delete blobs and snapshots nothing refers to any more
+    Gc {
+        /// Only remove objects older than this many days
+        #[arg(long, default_value = "14")]
+        grace_days: u32,
+
+        /// Report what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Also remove unreachable blobs from the configured R2 bucket
+        #[arg(long)]
+        remote: bool,
+    },
+
     /// Update DX-managed components
     Update {
         /// Component to update (or "all")
@@ -250,8 +331,48 @@ async fn main() -> Result<()> {
             watcher_legacy::watch(path, sync, peer).await?;
         }
 
-        Commands::OpLog { file, limit } => {
-            storage::show_log(file, limit.unwrap_or(50)).await?;
+        Commands::OpLog {
+            file,
+            limit,
+            actor,
+            op_type,
+            since,
+            until,
+            parent,
+            cursor,
+            oldest_first,
+        } => {
+            let mut query = storage::OpQuery::new().limit(limit.unwrap_or(50));
+            if let Some(file) = file {
+                let file = file.display().to_string();
+                query = if file.contains(['*', '?', '[']) {
+                    query.file_glob(file)
+                } else {
+                    query.file(file)
+                };
+            }
+            for actor in actor {
+                query = query.actor(actor);
+            }
+            for kind in op_type {
+                query = query.kind(kind);
+            }
+            if let Some(since) = since {
+                query = query.since(since);
+            }
+            if let Some(until) = until {
+                query = query.until(until);
+            }
+            if let Some(parent) = parent {
+                query = query.parent(parent);
+            }
+            if oldest_first {
+                query = query.order(storage::OpOrder::OldestFirst);
+            }
+            if let Some(cursor) = cursor {
+                query = query.cursor(&cursor)?;
+            }
+            storage::show_log(query).await?;
         }
 
         Commands::Anchor {
@@ -283,8 +404,8 @@ async fn main() -> Result<()> {
             context::show_context(&file, line).await?;
         }
 
-        Commands::ForgeSync { path } => {
-            storage::git_sync(&path).await?;
+        Commands::ForgeSync { path, branch } => {
+            storage::git_sync(&path, branch.as_deref()).await?;
         }
 
         Commands::GitPassthrough(args) => {
@@ -299,20 +420,50 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::Serve { port, path } => {
+        Commands::Serve {
+            port,
+            path,
+            repos,
+            require_auth,
+        } => {
             println!(
                 "{}",
                 format!("🌐 Starting server on port {}...", port)
                     .cyan()
                     .bold()
             );
-            server::start(port, path).await?;
+            let mut paths = vec![path];
+            paths.extend(repos);
+            server::start_multi(port, paths, require_auth).await?;
         }
 
         Commands::TimeTravel { file, timestamp } => {
             storage::time_travel(&file, timestamp).await?;
         }
 
+        Commands::Compact {
+            retention_days,
+            dry_run,
+        } => {
+            storage::compact(retention_days, dry_run).await?;
+        }
+
+        Commands::Repack => {
+            storage::repack().await?;
+        }
+
+        Commands::Fsck { repair, json } => {
+            storage::fsck(repair, json).await?;
+        }
+
+        Commands::Gc {
+            grace_days,
+            dry_run,
+            remote,
+        } => {
+            storage::collect_garbage(grace_days, dry_run, remote).await?;
+        }
+
         Commands::Update {
             component,
             force: _,
@@ -1,11 +1,12 @@
 use anyhow::Result;
-use automerge::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+use automerge::{transaction::Transactable, AutoCommit, ROOT};
 use parking_lot::RwLock;
 use ropey::Rope;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use super::operations::{Operation, OperationType, Position};
+use super::transform::Transformer;
 
 #[allow(dead_code)]
 pub struct CrdtDocument {
@@ -16,6 +17,8 @@ pub struct CrdtDocument {
     pub rope: Arc<RwLock<Rope>>,
     /// Lamport timestamp for ordering
     pub lamport: Arc<parking_lot::Mutex<u64>>,
+    /// Rebases incoming operations against concurrent ones
+    transformer: Arc<parking_lot::Mutex<Transformer>>,
 }
 
 #[allow(dead_code)]
@@ -29,69 +32,60 @@ impl CrdtDocument {
             doc: Arc::new(RwLock::new(doc)),
             rope: Arc::new(RwLock::new(Rope::from_str(initial_content))),
             lamport: Arc::new(parking_lot::Mutex::new(0)),
+            transformer: Arc::new(parking_lot::Mutex::new(Transformer::new(initial_content))),
         }
     }
 
+    /// Apply an operation, local or remote. Operations concurrent with ones
+    /// already applied are rebased first so every replica converges.
     pub fn apply_operation(&self, op: &Operation) -> Result<()> {
         let mut lamport = self.lamport.lock();
         *lamport += 1;
 
-        match &op.op_type {
-            OperationType::Insert {
-                position, content, ..
-            } => {
-                let mut rope = self.rope.write();
-                let char_idx = self.line_col_to_char(&rope, position.line, position.column);
-                rope.insert(char_idx, content);
-
-                // Update CRDT
-                let mut doc = self.doc.write();
-                if let Some((value, _)) = doc.get(ROOT, "content")? {
-                    let current: String = value.to_string();
-                    let mut chars: Vec<char> = current.chars().collect();
-                    chars.splice(char_idx..char_idx, content.chars());
-                    doc.put(ROOT, "content", chars.iter().collect::<String>())?;
+        let rebased = self.transformer.lock().rebase(op);
+        if rebased.is_empty() {
+            return Ok(());
+        }
+
+        let mut rope = self.rope.write();
+        for op_type in &rebased {
+            match op_type {
+                OperationType::Insert {
+                    position, content, ..
+                } => {
+                    rope.insert(position.offset, content);
                 }
-            }
 
-            OperationType::Delete { position, length } => {
-                let mut rope = self.rope.write();
-                let char_idx = self.line_col_to_char(&rope, position.line, position.column);
-                rope.remove(char_idx..char_idx + length);
-
-                // Update CRDT
-                let mut doc = self.doc.write();
-                if let Some((value, _)) = doc.get(ROOT, "content")? {
-                    let current: String = value.to_string();
-                    let mut chars: Vec<char> = current.chars().collect();
-                    chars.drain(char_idx..char_idx + length);
-                    doc.put(ROOT, "content", chars.iter().collect::<String>())?;
+                OperationType::Delete { position, length } => {
+                    rope.remove(position.offset..position.offset + length);
                 }
-            }
 
-            OperationType::Replace {
-                position,
-                old_content,
-                new_content,
-            } => {
-                let mut rope = self.rope.write();
-                let char_idx = self.line_col_to_char(&rope, position.line, position.column);
-                rope.remove(char_idx..char_idx + old_content.len());
-                rope.insert(char_idx, new_content);
-
-                // Update CRDT
-                let mut doc = self.doc.write();
-                if let Some((value, _)) = doc.get(ROOT, "content")? {
-                    let current: String = value.to_string();
-                    let mut chars: Vec<char> = current.chars().collect();
-                    chars.splice(char_idx..char_idx + old_content.len(), new_content.chars());
-                    doc.put(ROOT, "content", chars.iter().collect::<String>())?;
+                OperationType::Replace {
+                    position,
+                    old_content,
+                    new_content,
+                } => {
+                    let end = position.offset + old_content.chars().count();
+                    rope.remove(position.offset..end);
+                    rope.insert(position.offset, new_content);
                 }
-            }
 
-            _ => {}
+                OperationType::FileCreate { content } => {
+                    *rope = Rope::from_str(content);
+                }
+
+                OperationType::FileDelete => {
+                    *rope = Rope::new();
+                }
+
+                OperationType::FileRename { .. } => {}
+            }
         }
 
+        // Update CRDT
+        let mut doc = self.doc.write();
+        doc.put(ROOT, "content", rope.to_string())?;
+
         Ok(())
     }
 
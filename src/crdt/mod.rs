@@ -1,8 +1,10 @@
 pub mod anchor;
 pub mod document;
 pub mod operations;
+pub mod transform;
 
 pub use anchor::Anchor;
 #[allow(unused_imports)]
 pub use document::CrdtDocument;
 pub use operations::{Operation, OperationType, Position};
+pub use transform::Transformer;
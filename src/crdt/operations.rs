@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,14 +110,144 @@ impl Operation {
         }
 
         // Check if they are consecutive operations
-        match (&self.op_type, &other.op_type) {
-            (OperationType::Insert { .. }, OperationType::Insert { .. }) => true,
-            (OperationType::Delete { .. }, OperationType::Delete { .. }) => true,
-            _ => false,
+        matches!(
+            (&self.op_type, &other.op_type),
+            (OperationType::Insert { .. }, OperationType::Insert { .. })
+                | (OperationType::Delete { .. }, OperationType::Delete { .. })
+                | (OperationType::Insert { .. }, OperationType::Delete { .. })
+        )
+    }
+
+    /// Fold `next` into this operation when it continues the same edit.
+    ///
+    /// The result takes `next`'s id and timestamp and this operation's
+    /// parents, so later operations chained to `next` stay connected.
+    fn coalesce(&self, next: &Operation) -> Option<Coalesced> {
+        if !self.can_batch_with(next) {
+            return None;
+        }
+        // `next` may only depend on what this operation already depends on;
+        // merging would otherwise lose its other parents.
+        if !next
+            .parent_ops
+            .iter()
+            .all(|parent| *parent == self.id || self.parent_ops.contains(parent))
+        {
+            return None;
         }
+
+        let op_type = match (&self.op_type, &next.op_type) {
+            // Typing: the next insert lands inside or right after this one.
+            (
+                OperationType::Insert {
+                    position, content, ..
+                },
+                OperationType::Insert {
+                    position: next_position,
+                    content: next_content,
+                    ..
+                },
+            ) => {
+                let len = content.chars().count();
+                if next_position.offset < position.offset
+                    || next_position.offset > position.offset + len
+                {
+                    return None;
+                }
+                let split = next_position.offset - position.offset;
+                let merged: String = content
+                    .chars()
+                    .take(split)
+                    .chain(next_content.chars())
+                    .chain(content.chars().skip(split))
+                    .collect();
+                OperationType::Insert {
+                    position: Position {
+                        lamport_timestamp: next_position.lamport_timestamp,
+                        ..position.clone()
+                    },
+                    length: merged.chars().count(),
+                    content: merged,
+                }
+            }
+            // Backspace runs end where this delete starts; forward deletes
+            // start at the same offset.
+            (
+                OperationType::Delete { position, length },
+                OperationType::Delete {
+                    position: next_position,
+                    length: next_length,
+                },
+            ) => {
+                let start = if next_position.offset + next_length == position.offset {
+                    next_position
+                } else if next_position.offset == position.offset {
+                    position
+                } else {
+                    return None;
+                };
+                OperationType::Delete {
+                    position: Position {
+                        lamport_timestamp: next_position.lamport_timestamp,
+                        ..start.clone()
+                    },
+                    length: length + next_length,
+                }
+            }
+            // Deleting text that was just typed.
+            (
+                OperationType::Insert {
+                    position, content, ..
+                },
+                OperationType::Delete {
+                    position: next_position,
+                    length: next_length,
+                },
+            ) => {
+                let len = content.chars().count();
+                if next_position.offset < position.offset
+                    || next_position.offset + next_length > position.offset + len
+                {
+                    return None;
+                }
+                let cut = next_position.offset - position.offset;
+                let remaining: String = content
+                    .chars()
+                    .take(cut)
+                    .chain(content.chars().skip(cut + next_length))
+                    .collect();
+                if remaining.is_empty() {
+                    return Some(Coalesced::Cancelled);
+                }
+                OperationType::Insert {
+                    position: Position {
+                        lamport_timestamp: next_position.lamport_timestamp,
+                        ..position.clone()
+                    },
+                    length: remaining.chars().count(),
+                    content: remaining,
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Coalesced::Merged(Box::new(Operation {
+            id: next.id,
+            timestamp: next.timestamp,
+            actor_id: next.actor_id.clone(),
+            file_path: next.file_path.clone(),
+            op_type,
+            parent_ops: self.parent_ops.clone(),
+        })))
     }
 }
 
+enum Coalesced {
+    Merged(Box<Operation>),
+    /// The two operations undo each other.
+    Cancelled,
+}
+
 /// Batch of operations for efficient processing
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OperationBatch {
@@ -136,13 +267,55 @@ impl OperationBatch {
     }
 
     /// Merge consecutive operations in the batch
+    ///
+    /// Insert runs, backspace and forward-delete runs, and inserts followed by
+    /// deletes of the same text from one actor collapse into single
+    /// operations. The batch is left untouched if replaying the result would
+    /// not produce the same text as replaying the original.
     pub fn optimize(&mut self) {
-        // Simple optimization: remove redundant operations
-        self.operations.dedup_by(|a, b| {
-            a.file_path == b.file_path && 
-            a.actor_id == b.actor_id &&
-            a.timestamp == b.timestamp
-        });
+        self.optimize_keeping(&HashSet::new());
+    }
+
+    /// [`OperationBatch::optimize`] that never drops the id of an operation
+    /// in `referenced` (ids that operations outside the batch name as a
+    /// parent), nor of one another operation in the batch depends on, other
+    /// than the operation it merges with.
+    pub fn optimize_keeping(&mut self, referenced: &HashSet<Uuid>) {
+        let mut dependents: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for op in &self.operations {
+            for parent in &op.parent_ops {
+                dependents.entry(*parent).or_default().push(op.id);
+            }
+        }
+        let droppable = |id: Uuid, merging_with: Option<Uuid>| {
+            !referenced.contains(&id)
+                && dependents
+                    .get(&id)
+                    .is_none_or(|ids| ids.iter().all(|dep| Some(*dep) == merging_with))
+        };
+
+        let mut merged: Vec<Operation> = Vec::with_capacity(self.operations.len());
+        for op in &self.operations {
+            let coalesced = merged
+                .last()
+                .filter(|prev| droppable(prev.id, Some(op.id)))
+                .and_then(|prev| prev.coalesce(op));
+            match coalesced {
+                Some(Coalesced::Merged(combined)) => {
+                    if let Some(last) = merged.last_mut() {
+                        *last = *combined;
+                    }
+                }
+                Some(Coalesced::Cancelled) if droppable(op.id, None) => {
+                    merged.pop();
+                }
+                _ => merged.push(op.clone()),
+            }
+        }
+
+        if merged.len() < self.operations.len() && replays_equal(&self.operations, &merged) {
+            self.operations = merged;
+        }
     }
 
     /// Get total size of batch
@@ -155,3 +328,184 @@ impl OperationBatch {
         self.operations.is_empty()
     }
 }
+
+/// Replay both operation lists per file over a synthetic document and compare
+/// the results. The document is made of distinct characters and is long
+/// enough that no operation range ever needs clamping.
+fn replays_equal(original: &[Operation], optimized: &[Operation]) -> bool {
+    let mut files: Vec<&str> = original.iter().map(|op| op.file_path.as_str()).collect();
+    files.sort_unstable();
+    files.dedup();
+
+    files.into_iter().all(|file| {
+        let ops = |list: &[Operation]| -> Vec<OperationType> {
+            list.iter()
+                .filter(|op| op.file_path == file)
+                .map(|op| op.op_type.clone())
+                .collect()
+        };
+        let original = ops(original);
+        let optimized = ops(optimized);
+
+        let mut reach = 0;
+        let mut removed = 0;
+        for op_type in &original {
+            match op_type {
+                OperationType::Insert { position, .. } => reach = reach.max(position.offset),
+                OperationType::Delete { position, length } => {
+                    reach = reach.max(position.offset + length);
+                    removed += length;
+                }
+                OperationType::Replace {
+                    position,
+                    old_content,
+                    ..
+                } => {
+                    let length = old_content.chars().count();
+                    reach = reach.max(position.offset + length);
+                    removed += length;
+                }
+                _ => {}
+            }
+        }
+        let base: String = (0..reach + removed)
+            .map(|i| char::from_u32(0xE000 + (i % 0x1900) as u32).unwrap_or(' '))
+            .collect();
+
+        replay_sequential(&base, &original) == replay_sequential(&base, &optimized)
+    })
+}
+
+fn replay_sequential(base: &str, ops: &[OperationType]) -> String {
+    let mut rope = ropey::Rope::from_str(base);
+    for op_type in ops {
+        match op_type {
+            OperationType::Insert {
+                position, content, ..
+            } => {
+                let at = position.offset.min(rope.len_chars());
+                rope.insert(at, content);
+            }
+            OperationType::Delete { position, length } => {
+                let start = position.offset.min(rope.len_chars());
+                let end = (start + length).min(rope.len_chars());
+                rope.remove(start..end);
+            }
+            OperationType::Replace {
+                position,
+                old_content,
+                new_content,
+            } => {
+                let start = position.offset.min(rope.len_chars());
+                let end = (start + old_content.chars().count()).min(rope.len_chars());
+                rope.remove(start..end);
+                rope.insert(start, new_content);
+            }
+            OperationType::FileCreate { content } => rope = ropey::Rope::from_str(content),
+            OperationType::FileDelete => rope = ropey::Rope::new(),
+            OperationType::FileRename { .. } => {}
+        }
+    }
+    rope.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(op_type: OperationType) -> Operation {
+        Operation::new("src/main.rs".into(), op_type, "actor".into())
+    }
+
+    fn insert(offset: usize, text: &str) -> Operation {
+        op(OperationType::Insert {
+            position: Position::new(1, offset + 1, offset, "actor".into(), 0),
+            content: text.into(),
+            length: text.chars().count(),
+        })
+    }
+
+    fn delete(offset: usize, length: usize) -> Operation {
+        op(OperationType::Delete {
+            position: Position::new(1, offset + 1, offset, "actor".into(), 0),
+            length,
+        })
+    }
+
+    fn optimized(ops: Vec<Operation>) -> Vec<OperationType> {
+        let mut batch = OperationBatch::new(ops);
+        batch.optimize();
+        batch.operations.into_iter().map(|op| op.op_type).collect()
+    }
+
+    #[test]
+    fn typing_run_becomes_one_insert() {
+        let ops = optimized(vec![insert(4, "f"), insert(5, "n"), insert(6, " ")]);
+        assert!(matches!(
+            ops.as_slice(),
+            [OperationType::Insert { position, content, length: 3 }]
+                if position.offset == 4 && content == "fn "
+        ));
+    }
+
+    #[test]
+    fn backspace_run_becomes_one_delete() {
+        let ops = optimized(vec![delete(9, 1), delete(8, 1), delete(7, 1)]);
+        assert!(matches!(
+            ops.as_slice(),
+            [OperationType::Delete { position, length: 3 }] if position.offset == 7
+        ));
+    }
+
+    #[test]
+    fn typed_then_erased_text_cancels() {
+        let ops = optimized(vec![insert(0, "a"), insert(1, "b"), delete(1, 1), delete(0, 1)]);
+        assert!(ops.is_empty());
+    }
+
+    #[test]
+    fn keeps_parents_of_first_and_id_of_last() {
+        let parent = Uuid::new_v4();
+        let first = insert(0, "a").with_parents(vec![parent]);
+        let second = insert(1, "b").with_parents(vec![first.id]);
+        let last_id = second.id;
+
+        let mut batch = OperationBatch::new(vec![first, second]);
+        batch.optimize();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.operations[0].id, last_id);
+        assert_eq!(batch.operations[0].parent_ops, vec![parent]);
+    }
+
+    #[test]
+    fn referenced_ids_are_not_merged_away() {
+        let first = insert(0, "a");
+        let second = insert(1, "b").with_parents(vec![first.id]);
+        let third = insert(2, "c").with_parents(vec![second.id]);
+        let ids = [first.id, second.id, third.id];
+
+        // Something outside the batch builds on `second`
+        let mut batch = OperationBatch::new(vec![first, second, third]);
+        batch.optimize_keeping(&HashSet::from([ids[1]]));
+        let kept: Vec<Uuid> = batch.operations.iter().map(|op| op.id).collect();
+        assert_eq!(kept, vec![ids[1], ids[2]]);
+    }
+
+    #[test]
+    fn operations_with_other_parents_are_not_merged() {
+        let first = insert(0, "a");
+        let concurrent = Uuid::new_v4();
+        let second = insert(1, "b").with_parents(vec![first.id, concurrent]);
+
+        let ops = optimized(vec![first, second]);
+        assert_eq!(ops.len(), 2);
+    }
+
+    #[test]
+    fn unrelated_operations_are_left_alone() {
+        let mut other_actor = insert(1, "b");
+        other_actor.actor_id = "someone-else".into();
+        let ops = optimized(vec![insert(0, "a"), other_actor, insert(10, "c"), delete(3, 1)]);
+        assert_eq!(ops.len(), 4);
+    }
+}
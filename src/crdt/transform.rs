@@ -0,0 +1,803 @@
+//! Operational transform for replaying concurrent operations.
+//!
+//! Operation offsets are relative to the text their author saw, which is only
+//! the same text for everyone while edits are sequential. [`Transformer`] keeps
+//! every character ever inserted (deleted ones as tombstones) tagged with the
+//! operation that produced it, so each operation is interpreted against exactly
+//! the state described by its causal history and then rebased onto the
+//! current text.
+//!
+//! An operation has seen its `parent_ops` (transitively), every earlier
+//! operation of the same actor and, when it was recorded without parents,
+//! every operation with an earlier timestamp. Anything else already applied is
+//! concurrent. Concurrent inserts at the same place are ordered by Lamport
+//! timestamp, then actor id.
+//!
+//! Once every known actor's latest operation has seen a deletion, no later
+//! operation from those actors can address the deleted characters, so their
+//! tombstones are pruned.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::operations::{Operation, OperationType, Position};
+
+/// Order operations so that every operation comes after its parents, using
+/// timestamp, Lamport timestamp, actor id and operation id to break ties.
+///
+/// Parents missing from `ops` are ignored; parent cycles (which only corrupt
+/// data can produce) fall back to the tie-break order.
+pub fn causal_order(mut ops: Vec<Operation>) -> Vec<Operation> {
+    ops.sort_by(|a, b| {
+        a.timestamp
+            .cmp(&b.timestamp)
+            .then_with(|| a.lamport().unwrap_or(0).cmp(&b.lamport().unwrap_or(0)))
+            .then_with(|| a.actor_id.cmp(&b.actor_id))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let index: HashMap<Uuid, usize> = ops.iter().enumerate().map(|(i, op)| (op.id, i)).collect();
+    let mut pending = vec![0usize; ops.len()];
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); ops.len()];
+    for (i, op) in ops.iter().enumerate() {
+        for parent in &op.parent_ops {
+            if let Some(&p) = index.get(parent) {
+                if p != i {
+                    pending[i] += 1;
+                    children[p].push(i);
+                }
+            }
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..ops.len())
+        .filter(|&i| pending[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut emitted = vec![false; ops.len()];
+    let mut order = Vec::with_capacity(ops.len());
+
+    while order.len() < ops.len() {
+        let next = match ready.pop() {
+            Some(Reverse(i)) => i,
+            // Cycle: release the earliest remaining operation.
+            None => match (0..ops.len()).find(|&i| !emitted[i]) {
+                Some(i) => i,
+                None => break,
+            },
+        };
+        if emitted[next] {
+            continue;
+        }
+        emitted[next] = true;
+        order.push(next);
+        for &child in &children[next] {
+            pending[child] = pending[child].saturating_sub(1);
+            if pending[child] == 0 && !emitted[child] {
+                ready.push(Reverse(child));
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<Operation>> = ops.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| slots[i].take()).collect()
+}
+
+/// Replay `ops` in causal order on top of `initial` and return the resulting text.
+pub fn replay(initial: &str, ops: Vec<Operation>) -> String {
+    let mut transformer = Transformer::new(initial);
+    for op in causal_order(ops) {
+        transformer.rebase(&op);
+    }
+    transformer.text()
+}
+
+/// Tracks the operations applied to a single file and rebases new operations
+/// onto the current text.
+pub struct Transformer {
+    atoms: Vec<Atom>,
+    applied: Vec<Applied>,
+    index: HashMap<Uuid, usize>,
+    last_by_actor: HashMap<String, usize>,
+    /// Latest timestamp among applied operations.
+    latest: Option<DateTime<Utc>>,
+    /// Operations below this index have been seen by every known actor.
+    stable: usize,
+}
+
+/// Prune tombstones after the stable point has advanced this many operations.
+const PRUNE_INTERVAL: usize = 64;
+
+struct Atom {
+    ch: char,
+    /// `None` for characters of the initial text.
+    inserted_by: Option<usize>,
+    deleted_by: Vec<usize>,
+}
+
+struct Applied {
+    timestamp: DateTime<Utc>,
+    lamport: u64,
+    actor_id: String,
+    /// Operations this one had seen when it was created.
+    context: OpSet,
+}
+
+impl Transformer {
+    pub fn new(initial: &str) -> Self {
+        Self {
+            atoms: initial
+                .chars()
+                .map(|ch| Atom {
+                    ch,
+                    inserted_by: None,
+                    deleted_by: Vec::new(),
+                })
+                .collect(),
+            applied: Vec::new(),
+            index: HashMap::new(),
+            last_by_actor: HashMap::new(),
+            latest: None,
+            stable: 0,
+        }
+    }
+
+    /// Current text with every applied operation.
+    pub fn text(&self) -> String {
+        self.atoms
+            .iter()
+            .filter(|atom| atom.deleted_by.is_empty())
+            .map(|atom| atom.ch)
+            .collect()
+    }
+
+    /// Whether the operation has already been applied.
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.index.contains_key(id)
+    }
+
+    /// Number of deleted characters still kept to interpret concurrent
+    /// operations.
+    pub fn tombstones(&self) -> usize {
+        self.atoms
+            .iter()
+            .filter(|atom| !atom.deleted_by.is_empty())
+            .count()
+    }
+
+    /// Apply `op` and return the equivalent operations expressed against the
+    /// text as it was just before this call. Applying the returned operations
+    /// in order to that text yields [`Transformer::text`].
+    ///
+    /// Operations that were already applied return nothing.
+    pub fn rebase(&mut self, op: &Operation) -> Vec<OperationType> {
+        if self.contains(&op.id) {
+            return Vec::new();
+        }
+
+        let me = self.applied.len();
+        let context = self.context_of(op);
+        // When the operation has seen everything applied so far its offsets
+        // already refer to the current text.
+        let view = if context.covers(me) {
+            None
+        } else {
+            Some(&context)
+        };
+
+        let rebased = match &op.op_type {
+            OperationType::Insert {
+                position, content, ..
+            } => {
+                let offset = self.insert(view, me, op, position.offset, content);
+                vec![OperationType::Insert {
+                    position: self.position_at(position, offset, None),
+                    content: content.clone(),
+                    length: content.chars().count(),
+                }]
+            }
+            OperationType::Delete { position, length } => {
+                let runs = self.delete(view, me, position.offset, *length);
+                runs.into_iter()
+                    .rev()
+                    .map(|(start, deleted)| OperationType::Delete {
+                        position: self.position_at(position, start, Some(me)),
+                        length: deleted.chars().count(),
+                    })
+                    .collect()
+            }
+            OperationType::Replace {
+                position,
+                old_content,
+                new_content,
+            } => {
+                let runs = self.delete(view, me, position.offset, old_content.chars().count());
+                let offset = self.insert(view, me, op, position.offset, new_content);
+                let mut rebased = Vec::with_capacity(runs.len() + 1);
+                match runs.as_slice() {
+                    [(start, deleted)] if *start == offset => {
+                        rebased.push(OperationType::Replace {
+                            position: self.position_at(position, offset, None),
+                            old_content: deleted.clone(),
+                            new_content: new_content.clone(),
+                        });
+                    }
+                    _ => {
+                        rebased.extend(runs.into_iter().rev().map(|(start, deleted)| {
+                            OperationType::Delete {
+                                position: self.position_at(position, start, Some(me)),
+                                length: deleted.chars().count(),
+                            }
+                        }));
+                        if !new_content.is_empty() {
+                            rebased.push(OperationType::Insert {
+                                position: self.position_at(position, offset, None),
+                                content: new_content.clone(),
+                                length: new_content.chars().count(),
+                            });
+                        }
+                    }
+                }
+                rebased
+            }
+            OperationType::FileCreate { content } => {
+                for atom in &mut self.atoms {
+                    atom.deleted_by.push(me);
+                }
+                self.atoms.extend(content.chars().map(|ch| Atom {
+                    ch,
+                    inserted_by: Some(me),
+                    deleted_by: Vec::new(),
+                }));
+                vec![op.op_type.clone()]
+            }
+            OperationType::FileDelete => {
+                for atom in &mut self.atoms {
+                    atom.deleted_by.push(me);
+                }
+                vec![op.op_type.clone()]
+            }
+            OperationType::FileRename { .. } => vec![op.op_type.clone()],
+        };
+
+        self.applied.push(Applied {
+            timestamp: op.timestamp,
+            lamport: op.lamport().unwrap_or(0),
+            actor_id: op.actor_id.clone(),
+            context,
+        });
+        self.index.insert(op.id, me);
+        self.last_by_actor.insert(op.actor_id.clone(), me);
+        if self.latest.is_none_or(|latest| latest < op.timestamp) {
+            self.latest = Some(op.timestamp);
+        }
+        self.prune();
+
+        rebased
+    }
+
+    fn context_of(&self, op: &Operation) -> OpSet {
+        // A parentless operation newer than everything applied has seen all
+        // of it, which is the common case when replaying in causal order.
+        if op.parent_ops.is_empty() && self.latest.is_none_or(|latest| latest < op.timestamp) {
+            return OpSet::through(self.applied.len());
+        }
+
+        let mut context = OpSet::default();
+        let include = |context: &mut OpSet, i: usize| {
+            context.insert(i);
+            context.union(&self.applied[i].context);
+        };
+
+        for parent in &op.parent_ops {
+            if let Some(&i) = self.index.get(parent) {
+                include(&mut context, i);
+            }
+        }
+        if let Some(&i) = self.last_by_actor.get(&op.actor_id) {
+            include(&mut context, i);
+        }
+        if op.parent_ops.is_empty() {
+            for (i, applied) in self.applied.iter().enumerate() {
+                if applied.timestamp < op.timestamp && !context.contains(i) {
+                    include(&mut context, i);
+                }
+            }
+        }
+
+        context
+    }
+
+    /// Drop tombstones whose deletion every known actor has seen.
+    ///
+    /// Operations that reference history older than an actor's latest
+    /// operation (for example a new actor forking from an old parent) may
+    /// still address pruned characters; they are interpreted as if those
+    /// characters had never existed.
+    fn prune(&mut self) {
+        let stable = self
+            .last_by_actor
+            .values()
+            .map(|&head| self.applied[head].context.prefix)
+            .min()
+            .unwrap_or(0);
+        if stable < self.stable + PRUNE_INTERVAL {
+            return;
+        }
+        self.stable = stable;
+        self.atoms
+            .retain(|atom| !atom.deleted_by.iter().any(|&d| d < stable));
+    }
+
+    fn visible(atom: &Atom, view: Option<&OpSet>) -> bool {
+        match view {
+            None => atom.deleted_by.is_empty(),
+            Some(context) => {
+                atom.inserted_by.is_none_or(|i| context.contains(i))
+                    && !atom.deleted_by.iter().any(|&d| context.contains(d))
+            }
+        }
+    }
+
+    /// Insert `content` at `offset` of the text seen through `view` and return
+    /// the offset it landed at in the current text.
+    fn insert(
+        &mut self,
+        view: Option<&OpSet>,
+        me: usize,
+        op: &Operation,
+        offset: usize,
+        content: &str,
+    ) -> usize {
+        let mut idx = 0;
+        let mut seen = 0;
+        while idx < self.atoms.len() && seen < offset {
+            if Self::visible(&self.atoms[idx], view) {
+                seen += 1;
+            }
+            idx += 1;
+        }
+
+        // Between the anchor and the next character this operation saw there
+        // may be tombstones and concurrent inserts; earlier-ranked concurrent
+        // inserts stay in front.
+        let rank = (op.lamport().unwrap_or(0), op.actor_id.as_str());
+        while idx < self.atoms.len() && !Self::visible(&self.atoms[idx], view) {
+            let skip = match (view, self.atoms[idx].inserted_by) {
+                (Some(context), Some(i)) if !context.contains(i) => {
+                    let other = &self.applied[i];
+                    (other.lamport, other.actor_id.as_str()) < rank
+                }
+                _ => true,
+            };
+            if !skip {
+                break;
+            }
+            idx += 1;
+        }
+
+        let current = self.atoms[..idx]
+            .iter()
+            .filter(|atom| atom.deleted_by.is_empty())
+            .count();
+        self.atoms.splice(
+            idx..idx,
+            content.chars().map(|ch| Atom {
+                ch,
+                inserted_by: Some(me),
+                deleted_by: Vec::new(),
+            }),
+        );
+        current
+    }
+
+    /// Delete `length` characters at `offset` of the text seen through `view`.
+    /// Returns the runs that were still present as `(offset, text)` pairs in
+    /// ascending order, with offsets into the text before the deletion.
+    fn delete(
+        &mut self,
+        view: Option<&OpSet>,
+        me: usize,
+        offset: usize,
+        length: usize,
+    ) -> Vec<(usize, String)> {
+        let mut runs: Vec<(usize, String)> = Vec::new();
+        let mut run_end = None;
+        let mut seen = 0;
+        let mut current = 0;
+
+        for atom in self.atoms.iter_mut() {
+            if seen >= offset + length {
+                break;
+            }
+            let was_current = atom.deleted_by.is_empty();
+            if Self::visible(atom, view) {
+                if seen >= offset {
+                    if was_current {
+                        match runs.last_mut() {
+                            Some((_, text)) if run_end == Some(current) => text.push(atom.ch),
+                            _ => runs.push((current, atom.ch.to_string())),
+                        }
+                        run_end = Some(current + 1);
+                    }
+                    atom.deleted_by.push(me);
+                }
+                seen += 1;
+            }
+            if was_current {
+                current += 1;
+            }
+        }
+
+        runs
+    }
+
+    /// Position of `offset` in the current text, or in the text before
+    /// `undoing`'s deletions when given.
+    fn position_at(&self, original: &Position, offset: usize, undoing: Option<usize>) -> Position {
+        let mut line = 1;
+        let mut column = 1;
+        for ch in self
+            .atoms
+            .iter()
+            .filter(|atom| match undoing {
+                Some(me) => atom.deleted_by.iter().all(|&d| d == me),
+                None => atom.deleted_by.is_empty(),
+            })
+            .map(|atom| atom.ch)
+            .take(offset)
+        {
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        Position {
+            offset,
+            line,
+            column,
+            ..original.clone()
+        }
+    }
+}
+
+/// Set of applied-operation indices: every index below `prefix`, plus a
+/// dense bit set of the indices above it. Causal histories are mostly
+/// contiguous, so the bit set stays small.
+#[derive(Default, Clone)]
+struct OpSet {
+    prefix: usize,
+    /// Bit `k` stands for index `prefix + k`.
+    words: Vec<u64>,
+}
+
+impl OpSet {
+    /// Every index below `end`.
+    fn through(end: usize) -> Self {
+        Self {
+            prefix: end,
+            words: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i < self.prefix {
+            return;
+        }
+        let bit = i - self.prefix;
+        let word = bit / 64;
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (bit % 64);
+        self.normalize();
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        if i < self.prefix {
+            return true;
+        }
+        let bit = i - self.prefix;
+        self.words
+            .get(bit / 64)
+            .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    fn union(&mut self, other: &OpSet) {
+        if other.prefix > self.prefix {
+            self.advance(other.prefix - self.prefix);
+        }
+        if self.prefix == other.prefix {
+            if self.words.len() < other.words.len() {
+                self.words.resize(other.words.len(), 0);
+            }
+            for (word, other) in self.words.iter_mut().zip(&other.words) {
+                *word |= other;
+            }
+            self.normalize();
+        } else {
+            for i in other.extra() {
+                self.insert(i);
+            }
+        }
+    }
+
+    /// Whether the set holds every index below `end` (and nothing above).
+    fn covers(&self, end: usize) -> bool {
+        self.prefix == end && self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Indices above the prefix.
+    fn extra(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(move |(w, &word)| {
+            (0..64)
+                .filter(move |b| word & (1 << b) != 0)
+                .map(move |b| self.prefix + w * 64 + b)
+        })
+    }
+
+    /// Move the prefix up by `by`, dropping the bits it now covers.
+    fn advance(&mut self, by: usize) {
+        self.prefix += by;
+        let (words, bits) = (by / 64, by % 64);
+        if words >= self.words.len() {
+            self.words.clear();
+            return;
+        }
+        self.words.drain(..words);
+        if bits > 0 {
+            for i in 0..self.words.len() {
+                let high = self.words.get(i + 1).copied().unwrap_or(0);
+                self.words[i] = (self.words[i] >> bits) | (high << (64 - bits));
+            }
+        }
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+
+    /// Fold leading set bits into the prefix.
+    fn normalize(&mut self) {
+        let run = match self.words.first() {
+            Some(&first) => first.trailing_ones() as usize,
+            None => return,
+        };
+        if run > 0 {
+            let full = self.words.iter().take_while(|&&word| word == u64::MAX).count();
+            let by = if full > 0 { full * 64 } else { run };
+            self.advance(by);
+            self.normalize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn op(actor: &str, lamport: u64, op_type: OperationType) -> Operation {
+        let mut op = Operation::new("doc.txt".into(), op_type, actor.into());
+        op.timestamp = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        if let OperationType::Insert { position, .. }
+        | OperationType::Delete { position, .. }
+        | OperationType::Replace { position, .. } = &mut op.op_type
+        {
+            position.lamport_timestamp = lamport;
+            position.actor_id = actor.into();
+        }
+        op
+    }
+
+    fn insert(actor: &str, lamport: u64, offset: usize, text: &str) -> Operation {
+        op(
+            actor,
+            lamport,
+            OperationType::Insert {
+                position: Position::new(1, offset + 1, offset, actor.into(), lamport),
+                content: text.into(),
+                length: text.chars().count(),
+            },
+        )
+    }
+
+    fn delete(actor: &str, lamport: u64, offset: usize, length: usize) -> Operation {
+        op(
+            actor,
+            lamport,
+            OperationType::Delete {
+                position: Position::new(1, offset + 1, offset, actor.into(), lamport),
+                length,
+            },
+        )
+    }
+
+    fn apply_all(initial: &str, ops: &[OperationType]) -> String {
+        let mut rope = ropey::Rope::from_str(initial);
+        for op in ops {
+            match op {
+                OperationType::Insert {
+                    position, content, ..
+                } => rope.insert(position.offset, content),
+                OperationType::Delete { position, length } => {
+                    rope.remove(position.offset..position.offset + length)
+                }
+                OperationType::Replace {
+                    position,
+                    old_content,
+                    new_content,
+                } => {
+                    let end = position.offset + old_content.chars().count();
+                    rope.remove(position.offset..end);
+                    rope.insert(position.offset, new_content);
+                }
+                _ => {}
+            }
+        }
+        rope.to_string()
+    }
+
+    #[test]
+    fn concurrent_inserts_converge_in_any_order() {
+        let base = insert("a", 1, 0, "hello world");
+        let left = insert("a", 2, 5, ",").with_parents(vec![base.id]);
+        let right = insert("b", 3, 11, "!").with_parents(vec![base.id]);
+
+        let forward = replay("", vec![base.clone(), left.clone(), right.clone()]);
+        let backward = replay("", vec![right, left, base]);
+
+        assert_eq!(forward, "hello, world!");
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn same_offset_inserts_tie_break_by_lamport_then_actor() {
+        let base = insert("a", 1, 0, "ac");
+        let x = insert("b", 5, 1, "X").with_parents(vec![base.id]);
+        let y = insert("c", 5, 1, "Y").with_parents(vec![base.id]);
+        let z = insert("d", 4, 1, "Z").with_parents(vec![base.id]);
+
+        assert_eq!(
+            replay("", vec![base.clone(), x.clone(), y.clone(), z.clone()]),
+            "aZXYc"
+        );
+        assert_eq!(replay("", vec![y, base, z, x]), "aZXYc");
+    }
+
+    #[test]
+    fn concurrent_delete_and_insert_do_not_clobber() {
+        let base = insert("a", 1, 0, "abcdef");
+        let removal = delete("a", 2, 1, 3).with_parents(vec![base.id]);
+        let addition = insert("b", 3, 6, "g").with_parents(vec![base.id]);
+        let inside = insert("b", 4, 2, "X").with_parents(vec![addition.id]);
+
+        assert_eq!(replay("", vec![base, removal, addition, inside]), "aXefg");
+    }
+
+    #[test]
+    fn overlapping_deletes_remove_text_once() {
+        let base = insert("a", 1, 0, "abcdef");
+        let first = delete("a", 2, 1, 3).with_parents(vec![base.id]);
+        let second = delete("b", 3, 2, 3).with_parents(vec![base.id]);
+
+        assert_eq!(replay("", vec![base, first, second]), "af");
+    }
+
+    #[test]
+    fn rebased_operations_reproduce_the_text() {
+        let base = insert("a", 1, 0, "one two three");
+        let concurrent = vec![
+            delete("a", 2, 4, 4).with_parents(vec![base.id]),
+            insert("b", 3, 13, " four").with_parents(vec![base.id]),
+            insert("b", 4, 8, "2 ").with_parents(vec![base.id]),
+        ];
+
+        let mut transformer = Transformer::new("");
+        transformer.rebase(&base);
+        for op in causal_order(concurrent) {
+            let before = transformer.text();
+            let rebased = transformer.rebase(&op);
+            assert_eq!(apply_all(&before, &rebased), transformer.text());
+        }
+        assert_eq!(transformer.text(), "one 2 three four");
+    }
+
+    #[test]
+    fn parentless_operations_replay_sequentially() {
+        let mut first = insert("a", 1, 0, "abc");
+        let mut second = insert("b", 2, 3, "d");
+        let mut third = delete("a", 3, 0, 1);
+        second.timestamp = first.timestamp + Duration::milliseconds(1);
+        third.timestamp = second.timestamp + Duration::milliseconds(1);
+        first.parent_ops.clear();
+
+        assert_eq!(replay("", vec![third, second, first]), "bcd");
+    }
+
+    #[test]
+    fn parents_order_before_children_despite_clock_skew() {
+        let parent = insert("a", 1, 0, "x");
+        let mut child = insert("b", 2, 1, "y").with_parents(vec![parent.id]);
+        child.timestamp = parent.timestamp - Duration::seconds(5);
+
+        let ordered = causal_order(vec![child.clone(), parent.clone()]);
+        assert_eq!(ordered[0].id, parent.id);
+        assert_eq!(replay("", vec![child, parent]), "xy");
+    }
+
+    #[test]
+    fn tombstones_are_pruned_once_every_actor_has_seen_them() {
+        let mut transformer = Transformer::new("");
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut tick = 0;
+        let mut next = |mut op: Operation| {
+            tick += 1;
+            op.timestamp = start + Duration::milliseconds(tick);
+            op.parent_ops.clear();
+            op
+        };
+
+        // A second actor whose latest operation predates the deletions holds
+        // the tombstones back.
+        transformer.rebase(&next(insert("b", 1, 0, "b")));
+        for i in 0..PRUNE_INTERVAL * 2 {
+            transformer.rebase(&next(insert("a", 2, 1, "x")));
+            transformer.rebase(&next(delete("a", 3, 1, 1)));
+            assert_eq!(transformer.tombstones(), i + 1);
+        }
+
+        // Once it catches up, everything it has seen can go.
+        transformer.rebase(&next(insert("b", 4, 1, "!")));
+        transformer.rebase(&next(insert("a", 5, 2, "?")));
+        assert!(transformer.tombstones() < PRUNE_INTERVAL);
+        assert_eq!(transformer.text(), "b!?");
+    }
+
+    #[test]
+    fn concurrent_operations_rebase_after_pruning() {
+        let mut transformer = Transformer::new("");
+        let base = insert("a", 1, 0, "abc");
+        transformer.rebase(&base);
+        let mut parent = base.id;
+        for lamport in 0..PRUNE_INTERVAL as u64 * 2 {
+            let add = insert("a", lamport * 2 + 2, 3, "z").with_parents(vec![parent]);
+            let remove = delete("a", lamport * 2 + 3, 3, 1).with_parents(vec![add.id]);
+            parent = remove.id;
+            transformer.rebase(&add);
+            transformer.rebase(&remove);
+        }
+        let left = insert("a", 1000, 1, "-").with_parents(vec![parent]);
+        let right = insert("b", 1001, 3, "!").with_parents(vec![parent]);
+        transformer.rebase(&left);
+        transformer.rebase(&right);
+
+        assert_eq!(transformer.text(), "a-bc!");
+    }
+
+    #[test]
+    fn op_set_folds_contiguous_indices_into_the_prefix() {
+        let mut set = OpSet::default();
+        set.insert(1);
+        set.insert(130);
+        assert!(!set.contains(0) && set.contains(1) && set.contains(130));
+        set.insert(0);
+        assert_eq!(set.prefix, 2);
+
+        let mut other = OpSet::through(100);
+        other.insert(140);
+        set.union(&other);
+        assert_eq!(set.prefix, 100);
+        assert!(set.contains(130) && set.contains(140) && !set.contains(135));
+        assert!(!set.covers(141));
+        for i in 100..141 {
+            set.insert(i);
+        }
+        assert!(set.covers(141));
+    }
+}
@@ -1,6 +1,6 @@
 //! Component Injection System
 //!
-//! Fetches components from R2 storage, caches them locally,
+//! Fetches components from an object store (R2 by default), caches them locally,
 //! and injects them into user files with proper imports.
 
 use anyhow::Result;
@@ -8,9 +8,11 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs;
 
 use crate::patterns::{DxToolType, PatternMatch};
+use crate::storage::object_store::{component_key, ObjectStore};
 use crate::storage::r2::{R2Config, R2Storage};
 
 /// Component metadata
@@ -38,12 +40,23 @@ pub struct CacheEntry {
 pub struct InjectionManager {
     cache_dir: PathBuf,
     cache_index: HashMap<String, CacheEntry>,
-    r2_storage: Option<R2Storage>,
+    store: Option<Arc<dyn ObjectStore>>,
 }
 
 impl InjectionManager {
-    /// Create a new injection manager
+    /// Create a new injection manager backed by R2 when it is configured
     pub fn new(forge_dir: &Path) -> Result<Self> {
+        // Try to initialize R2 storage (optional)
+        let store = R2Config::from_env()
+            .ok()
+            .and_then(|config| R2Storage::new(config).ok())
+            .map(|storage| Arc::new(storage) as Arc<dyn ObjectStore>);
+
+        Self::with_store(forge_dir, store)
+    }
+
+    /// Create an injection manager fetching components from `store`
+    pub fn with_store(forge_dir: &Path, store: Option<Arc<dyn ObjectStore>>) -> Result<Self> {
         let cache_dir = forge_dir.join("component_cache");
         std::fs::create_dir_all(&cache_dir)?;
 
@@ -55,15 +68,10 @@ impl InjectionManager {
             HashMap::new()
         };
 
-        // Try to initialize R2 storage (optional)
-        let r2_storage = R2Config::from_env()
-            .ok()
-            .and_then(|config| R2Storage::new(config).ok());
-
         Ok(Self {
             cache_dir,
             cache_index,
-            r2_storage,
+            store,
         })
     }
 
@@ -103,7 +111,7 @@ impl InjectionManager {
         }
     }
 
-    /// Fetch component from R2 and cache it
+    /// Fetch component from the object store and cache it
     pub async fn fetch_component(
         &mut self,
         tool: &DxToolType,
@@ -115,17 +123,20 @@ impl InjectionManager {
             return Ok(cached);
         }
 
-        // Fetch from R2 if available
-        if let Some(r2) = &self.r2_storage {
-            // Try to fetch from R2 with retry logic
+        // Fetch from the object store if available
+        if let Some(store) = self.store.clone() {
+            // Try to fetch with retry logic
+            let key = component_key(tool.tool_name(), version.unwrap_or("latest"), component);
             let max_retries = 3;
             let mut last_error = None;
 
             for attempt in 0..max_retries {
-                match r2
-                    .download_component(tool.tool_name(), component, version)
-                    .await
-                {
+                let fetched = match store.get(&key).await {
+                    Ok(Some(bytes)) => String::from_utf8(bytes).map_err(anyhow::Error::from),
+                    Ok(None) => Err(anyhow::anyhow!("Component not found: {}", key)),
+                    Err(e) => Err(e),
+                };
+                match fetched {
                     Ok(content) => {
                         // Verify content hash
                         let mut hasher = Sha256::new();
@@ -136,9 +147,10 @@ impl InjectionManager {
                         self.cache_component(tool, component, &content).await?;
 
                         tracing::info!(
-                            "✅ Fetched component {}/{} from R2 (hash: {})",
+                            "✅ Fetched component {}/{} from {} (hash: {})",
                             tool.tool_name(),
                             component,
+                            store.name(),
                             &hash[..8]
                         );
 
@@ -148,7 +160,7 @@ impl InjectionManager {
                         last_error = Some(e);
                         if attempt < max_retries - 1 {
                             tracing::warn!(
-                                "⚠️ R2 fetch attempt {}/{} failed for {}/{}, retrying...",
+                                "⚠️ Component fetch attempt {}/{} failed for {}/{}, retrying...",
                                 attempt + 1,
                                 max_retries,
                                 tool.tool_name(),
@@ -166,9 +178,10 @@ impl InjectionManager {
 
             // All retries failed, fall back to placeholder
             tracing::error!(
-                "❌ Failed to fetch {}/{} from R2 after {} attempts: {:?}",
+                "❌ Failed to fetch {}/{} from {} after {} attempts: {:?}",
                 tool.tool_name(),
                 component,
+                store.name(),
                 max_retries,
                 last_error
             );
@@ -182,7 +195,7 @@ impl InjectionManager {
             self.cache_component(tool, component, &content).await?;
             Ok(content)
         } else {
-            // No R2 storage configured, return placeholder
+            // No object store configured, return placeholder
             let content = self.create_placeholder_component(tool, component);
             self.cache_component(tool, component, &content).await?;
             Ok(content)
@@ -406,6 +419,14 @@ export default {};
         Ok(())
     }
 
+    /// Content hashes of every cached component
+    pub fn cached_hashes(&self) -> Vec<String> {
+        self.cache_index
+            .values()
+            .map(|entry| entry.metadata.hash.clone())
+            .collect()
+    }
+
     /// Get cache statistics
     pub fn cache_stats(&self) -> CacheStats {
         let total_size: usize = self
@@ -464,6 +485,27 @@ mod tests {
         assert!(manager.is_cached(&DxToolType::Ui, "Button"));
     }
 
+    #[tokio::test]
+    async fn test_fetch_from_object_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(crate::storage::MemoryObjectStore::new());
+        store
+            .put(
+                &component_key("dx-ui", "latest", "Card"),
+                b"export const Card = () => null;".to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let mut manager = InjectionManager::with_store(temp_dir.path(), Some(store)).unwrap();
+        let content = manager
+            .fetch_component(&DxToolType::Ui, "Card", None)
+            .await
+            .unwrap();
+
+        assert_eq!(content, "export const Card = () => null;");
+    }
+
     #[tokio::test]
     async fn test_cache_stats() {
         let temp_dir = TempDir::new().unwrap();
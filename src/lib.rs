@@ -151,6 +151,7 @@ pub use storage::{Database, OperationLog};
 pub use version::{
     ToolInfo, ToolRegistry, ToolSource, Version, VersionReq,
     Snapshot, SnapshotId, SnapshotManager, Branch, ToolState, FileSnapshot, SnapshotDiff,
+    ContentDiff, DiffFormat, MergeResult, MergeOutcome,
 };
 pub use patterns::{DxToolType, PatternDetector, PatternMatch};
 pub use injection::{CacheStats, ComponentMetadata, InjectionManager};
@@ -1,10 +1,11 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::Result;
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::State,
+    extract::{DefaultBodyLimit, State},
     extract::{Path as AxumPath, Query},
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -16,23 +17,24 @@ use futures::{SinkExt, StreamExt};
 use tower_http::cors::{Any, CorsLayer};
 
 use crate::crdt::Operation;
-use crate::storage::{Blob, Database, OperationLog, R2Config, R2Storage};
-use crate::sync::{SyncManager, SyncMessage, GLOBAL_CLOCK};
-use crate::server::authentication::{AuthManager, LoginRequest, LoginResponse, CreateUserRequest, ChangePasswordRequest};
+use crate::storage::chunking;
+use crate::storage::http_store::OBJECT_SIZE_HEADER;
+use crate::storage::object_store::{blob_key, is_blob_hash, DEFAULT_LIST_LIMIT};
+use crate::storage::op_query::{self, OpOrder, OpQuery};
+use crate::storage::{Blob, ObjectStore, PackedObjectStore, R2Config, R2Storage};
+use crate::sync::presence::PRESENCE_TIMEOUT;
+use crate::sync::{PresenceEvent, SyncMessage, GLOBAL_CLOCK};
+use crate::server::authentication::{AuthManager, LoginRequest, LoginResponse, CreateUserRequest, ChangePasswordRequest, Session};
+use crate::server::rooms::{RepoRoom, RoomDenied, RoomRegistry};
 use dashmap::DashSet;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub oplog: Arc<OperationLog>,
-    pub db: Arc<Database>,
-    pub sync: SyncManager,
+    pub rooms: Arc<RoomRegistry>, // Hosted repositories, one sync room each
     pub actor_id: String,
-    pub repo_id: String,
-    pub seen: Arc<DashSet<Uuid>>,
-    pub r2: Option<Arc<R2Storage>>, // R2 storage for blobs
+    pub blobs: Arc<dyn ObjectStore>, // Object store for uploaded blobs
     pub auth: Arc<AuthManager>, // Authentication manager
 }
 
@@ -42,6 +44,8 @@ pub enum ApiError {
     Internal(anyhow::Error),
     NotFound(String),
     BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
 }
 
 impl IntoResponse for ApiError {
@@ -50,6 +54,8 @@ impl IntoResponse for ApiError {
             ApiError::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            ApiError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            ApiError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
         };
 
         let body = Json(serde_json::json!({
@@ -85,43 +91,51 @@ pub struct UploadBlobResponse {
 }
 
 pub async fn serve(port: u16, path: PathBuf) -> Result<()> {
-    // Initialize DB/oplog
-    let forge_path = path.join(".dx/forge");
-    let db = Arc::new(Database::new(&forge_path)?);
-    db.initialize()?;
-    let oplog = Arc::new(OperationLog::new(db.clone()));
-
-    // Load actor/repo identifiers
-    let config_path = forge_path.join("config.json");
-    let default_repo_id = {
-        let mut hasher = Sha256::new();
-        let path_string = forge_path.to_string_lossy().into_owned();
-        hasher.update(path_string.as_bytes());
-        format!("repo-{:x}", hasher.finalize())
-    };
+    serve_repos(port, vec![path], false).await
+}
 
-    let (actor_id, repo_id) = if let Ok(bytes) = tokio::fs::read(&config_path).await {
-        if let Ok(cfg) = serde_json::from_slice::<serde_json::Value>(&bytes) {
-            let actor = cfg
-                .get("actor_id")
-                .and_then(|s| s.as_str())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| whoami::username());
-            let repo = cfg
-                .get("repo_id")
-                .and_then(|s| s.as_str())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| default_repo_id.clone());
-            (actor, repo)
-        } else {
-            (whoami::username(), default_repo_id.clone())
+/// Serve several repositories from one process. The first path is the
+/// primary repository used by clients that don't name one.
+pub async fn serve_repos(port: u16, paths: Vec<PathBuf>, require_auth: bool) -> Result<()> {
+    let mut rooms = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let room = RepoRoom::open(path).await?;
+        println!(
+            "{} Hosting {} {}",
+            "✓".green(),
+            room.repo_id.bright_white(),
+            format!("({})", path.display()).bright_black()
+        );
+        rooms.push(room);
+    }
+    let rooms = Arc::new(RoomRegistry::new(rooms, require_auth)?);
+
+    // Expire presence of peers that stopped sending heartbeats
+    let presence_rooms = rooms.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRESENCE_TIMEOUT / 3);
+        loop {
+            interval.tick().await;
+            for repo_id in presence_rooms.repo_ids() {
+                if let Some(room) = presence_rooms.get(&repo_id) {
+                    room.sync.prune_stale_presence(PRESENCE_TIMEOUT);
+                }
+            }
         }
-    } else {
-        (whoami::username(), default_repo_id)
-    };
+    });
 
-    // Try to load R2 config
-    let r2 = match R2Config::from_env() {
+    // Load actor identifier from the primary repository
+    let config_path = paths[0].join(".dx/forge").join("config.json");
+    let actor_id = tokio::fs::read(&config_path)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .and_then(|cfg| cfg.get("actor_id").and_then(|s| s.as_str()).map(|s| s.to_string()))
+        .unwrap_or_else(whoami::username);
+
+    // Blobs go to R2 (or another S3-compatible endpoint) when configured,
+    // otherwise into the primary repository's local object store
+    let r2: Option<Arc<dyn ObjectStore>> = match R2Config::from_env() {
         Ok(config) => {
             println!(
                 "{} R2 Bucket: {}",
@@ -139,23 +153,25 @@ pub async fn serve(port: u16, path: PathBuf) -> Result<()> {
                 }
             }
         }
-        Err(_) => {
+        Err(_) => None,
+    };
+    let blobs = match r2 {
+        Some(store) => store,
+        None => {
+            let store = PackedObjectStore::open(paths[0].join(".dx/forge"))?;
             println!(
-                "{} R2 not configured (set R2_* in .env for blob storage)",
-                "ℹ".blue()
+                "{} R2 not configured, storing blobs in {}",
+                "ℹ".blue(),
+                store.root().display()
             );
-            None
+            Arc::new(store)
         }
     };
 
     let state = AppState {
-        oplog,
-        db,
-        sync: SyncManager::new(),
+        rooms,
         actor_id,
-        repo_id,
-        seen: Arc::new(DashSet::new()),
-        r2,
+        blobs,
         auth: Arc::new(AuthManager::new()),
     };
 
@@ -168,6 +184,7 @@ pub async fn serve(port: u16, path: PathBuf) -> Result<()> {
         .route("/health", get(health_check))
         .route("/ops", get(get_ops))
         .route("/ws", get(ws_handler))
+        .route("/api/v1/repos", get(list_repos))
         // Authentication endpoints
         .route("/api/v1/auth/login", post(login))
         .route("/api/v1/auth/validate", get(validate_session))
@@ -180,12 +197,23 @@ pub async fn serve(port: u16, path: PathBuf) -> Result<()> {
         // File browser endpoints
         .route("/api/v1/files", get(list_files))
         .route("/api/v1/files/{*path}", get(get_file_content))
-        // Blob endpoints (if R2 is configured)
+        // Blob endpoints
         .route("/api/v1/blobs", post(upload_blob))
         .route("/api/v1/blobs/{hash}", get(download_blob))
         .route("/api/v1/blobs/{hash}", delete(delete_blob_handler))
         .route("/api/v1/blobs/{hash}/exists", get(check_blob_exists))
+        .route("/api/v1/blobs/{hash}/presign", get(presign_blob))
         .route("/api/v1/blobs/batch", post(batch_upload))
+        .route("/api/v1/objects", get(list_objects))
+        .route(
+            "/api/v1/objects/{*key}",
+            get(get_object)
+                .head(head_object)
+                .put(put_object)
+                .delete(delete_object)
+                // Bodies are streamed into the store, whatever their size
+                .layer(DefaultBodyLimit::disable()),
+        )
         // CORS for web clients
         .layer(
             CorsLayer::new()
@@ -208,115 +236,387 @@ pub async fn serve(port: u16, path: PathBuf) -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct WsQuery {
+    repo: Option<String>,
+    token: Option<String>,
+}
+
 async fn ws_handler(
     State(state): State<AppState>,
+    Query(query): Query<WsQuery>,
+    headers: axum::http::HeaderMap,
     ws: WebSocketUpgrade,
-) -> impl axum::response::IntoResponse {
-    ws.on_upgrade(move |socket| handle_ws(state, socket))
+) -> Result<Response, ApiError> {
+    let session = session_from(&state, &headers, query.token.as_deref())?;
+    Ok(ws
+        .on_upgrade(move |socket| handle_ws(state, query.repo, session, socket))
+        .into_response())
+}
+
+/// Resolve the optional session of a request from its bearer token or a
+/// `token` query parameter. A token that is present but invalid is an error.
+fn session_from(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    query_token: Option<&str>,
+) -> Result<Option<Session>, ApiError> {
+    let token = match headers.get("Authorization") {
+        Some(_) => Some(extract_token(headers)?),
+        None => query_token.map(str::to_string),
+    };
+
+    token
+        .map(|token| {
+            state
+                .auth
+                .validate_token(&token)
+                .map_err(|e| ApiError::BadRequest(e.to_string()))
+        })
+        .transpose()
 }
 
-async fn handle_ws(state: AppState, socket: WebSocket) {
+/// Decode any of the accepted wire encodings of an incoming message.
+fn decode_incoming(msg: Message) -> Option<SyncMessage> {
+    match msg {
+        Message::Text(text) => {
+            let text: String = text.to_string();
+            serde_json::from_str::<SyncMessage>(&text).ok().or_else(|| {
+                serde_json::from_str::<Operation>(&text)
+                    .ok()
+                    .map(SyncMessage::operation)
+            })
+        }
+        Message::Binary(bin) => serde_cbor::from_slice::<Operation>(&bin)
+            .ok()
+            .map(SyncMessage::operation),
+        _ => None,
+    }
+}
+
+async fn handle_ws(
+    state: AppState,
+    requested_repo: Option<String>,
+    session: Option<Session>,
+    socket: WebSocket,
+) {
     let (mut sender, mut receiver) = socket.split();
 
-    // Send handshake immediately with server metadata
-    let handshake = SyncMessage::handshake(state.actor_id.clone(), state.repo_id.clone());
-    if let Ok(text) = serde_json::to_string(&handshake) {
-        let _ = sender.send(Message::Text(text.into())).await;
+    // Join a room up front when the client named one or there is only one;
+    // otherwise wait for the client's handshake to say which repository.
+    let mut room = None;
+    if requested_repo.is_some() || state.rooms.len() == 1 {
+        match state.rooms.join(requested_repo.as_deref(), session.as_ref()) {
+            Ok(joined) => room = Some(joined),
+            Err(denied) => {
+                let _ = send_message(&mut sender, &SyncMessage::rejected(requested_repo, denied.to_string())).await;
+                let _ = sender.close().await;
+                return;
+            }
+        }
+    }
+
+    if let Some(joined) = &room {
+        let handshake = SyncMessage::handshake(state.actor_id.clone(), joined.repo_id.clone());
+        let _ = send_message(&mut sender, &handshake).await;
+        let _ = send_presence_snapshot(&mut sender, joined).await;
     }
+    let mut rx = room.as_ref().map(|joined| joined.sync.subscribe());
+    let mut presence_rx = room.as_ref().map(|joined| joined.sync.subscribe_presence());
+    // Actors whose presence this connection published; cleared on disconnect
+    let mut local_actors: HashSet<String> = HashSet::new();
+    // Presence is tied to this connection and to the actor it speaks for:
+    // the one named in its handshake, or else the first it publishes
+    let connection_id = Uuid::new_v4();
+    let mut connection_actor: Option<String> = None;
+
+    loop {
+        tokio::select! {
+            // Forward operations of the joined room to this client
+            forwarded = async {
+                match rx.as_mut() {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match forwarded {
+                    Ok(op_arc) => {
+                        if send_message(&mut sender, &SyncMessage::operation((*op_arc).clone())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
 
-    // Subscribe to local operations and forward to this client
-    let mut rx = state.sync.subscribe();
-    let send_task = tokio::spawn(async move {
-        while let Ok(op_arc) = rx.recv().await {
-            // Forward as JSON text
-            if let Ok(text) = serde_json::to_string(&SyncMessage::operation((*op_arc).clone())) {
-                if sender.send(Message::Text(text.into())).await.is_err() {
+            // Relay presence of the other peers in the room
+            event = async {
+                match presence_rx.as_mut() {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let msg = match event {
+                    Ok(PresenceEvent::Updated(presence)) if !local_actors.contains(&presence.actor_id) => {
+                        SyncMessage::presence((*presence).clone())
+                    }
+                    Ok(PresenceEvent::Left(actor_id)) if !local_actors.contains(&actor_id) => {
+                        SyncMessage::PresenceLeft { actor_id }
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                };
+                if send_message(&mut sender, &msg).await.is_err() {
                     break;
                 }
             }
-        }
-    });
 
-    // Receive from client and publish
-    let state_recv = state.clone();
-    let recv_task = tokio::spawn(async move {
-        let oplog = state_recv.oplog.clone();
-        while let Some(msg) = receiver.next().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    let text: String = text.to_string();
-                    if let Ok(msg) = serde_json::from_str::<SyncMessage>(&text) {
-                        match msg {
-                            SyncMessage::Handshake { actor_id, repo_id } => {
-                                println!(
-                                    "{} Peer handshake: actor={} repo={}",
-                                    "↔".bright_blue(),
-                                    actor_id.bright_yellow(),
-                                    repo_id.bright_white()
-                                );
-                            }
-                            SyncMessage::Operation { operation: op } => {
-                                if insert_seen(&state_recv.seen, op.id) {
-                                    if let Some(lamport) = op.lamport() {
-                                        GLOBAL_CLOCK.observe(lamport);
+            incoming = receiver.next() => {
+                let msg = match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(msg)) => msg,
+                };
+                let Some(msg) = decode_incoming(msg) else {
+                    continue;
+                };
+
+                match msg {
+                    SyncMessage::Handshake { actor_id, repo_id } => {
+                        println!(
+                            "{} Peer handshake: actor={} repo={}",
+                            "↔".bright_blue(),
+                            actor_id.bright_yellow(),
+                            repo_id.bright_white()
+                        );
+
+                        if connection_actor.is_none() {
+                            connection_actor = Some(actor_id.clone());
+                        }
+                        if room.as_ref().is_some_and(|joined| joined.repo_id == repo_id) {
+                            continue;
+                        }
+
+                        match state.rooms.join(Some(&repo_id), session.as_ref()) {
+                            Ok(joined) => {
+                                if let Some(previous) = room.take() {
+                                    for actor_id in local_actors.drain() {
+                                        previous.sync.remove_presence(&actor_id);
                                     }
-                                    let _ = oplog.append(op.clone());
-                                    let _ = state_recv.sync.publish(Arc::new(op));
                                 }
+                                rx = Some(joined.sync.subscribe());
+                                presence_rx = Some(joined.sync.subscribe_presence());
+                                let handshake = SyncMessage::handshake(state.actor_id.clone(), joined.repo_id.clone());
+                                let sent = send_message(&mut sender, &handshake).await.is_ok()
+                                    && send_presence_snapshot(&mut sender, &joined).await.is_ok();
+                                room = Some(joined);
+                                if !sent {
+                                    break;
+                                }
+                            }
+                            Err(denied) => {
+                                let _ = send_message(&mut sender, &SyncMessage::rejected(Some(repo_id), denied.to_string())).await;
+                                let _ = sender.close().await;
+                                break;
+                            }
+                        }
+                    }
+                    SyncMessage::Operation { operation: op } => {
+                        let Some(joined) = &room else {
+                            let reject = SyncMessage::rejected(None, "send a handshake naming the repository first");
+                            if send_message(&mut sender, &reject).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        };
+                        if let Err(denied) = state.rooms.authorize_write(joined, session.as_ref()) {
+                            let reject = SyncMessage::rejected(Some(joined.repo_id.clone()), denied.to_string());
+                            if send_message(&mut sender, &reject).await.is_err() {
+                                break;
                             }
+                            continue;
                         }
-                    } else if let Ok(op) = serde_json::from_str::<Operation>(&text) {
-                        if insert_seen(&state_recv.seen, op.id) {
+
+                        if insert_seen(&joined.seen, op.id) {
                             if let Some(lamport) = op.lamport() {
                                 GLOBAL_CLOCK.observe(lamport);
                             }
-                            let _ = oplog.append(op.clone());
-                            let _ = state_recv.sync.publish(Arc::new(op));
+                            let _ = joined.oplog.append(op.clone());
+                            let _ = joined.sync.publish(Arc::new(op));
                         }
                     }
-                }
-                Ok(Message::Binary(bin)) => {
-                    if let Ok(op) = serde_cbor::from_slice::<Operation>(&bin) {
-                        if insert_seen(&state_recv.seen, op.id) {
-                            if let Some(lamport) = op.lamport() {
-                                GLOBAL_CLOCK.observe(lamport);
+                    // Presence is relayed to the room but never persisted
+                    SyncMessage::Presence { presence } => {
+                        let Some(joined) = &room else {
+                            continue;
+                        };
+                        let actor = connection_actor.get_or_insert_with(|| presence.actor_id.clone());
+                        if *actor != presence.actor_id {
+                            continue;
+                        }
+                        let actor_id = presence.actor_id.clone();
+                        if joined.sync.publish_presence_from(connection_id, presence) {
+                            local_actors.insert(actor_id);
+                        }
+                    }
+                    SyncMessage::PresenceLeft { actor_id } => {
+                        if let Some(joined) = &room {
+                            if local_actors.remove(&actor_id) {
+                                joined.sync.remove_presence(&actor_id);
                             }
-                            let _ = oplog.append(op.clone());
-                            let _ = state_recv.sync.publish(Arc::new(op));
                         }
                     }
+                    SyncMessage::Rejected { .. } => {}
                 }
-                Ok(Message::Close(_)) | Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {}
-                Err(_) => break,
             }
         }
-    });
+    }
 
-    let _ = tokio::join!(send_task, recv_task);
+    if let Some(joined) = &room {
+        for actor_id in &local_actors {
+            joined.sync.remove_presence(actor_id);
+        }
+    }
 }
 
-#[derive(Deserialize)]
+/// Tell a client who else is already in the room it just joined.
+async fn send_presence_snapshot(
+    sender: &mut futures::stream::SplitSink<WebSocket, Message>,
+    room: &RepoRoom,
+) -> Result<(), axum::Error> {
+    for presence in room.sync.active_peers() {
+        send_message(sender, &SyncMessage::presence((*presence).clone())).await?;
+    }
+    Ok(())
+}
+
+async fn send_message(
+    sender: &mut futures::stream::SplitSink<WebSocket, Message>,
+    msg: &SyncMessage,
+) -> Result<(), axum::Error> {
+    match serde_json::to_string(msg) {
+        Ok(text) => sender.send(Message::Text(text.into())).await,
+        Err(_) => Ok(()),
+    }
+}
+
+#[derive(Default, Deserialize)]
 struct OpsQuery {
     file: Option<String>,
+    /// Glob over file paths, e.g. `src/*.rs`
+    glob: Option<String>,
+    /// Comma-separated actors
+    actor: Option<String>,
+    /// Comma-separated operation types
+    op_type: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    parent: Option<Uuid>,
+    cursor: Option<String>,
+    order: Option<OpOrder>,
     limit: Option<usize>,
+    repo: Option<String>,
+    token: Option<String>,
 }
 
+impl OpsQuery {
+    fn to_query(&self) -> Result<OpQuery> {
+        let limit = self
+            .limit
+            .unwrap_or(op_query::DEFAULT_PAGE_SIZE)
+            .clamp(1, op_query::MAX_PAGE_SIZE);
+        let mut query = OpQuery::new().limit(limit);
+        if let Some(file) = &self.file {
+            query = query.file(file.as_str());
+        }
+        if let Some(glob) = &self.glob {
+            query = query.file_glob(glob.as_str());
+        }
+        for actor in list(&self.actor) {
+            query = query.actor(actor);
+        }
+        for kind in list(&self.op_type) {
+            query = query.kind(kind.parse()?);
+        }
+        if let Some(since) = &self.since {
+            query = query.since(op_query::parse_time(since)?);
+        }
+        if let Some(until) = &self.until {
+            query = query.until(op_query::parse_time(until)?);
+        }
+        if let Some(parent) = self.parent {
+            query = query.parent(parent);
+        }
+        if let Some(order) = self.order {
+            query = query.order(order);
+        }
+        if let Some(cursor) = &self.cursor {
+            query = query.cursor(cursor)?;
+        }
+        Ok(query)
+    }
+}
+
+fn list(values: &Option<String>) -> impl Iterator<Item = &str> {
+    values
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Query the operation log; the cursor for the next page, if any, is in
+/// the `X-Next-Cursor` header
 async fn get_ops(
     State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
     Query(query): Query<OpsQuery>,
-) -> Result<Json<Vec<Operation>>, axum::http::StatusCode> {
-    let limit = query.limit.unwrap_or(50);
-    let result = if let Some(file) = query.file.as_deref() {
-        let p = std::path::PathBuf::from(file);
-        state.db.get_operations(Some(&p), limit)
-    } else {
-        state.db.get_operations(None, limit)
-    };
-
-    match result {
-        Ok(ops) => Ok(Json(ops)),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Response, axum::http::StatusCode> {
+    let session = session_from(&state, &headers, query.token.as_deref())
+        .map_err(|_| axum::http::StatusCode::UNAUTHORIZED)?;
+    let room = state
+        .rooms
+        .join(query.repo.as_deref(), session.as_ref())
+        .map_err(|denied| match denied {
+            crate::server::rooms::RoomDenied::UnknownRepo(_) => axum::http::StatusCode::NOT_FOUND,
+            _ => axum::http::StatusCode::FORBIDDEN,
+        })?;
+
+    let ops_query = query
+        .to_query()
+        .map_err(|_| axum::http::StatusCode::BAD_REQUEST)?;
+    let page = room
+        .db
+        .query_operations(&ops_query)
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut response = Json(page.operations).into_response();
+    if let Some(cursor) = page.next_cursor {
+        if let Ok(value) = axum::http::HeaderValue::from_str(&cursor) {
+            response.headers_mut().insert("X-Next-Cursor", value);
+        }
     }
+    Ok(response)
+}
+
+/// List the repositories hosted by this server
+async fn list_repos(State(state): State<AppState>) -> impl IntoResponse {
+    let repos: Vec<_> = state
+        .rooms
+        .repo_ids()
+        .into_iter()
+        .filter_map(|id| state.rooms.get(&id))
+        .map(|room| {
+            serde_json::json!({
+                "repo_id": room.repo_id,
+                "name": room.root.file_name().and_then(|n| n.to_str()).unwrap_or_default(),
+                "primary": room.repo_id == state.rooms.primary().repo_id,
+            })
+        })
+        .collect();
+
+    Json(repos)
 }
 
 const SEEN_LIMIT: usize = 10_000;
@@ -343,13 +643,13 @@ fn enforce_seen_limit(cache: &DashSet<Uuid>) {
 
 // ========== Blob Storage Endpoints ==========
 
-/// Health check endpoint with R2 status
+/// Health check endpoint with object store status
 async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     Json(serde_json::json!({
         "status": "healthy",
         "service": "forge-api",
         "version": env!("CARGO_PKG_VERSION"),
-        "r2_enabled": state.r2.is_some(),
+        "object_store": state.blobs.name(),
     }))
 }
 
@@ -358,11 +658,6 @@ async fn upload_blob(
     State(state): State<AppState>,
     Json(req): Json<UploadBlobRequest>,
 ) -> Result<Json<UploadBlobResponse>, ApiError> {
-    let r2 = state
-        .r2
-        .as_ref()
-        .ok_or_else(|| ApiError::BadRequest("R2 storage not configured".to_string()))?;
-
     // Decode base64 content
     use base64::Engine;
     let content = base64::engine::general_purpose::STANDARD
@@ -373,8 +668,7 @@ async fn upload_blob(
     let hash = blob.hash().to_string();
     let size = blob.metadata.size;
 
-    // Upload to R2
-    let key = r2.upload_blob(&blob).await?;
+    let key = state.blobs.put_blob(&blob).await?;
 
     Ok(Json(UploadBlobResponse { hash, key, size }))
 }
@@ -384,15 +678,13 @@ async fn download_blob(
     State(state): State<AppState>,
     AxumPath(hash): AxumPath<String>,
 ) -> Result<Response, ApiError> {
-    let r2 = state
-        .r2
-        .as_ref()
-        .ok_or_else(|| ApiError::BadRequest("R2 storage not configured".to_string()))?;
-
-    let blob = r2
-        .download_blob(&hash)
-        .await
-        .map_err(|_| ApiError::NotFound(format!("Blob not found: {}", hash)))?;
+    let hash = checked_hash(hash)?;
+    let mut blob = state
+        .blobs
+        .get_blob(&hash)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Blob not found: {}", hash)))?;
+    chunking::resolve(state.blobs.as_ref(), &mut blob).await?;
 
     // Return blob content with metadata headers
     Ok((
@@ -407,17 +699,22 @@ async fn download_blob(
         .into_response())
 }
 
+/// Reject path segments that are not blob hashes before they become keys
+fn checked_hash(hash: String) -> Result<String, ApiError> {
+    if is_blob_hash(&hash) {
+        Ok(hash)
+    } else {
+        Err(ApiError::BadRequest(format!("Invalid blob hash: {}", hash)))
+    }
+}
+
 /// Delete blob endpoint
 async fn delete_blob_handler(
     State(state): State<AppState>,
     AxumPath(hash): AxumPath<String>,
 ) -> Result<StatusCode, ApiError> {
-    let r2 = state
-        .r2
-        .as_ref()
-        .ok_or_else(|| ApiError::BadRequest("R2 storage not configured".to_string()))?;
-
-    r2.delete_blob(&hash).await?;
+    let hash = checked_hash(hash)?;
+    state.blobs.delete(&blob_key(&hash)).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -426,12 +723,8 @@ async fn check_blob_exists(
     State(state): State<AppState>,
     AxumPath(hash): AxumPath<String>,
 ) -> Result<Json<serde_json::Value>, ApiError> {
-    let r2 = state
-        .r2
-        .as_ref()
-        .ok_or_else(|| ApiError::BadRequest("R2 storage not configured".to_string()))?;
-
-    let exists = r2.blob_exists(&hash).await?;
+    let hash = checked_hash(hash)?;
+    let exists = state.blobs.has_blob(&hash).await?;
 
     Ok(Json(serde_json::json!({
         "exists": exists,
@@ -439,6 +732,197 @@ async fn check_blob_exists(
     })))
 }
 
+#[derive(Deserialize)]
+struct ObjectListQuery {
+    prefix: Option<String>,
+    token: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Namespace below the store root that the object routes are confined
+/// to, so clients can't reach the server's own repository files
+const OBJECT_PREFIX: &str = "objects/";
+
+/// Objects are served to sessions that may read the primary repository
+/// (whose forge directory backs the store) and changed only by sessions
+/// that may write to it. Unlike the sync socket, anonymous access is never
+/// allowed here.
+fn authorize_objects(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    write: bool,
+) -> Result<(), ApiError> {
+    let session = session_from(state, headers, None)?
+        .ok_or_else(|| ApiError::Unauthorized(RoomDenied::AuthenticationRequired.to_string()))?;
+    let room = state
+        .rooms
+        .join(None, Some(&session))
+        .map_err(|denied| ApiError::Forbidden(denied.to_string()))?;
+    if write {
+        state
+            .rooms
+            .authorize_write(&room, Some(&session))
+            .map_err(|denied| ApiError::Forbidden(denied.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Store key for a client object key (or listing prefix). Every segment but
+/// a prefix's trailing one must be a plain name.
+fn object_key(key: &str, is_prefix: bool) -> Result<String, ApiError> {
+    let mut segments: Vec<&str> = key.split('/').collect();
+    if is_prefix {
+        segments.pop();
+    }
+    let valid = (is_prefix || !key.is_empty())
+        && !key.contains('\\')
+        && segments
+            .iter()
+            .all(|segment| !matches!(*segment, "" | "." | ".."));
+    if !valid {
+        return Err(ApiError::BadRequest(format!("Invalid object key: {}", key)));
+    }
+    Ok(format!("{}{}", OBJECT_PREFIX, key))
+}
+
+/// Client object key for a store key
+fn client_key(key: &str) -> String {
+    key.strip_prefix(OBJECT_PREFIX).unwrap_or(key).to_string()
+}
+
+/// Raw object listing, so clients can use the server as an object store
+/// (`HttpObjectStore`)
+async fn list_objects(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Query(q): Query<ObjectListQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    authorize_objects(&state, &headers, false)?;
+    let prefix = object_key(q.prefix.as_deref().unwrap_or(""), true)?;
+    let token = q.token.as_deref().map(|token| format!("{}{}", OBJECT_PREFIX, token));
+    let limit = q.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, DEFAULT_LIST_LIMIT);
+    let mut page = state.blobs.list(&prefix, token.as_deref(), limit).await?;
+    for meta in &mut page.objects {
+        meta.key = client_key(&meta.key);
+    }
+
+    Ok(Json(serde_json::json!({
+        "objects": page.objects,
+        "next_token": page.next_token.as_deref().map(client_key),
+    })))
+}
+
+/// Object bytes exactly as stored
+async fn get_object(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    AxumPath(key): AxumPath<String>,
+) -> Result<Response, ApiError> {
+    authorize_objects(&state, &headers, false)?;
+    let (reader, size) = state
+        .blobs
+        .get_reader(&object_key(&key, false)?)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Object not found: {}", key)))?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/octet-stream".to_string()),
+            ("Content-Length", size.to_string()),
+        ],
+        axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(reader)),
+    )
+        .into_response())
+}
+
+async fn head_object(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    AxumPath(key): AxumPath<String>,
+) -> Result<Response, ApiError> {
+    authorize_objects(&state, &headers, false)?;
+    let meta = state
+        .blobs
+        .head(&object_key(&key, false)?)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Object not found: {}", key)))?;
+
+    Ok((StatusCode::OK, [(OBJECT_SIZE_HEADER, meta.size.to_string())]).into_response())
+}
+
+/// Store an object, streaming the body rather than buffering it
+async fn put_object(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    AxumPath(key): AxumPath<String>,
+    body: axum::body::Body,
+) -> Result<StatusCode, ApiError> {
+    authorize_objects(&state, &headers, true)?;
+    let key = object_key(&key, false)?;
+    // Only a hint: stores that need the size up front buffer without it
+    let size = headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    let stream = body.into_data_stream().map(|chunk| chunk.map_err(std::io::Error::other));
+    let reader = tokio_util::io::StreamReader::new(stream);
+    state.blobs.put_reader(&key, Box::new(reader), size).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_object(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    AxumPath(key): AxumPath<String>,
+) -> Result<StatusCode, ApiError> {
+    authorize_objects(&state, &headers, true)?;
+    state.blobs.delete(&object_key(&key, false)?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct PresignQuery {
+    method: Option<String>,
+    expires: Option<u64>,
+}
+
+/// Longest lifetime of a presigned URL, in seconds
+const MAX_PRESIGN_EXPIRY: u64 = 3600;
+
+/// Presigned URL so clients can GET or PUT a blob's binary encoding
+/// directly against the object store. The URL bypasses the server, so it
+/// takes the same session as the object routes: a write grant for PUT.
+async fn presign_blob(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    AxumPath(hash): AxumPath<String>,
+    Query(q): Query<PresignQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let hash = checked_hash(hash)?;
+    let method = q.method.unwrap_or_else(|| "GET".to_string()).to_uppercase();
+    if method != "GET" && method != "PUT" {
+        return Err(ApiError::BadRequest(format!("Cannot presign {}", method)));
+    }
+    authorize_objects(&state, &headers, method == "PUT")?;
+    let expires = q.expires.unwrap_or(900).clamp(1, MAX_PRESIGN_EXPIRY);
+
+    let url = state
+        .blobs
+        .presigned_url(&method, &blob_key(&hash), std::time::Duration::from_secs(expires))
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!("{} does not support presigned URLs", state.blobs.name()))
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "url": url,
+        "method": method,
+        "expires_in": expires,
+    })))
+}
+
 /// Batch upload request
 #[derive(Debug, Deserialize)]
 pub struct BatchUploadRequest {
@@ -457,11 +941,6 @@ async fn batch_upload(
     State(state): State<AppState>,
     Json(req): Json<BatchUploadRequest>,
 ) -> Result<Json<BatchUploadResponse>, ApiError> {
-    let r2 = state
-        .r2
-        .as_ref()
-        .ok_or_else(|| ApiError::BadRequest("R2 storage not configured".to_string()))?;
-
     let mut uploaded = Vec::new();
     let mut failed = Vec::new();
 
@@ -473,7 +952,7 @@ async fn batch_upload(
                 let hash = blob.hash().to_string();
                 let size = blob.metadata.size;
 
-                match r2.upload_blob(&blob).await {
+                match state.blobs.put_blob(&blob).await {
                     Ok(key) => {
                         uploaded.push(UploadBlobResponse { hash, key, size });
                     }
@@ -623,7 +1102,11 @@ async fn create_user(
         return Err(ApiError::BadRequest("Insufficient permissions".to_string()));
     }
     
+    let username = req.username.clone();
     state.auth.register(req.username, &req.password, req.role)?;
+    if !req.repos.is_empty() {
+        state.auth.set_repo_access(&username, req.repos)?;
+    }
     Ok(StatusCode::CREATED)
 }
 
@@ -760,3 +1243,157 @@ async fn get_file_content(
         path,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::authentication::Role;
+    use crate::storage::MemoryObjectStore;
+
+    async fn state(dir: &std::path::Path) -> AppState {
+        let forge = dir.join(".dx/forge");
+        std::fs::create_dir_all(&forge).unwrap();
+        std::fs::write(
+            forge.join("config.json"),
+            serde_json::json!({ "repo_id": "repo-a" }).to_string(),
+        )
+        .unwrap();
+        let room = RepoRoom::open(dir).await.unwrap();
+        AppState {
+            rooms: Arc::new(RoomRegistry::new(vec![room], false).unwrap()),
+            actor_id: "server".into(),
+            blobs: Arc::new(MemoryObjectStore::new()),
+            auth: Arc::new(AuthManager::new()),
+        }
+    }
+
+    fn bearer(state: &AppState, username: &str, role: Role) -> axum::http::HeaderMap {
+        state.auth.register(username.into(), "pw", role).unwrap();
+        let session = state.auth.login(username, "pw").unwrap();
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", session.token).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn object_keys_stay_inside_the_object_prefix() {
+        assert_eq!(object_key("blobs/ab/cd", false).unwrap(), "objects/blobs/ab/cd");
+        assert_eq!(object_key("refs/heads/", true).unwrap(), "objects/refs/heads/");
+        assert_eq!(object_key("", true).unwrap(), "objects/");
+        for key in ["", "../forge.db", "blobs/../../HEAD", "a//b", "./x", "a\\..\\b"] {
+            assert!(object_key(key, false).is_err(), "{key}");
+        }
+        assert!(object_key("../", true).is_err());
+        assert_eq!(client_key("objects/refs/heads/main"), "refs/heads/main");
+    }
+
+    #[tokio::test]
+    async fn presigning_needs_a_session_and_caps_the_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state(dir.path()).await;
+        state.blobs = Arc::new(
+            R2Storage::new(R2Config {
+                bucket_name: "forge".to_string(),
+                access_key_id: "minio".to_string(),
+                secret_access_key: "minio-secret".to_string(),
+                endpoint: Some("http://localhost:9000/".to_string()),
+                region: Some("us-east-1".to_string()),
+                ..R2Config::default()
+            })
+            .unwrap(),
+        );
+        let viewer = bearer(&state, "viewer", Role::Viewer);
+        let developer = bearer(&state, "dev", Role::Developer);
+        let hash = "ab".repeat(32);
+        let presign = |headers: axum::http::HeaderMap, method: &str| {
+            presign_blob(
+                State(state.clone()),
+                headers,
+                AxumPath(hash.clone()),
+                Query(PresignQuery {
+                    method: Some(method.to_string()),
+                    expires: Some(7 * 24 * 3600),
+                }),
+            )
+        };
+
+        assert!(matches!(
+            presign(axum::http::HeaderMap::new(), "GET").await,
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            presign(viewer.clone(), "PUT").await,
+            Err(ApiError::Forbidden(_))
+        ));
+        let Json(url) = presign(viewer, "GET").await.unwrap();
+        assert_eq!(url["expires_in"], MAX_PRESIGN_EXPIRY);
+        assert!(presign(developer, "PUT").await.is_ok());
+    }
+
+    #[test]
+    fn ops_queries_cap_their_page_size_and_reject_huge_ages() {
+        let query = OpsQuery {
+            limit: Some(usize::MAX),
+            ..Default::default()
+        };
+        assert_eq!(query.to_query().unwrap().page_size(), Some(op_query::MAX_PAGE_SIZE));
+        assert_eq!(
+            OpsQuery::default().to_query().unwrap().page_size(),
+            Some(op_query::DEFAULT_PAGE_SIZE)
+        );
+
+        let query = OpsQuery {
+            since: Some("9999999999999d".into()),
+            ..Default::default()
+        };
+        assert!(query.to_query().is_err());
+    }
+
+    #[tokio::test]
+    async fn object_routes_need_a_session_and_writes_need_a_grant() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path()).await;
+        let anonymous = axum::http::HeaderMap::new();
+        let viewer = bearer(&state, "viewer", Role::Viewer);
+        let developer = bearer(&state, "dev", Role::Developer);
+
+        assert!(matches!(
+            authorize_objects(&state, &anonymous, false),
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(authorize_objects(&state, &viewer, false).is_ok());
+        assert!(matches!(
+            authorize_objects(&state, &viewer, true),
+            Err(ApiError::Forbidden(_))
+        ));
+        assert!(authorize_objects(&state, &developer, true).is_ok());
+
+        let response = put_object(
+            State(state.clone()),
+            developer.clone(),
+            AxumPath("refs/heads/main".into()),
+            axum::body::Body::from(vec![7u8; 3 * 1024 * 1024]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, StatusCode::NO_CONTENT);
+        let stored = state.blobs.get("objects/refs/heads/main").await.unwrap().unwrap();
+        assert_eq!(stored.len(), 3 * 1024 * 1024);
+
+        let Json(listing) = list_objects(
+            State(state.clone()),
+            developer,
+            Query(ObjectListQuery {
+                prefix: Some("refs/".into()),
+                token: None,
+                limit: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listing["objects"][0]["key"], "refs/heads/main");
+    }
+}
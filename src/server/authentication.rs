@@ -25,6 +25,9 @@ pub struct User {
     pub password_hash: String,
     pub email: Option<String>,
     pub role: Role,
+    /// Repositories this user may sync; empty means all of them
+    #[serde(default)]
+    pub repos: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
 }
@@ -38,6 +41,7 @@ impl User {
             password_hash: hash_password(password),
             email: None,
             role,
+            repos: Vec::new(),
             created_at: Utc::now(),
             last_login: None,
         }
@@ -66,6 +70,9 @@ pub struct Session {
     pub user_id: String,
     pub username: String,
     pub role: Role,
+    /// Repositories granted to the user; empty means all of them
+    #[serde(default)]
+    pub repos: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
 }
@@ -79,6 +86,7 @@ impl Session {
             user_id: user.id.clone(),
             username: user.username.clone(),
             role: user.role.clone(),
+            repos: user.repos.clone(),
             created_at: now,
             expires_at: now + Duration::hours(duration_hours),
         }
@@ -88,6 +96,11 @@ impl Session {
     pub fn is_expired(&self) -> bool {
         Utc::now() > self.expires_at
     }
+
+    /// Check if the session may sync the given repository
+    pub fn can_access_repo(&self, repo_id: &str) -> bool {
+        self.role == Role::Admin || self.repos.is_empty() || self.repos.iter().any(|r| r == repo_id)
+    }
 }
 
 /// Authentication manager
@@ -200,6 +213,22 @@ impl AuthManager {
         Ok(())
     }
 
+    /// Restrict a user to the given repositories (empty grants all)
+    pub fn set_repo_access(&self, username: &str, repos: Vec<String>) -> Result<()> {
+        let mut users = self.users.write();
+        let user = users
+            .get_mut(username)
+            .ok_or_else(|| anyhow!("User not found"))?;
+        user.repos = repos.clone();
+
+        // Existing sessions pick up the new grants immediately
+        let mut sessions = self.sessions.write();
+        for session in sessions.values_mut().filter(|s| s.username == username) {
+            session.repos = repos.clone();
+        }
+        Ok(())
+    }
+
     /// Delete user
     pub fn delete_user(&self, username: &str) -> Result<()> {
         let mut users = self.users.write();
@@ -250,6 +279,9 @@ pub struct CreateUserRequest {
     pub password: String,
     pub role: Role,
     pub email: Option<String>,
+    /// Repositories the user may sync; empty means all of them
+    #[serde(default)]
+    pub repos: Vec<String>,
 }
 
 /// Password change request
@@ -2,6 +2,7 @@ pub mod api;
 pub mod lsp;
 pub mod semantic_analyzer;
 pub mod authentication;
+pub mod rooms;
 
 
 use anyhow::Result;
@@ -10,3 +11,8 @@ use std::path::PathBuf;
 pub async fn start(port: u16, path: PathBuf) -> Result<()> {
     api::serve(port, path).await
 }
+
+/// Host several repositories from one server; the first is the primary one.
+pub async fn start_multi(port: u16, paths: Vec<PathBuf>, require_auth: bool) -> Result<()> {
+    api::serve_repos(port, paths, require_auth).await
+}
@@ -0,0 +1,305 @@
+//! Per-repository rooms for the sync server.
+//!
+//! Every repository hosted by `forge-cli serve` gets its own database,
+//! operation log and broadcast channel. WebSocket sessions join exactly one
+//! room, so operations never leak between repositories.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use dashmap::DashSet;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::server::authentication::{Role, Session};
+use crate::storage::{Database, OperationLog};
+use crate::sync::SyncManager;
+
+/// A hosted repository and the sessions' shared broadcast channel.
+pub struct RepoRoom {
+    pub repo_id: String,
+    pub root: PathBuf,
+    pub db: Arc<Database>,
+    pub oplog: Arc<OperationLog>,
+    pub sync: SyncManager,
+    pub seen: Arc<DashSet<Uuid>>,
+}
+
+impl RepoRoom {
+    /// Open the forge repository at `root`, reading its `repo_id` from
+    /// `.dx/forge/config.json` (or deriving one from the path).
+    pub async fn open(root: &Path) -> Result<Self> {
+        let forge_path = root.join(".dx/forge");
+        let db = Arc::new(Database::new(&forge_path)?);
+        db.initialize()?;
+        let oplog = Arc::new(OperationLog::new(db.clone()));
+
+        let repo_id = tokio::fs::read(forge_path.join("config.json"))
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+            .and_then(|cfg| {
+                cfg.get("repo_id")
+                    .and_then(|s| s.as_str())
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| {
+                let mut hasher = Sha256::new();
+                hasher.update(forge_path.to_string_lossy().as_bytes());
+                format!("repo-{:x}", hasher.finalize())
+            });
+
+        Ok(Self {
+            repo_id,
+            root: root.to_path_buf(),
+            db,
+            oplog,
+            sync: SyncManager::new(),
+            seen: Arc::new(DashSet::new()),
+        })
+    }
+}
+
+/// Why a session could not join or write to a room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomDenied {
+    UnknownRepo(String),
+    AuthenticationRequired,
+    NotAuthorized(String),
+    ReadOnly,
+}
+
+impl std::fmt::Display for RoomDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomDenied::UnknownRepo(repo) => write!(f, "repository {repo} is not hosted here"),
+            RoomDenied::AuthenticationRequired => write!(f, "authentication required"),
+            RoomDenied::NotAuthorized(repo) => write!(f, "not authorized for repository {repo}"),
+            RoomDenied::ReadOnly => write!(f, "session is read-only"),
+        }
+    }
+}
+
+/// All rooms hosted by one server process.
+pub struct RoomRegistry {
+    rooms: HashMap<String, Arc<RepoRoom>>,
+    /// Room used by clients that don't name a repository
+    primary: String,
+    require_auth: bool,
+}
+
+impl RoomRegistry {
+    /// Build the registry; the first room is the primary one.
+    pub fn new(rooms: Vec<RepoRoom>, require_auth: bool) -> Result<Self> {
+        let Some(primary) = rooms.first().map(|room| room.repo_id.clone()) else {
+            bail!("at least one repository must be hosted");
+        };
+
+        let mut by_id: HashMap<String, Arc<RepoRoom>> = HashMap::new();
+        for room in rooms {
+            if let Some(existing) = by_id.get(&room.repo_id) {
+                bail!(
+                    "{} and {} share repo_id {}",
+                    existing.root.display(),
+                    room.root.display(),
+                    room.repo_id
+                );
+            }
+            by_id.insert(room.repo_id.clone(), Arc::new(room));
+        }
+
+        Ok(Self {
+            rooms: by_id,
+            primary,
+            require_auth,
+        })
+    }
+
+    pub fn primary(&self) -> Arc<RepoRoom> {
+        self.rooms[&self.primary].clone()
+    }
+
+    pub fn get(&self, repo_id: &str) -> Option<Arc<RepoRoom>> {
+        self.rooms.get(repo_id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    pub fn repo_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.rooms.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Resolve the room a session may read from.
+    ///
+    /// With no `repo_id` the primary room is used; an id this server doesn't
+    /// host is always refused, however many rooms there are.
+    pub fn join(
+        &self,
+        repo_id: Option<&str>,
+        session: Option<&Session>,
+    ) -> Result<Arc<RepoRoom>, RoomDenied> {
+        let room = match repo_id {
+            None => self.primary(),
+            Some(id) => self
+                .get(id)
+                .ok_or_else(|| RoomDenied::UnknownRepo(id.to_string()))?,
+        };
+
+        match session {
+            None if self.require_auth => Err(RoomDenied::AuthenticationRequired),
+            None => Ok(room),
+            Some(session) if session.can_access_repo(&room.repo_id) => Ok(room),
+            Some(_) => Err(RoomDenied::NotAuthorized(room.repo_id.clone())),
+        }
+    }
+
+    /// Check that a session may publish operations to `room`.
+    ///
+    /// Logged-in sessions need a grant for the repository and a role above
+    /// `Viewer`. Anonymous clients can only write when the server runs
+    /// without `--require-auth`, which deliberately makes it an open server.
+    pub fn authorize_write(
+        &self,
+        room: &RepoRoom,
+        session: Option<&Session>,
+    ) -> Result<(), RoomDenied> {
+        match session {
+            None if self.require_auth => Err(RoomDenied::AuthenticationRequired),
+            None => Ok(()),
+            Some(session) if !session.can_access_repo(&room.repo_id) => {
+                Err(RoomDenied::NotAuthorized(room.repo_id.clone()))
+            }
+            Some(session) if session.role == Role::Viewer => Err(RoomDenied::ReadOnly),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::authentication::AuthManager;
+
+    async fn room(dir: &Path, repo_id: &str) -> RepoRoom {
+        let forge = dir.join(".dx/forge");
+        std::fs::create_dir_all(&forge).unwrap();
+        std::fs::write(
+            forge.join("config.json"),
+            serde_json::json!({ "repo_id": repo_id }).to_string(),
+        )
+        .unwrap();
+        RepoRoom::open(dir).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_sessions_by_repo_and_grants() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let rooms = RoomRegistry::new(
+            vec![
+                room(a.path(), "repo-a").await,
+                room(b.path(), "repo-b").await,
+            ],
+            false,
+        )
+        .unwrap();
+
+        let auth = AuthManager::new();
+        auth.register("dev".into(), "pw", Role::Developer).unwrap();
+        auth.set_repo_access("dev", vec!["repo-a".into()]).unwrap();
+        let dev = auth.login("dev", "pw").unwrap();
+
+        assert_eq!(rooms.join(Some("repo-b"), None).unwrap().repo_id, "repo-b");
+        assert_eq!(
+            rooms.join(Some("repo-a"), Some(&dev)).unwrap().repo_id,
+            "repo-a"
+        );
+        assert_eq!(
+            rooms.join(Some("repo-b"), Some(&dev)).err(),
+            Some(RoomDenied::NotAuthorized("repo-b".into()))
+        );
+        assert_eq!(
+            rooms.join(Some("repo-c"), None).err(),
+            Some(RoomDenied::UnknownRepo("repo-c".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn single_repo_server_refuses_foreign_repo_ids_and_can_require_auth() {
+        let dir = tempfile::tempdir().unwrap();
+        let rooms = RoomRegistry::new(vec![room(dir.path(), "repo-a").await], true).unwrap();
+
+        assert_eq!(
+            rooms.join(Some("elsewhere"), None).err(),
+            Some(RoomDenied::UnknownRepo("elsewhere".into()))
+        );
+        assert_eq!(
+            rooms.join(None, None).err(),
+            Some(RoomDenied::AuthenticationRequired)
+        );
+
+        let auth = AuthManager::new();
+        auth.register("viewer".into(), "pw", Role::Viewer).unwrap();
+        let viewer = auth.login("viewer", "pw").unwrap();
+        assert_eq!(
+            rooms.join(Some("elsewhere"), Some(&viewer)).err(),
+            Some(RoomDenied::UnknownRepo("elsewhere".into()))
+        );
+        let joined = rooms.join(None, Some(&viewer)).unwrap();
+        assert_eq!(joined.repo_id, "repo-a");
+        assert_eq!(
+            rooms.authorize_write(&joined, Some(&viewer)),
+            Err(RoomDenied::ReadOnly)
+        );
+        assert_eq!(
+            rooms.authorize_write(&joined, None),
+            Err(RoomDenied::AuthenticationRequired)
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_follow_grants_and_open_servers_accept_anonymous_writes() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let rooms = RoomRegistry::new(
+            vec![
+                room(a.path(), "repo-a").await,
+                room(b.path(), "repo-b").await,
+            ],
+            false,
+        )
+        .unwrap();
+        let (repo_a, repo_b) = (rooms.get("repo-a").unwrap(), rooms.get("repo-b").unwrap());
+
+        let auth = AuthManager::new();
+        auth.register("dev".into(), "pw", Role::Developer).unwrap();
+        auth.set_repo_access("dev", vec!["repo-a".into()]).unwrap();
+        let dev = auth.login("dev", "pw").unwrap();
+
+        assert_eq!(rooms.authorize_write(&repo_a, Some(&dev)), Ok(()));
+        assert_eq!(
+            rooms.authorize_write(&repo_b, Some(&dev)),
+            Err(RoomDenied::NotAuthorized("repo-b".into()))
+        );
+        // Without --require-auth the server is intentionally open
+        assert_eq!(rooms.authorize_write(&repo_b, None), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn duplicate_repo_ids_are_rejected() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let result = RoomRegistry::new(
+            vec![room(a.path(), "same").await, room(b.path(), "same").await],
+            false,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -7,7 +7,16 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::chunking::{self, ChunkRef, ChunkingConfig};
+use super::object_store::{blob_key, ObjectReader, ObjectStore};
+use super::pack::PackedObjectStore;
+
+/// Buffer size used when hashing and copying blobs as streams
+const STREAM_BUFFER_SIZE: usize = 256 * 1024;
 
 /// Blob metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,8 +41,77 @@ pub struct BlobMetadata {
 
     /// Compression algorithm used (if any)
     pub compression: Option<String>,
+
+    /// Chunks holding the content of a chunked blob, whose own content is
+    /// then empty (see `chunking`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<ChunkRef>>,
+}
+
+impl BlobMetadata {
+    /// The `[metadata_len: u32][metadata_json]` prefix of the binary format,
+    /// for writing a blob whose content is streamed separately
+    pub fn header_bytes(&self) -> Result<Vec<u8>> {
+        let metadata_json = serde_json::to_vec(self)?;
+        let mut header = Vec::with_capacity(4 + metadata_json.len());
+        header.extend_from_slice(&(metadata_json.len() as u32).to_le_bytes());
+        header.extend_from_slice(&metadata_json);
+        Ok(header)
+    }
+}
+
+/// Compression applied to blob content when it is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Lz4,
+    /// Better ratio than LZ4 at a higher CPU cost; needs the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    /// Name recorded in `BlobMetadata::compression`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// The `compression` setting from `.dx/forge/config.json`, defaulting
+    /// to LZ4 when the file or the setting is missing.
+    pub fn from_config(forge_dir: &Path) -> Result<Self> {
+        let Ok(raw) = std::fs::read(forge_dir.join("config.json")) else {
+            return Ok(Self::default());
+        };
+        let config: serde_json::Value = serde_json::from_slice(&raw)?;
+        match config.get("compression").and_then(|value| value.as_str()) {
+            Some(name) => name.parse(),
+            None => Ok(Self::default()),
+        }
+    }
 }
 
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            other => anyhow::bail!("Unknown compression algorithm: {}", other),
+        }
+    }
+}
+
+/// zstd level used for blobs: a good ratio while staying fast
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
 /// Binary blob representation
 #[derive(Debug)]
 pub struct Blob {
@@ -58,11 +136,29 @@ impl Blob {
             mime_type,
             created_at: chrono::Utc::now(),
             compression: None,
+            chunks: None,
         };
 
         Ok(Self { metadata, content })
     }
 
+    /// Metadata for a file, hashing it as a stream instead of loading it
+    pub async fn metadata_for_file(path: &Path) -> Result<BlobMetadata> {
+        let file = fs::File::open(path).await.context("Failed to read file")?;
+        let (hash, size) = hash_reader(file).await?;
+
+        Ok(BlobMetadata {
+            hash,
+            path: path.display().to_string(),
+            size,
+            original_size: None,
+            mime_type: detect_mime_type(path),
+            created_at: chrono::Utc::now(),
+            compression: None,
+            chunks: None,
+        })
+    }
+
     /// Create blob from raw content
     pub fn from_content(path: &str, content: Vec<u8>) -> Self {
         let hash = compute_hash(&content);
@@ -77,6 +173,7 @@ impl Blob {
             mime_type,
             created_at: chrono::Utc::now(),
             compression: None,
+            chunks: None,
         };
 
         Self { metadata, content }
@@ -121,18 +218,30 @@ impl Blob {
 
     /// Compress blob content using LZ4
     pub fn compress(&mut self) -> Result<()> {
+        self.compress_with(Compression::Lz4)
+    }
+
+    /// Compress blob content with `algorithm`
+    pub fn compress_with(&mut self, algorithm: Compression) -> Result<()> {
         if self.metadata.compression.is_some() {
             return Ok(()); // Already compressed
         }
 
-        let compressed = lz4::block::compress(&self.content, None, false)?;
+        let compressed = match algorithm {
+            Compression::None => return Ok(()),
+            Compression::Lz4 => lz4::block::compress(&self.content, None, false)?,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(&self.content, ZSTD_LEVEL)?,
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => anyhow::bail!("zstd compression requires the `zstd` feature"),
+        };
 
         // Only use compression if it actually reduces size
         if compressed.len() < self.content.len() {
             // Remember original size so we can safely decompress later
             self.metadata.original_size = Some(self.metadata.size);
             self.content = compressed;
-            self.metadata.compression = Some("lz4".to_string());
+            self.metadata.compression = Some(algorithm.as_str().to_string());
             self.metadata.size = self.content.len() as u64;
         }
 
@@ -147,12 +256,25 @@ impl Blob {
 
         // Use the recorded original size when available to avoid
         // decompression errors due to missing size hints.
-        let original_size = self
-            .metadata
-            .original_size
-            .unwrap_or(self.metadata.size) as i32;
+        let original_size = self.metadata.original_size.unwrap_or(self.metadata.size);
 
-        let decompressed = lz4::block::decompress(&self.content, Some(original_size))?;
+        let algorithm: Compression = self
+            .metadata
+            .compression
+            .as_deref()
+            .unwrap_or("lz4")
+            .parse()?;
+        let decompressed = match algorithm {
+            Compression::None => std::mem::take(&mut self.content),
+            Compression::Lz4 => lz4::block::decompress(&self.content, Some(original_size as i32))?,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(&self.content, original_size as usize)?,
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => anyhow::bail!(
+                "Blob {} is zstd-compressed; rebuild with the `zstd` feature",
+                self.metadata.hash
+            ),
+        };
         self.content = decompressed;
         self.metadata.compression = None;
         self.metadata.original_size = None;
@@ -161,6 +283,11 @@ impl Blob {
         Ok(())
     }
 
+    /// Whether this is a manifest whose content lives in chunks
+    pub fn is_chunked(&self) -> bool {
+        self.metadata.chunks.is_some()
+    }
+
     /// Get blob hash (content-addressable)
     pub fn hash(&self) -> &str {
         &self.metadata.hash
@@ -174,6 +301,66 @@ fn compute_hash(content: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// SHA-256 and length of everything `reader` yields
+async fn hash_reader<R: AsyncRead + Unpin>(mut reader: R) -> Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; STREAM_BUFFER_SIZE];
+    let mut size = 0u64;
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Check that a file in blob binary format holds the blob `hash`, without
+/// loading uncompressed content into memory. For a chunked blob only the
+/// manifest is checked; see `chunking::verify_chunks`.
+pub async fn verify_encoded_file(path: &Path, hash: &str) -> Result<BlobMetadata> {
+    let mut file = fs::File::open(path).await?;
+    let mut len = [0u8; 4];
+    file.read_exact(&mut len)
+        .await
+        .context("Invalid blob: too short")?;
+    let mut metadata_json = vec![0u8; u32::from_le_bytes(len) as usize];
+    file.read_exact(&mut metadata_json)
+        .await
+        .context("Invalid blob: metadata truncated")?;
+    let metadata: BlobMetadata = serde_json::from_slice(&metadata_json)?;
+
+    let actual = if metadata.chunks.is_some() {
+        metadata.hash.clone()
+    } else if metadata.compression.is_some() {
+        let mut blob = Blob::from_binary(&fs::read(path).await?)?;
+        blob.decompress()?;
+        compute_hash(&blob.content)
+    } else {
+        let (actual, size) = hash_reader(file).await?;
+        if size != metadata.size {
+            anyhow::bail!(
+                "Blob {} is truncated: {} of {} bytes",
+                hash,
+                size,
+                metadata.size
+            );
+        }
+        actual
+    };
+
+    if actual != hash || metadata.hash != hash {
+        anyhow::bail!(
+            "Blob {} failed verification (content hashes to {})",
+            hash,
+            actual
+        );
+    }
+    Ok(metadata)
+}
+
 /// Detect MIME type from file path
 fn detect_mime_type(path: &Path) -> String {
     detect_mime_type_from_path(&path.display().to_string())
@@ -210,55 +397,181 @@ fn detect_mime_type_from_path(path: &str) -> String {
 
 /// Blob repository for local caching
 pub struct BlobRepository {
-    cache_dir: PathBuf,
+    store: Arc<dyn ObjectStore>,
+    compression: Compression,
+    chunking: Option<ChunkingConfig>,
 }
 
 impl BlobRepository {
-    /// Create new blob repository
+    /// Create new blob repository, compressing and chunking as configured
+    /// in the forge directory's `config.json`
     pub fn new(forge_dir: &Path) -> Result<Self> {
-        let cache_dir = forge_dir.join("blobs");
-        std::fs::create_dir_all(&cache_dir)?;
+        std::fs::create_dir_all(forge_dir.join("blobs"))?;
+
+        // Blob keys (`blobs/ab/cdef...`) land under the forge directory,
+        // loose or in its packfiles
+        let store = Arc::new(PackedObjectStore::open(forge_dir)?);
+        Ok(Self::with_store(store)
+            .with_compression(Compression::from_config(forge_dir)?)
+            .with_chunking(ChunkingConfig::from_config(forge_dir)?))
+    }
 
-        Ok(Self { cache_dir })
+    /// Keep blobs in an arbitrary object store
+    pub fn with_store(store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            store,
+            compression: Compression::default(),
+            chunking: None,
+        }
     }
 
-    /// Store blob locally
-    pub async fn store_local(&self, blob: &Blob) -> Result<()> {
-        let hash = blob.hash();
-        let blob_path = self.get_blob_path(hash);
+    /// Compression applied by `store_local`
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Split large blobs into content-defined chunks (off when `None`)
+    pub fn with_chunking(mut self, chunking: Option<ChunkingConfig>) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// The store blobs are kept in
+    pub fn store(&self) -> Arc<dyn ObjectStore> {
+        self.store.clone()
+    }
 
-        // Create directory structure (first 2 chars of hash)
-        if let Some(parent) = blob_path.parent() {
-            fs::create_dir_all(parent).await?;
+    /// Store blob locally, chunked or compressed as configured
+    pub async fn store_local(&self, blob: &Blob) -> Result<()> {
+        let plain = blob.metadata.compression.is_none() && !blob.is_chunked();
+        if let Some(chunking) = self
+            .chunking
+            .filter(|c| plain && c.applies_to(blob.metadata.size))
+        {
+            chunking::put_chunked(self.store.as_ref(), blob, &chunking).await?;
+            return Ok(());
         }
 
-        let binary = blob.to_binary()?;
-        fs::write(&blob_path, binary).await?;
+        if !plain || self.compression == Compression::None {
+            self.store.put_blob(blob).await?;
+            return Ok(());
+        }
 
+        let mut compressed = Blob {
+            metadata: blob.metadata.clone(),
+            content: blob.content.clone(),
+        };
+        compressed.compress_with(self.compression)?;
+        self.store.put_blob(&compressed).await?;
         Ok(())
     }
 
-    /// Load blob from local cache
+    /// Load blob from local cache, decompressed and reassembled
     pub async fn load_local(&self, hash: &str) -> Result<Blob> {
-        let blob_path = self.get_blob_path(hash);
-        let binary = fs::read(&blob_path)
-            .await
+        let mut blob = self
+            .store
+            .get_blob(hash)
+            .await?
             .context("Blob not found in cache")?;
-
-        Blob::from_binary(&binary)
+        chunking::resolve(self.store.as_ref(), &mut blob).await?;
+        Ok(blob)
     }
 
     /// Check if blob exists locally
     pub async fn exists_local(&self, hash: &str) -> bool {
-        self.get_blob_path(hash).exists()
+        self.store.has_blob(hash).await.unwrap_or(false)
     }
 
-    /// Get blob storage path (content-addressable)
-    fn get_blob_path(&self, hash: &str) -> PathBuf {
-        // Store blobs like Git: .dx/forge/blobs/ab/cdef1234...
-        let prefix = &hash[..2];
-        let suffix = &hash[2..];
-        self.cache_dir.join(prefix).join(suffix)
+    /// Store a file as a blob, streaming it rather than reading it whole.
+    /// Large files are stored uncompressed, or chunked if enabled.
+    pub async fn store_file(&self, path: &Path) -> Result<BlobMetadata> {
+        let metadata = Blob::metadata_for_file(path).await?;
+        if let Some(chunking) = self.chunking.filter(|c| c.applies_to(metadata.size)) {
+            let upload =
+                chunking::put_chunked_file(self.store.as_ref(), path, metadata, &chunking).await?;
+            return Ok(upload.manifest.metadata);
+        }
+        let header = metadata.header_bytes()?;
+        let size = header.len() as u64 + metadata.size;
+
+        let file = fs::File::open(path).await?;
+        let reader: ObjectReader = Box::new(std::io::Cursor::new(header).chain(file));
+        self.store
+            .put_reader(&blob_key(&metadata.hash), reader, size)
+            .await?;
+        Ok(metadata)
+    }
+
+    /// Stream a blob from this repository to `remote`. For chunked blobs
+    /// only the chunks `remote` lacks are sent.
+    pub async fn upload_to(&self, remote: &dyn ObjectStore, hash: &str) -> Result<()> {
+        let key = blob_key(hash);
+        let metadata = chunking::read_metadata(self.store.as_ref(), &key)
+            .await?
+            .with_context(|| format!("Blob {} not found in cache", hash))?;
+        if let Some(chunks) = &metadata.chunks {
+            chunking::copy_missing_chunks(self.store.as_ref(), remote, chunks).await?;
+            let manifest = self.store.get(&key).await?.context("Blob vanished")?;
+            return remote.put(&key, manifest).await;
+        }
+
+        let (reader, size) = self
+            .store
+            .get_reader(&key)
+            .await?
+            .with_context(|| format!("Blob {} not found in cache", hash))?;
+        remote.put_reader(&key, reader, size).await
+    }
+
+    /// Download a blob from `remote` into this repository. The blob is
+    /// streamed to a staging file (which an interrupted download resumes)
+    /// and verified against its hash before it is stored. For chunked
+    /// blobs only the chunks missing locally are fetched.
+    pub async fn download_from(
+        &self,
+        remote: &dyn ObjectStore,
+        hash: &str,
+    ) -> Result<BlobMetadata> {
+        let key = blob_key(hash);
+        let remote_metadata = chunking::read_metadata(remote, &key)
+            .await?
+            .with_context(|| format!("Blob {} not found in {}", hash, remote.name()))?;
+        if let Some(chunks) = &remote_metadata.chunks {
+            chunking::copy_missing_chunks(remote, self.store.as_ref(), chunks).await?;
+            chunking::verify_chunks(self.store.as_ref(), &remote_metadata).await?;
+            if remote_metadata.hash != hash {
+                anyhow::bail!(
+                    "Blob {} failed verification (manifest is for {})",
+                    hash,
+                    remote_metadata.hash
+                );
+            }
+            let manifest = remote.get(&key).await?.context("Blob vanished")?;
+            self.store.put(&key, manifest).await?;
+            return Ok(remote_metadata);
+        }
+
+        let staging_dir = std::env::temp_dir().join("dx-forge-downloads");
+        fs::create_dir_all(&staging_dir).await?;
+        let staging: PathBuf = staging_dir.join(hash);
+
+        remote
+            .get_to_file(&blob_key(hash), &staging)
+            .await?
+            .with_context(|| format!("Blob {} not found in {}", hash, remote.name()))?;
+
+        let metadata = match verify_encoded_file(&staging, hash).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                let _ = fs::remove_file(&staging).await;
+                return Err(e);
+            }
+        };
+
+        self.store.put_file(&blob_key(hash), &staging).await?;
+        let _ = fs::remove_file(&staging).await;
+        Ok(metadata)
     }
 }
 
@@ -295,4 +608,127 @@ mod tests {
         assert_eq!(blob.content, content);
         assert_eq!(blob.metadata.compression, None);
     }
+
+    #[tokio::test]
+    async fn repository_compresses_on_write() {
+        use crate::storage::object_store::MemoryObjectStore;
+
+        let store = Arc::new(MemoryObjectStore::new());
+        let repo = BlobRepository::with_store(store.clone());
+        let content = b"fn main() {}\n".repeat(200);
+        let blob = Blob::from_content("main.rs", content.clone());
+        repo.store_local(&blob).await.unwrap();
+
+        let stored = store.get_blob(blob.hash()).await.unwrap().unwrap();
+        assert_eq!(stored.metadata.compression.as_deref(), Some("lz4"));
+        assert!(stored.content.len() < content.len());
+        assert_eq!(repo.load_local(blob.hash()).await.unwrap().content, content);
+
+        let plain = BlobRepository::with_store(store.clone()).with_compression(Compression::None);
+        let other = Blob::from_content("other.rs", b"x".repeat(500));
+        plain.store_local(&other).await.unwrap();
+        let stored = store.get_blob(other.hash()).await.unwrap().unwrap();
+        assert_eq!(stored.metadata.compression, None);
+
+        assert_eq!("ZSTD".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("brotli".parse::<Compression>().is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let content = b"Hello, zstd! ".repeat(1000);
+        let mut blob = Blob::from_content("test.txt", content.clone());
+        blob.compress_with(Compression::Zstd).unwrap();
+        assert_eq!(blob.metadata.compression.as_deref(), Some("zstd"));
+
+        let mut restored = Blob::from_binary(&blob.to_binary().unwrap()).unwrap();
+        restored.decompress().unwrap();
+        assert_eq!(restored.content, content);
+    }
+
+    #[tokio::test]
+    async fn streams_files_between_stores() {
+        use crate::storage::object_store::MemoryObjectStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("bundle.bin");
+        let content: Vec<u8> = (0..STREAM_BUFFER_SIZE * 3 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&source, &content).unwrap();
+
+        let local = BlobRepository::new(&dir.path().join("forge")).unwrap();
+        let metadata = local.store_file(&source).await.unwrap();
+        assert_eq!(metadata.hash, compute_hash(&content));
+        assert_eq!(
+            local.load_local(&metadata.hash).await.unwrap().content,
+            content
+        );
+
+        let remote = MemoryObjectStore::new();
+        local.upload_to(&remote, &metadata.hash).await.unwrap();
+
+        let other = BlobRepository::with_store(Arc::new(MemoryObjectStore::new()));
+        other.download_from(&remote, &metadata.hash).await.unwrap();
+        assert_eq!(
+            other.load_local(&metadata.hash).await.unwrap().content,
+            content
+        );
+
+        // Corrupted remote copies are rejected
+        let mut corrupted = Blob::from_content("x", content.clone());
+        corrupted.content[0] ^= 1;
+        remote
+            .put(&blob_key(&metadata.hash), corrupted.to_binary().unwrap())
+            .await
+            .unwrap();
+        let fresh = BlobRepository::with_store(Arc::new(MemoryObjectStore::new()));
+        assert!(fresh.download_from(&remote, &metadata.hash).await.is_err());
+        assert!(!fresh.exists_local(&metadata.hash).await);
+    }
+
+    #[tokio::test]
+    async fn chunked_blobs_transfer_only_missing_chunks() {
+        use crate::storage::object_store::MemoryObjectStore;
+
+        let chunking = ChunkingConfig {
+            threshold: 1024,
+            min_size: 256,
+            avg_size: 1024,
+            max_size: 4096,
+        };
+        let content: Vec<u8> = (0..64 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let local = BlobRepository::with_store(Arc::new(MemoryObjectStore::new()))
+            .with_chunking(Some(chunking));
+        let blob = Blob::from_content("bundle.js", content.clone());
+        local.store_local(&blob).await.unwrap();
+        assert_eq!(
+            local.load_local(blob.hash()).await.unwrap().content,
+            content
+        );
+
+        let remote = MemoryObjectStore::new();
+        local.upload_to(&remote, blob.hash()).await.unwrap();
+        let chunks = remote.list_all(chunking::CHUNK_PREFIX).await.unwrap().len();
+        assert!(chunks > 1);
+
+        // A one-byte edit uploads the new manifest and a chunk or two
+        let mut edited = content.clone();
+        edited[30_000] ^= 1;
+        let edited = Blob::from_content("bundle.js", edited);
+        local.store_local(&edited).await.unwrap();
+        local.upload_to(&remote, edited.hash()).await.unwrap();
+        let added = remote.list_all(chunking::CHUNK_PREFIX).await.unwrap().len() - chunks;
+        assert!((1..=2).contains(&added), "added {} chunks", added);
+
+        let other = BlobRepository::with_store(Arc::new(MemoryObjectStore::new()));
+        other.download_from(&remote, edited.hash()).await.unwrap();
+        assert_eq!(
+            other.load_local(edited.hash()).await.unwrap().content,
+            edited.content
+        );
+    }
 }
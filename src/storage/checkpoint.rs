@@ -0,0 +1,352 @@
+//! Per-file content checkpoints and oplog compaction.
+//!
+//! A checkpoint stores the full content of a file as a blob together with the
+//! last operation it reflects, so reconstructing a file only replays the
+//! operations recorded after the nearest checkpoint. Compaction folds
+//! operations older than a retention window into a checkpoint and removes
+//! them from the `operations` table.
+//!
+//! Operations synced late can carry timestamps older than a checkpoint, so a
+//! checkpoint covers operations up to its timestamp *and* up to the arrival
+//! sequence number it was built from (`covers_seq`); anything that arrived
+//! afterwards is replayed on top of it whatever its timestamp.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::op_query::{OpOrder, OpQuery};
+use super::oplog::{coalesce_settled, SETTLE_WINDOW};
+use super::{Blob, BlobRepository, Database};
+use crate::crdt::transform::causal_order;
+use crate::crdt::{Operation, Transformer};
+
+/// Number of replayed operations after which a new checkpoint is written.
+pub const CHECKPOINT_INTERVAL: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: Uuid,
+    pub file_path: String,
+    /// Last operation reflected in the stored content
+    pub op_id: Uuid,
+    /// Timestamp of `op_id`; every operation up to it is included
+    pub timestamp: DateTime<Utc>,
+    pub blob_hash: String,
+    pub created_at: DateTime<Utc>,
+    /// Highest operation arrival sequence number reflected
+    #[serde(default)]
+    pub covers_seq: i64,
+}
+
+/// File content reconstructed at a point in time.
+#[derive(Debug, Clone)]
+pub struct Reconstruction {
+    pub content: String,
+    /// Checkpoint the replay started from, if any
+    pub checkpoint: Option<Checkpoint>,
+    /// Operations replayed on top of the checkpoint
+    pub replayed: usize,
+}
+
+/// Outcome of [`compact`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompactionReport {
+    pub files_checkpointed: usize,
+    pub operations_removed: usize,
+    /// Rows merged into neighbouring operations of the same keystroke run
+    pub operations_coalesced: usize,
+    pub dry_run: bool,
+}
+
+/// Reconstruct `file_path` as of `at`, starting from the nearest checkpoint.
+///
+/// When more than [`CHECKPOINT_INTERVAL`] operations had to be replayed a new
+/// checkpoint is written so the next reconstruction starts closer.
+pub async fn reconstruct(
+    db: &Database,
+    blobs: &BlobRepository,
+    file_path: &str,
+    at: DateTime<Utc>,
+) -> Result<Reconstruction> {
+    let (state, covered) = replay_from_checkpoint(db, blobs, file_path, at).await?;
+
+    if state.replayed >= CHECKPOINT_INTERVAL {
+        if let Some(covered) = covered {
+            write_checkpoint(db, blobs, file_path, &covered, &state.content).await?;
+        }
+    }
+
+    Ok(state)
+}
+
+/// Fold operations older than `retention` into checkpoints and delete them,
+/// then coalesce keystroke runs among the remaining settled operations.
+/// With `dry_run` nothing is written; the report shows what would change
+/// (coalescing is not simulated).
+pub async fn compact(
+    db: &Database,
+    blobs: &BlobRepository,
+    retention: Duration,
+    dry_run: bool,
+) -> Result<CompactionReport> {
+    let cutoff = Utc::now() - retention;
+    let mut report = CompactionReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for file_path in db.operation_files()? {
+        let latest = db.latest_checkpoint(&file_path, cutoff)?;
+        let seq = db.max_operation_seq()?;
+        let expired = file_operations(db, &file_path, latest.as_ref(), cutoff, seq)?;
+        let Some(last) = expired.iter().max_by_key(|op| op.timestamp) else {
+            continue;
+        };
+
+        report.files_checkpointed += 1;
+        if dry_run {
+            report.operations_removed += expired.len();
+            continue;
+        }
+
+        // Late operations may predate the checkpoint they are folded into
+        let through = match &latest {
+            Some(cp) if cp.timestamp > last.timestamp => cp.timestamp,
+            _ => last.timestamp,
+        };
+        let (state, covered) = replay_from_checkpoint(db, blobs, &file_path, through).await?;
+        let Some(covered) = covered else {
+            continue;
+        };
+        let checkpoint = write_checkpoint(db, blobs, &file_path, &covered, &state.content).await?;
+        report.operations_removed += db.delete_covered_operations(&checkpoint)?;
+        db.delete_stale_checkpoints(&checkpoint)?;
+    }
+
+    if !dry_run {
+        report.operations_coalesced = coalesce_settled(db, Utc::now() - SETTLE_WINDOW)?;
+    }
+
+    Ok(report)
+}
+
+/// What a reconstruction reflects: the newest operation (or the base
+/// checkpoint's, when that is newer) and the arrivals it covers.
+struct Covered {
+    op_id: Uuid,
+    timestamp: DateTime<Utc>,
+    seq: i64,
+}
+
+/// Replay from the nearest checkpoint, also returning what the result
+/// covers when anything was replayed.
+async fn replay_from_checkpoint(
+    db: &Database,
+    blobs: &BlobRepository,
+    file_path: &str,
+    at: DateTime<Utc>,
+) -> Result<(Reconstruction, Option<Covered>)> {
+    let checkpoint = db.latest_checkpoint(file_path, at)?;
+    let base = match &checkpoint {
+        Some(cp) => load_content(blobs, cp).await?,
+        None => String::new(),
+    };
+
+    // Read the sequence number first so operations arriving during the
+    // replay are left for the next one.
+    let seq = db.max_operation_seq()?;
+    let operations = file_operations(db, file_path, checkpoint.as_ref(), at, seq)?;
+    let replayed = operations.len();
+    let covered = operations
+        .iter()
+        .max_by_key(|op| op.timestamp)
+        .map(|op| match &checkpoint {
+            Some(cp) if cp.timestamp > op.timestamp => Covered {
+                op_id: cp.op_id,
+                timestamp: cp.timestamp,
+                seq,
+            },
+            _ => Covered {
+                op_id: op.id,
+                timestamp: op.timestamp,
+                seq,
+            },
+        });
+
+    let mut transformer = Transformer::new(&base);
+    for op in causal_order(operations) {
+        transformer.rebase(&op);
+    }
+
+    let state = Reconstruction {
+        content: transformer.text(),
+        checkpoint,
+        replayed,
+    };
+    Ok((state, covered))
+}
+
+/// Operations on `file_path` that `checkpoint` (if given) doesn't cover, no
+/// newer than `until` and arrived by `seq`, oldest first.
+fn file_operations(
+    db: &Database,
+    file_path: &str,
+    checkpoint: Option<&Checkpoint>,
+    until: DateTime<Utc>,
+    seq: i64,
+) -> Result<Vec<Operation>> {
+    let mut query = OpQuery::new()
+        .file(file_path)
+        .until(until)
+        .received_through(seq)
+        .order(OpOrder::OldestFirst)
+        .unlimited();
+    if let Some(cp) = checkpoint {
+        query = query.beyond(cp.timestamp, cp.covers_seq);
+    }
+
+    Ok(db.query_operations(&query)?.operations)
+}
+
+async fn write_checkpoint(
+    db: &Database,
+    blobs: &BlobRepository,
+    file_path: &str,
+    covered: &Covered,
+    content: &str,
+) -> Result<Checkpoint> {
+    let blob = Blob::from_content(file_path, content.as_bytes().to_vec());
+    blobs.store_local(&blob).await?;
+
+    let checkpoint = Checkpoint {
+        id: Uuid::new_v4(),
+        file_path: file_path.to_string(),
+        op_id: covered.op_id,
+        timestamp: covered.timestamp,
+        blob_hash: blob.hash().to_string(),
+        created_at: Utc::now(),
+        covers_seq: covered.seq,
+    };
+    db.store_checkpoint(&checkpoint)?;
+
+    Ok(checkpoint)
+}
+
+async fn load_content(blobs: &BlobRepository, checkpoint: &Checkpoint) -> Result<String> {
+    let blob = blobs
+        .load_local(&checkpoint.blob_hash)
+        .await
+        .with_context(|| format!("checkpoint {} is missing its blob", checkpoint.id))?;
+
+    Ok(String::from_utf8_lossy(&blob.content).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{OperationType, Position};
+
+    fn insert(offset: usize, text: &str, age: Duration, parent: Option<Uuid>) -> Operation {
+        let mut op = Operation::new(
+            "/repo/notes.txt".into(),
+            OperationType::Insert {
+                position: Position::new(1, offset + 1, offset, "actor".into(), 0),
+                content: text.into(),
+                length: text.chars().count(),
+            },
+            "actor".into(),
+        );
+        op.timestamp = Utc::now() - age;
+        op.with_parents(parent.into_iter().collect())
+    }
+
+    #[tokio::test]
+    async fn compaction_preserves_content_and_history_after_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+        let blobs = BlobRepository::new(dir.path()).unwrap();
+
+        let old = insert(0, "hello", Duration::days(10), None);
+        let older_mid = insert(5, " world", Duration::days(9), Some(old.id));
+        let recent = insert(11, "!", Duration::hours(1), Some(older_mid.id));
+        for op in [&old, &older_mid, &recent] {
+            db.store_operation(op).unwrap();
+        }
+
+        let report = compact(&db, &blobs, Duration::days(7), false)
+            .await
+            .unwrap();
+        assert_eq!(report.files_checkpointed, 1);
+        assert_eq!(report.operations_removed, 2);
+
+        let now = reconstruct(&db, &blobs, "/repo/notes.txt", Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(now.content, "hello world!");
+        assert_eq!(now.replayed, 1);
+        assert_eq!(now.checkpoint.map(|cp| cp.op_id), Some(older_mid.id));
+
+        let before = reconstruct(
+            &db,
+            &blobs,
+            "/repo/notes.txt",
+            Utc::now() - Duration::days(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(before.content, "hello world");
+    }
+
+    #[tokio::test]
+    async fn dry_run_leaves_operations_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+        let blobs = BlobRepository::new(dir.path()).unwrap();
+
+        db.store_operation(&insert(0, "x", Duration::days(30), None))
+            .unwrap();
+
+        let report = compact(&db, &blobs, Duration::days(7), true).await.unwrap();
+        assert_eq!(report.operations_removed, 1);
+        assert_eq!(db.get_operations(None, 10).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn late_operations_older_than_a_checkpoint_are_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+        let blobs = BlobRepository::new(dir.path()).unwrap();
+
+        let old = insert(0, "hello", Duration::days(10), None);
+        let older_mid = insert(5, " world", Duration::days(9), Some(old.id));
+        for op in [&old, &older_mid] {
+            db.store_operation(op).unwrap();
+        }
+        compact(&db, &blobs, Duration::days(7), false).await.unwrap();
+
+        // A peer syncs an edit made before the checkpoint was taken
+        let late = insert(11, "!", Duration::days(12), None);
+        db.store_operation(&late).unwrap();
+
+        let now = reconstruct(&db, &blobs, "/repo/notes.txt", Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(now.content, "hello world!");
+        assert_eq!(now.replayed, 1);
+
+        // Compaction folds it into a checkpoint at least as new as the old one
+        let report = compact(&db, &blobs, Duration::days(7), false).await.unwrap();
+        assert_eq!(report.operations_removed, 1);
+        let now = reconstruct(&db, &blobs, "/repo/notes.txt", Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(now.content, "hello world!");
+        assert_eq!(now.replayed, 0);
+        assert_eq!(now.checkpoint.map(|cp| cp.op_id), Some(older_mid.id));
+    }
+}
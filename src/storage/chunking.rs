@@ -0,0 +1,418 @@
+/// Content-Defined Chunking
+///
+/// Blobs above a size threshold can be split with FastCDC into chunks of a
+/// few MiB whose boundaries depend on the content, so an edit only changes
+/// the chunks around it. Chunks are stored by hash under `chunks/ab/...`;
+/// the blob itself becomes a manifest, an empty blob whose metadata lists
+/// the chunks. Uploads and downloads then skip every chunk the other side
+/// already has.
+use anyhow::{Context, Result};
+use fastcdc::v2020::{FastCDC, StreamCDC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use super::blob::{Blob, BlobMetadata};
+use super::object_store::ObjectStore;
+
+/// Prefix all chunks are stored under
+pub const CHUNK_PREFIX: &str = "chunks/";
+
+/// One chunk of a chunked blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// SHA-256 of the chunk's bytes
+    pub hash: String,
+    pub size: u64,
+}
+
+/// When and how blobs are chunked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkingConfig {
+    /// Blobs at least this large are chunked
+    pub threshold: u64,
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 8 * 1024 * 1024,
+            min_size: 256 * 1024,
+            avg_size: 1024 * 1024,
+            max_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl ChunkingConfig {
+    /// The `chunking` setting from `.dx/forge/config.json`: `true` for the
+    /// defaults or an object overriding some of them. Off when absent.
+    pub fn from_config(forge_dir: &Path) -> Result<Option<Self>> {
+        let Ok(raw) = std::fs::read(forge_dir.join("config.json")) else {
+            return Ok(None);
+        };
+        let config: serde_json::Value = serde_json::from_slice(&raw)?;
+        match config.get("chunking") {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => Ok(None),
+            Some(serde_json::Value::Bool(true)) => Ok(Some(Self::default())),
+            Some(value) => Ok(Some(
+                serde_json::from_value(value.clone()).context("Invalid chunking config")?,
+            )),
+        }
+    }
+
+    pub fn applies_to(&self, size: u64) -> bool {
+        size >= self.threshold
+    }
+
+    /// Sizes clamped to the limits FastCDC accepts
+    fn sizes(&self) -> (u32, u32, u32) {
+        use fastcdc::v2020::{
+            AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+        };
+        (
+            self.min_size.clamp(MINIMUM_MIN, MINIMUM_MAX),
+            self.avg_size.clamp(AVERAGE_MIN, AVERAGE_MAX),
+            self.max_size.clamp(MAXIMUM_MIN, MAXIMUM_MAX),
+        )
+    }
+}
+
+/// Key a chunk is stored under
+pub fn chunk_key(hash: &str) -> String {
+    if hash.len() > 2 {
+        format!("{}{}/{}", CHUNK_PREFIX, &hash[..2], &hash[2..])
+    } else {
+        format!("{}{}", CHUNK_PREFIX, hash)
+    }
+}
+
+/// Inverse of `chunk_key`
+pub fn chunk_hash_from_key(key: &str) -> Option<String> {
+    let (prefix, suffix) = key.strip_prefix(CHUNK_PREFIX)?.split_once('/')?;
+    (prefix.len() == 2 && !suffix.is_empty()).then(|| format!("{}{}", prefix, suffix))
+}
+
+/// Split `data` at content-defined boundaries
+pub fn split<'a>(data: &'a [u8], config: &ChunkingConfig) -> Vec<&'a [u8]> {
+    let (min, avg, max) = config.sizes();
+    FastCDC::new(data, min, avg, max)
+        .map(|chunk| &data[chunk.offset..chunk.offset + chunk.length])
+        .collect()
+}
+
+/// What a chunked upload transferred
+#[derive(Debug)]
+pub struct ChunkedUpload {
+    pub manifest: Blob,
+    pub chunks_uploaded: usize,
+    pub chunks_reused: usize,
+    pub bytes_uploaded: u64,
+}
+
+impl ChunkedUpload {
+    fn new(metadata: BlobMetadata) -> Self {
+        Self {
+            manifest: Blob {
+                metadata,
+                content: Vec::new(),
+            },
+            chunks_uploaded: 0,
+            chunks_reused: 0,
+            bytes_uploaded: 0,
+        }
+    }
+
+    /// Store one chunk unless `store` already has it
+    async fn add(&mut self, store: &dyn ObjectStore, data: Vec<u8>) -> Result<()> {
+        let hash = format!("{:x}", Sha256::digest(&data));
+        let size = data.len() as u64;
+        let key = chunk_key(&hash);
+
+        if store.head(&key).await?.is_some() {
+            self.chunks_reused += 1;
+        } else {
+            store.put(&key, data).await?;
+            self.chunks_uploaded += 1;
+            self.bytes_uploaded += size;
+        }
+
+        self.manifest
+            .metadata
+            .chunks
+            .get_or_insert_with(Vec::new)
+            .push(ChunkRef { hash, size });
+        Ok(())
+    }
+
+    async fn finish(mut self, store: &dyn ObjectStore) -> Result<Self> {
+        self.manifest.metadata.chunks.get_or_insert_with(Vec::new);
+        self.manifest.metadata.compression = None;
+        self.manifest.metadata.original_size = None;
+        store.put_blob(&self.manifest).await?;
+        Ok(self)
+    }
+}
+
+/// Store `blob` (uncompressed) as chunks plus a manifest under its hash.
+pub async fn put_chunked(
+    store: &dyn ObjectStore,
+    blob: &Blob,
+    config: &ChunkingConfig,
+) -> Result<ChunkedUpload> {
+    let mut upload = ChunkedUpload::new(blob.metadata.clone());
+    for chunk in split(&blob.content, config) {
+        upload.add(store, chunk.to_vec()).await?;
+    }
+    upload.finish(store).await
+}
+
+/// Like `put_chunked` for a file on disk, holding only a few chunks in
+/// memory at a time. `metadata` describes the whole file.
+pub async fn put_chunked_file(
+    store: &dyn ObjectStore,
+    path: &Path,
+    metadata: BlobMetadata,
+    config: &ChunkingConfig,
+) -> Result<ChunkedUpload> {
+    let (min, avg, max) = config.sizes();
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    // FastCDC reads synchronously; hand chunks over from a blocking thread
+    let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+    let reader = tokio::task::spawn_blocking(move || {
+        for chunk in StreamCDC::new(file, min, avg, max) {
+            let chunk = chunk.map(|chunk| chunk.data).map_err(anyhow::Error::from);
+            let failed = chunk.is_err();
+            if tx.blocking_send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut upload = ChunkedUpload::new(metadata);
+    while let Some(chunk) = rx.recv().await {
+        upload.add(store, chunk?).await?;
+    }
+    reader.await?;
+
+    let stored: u64 = upload
+        .manifest
+        .metadata
+        .chunks
+        .iter()
+        .flatten()
+        .map(|chunk| chunk.size)
+        .sum();
+    if stored != upload.manifest.metadata.size {
+        anyhow::bail!("{} changed while it was being chunked", path.display());
+    }
+    upload.finish(store).await
+}
+
+/// Fetch a chunk, checking it against its hash
+async fn get_chunk(store: &dyn ObjectStore, chunk: &ChunkRef) -> Result<Vec<u8>> {
+    let data = store
+        .get(&chunk_key(&chunk.hash))
+        .await?
+        .with_context(|| format!("Missing chunk {}", chunk.hash))?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if actual != chunk.hash {
+        anyhow::bail!("Chunk {} is corrupt (hashes to {})", chunk.hash, actual);
+    }
+    Ok(data)
+}
+
+/// Rebuild the content of a chunked blob from the chunks in `store`.
+pub async fn reassemble(store: &dyn ObjectStore, manifest: &Blob) -> Result<Vec<u8>> {
+    let chunks = manifest.metadata.chunks.as_deref().unwrap_or_default();
+    let mut content = Vec::with_capacity(manifest.metadata.size as usize);
+    for chunk in chunks {
+        content.extend_from_slice(&get_chunk(store, chunk).await?);
+    }
+
+    let actual = format!("{:x}", Sha256::digest(&content));
+    if actual != manifest.metadata.hash {
+        anyhow::bail!(
+            "Reassembled blob {} hashes to {}",
+            manifest.metadata.hash,
+            actual
+        );
+    }
+    Ok(content)
+}
+
+/// Turn a blob as stored into its plain content: reassemble it if it is a
+/// manifest, then decompress.
+pub async fn resolve(store: &dyn ObjectStore, blob: &mut Blob) -> Result<()> {
+    if blob.is_chunked() {
+        blob.content = reassemble(store, blob).await?;
+        blob.metadata.chunks = None;
+    }
+    blob.decompress()
+}
+
+/// Check that every chunk of a manifest is present and the whole hashes to
+/// the blob's hash, without holding the content in memory.
+pub async fn verify_chunks(store: &dyn ObjectStore, metadata: &BlobMetadata) -> Result<()> {
+    let mut hasher = Sha256::new();
+    for chunk in metadata.chunks.as_deref().unwrap_or_default() {
+        hasher.update(get_chunk(store, chunk).await?);
+    }
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != metadata.hash {
+        anyhow::bail!("Chunks of {} hash to {}", metadata.hash, actual);
+    }
+    Ok(())
+}
+
+/// Copy the chunks `target` lacks from `source`; returns how many chunks
+/// and bytes were transferred.
+pub async fn copy_missing_chunks(
+    source: &dyn ObjectStore,
+    target: &dyn ObjectStore,
+    chunks: &[ChunkRef],
+) -> Result<(usize, u64)> {
+    let mut copied = 0;
+    let mut bytes = 0;
+    for chunk in chunks {
+        let key = chunk_key(&chunk.hash);
+        if target.head(&key).await?.is_some() {
+            continue;
+        }
+        let data = get_chunk(source, chunk).await?;
+        bytes += data.len() as u64;
+        target.put(&key, data).await?;
+        copied += 1;
+    }
+    Ok((copied, bytes))
+}
+
+/// Read only the metadata header of the blob stored under `key`.
+pub async fn read_metadata(store: &dyn ObjectStore, key: &str) -> Result<Option<BlobMetadata>> {
+    let Some(len) = store.get_range(key, 0, Some(4)).await? else {
+        return Ok(None);
+    };
+    let len: [u8; 4] = len
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid blob: too short"))?;
+    let len = u32::from_le_bytes(len) as u64;
+    let json = store
+        .get_range(key, 4, Some(4 + len))
+        .await?
+        .context("Blob vanished while reading it")?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryObjectStore;
+
+    fn config() -> ChunkingConfig {
+        ChunkingConfig {
+            threshold: 0,
+            min_size: 1024,
+            avg_size: 4096,
+            max_size: 16384,
+        }
+    }
+
+    /// Deterministic pseudo-random bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn small_edits_reupload_few_chunks() {
+        let store = MemoryObjectStore::new();
+        let original = noise(200_000, 7);
+        let first = put_chunked(
+            &store,
+            &Blob::from_content("bundle.js", original.clone()),
+            &config(),
+        )
+        .await
+        .unwrap();
+        assert!(first.chunks_uploaded > 10);
+        assert_eq!(first.chunks_reused, 0);
+
+        let mut edited = original.clone();
+        edited[100_000] ^= 1;
+        let second = put_chunked(
+            &store,
+            &Blob::from_content("bundle.js", edited.clone()),
+            &config(),
+        )
+        .await
+        .unwrap();
+        assert!(
+            second.chunks_uploaded <= 2,
+            "uploaded {}",
+            second.chunks_uploaded
+        );
+        assert!(second.bytes_uploaded < 40_000);
+
+        let manifest = store
+            .get_blob(&second.manifest.metadata.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(manifest.content.is_empty());
+        assert_eq!(reassemble(&store, &manifest).await.unwrap(), edited);
+        verify_chunks(&store, &manifest.metadata).await.unwrap();
+
+        let header = read_metadata(
+            &store,
+            &crate::storage::object_store::blob_key(manifest.hash()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(header.chunks, manifest.metadata.chunks);
+    }
+
+    #[tokio::test]
+    async fn chunks_files_as_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("media.bin");
+        let content = noise(100_000, 3);
+        std::fs::write(&path, &content).unwrap();
+
+        let store = MemoryObjectStore::new();
+        let metadata = Blob::metadata_for_file(&path).await.unwrap();
+        let upload = put_chunked_file(&store, &path, metadata, &config())
+            .await
+            .unwrap();
+
+        // Same boundaries as chunking the bytes in memory
+        let in_memory: Vec<u64> = split(&content, &config())
+            .iter()
+            .map(|c| c.len() as u64)
+            .collect();
+        let streamed: Vec<u64> = upload
+            .manifest
+            .metadata
+            .chunks
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|c| c.size)
+            .collect();
+        assert_eq!(streamed, in_memory);
+        assert_eq!(reassemble(&store, &upload.manifest).await.unwrap(), content);
+    }
+}
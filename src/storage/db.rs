@@ -1,10 +1,21 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use parking_lot::Mutex;
-use rusqlite::{params, Connection};
+use rusqlite::types::Type;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
 use std::path::Path;
 use std::sync::Arc;
+use uuid::Uuid;
 
-use crate::crdt::{Anchor, Operation};
+use crate::crdt::{Anchor, Operation, Position};
+use crate::storage::checkpoint::Checkpoint;
+use crate::storage::op_query::{OpKind, OpPage, OpQuery};
+use crate::version::{ReflogEntry, SnapshotId};
+
+/// Next arrival sequence number. Checkpoints remember the highest number
+/// they cover, so it never goes back even after covered rows are deleted.
+const NEXT_SEQ: &str = "(SELECT MAX(COALESCE((SELECT MAX(seq) FROM operations), 0),
+                                  COALESCE((SELECT MAX(covers_seq) FROM checkpoints), 0)) + 1)";
 
 pub struct Database {
     pub conn: Arc<Mutex<Connection>>,
@@ -68,12 +79,63 @@ impl Database {
             [],
         )?;
 
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS checkpoints (
+                id TEXT PRIMARY KEY,
+                file_path TEXT NOT NULL,
+                op_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                blob_hash TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                covers_seq INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS git_commits (
+                snapshot_id TEXT PRIMARY KEY,
+                commit_oid TEXT NOT NULL UNIQUE,
+                origin TEXT NOT NULL,
+                mapped_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS reflog (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                ref_name TEXT NOT NULL,
+                old_head TEXT,
+                new_head TEXT,
+                reason TEXT NOT NULL,
+                actor TEXT NOT NULL,
+                timestamp TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_checkpoints_file_time
+             ON checkpoints(file_path, timestamp)",
+            [],
+        )?;
+
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_ops_file_time
              ON operations(file_path, timestamp)",
             [],
         )?;
 
+        // Arrival order of operations, so checkpoints can tell which ones
+        // they cover regardless of timestamps
+        add_column(&conn, "operations", "seq", "INTEGER")?;
+        add_column(&conn, "checkpoints", "covers_seq", "INTEGER NOT NULL DEFAULT 0")?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_ops_seq ON operations(seq)",
+            [],
+        )?;
+
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_anchors_file
              ON anchors(file_path)",
@@ -95,14 +157,16 @@ impl Database {
         let parent_ops = serde_json::to_string(&op.parent_ops)?;
 
         conn.execute(
-            "INSERT OR IGNORE INTO operations (id, timestamp, actor_id, file_path, op_type, op_data, parent_ops)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &format!(
+                "INSERT OR IGNORE INTO operations (id, timestamp, actor_id, file_path, op_type, op_data, parent_ops, seq)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, {NEXT_SEQ})"
+            ),
             params![
                 op.id.to_string(),
                 op.timestamp.to_rfc3339(),
                 op.actor_id,
                 op.file_path,
-                format!("{:?}", op.op_type).split('{').next().unwrap(),
+                OpKind::of(&op.op_type).as_str(),
                 op_data,
                 parent_ops,
             ],
@@ -111,54 +175,357 @@ impl Database {
         .map_err(Into::into)
     }
 
+    /// Every operation id with the parents it names.
+    pub fn operation_parents(&self) -> Result<Vec<(Uuid, Vec<Uuid>)>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT id, parent_ops FROM operations")?;
+        let mut rows = stmt.query([])?;
+
+        let mut parents = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let parent_ops: String = row.get(1)?;
+            let id = Uuid::parse_str(&id)
+                .with_context(|| format!("Malformed operation id {}", id))?;
+            let parent_ops = serde_json::from_str(&parent_ops)
+                .with_context(|| format!("Malformed parents of operation {}", id))?;
+            parents.push((id, parent_ops));
+        }
+
+        Ok(parents)
+    }
+
+    /// Atomically delete `removed` and overwrite the contents and parents of
+    /// the existing operations in `rewritten`, keeping their arrival order.
+    pub fn rewrite_operations(&self, removed: &[Uuid], rewritten: &[Operation]) -> Result<()> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        for id in removed {
+            tx.execute("DELETE FROM operations WHERE id = ?1", params![id.to_string()])?;
+        }
+        for op in rewritten {
+            tx.execute(
+                "UPDATE operations SET op_type = ?2, op_data = ?3, parent_ops = ?4 WHERE id = ?1",
+                params![
+                    op.id.to_string(),
+                    OpKind::of(&op.op_type).as_str(),
+                    bincode::serialize(&op.op_type)?,
+                    serde_json::to_string(&op.parent_ops)?,
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Highest arrival sequence number handed out so far.
+    pub fn max_operation_seq(&self) -> Result<i64> {
+        let conn = self.conn.lock();
+        let seq = conn.query_row(&format!("SELECT {NEXT_SEQ} - 1"), [], |row| row.get(0))?;
+
+        Ok(seq)
+    }
+
+    /// The most recent operations, optionally only those on `file`.
     pub fn get_operations(&self, file: Option<&Path>, limit: usize) -> Result<Vec<Operation>> {
+        let mut query = OpQuery::new().limit(limit);
+        if let Some(file) = file {
+            query = query.file(file.display().to_string());
+        }
+
+        Ok(self.query_operations(&query)?.operations)
+    }
+
+    /// Run a typed query; malformed rows fail the query naming the row.
+    pub fn query_operations(&self, query: &OpQuery) -> Result<OpPage> {
+        let (sql, values) = query.to_sql();
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(values))?;
+
+        let mut decoded = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let timestamp: String = row.get(1)?;
+            let op = operation_from_row(row)
+                .with_context(|| format!("Malformed operation row {}", id))?;
+            decoded.push((timestamp, op));
+        }
+
+        Ok(OpPage::from_rows(decoded, query.page_size()))
+    }
+
+    /// Distinct file paths that have operations or checkpoints recorded.
+    pub fn operation_files(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT file_path FROM operations
+             UNION
+             SELECT file_path FROM checkpoints
+             ORDER BY file_path",
+        )?;
+        let files = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(files.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Remove the operations on `file_path` that `checkpoint` covers.
+    pub fn delete_covered_operations(&self, checkpoint: &Checkpoint) -> Result<usize> {
         let conn = self.conn.lock();
+        let removed = conn.execute(
+            "DELETE FROM operations
+             WHERE file_path = ?1 AND timestamp <= ?2 AND COALESCE(seq, 0) <= ?3",
+            params![
+                checkpoint.file_path,
+                checkpoint.timestamp.to_rfc3339(),
+                checkpoint.covers_seq
+            ],
+        )?;
+
+        Ok(removed)
+    }
 
-        let query = if let Some(f) = file {
-            format!(
-                "SELECT id, timestamp, actor_id, file_path, op_data, parent_ops
-                 FROM operations
-                 WHERE file_path = '{}'
-                 ORDER BY timestamp DESC
-                 LIMIT {}",
-                f.display(),
-                limit
+    /// Remove checkpoints of `checkpoint`'s file that are newer than it but
+    /// miss operations it covers. Returns how many were removed.
+    pub fn delete_stale_checkpoints(&self, checkpoint: &Checkpoint) -> Result<usize> {
+        let conn = self.conn.lock();
+        let removed = conn.execute(
+            "DELETE FROM checkpoints
+             WHERE file_path = ?1 AND timestamp > ?2 AND covers_seq < ?3",
+            params![
+                checkpoint.file_path,
+                checkpoint.timestamp.to_rfc3339(),
+                checkpoint.covers_seq
+            ],
+        )?;
+
+        Ok(removed)
+    }
+
+    pub fn store_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO checkpoints (id, file_path, op_id, timestamp, blob_hash, created_at, covers_seq)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                checkpoint.id.to_string(),
+                checkpoint.file_path,
+                checkpoint.op_id.to_string(),
+                checkpoint.timestamp.to_rfc3339(),
+                checkpoint.blob_hash,
+                checkpoint.created_at.to_rfc3339(),
+                checkpoint.covers_seq,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Blob hashes referenced by any checkpoint.
+    pub fn checkpoint_blob_hashes(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT DISTINCT blob_hash FROM checkpoints")?;
+        let hashes = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(hashes.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Record that a snapshot and a Git commit hold the same state.
+    /// `origin` says which side it was created on ("git" or "forge").
+    pub fn map_git_commit(&self, snapshot_id: &str, commit_oid: &str, origin: &str) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO git_commits (snapshot_id, commit_oid, origin, mapped_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![snapshot_id, commit_oid, origin, Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Git commit a snapshot was imported from or exported to.
+    pub fn git_commit_for_snapshot(&self, snapshot_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock();
+        let oid = conn
+            .query_row(
+                "SELECT commit_oid FROM git_commits WHERE snapshot_id = ?1",
+                params![snapshot_id],
+                |row| row.get(0),
             )
-        } else {
-            format!(
-                "SELECT id, timestamp, actor_id, file_path, op_data, parent_ops
-                 FROM operations
-                 ORDER BY timestamp DESC
-                 LIMIT {}",
-                limit
+            .optional()?;
+
+        Ok(oid)
+    }
+
+    /// Snapshot a Git commit was imported as or exported from.
+    pub fn snapshot_for_git_commit(&self, commit_oid: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock();
+        let id = conn
+            .query_row(
+                "SELECT snapshot_id FROM git_commits WHERE commit_oid = ?1",
+                params![commit_oid],
+                |row| row.get(0),
             )
-        };
+            .optional()?;
+
+        Ok(id)
+    }
+
+    /// Record a movement of a branch or HEAD.
+    pub fn append_reflog(&self, entry: &ReflogEntry) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT INTO reflog (ref_name, old_head, new_head, reason, actor, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.ref_name,
+                entry.old.as_ref().map(|id| id.as_str()),
+                entry.new.as_ref().map(|id| id.as_str()),
+                entry.reason,
+                entry.actor,
+                entry.timestamp.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Movements of `ref_name`, newest first.
+    pub fn reflog(&self, ref_name: &str, limit: usize) -> Result<Vec<ReflogEntry>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT ref_name, old_head, new_head, reason, actor, timestamp
+             FROM reflog
+             WHERE ref_name = ?1
+             ORDER BY seq DESC
+             LIMIT ?2",
+        )?;
+        let entries = stmt.query_map(params![ref_name, limit as i64], reflog_from_row)?;
+
+        Ok(entries.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Move the reflog of a renamed branch to its new name.
+    pub fn rename_reflog(&self, old: &str, new: &str) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "UPDATE reflog SET ref_name = ?2 WHERE ref_name = ?1",
+            params![old, new],
+        )?;
+
+        Ok(())
+    }
+
+    /// Most recent checkpoint of `file_path` that reflects no operation newer
+    /// than `at`.
+    pub fn latest_checkpoint(&self, file_path: &str, at: DateTime<Utc>) -> Result<Option<Checkpoint>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, file_path, op_id, timestamp, blob_hash, created_at, covers_seq
+             FROM checkpoints
+             WHERE file_path = ?1 AND timestamp <= ?2
+             ORDER BY timestamp DESC, covers_seq DESC
+             LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map(params![file_path, at.to_rfc3339()], checkpoint_from_row)?;
+
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Every checkpoint, for integrity checks.
+    pub fn checkpoints(&self) -> Result<Vec<Checkpoint>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, file_path, op_id, timestamp, blob_hash, created_at, covers_seq FROM checkpoints",
+        )?;
+        let checkpoints = stmt.query_map([], checkpoint_from_row)?;
+
+        Ok(checkpoints.collect::<Result<Vec<_>, _>>()?)
+    }
 
-        let mut stmt = conn.prepare(&query)?;
-        let ops = stmt.query_map([], |row| {
+    /// Number of operation rows, and the id and error of each row that
+    /// doesn't decode.
+    pub fn invalid_operations(&self) -> Result<(usize, Vec<(String, String)>)> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, timestamp, actor_id, file_path, op_data, parent_ops FROM operations",
+        )?;
+        let mut rows = stmt.query([])?;
+
+        let mut total = 0;
+        let mut invalid = Vec::new();
+        while let Some(row) = rows.next()? {
+            total += 1;
+            if let Err(e) = operation_from_row(row) {
+                let id: String = row.get(0).unwrap_or_default();
+                invalid.push((id, e.to_string()));
+            }
+        }
+
+        Ok((total, invalid))
+    }
+
+    pub fn delete_operation(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock();
+        let removed = conn.execute("DELETE FROM operations WHERE id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
+
+    /// Anchors whose id, position or timestamp don't decode.
+    pub fn invalid_anchors(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT id, position, created_at FROM anchors")?;
+        let mut rows = stmt.query([])?;
+
+        let mut invalid = Vec::new();
+        while let Some(row) = rows.next()? {
             let id: String = row.get(0)?;
-            let timestamp: String = row.get(1)?;
-            let actor_id: String = row.get(2)?;
-            let file_path: String = row.get(3)?;
-            let op_data: Vec<u8> = row.get(4)?;
-            let parent_ops: String = row.get(5)?;
+            let position: Vec<u8> = row.get(1)?;
+            let created_at: String = row.get(2)?;
+
+            let problem = if let Err(e) = uuid::Uuid::parse_str(&id) {
+                Some(format!("invalid id: {}", e))
+            } else if let Err(e) = bincode::deserialize::<Position>(&position) {
+                Some(format!("invalid position: {}", e))
+            } else if let Err(e) = DateTime::parse_from_rfc3339(&created_at) {
+                Some(format!("invalid created_at: {}", e))
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                invalid.push((id, problem));
+            }
+        }
+
+        Ok(invalid)
+    }
+
+    /// Delete an anchor, detaching any annotations that referenced it.
+    pub fn delete_anchor(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock();
+        conn.execute("UPDATE annotations SET anchor_id = NULL WHERE anchor_id = ?1", params![id])?;
+        let removed = conn.execute("DELETE FROM anchors WHERE id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
 
-            let op_type = bincode::deserialize(&op_data).unwrap();
-            let parents: Vec<uuid::Uuid> = serde_json::from_str(&parent_ops).unwrap();
+    /// Annotations (id, anchor_id) referring to an anchor that doesn't exist.
+    pub fn orphaned_annotations(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, anchor_id FROM annotations
+             WHERE anchor_id IS NOT NULL AND anchor_id NOT IN (SELECT id FROM anchors)",
+        )?;
+        let orphans = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
 
-            Ok(Operation {
-                id: uuid::Uuid::parse_str(&id).unwrap(),
-                timestamp: chrono::DateTime::parse_from_rfc3339(&timestamp)
-                    .unwrap()
-                    .into(),
-                actor_id,
-                file_path,
-                op_type,
-                parent_ops: parents,
-            })
-        })?;
+        Ok(orphans.collect::<Result<Vec<_>, _>>()?)
+    }
 
-        Ok(ops.collect::<Result<Vec<_>, _>>()?)
+    /// Keep an annotation on its line but drop its anchor reference.
+    pub fn detach_annotation(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock();
+        conn.execute("UPDATE annotations SET anchor_id = NULL WHERE id = ?1", params![id])?;
+        Ok(())
     }
 
     pub fn store_anchor(&self, anchor: &Anchor) -> Result<()> {
@@ -183,3 +550,88 @@ impl Database {
         Ok(())
     }
 }
+
+fn operation_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Operation> {
+    let id: String = row.get(0)?;
+    let timestamp: String = row.get(1)?;
+    let actor_id: String = row.get(2)?;
+    let file_path: String = row.get(3)?;
+    let op_data: Vec<u8> = row.get(4)?;
+    let parent_ops: String = row.get(5)?;
+
+    let op_type = bincode::deserialize(&op_data).map_err(|e| conversion_error(4, Type::Blob, e))?;
+    let parents: Vec<uuid::Uuid> =
+        serde_json::from_str(&parent_ops).map_err(|e| conversion_error(5, Type::Text, e))?;
+
+    Ok(Operation {
+        id: uuid::Uuid::parse_str(&id).map_err(|e| conversion_error(0, Type::Text, e))?,
+        timestamp: DateTime::parse_from_rfc3339(&timestamp)
+            .map_err(|e| conversion_error(1, Type::Text, e))?
+            .into(),
+        actor_id,
+        file_path,
+        op_type,
+        parent_ops: parents,
+    })
+}
+
+fn conversion_error(
+    idx: usize,
+    ty: Type,
+    e: impl std::error::Error + Send + Sync + 'static,
+) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(idx, ty, Box::new(e))
+}
+
+fn reflog_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ReflogEntry> {
+    let timestamp: String = row.get(5)?;
+
+    Ok(ReflogEntry {
+        ref_name: row.get(0)?,
+        old: row.get::<_, Option<String>>(1)?.map(SnapshotId::from_str),
+        new: row.get::<_, Option<String>>(2)?.map(SnapshotId::from_str),
+        reason: row.get(3)?,
+        actor: row.get(4)?,
+        timestamp: DateTime::parse_from_rfc3339(&timestamp)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| conversion_error(5, Type::Text, e))?,
+    })
+}
+
+fn checkpoint_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Checkpoint> {
+    let parse_time = |idx: usize, value: String| {
+        DateTime::parse_from_rfc3339(&value)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| conversion_error(idx, Type::Text, e))
+    };
+    let parse_id = |idx: usize, value: String| {
+        uuid::Uuid::parse_str(&value).map_err(|e| conversion_error(idx, Type::Text, e))
+    };
+
+    Ok(Checkpoint {
+        id: parse_id(0, row.get(0)?)?,
+        file_path: row.get(1)?,
+        op_id: parse_id(2, row.get(2)?)?,
+        timestamp: parse_time(3, row.get(3)?)?,
+        blob_hash: row.get(4)?,
+        created_at: parse_time(5, row.get(5)?)?,
+        covers_seq: row.get(6)?,
+    })
+}
+
+/// Add `column` to `table` unless an older schema already has it.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,556 @@
+//! Integrity checks for a forge repository (`forge-cli fsck`).
+//!
+//! Verifies blobs (loose and packed) against their content hash, pack
+//! files against their indexes, snapshot parents, branch heads and tags,
+//! and the SQLite tables (operations, anchors, annotations, checkpoints).
+//! Issues that can be fixed without losing good data are repaired on
+//! request.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::blob::{verify_encoded_file, Blob};
+use super::chunking;
+use super::object_store::{blob_hash_from_key, ObjectStore, BLOB_PREFIX};
+use super::pack::{PackIndex, PackedObjectStore, PACKS_DIR};
+use super::Database;
+use crate::version::{SnapshotId, SnapshotManager};
+
+/// One problem found by `check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FsckIssue {
+    /// Blob that doesn't decode or doesn't hash to its key
+    CorruptBlob {
+        key: String,
+        reason: String,
+    },
+    /// Leftover from an interrupted write
+    StaleTempFile {
+        path: PathBuf,
+    },
+    /// Pack data without an index; never read
+    OrphanedPackFile {
+        path: PathBuf,
+    },
+    /// Index whose pack data is missing or unreadable
+    BrokenPackIndex {
+        path: PathBuf,
+        reason: String,
+    },
+    UnreadableSnapshot {
+        id: String,
+        reason: String,
+    },
+    MissingSnapshotParent {
+        snapshot: String,
+        parent: String,
+    },
+    DanglingBranchHead {
+        branch: String,
+        head: String,
+    },
+    DanglingTag {
+        tag: String,
+        target: String,
+    },
+    /// Oplog row that doesn't deserialize
+    CorruptOperation {
+        id: String,
+        reason: String,
+    },
+    CorruptAnchor {
+        id: String,
+        reason: String,
+    },
+    /// Annotation pointing at an anchor that no longer exists
+    OrphanedAnnotation {
+        id: String,
+        anchor_id: String,
+    },
+    MissingCheckpointBlob {
+        checkpoint: String,
+        blob_hash: String,
+    },
+}
+
+impl FsckIssue {
+    /// Whether `repair` can fix this without losing good data. Corrupt
+    /// objects are removed: they are content-addressed and can be fetched
+    /// again, and their bytes are useless as they are.
+    pub fn repairable(&self) -> bool {
+        matches!(
+            self,
+            FsckIssue::CorruptBlob { .. }
+                | FsckIssue::StaleTempFile { .. }
+                | FsckIssue::OrphanedPackFile { .. }
+                | FsckIssue::BrokenPackIndex { .. }
+                | FsckIssue::CorruptOperation { .. }
+                | FsckIssue::CorruptAnchor { .. }
+                | FsckIssue::OrphanedAnnotation { .. }
+        )
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::CorruptBlob { key, reason } => write!(f, "corrupt blob {}: {}", key, reason),
+            FsckIssue::StaleTempFile { path } => {
+                write!(f, "stale temporary file {}", path.display())
+            }
+            FsckIssue::OrphanedPackFile { path } => {
+                write!(f, "pack without index {}", path.display())
+            }
+            FsckIssue::BrokenPackIndex { path, reason } => {
+                write!(f, "broken pack index {}: {}", path.display(), reason)
+            }
+            FsckIssue::UnreadableSnapshot { id, reason } => {
+                write!(f, "unreadable snapshot {}: {}", id, reason)
+            }
+            FsckIssue::MissingSnapshotParent { snapshot, parent } => {
+                write!(f, "snapshot {} has missing parent {}", snapshot, parent)
+            }
+            FsckIssue::DanglingBranchHead { branch, head } => {
+                write!(f, "branch {} points at missing snapshot {}", branch, head)
+            }
+            FsckIssue::DanglingTag { tag, target } => {
+                write!(f, "tag {} points at missing snapshot {}", tag, target)
+            }
+            FsckIssue::CorruptOperation { id, reason } => {
+                write!(f, "corrupt operation {}: {}", id, reason)
+            }
+            FsckIssue::CorruptAnchor { id, reason } => {
+                write!(f, "corrupt anchor {}: {}", id, reason)
+            }
+            FsckIssue::OrphanedAnnotation { id, anchor_id } => {
+                write!(
+                    f,
+                    "annotation {} refers to missing anchor {}",
+                    id, anchor_id
+                )
+            }
+            FsckIssue::MissingCheckpointBlob {
+                checkpoint,
+                blob_hash,
+            } => write!(f, "checkpoint {} is missing blob {}", checkpoint, blob_hash),
+        }
+    }
+}
+
+/// Result of a check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FsckReport {
+    pub blobs_checked: usize,
+    pub snapshots_checked: usize,
+    pub operations_checked: usize,
+    pub issues: Vec<FsckIssue>,
+    /// Issues fixed by `repair`
+    pub repaired: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Issues still present after any repair
+    pub fn outstanding(&self) -> impl Iterator<Item = &FsckIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| !self.repaired.contains(issue))
+    }
+}
+
+/// Check the repository in `forge_dir`, repairing what can be repaired if
+/// `repair` is set.
+pub async fn check(forge_dir: &Path, repair: bool) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    // Packs first: a broken index would stop the store from opening
+    check_packs(forge_dir, &mut report)?;
+    if repair {
+        repair_issues(forge_dir, None, None, &mut report).await?;
+    }
+
+    let store = PackedObjectStore::open(forge_dir)?;
+    let present = check_blobs(forge_dir, &store, &mut report).await?;
+    check_snapshots(forge_dir, &mut report)?;
+
+    let db = Database::new(forge_dir)?;
+    db.initialize()?;
+    check_database(&db, &present, &mut report)?;
+
+    if repair {
+        repair_issues(forge_dir, Some(&store), Some(&db), &mut report).await?;
+    }
+
+    Ok(report)
+}
+
+fn check_packs(forge_dir: &Path, report: &mut FsckReport) -> Result<()> {
+    let packs_dir = forge_dir.join(PACKS_DIR);
+    let Ok(entries) = std::fs::read_dir(&packs_dir) else {
+        return Ok(());
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if name.starts_with("tmp-") || name.ends_with(".tmp") {
+            report.issues.push(FsckIssue::StaleTempFile { path });
+            continue;
+        }
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pack") if !path.with_extension("idx").exists() => {
+                report.issues.push(FsckIssue::OrphanedPackFile { path });
+            }
+            Some("idx") => {
+                if let Err(reason) = check_pack_index(&path) {
+                    report
+                        .issues
+                        .push(FsckIssue::BrokenPackIndex { path, reason });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Index parses and every entry lies within the pack data.
+fn check_pack_index(path: &Path) -> std::result::Result<(), String> {
+    let raw = std::fs::read(path).map_err(|e| e.to_string())?;
+    let index: PackIndex = serde_json::from_slice(&raw).map_err(|e| e.to_string())?;
+    let pack_len = std::fs::metadata(path.with_extension("pack"))
+        .map_err(|e| format!("pack data: {}", e))?
+        .len();
+    match index
+        .entries
+        .iter()
+        .find(|entry| entry.offset + entry.size > pack_len)
+    {
+        Some(entry) => Err(format!("{} extends past the end of the pack", entry.key)),
+        None => Ok(()),
+    }
+}
+
+/// Verify every blob; returns the hashes that are present and intact.
+async fn check_blobs(
+    forge_dir: &Path,
+    store: &PackedObjectStore,
+    report: &mut FsckReport,
+) -> Result<HashSet<String>> {
+    let blobs_dir = forge_dir.join(BLOB_PREFIX);
+    if blobs_dir.exists() {
+        for entry in walkdir::WalkDir::new(&blobs_dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+        {
+            let in_progress = entry
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.starts_with("tmp-"));
+            if entry.file_type().is_file() && in_progress {
+                report.issues.push(FsckIssue::StaleTempFile {
+                    path: entry.into_path(),
+                });
+            }
+        }
+    }
+
+    let packed: HashSet<String> = store
+        .packed_entries()
+        .into_iter()
+        .map(|(_, entry)| entry.key)
+        .collect();
+
+    let mut intact = HashSet::new();
+    for meta in store.list_all(BLOB_PREFIX).await? {
+        let Some(hash) = blob_hash_from_key(&meta.key) else {
+            continue;
+        };
+        report.blobs_checked += 1;
+
+        let loose = forge_dir.join(&meta.key);
+        let verified = if loose.is_file() || !packed.contains(&meta.key) {
+            match verify_encoded_file(&loose, &hash).await {
+                Ok(metadata) if metadata.chunks.is_some() => {
+                    chunking::verify_chunks(store, &metadata).await
+                }
+                verified => verified.map(|_| ()),
+            }
+        } else {
+            verify_packed(store, &meta.key, &hash).await
+        };
+
+        match verified {
+            Ok(()) => {
+                intact.insert(hash);
+            }
+            Err(e) => report.issues.push(FsckIssue::CorruptBlob {
+                key: meta.key,
+                reason: format!("{:#}", e),
+            }),
+        }
+    }
+
+    Ok(intact)
+}
+
+async fn verify_packed(store: &PackedObjectStore, key: &str, hash: &str) -> Result<()> {
+    let data = store
+        .get(key)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("missing from pack"))?;
+    let mut blob = Blob::from_binary(&data)?;
+    chunking::resolve(store, &mut blob).await?;
+
+    let actual = Blob::from_content("", blob.content).metadata.hash;
+    if actual != hash || blob.metadata.hash != hash {
+        anyhow::bail!("content hashes to {}", actual);
+    }
+    Ok(())
+}
+
+fn check_snapshots(forge_dir: &Path, report: &mut FsckReport) -> Result<()> {
+    let snapshots = SnapshotManager::new(forge_dir)?;
+    let ids: HashSet<String> = snapshots
+        .all_snapshot_ids()?
+        .into_iter()
+        .map(|id| id.as_str().to_string())
+        .collect();
+
+    for id in &ids {
+        report.snapshots_checked += 1;
+        let snapshot_id = SnapshotId::from_str(id.clone());
+        let snapshot = match snapshots.get_snapshot(&snapshot_id) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => continue,
+            Err(e) => {
+                report.issues.push(FsckIssue::UnreadableSnapshot {
+                    id: id.clone(),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        if snapshot.id.as_str() != id {
+            report.issues.push(FsckIssue::UnreadableSnapshot {
+                id: id.clone(),
+                reason: format!("file holds snapshot {}", snapshot.id.as_str()),
+            });
+        }
+        for parent in &snapshot.parents {
+            if !ids.contains(parent.as_str()) {
+                report.issues.push(FsckIssue::MissingSnapshotParent {
+                    snapshot: id.clone(),
+                    parent: parent.as_str().to_string(),
+                });
+            }
+        }
+    }
+
+    for branch in snapshots.list_branches()? {
+        if !ids.contains(branch.head.as_str()) {
+            report.issues.push(FsckIssue::DanglingBranchHead {
+                branch: branch.name,
+                head: branch.head.as_str().to_string(),
+            });
+        }
+    }
+    for tag in snapshots.list_tags()? {
+        if !ids.contains(tag.target.as_str()) {
+            report.issues.push(FsckIssue::DanglingTag {
+                tag: tag.name,
+                target: tag.target.as_str().to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_database(db: &Database, blobs: &HashSet<String>, report: &mut FsckReport) -> Result<()> {
+    let (total, invalid) = db.invalid_operations()?;
+    report.operations_checked = total;
+    for (id, reason) in invalid {
+        report
+            .issues
+            .push(FsckIssue::CorruptOperation { id, reason });
+    }
+
+    for (id, reason) in db.invalid_anchors()? {
+        report.issues.push(FsckIssue::CorruptAnchor { id, reason });
+    }
+    for (id, anchor_id) in db.orphaned_annotations()? {
+        report
+            .issues
+            .push(FsckIssue::OrphanedAnnotation { id, anchor_id });
+    }
+
+    for checkpoint in db.checkpoints()? {
+        if !blobs.contains(&checkpoint.blob_hash) {
+            report.issues.push(FsckIssue::MissingCheckpointBlob {
+                checkpoint: checkpoint.id.to_string(),
+                blob_hash: checkpoint.blob_hash,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Fix the repairable issues not yet repaired. Pack and temp-file issues
+/// need no store or database; the rest are skipped until those are open.
+async fn repair_issues(
+    forge_dir: &Path,
+    store: Option<&PackedObjectStore>,
+    db: Option<&Database>,
+    report: &mut FsckReport,
+) -> Result<()> {
+    let pending: Vec<FsckIssue> = report
+        .outstanding()
+        .filter(|issue| issue.repairable())
+        .cloned()
+        .collect();
+
+    for issue in pending {
+        let repaired = match (&issue, store, db) {
+            (FsckIssue::StaleTempFile { path }, _, _)
+            | (FsckIssue::OrphanedPackFile { path }, _, _) => {
+                remove_file(path)?;
+                true
+            }
+            (FsckIssue::BrokenPackIndex { path, .. }, _, _) => {
+                remove_file(path)?;
+                remove_file(&path.with_extension("pack"))?;
+                true
+            }
+            (FsckIssue::CorruptBlob { key, .. }, Some(store), _) => {
+                store.delete(key).await?;
+                // The fan-out directory may now be empty
+                if let Some(dir) = forge_dir.join(key).parent() {
+                    let _ = std::fs::remove_dir(dir);
+                }
+                true
+            }
+            (FsckIssue::CorruptOperation { id, .. }, _, Some(db)) => {
+                db.delete_operation(id)?;
+                true
+            }
+            (FsckIssue::CorruptAnchor { id, .. }, _, Some(db)) => {
+                db.delete_anchor(id)?;
+                true
+            }
+            (FsckIssue::OrphanedAnnotation { id, .. }, _, Some(db)) => {
+                db.detach_annotation(id)?;
+                true
+            }
+            _ => false,
+        };
+        if repaired {
+            report.repaired.push(issue);
+        }
+    }
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::object_store::blob_key;
+    use crate::storage::BlobRepository;
+
+    #[tokio::test]
+    async fn finds_and_repairs_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge = dir.path();
+        let blobs = BlobRepository::new(forge).unwrap();
+        let good = Blob::from_content("good.txt", b"good".to_vec());
+        let bad = Blob::from_content("bad.txt", b"bad".to_vec());
+        blobs.store_local(&good).await.unwrap();
+        blobs.store_local(&bad).await.unwrap();
+
+        // Flip a content byte of one blob and leave a crashed write behind
+        let bad_path = forge.join(blob_key(bad.hash()));
+        let mut bytes = std::fs::read(&bad_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&bad_path, bytes).unwrap();
+        std::fs::write(bad_path.with_extension("tmp-1234"), b"partial").unwrap();
+
+        let db = Database::new(forge).unwrap();
+        db.initialize().unwrap();
+        db.conn
+            .lock()
+            .execute(
+                "INSERT INTO operations (id, timestamp, actor_id, file_path, op_type, op_data, parent_ops)
+                 VALUES ('broken', 'yesterday', 'a', 'f.rs', 'Insert', x'00', '[]')",
+                [],
+            )
+            .unwrap();
+        // Written by an older build that didn't enforce foreign keys
+        {
+            let conn = db.conn.lock();
+            conn.execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 INSERT INTO annotations (id, file_path, anchor_id, line, content, author, created_at, is_ai)
+                 VALUES ('note', 'f.rs', 'gone', 1, 'hi', 'me', '2024-01-01T00:00:00Z', 0);
+                 PRAGMA foreign_keys = ON;",
+            )
+            .unwrap();
+        }
+        // Reading the oplog reports the bad row instead of panicking
+        assert!(db.get_operations(None, 10).is_err());
+
+        let report = check(forge, false).await.unwrap();
+        assert_eq!(report.blobs_checked, 2);
+        assert_eq!(report.issues.len(), 4, "{:?}", report.issues);
+        assert!(report.repaired.is_empty());
+
+        let report = check(forge, true).await.unwrap();
+        assert_eq!(report.repaired.len(), 4);
+        assert_eq!(report.outstanding().count(), 0);
+
+        let report = check(forge, false).await.unwrap();
+        assert!(report.is_clean(), "{:?}", report.issues);
+        assert!(blobs.exists_local(good.hash()).await);
+        assert!(db.get_operations(None, 10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_dangling_branch_heads() {
+        let dir = tempfile::tempdir().unwrap();
+        let forge = dir.path();
+        let mut snapshots = SnapshotManager::new(forge).unwrap();
+        let id = snapshots
+            .create_snapshot("init", Default::default(), vec![])
+            .await
+            .unwrap();
+        snapshots.delete_snapshot(&id).unwrap();
+
+        let report = check(forge, true).await.unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::DanglingBranchHead {
+                branch: "main".into(),
+                head: id.as_str().to_string(),
+            }]
+        );
+        assert!(report.repaired.is_empty());
+    }
+}
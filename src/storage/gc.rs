@@ -0,0 +1,397 @@
+//! Garbage collection of unreachable blobs and snapshots.
+//!
+//! A blob is reachable when something still refers to its hash:
+//! - a file in a snapshot reachable from any branch head,
+//! - a managed component's base content (`ComponentStateManager`),
+//! - an oplog checkpoint,
+//! - a component in the injection cache index.
+//!
+//! Everything else is garbage once it is older than the grace period, which
+//! protects blobs written by operations still in flight. Chunks are garbage
+//! once no surviving chunked blob lists them.
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::chunking::{self, chunk_hash_from_key, CHUNK_PREFIX};
+use super::object_store::{blob_hash_from_key, blob_key, ObjectStore, BLOB_PREFIX};
+use super::pack::PackedObjectStore;
+use super::Database;
+use crate::context::ComponentStateManager;
+use crate::injection::InjectionManager;
+use crate::version::{SnapshotId, SnapshotManager};
+
+/// Default grace period, as in Git: two weeks
+pub const DEFAULT_GRACE_DAYS: u32 = 14;
+
+/// How a collection runs
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    /// Only objects older than this are removed
+    pub grace_period: Duration,
+    /// Report what would be removed without removing it
+    pub dry_run: bool,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            grace_period: Self::grace_days(DEFAULT_GRACE_DAYS),
+            dry_run: false,
+        }
+    }
+}
+
+impl GcOptions {
+    /// Grace period of `days`; one too long to represent keeps everything
+    pub fn grace_days(days: u32) -> Duration {
+        Duration::try_days(i64::from(days)).unwrap_or(Duration::MAX)
+    }
+
+    /// Objects from before this are old enough to remove. A grace period
+    /// reaching past the earliest representable time means none are.
+    pub fn cutoff(&self) -> DateTime<Utc> {
+        Utc::now()
+            .checked_sub_signed(self.grace_period)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+}
+
+/// Everything that keeps objects alive
+#[derive(Debug, Clone, Default)]
+pub struct GcRoots {
+    pub blobs: HashSet<String>,
+    pub snapshots: HashSet<SnapshotId>,
+}
+
+impl GcRoots {
+    /// Collect roots from the repository in `forge_dir`.
+    pub fn load(forge_dir: &Path) -> Result<Self> {
+        let mut roots = Self::default();
+
+        let snapshots = SnapshotManager::new(forge_dir)?;
+        for snapshot in snapshots.reachable_snapshots()? {
+            roots
+                .blobs
+                .extend(snapshot.files.values().map(|file| file.hash.clone()));
+            roots.snapshots.insert(snapshot.id);
+        }
+
+        let components = ComponentStateManager::new(forge_dir)?;
+        roots.blobs.extend(
+            components
+                .list_components()
+                .into_iter()
+                .map(|component| component.base_hash.clone()),
+        );
+
+        let db = Database::new(forge_dir)?;
+        db.initialize()?;
+        roots.blobs.extend(db.checkpoint_blob_hashes()?);
+
+        let injection = InjectionManager::with_store(forge_dir, None)?;
+        roots.blobs.extend(injection.cached_hashes());
+
+        Ok(roots)
+    }
+}
+
+/// Outcome of a collection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Blobs still referenced
+    pub reachable_blobs: usize,
+    /// Unreferenced blobs removed (or that would be)
+    pub removed_blobs: Vec<String>,
+    /// Chunks no surviving blob refers to, removed (or that would be)
+    #[serde(default)]
+    pub removed_chunks: Vec<String>,
+    pub bytes_freed: u64,
+    /// Unreferenced objects spared by the grace period
+    pub kept_recent: usize,
+    pub removed_snapshots: Vec<String>,
+}
+
+/// Collect garbage in the local repository at `forge_dir`.
+pub async fn collect_local(
+    forge_dir: &Path,
+    roots: &GcRoots,
+    options: &GcOptions,
+) -> Result<GcReport> {
+    let store = PackedObjectStore::open(forge_dir)?;
+    let packed: HashSet<String> = store
+        .packed_entries()
+        .into_iter()
+        .map(|(_, entry)| entry.key)
+        .collect();
+
+    let mut report = sweep_blobs(&store, roots, options).await?;
+
+    // Deleting a packed blob only drops it from the index; repack to
+    // actually give the space back.
+    let packed_garbage = report
+        .removed_blobs
+        .iter()
+        .any(|hash| packed.contains(&blob_key(hash)));
+    if packed_garbage && !options.dry_run {
+        store.repack().await?;
+    }
+
+    let snapshots = SnapshotManager::new(forge_dir)?;
+    let cutoff = options.cutoff();
+    for id in snapshots.all_snapshot_ids()? {
+        if roots.snapshots.contains(&id) {
+            continue;
+        }
+        let Some(snapshot) = snapshots.get_snapshot(&id).ok().flatten() else {
+            continue;
+        };
+        if snapshot.timestamp > cutoff {
+            report.kept_recent += 1;
+            continue;
+        }
+        if !options.dry_run {
+            snapshots.delete_snapshot(&id)?;
+        }
+        report.removed_snapshots.push(id.as_str().to_string());
+    }
+
+    Ok(report)
+}
+
+/// Remove unreferenced blobs from any object store, e.g. a remote one.
+pub async fn sweep_blobs(
+    store: &dyn ObjectStore,
+    roots: &GcRoots,
+    options: &GcOptions,
+) -> Result<GcReport> {
+    let cutoff = options.cutoff();
+    let mut report = GcReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    let mut kept = Vec::new();
+    for meta in store.list_all(BLOB_PREFIX).await? {
+        let Some(hash) = blob_hash_from_key(&meta.key) else {
+            continue;
+        };
+        if roots.blobs.contains(&hash) {
+            report.reachable_blobs += 1;
+            kept.push(meta.key);
+            continue;
+        }
+        if is_recent(meta.last_modified, cutoff) {
+            report.kept_recent += 1;
+            kept.push(meta.key);
+            continue;
+        }
+
+        if !options.dry_run {
+            store.delete(&meta.key).await?;
+        }
+        report.bytes_freed += meta.size;
+        report.removed_blobs.push(hash);
+    }
+
+    let mut live_chunks = HashSet::new();
+    for key in kept {
+        if let Some(metadata) = chunking::read_metadata(store, &key).await? {
+            live_chunks.extend(
+                metadata
+                    .chunks
+                    .into_iter()
+                    .flatten()
+                    .map(|chunk| chunk.hash),
+            );
+        }
+    }
+    for meta in store.list_all(CHUNK_PREFIX).await? {
+        let Some(hash) = chunk_hash_from_key(&meta.key) else {
+            continue;
+        };
+        if live_chunks.contains(&hash) {
+            continue;
+        }
+        if is_recent(meta.last_modified, cutoff) {
+            report.kept_recent += 1;
+            continue;
+        }
+        if !options.dry_run {
+            store.delete(&meta.key).await?;
+        }
+        report.bytes_freed += meta.size;
+        report.removed_chunks.push(hash);
+    }
+
+    tracing::info!(
+        "gc on {}: {} unreachable blobs, {} chunks ({} bytes){}",
+        store.name(),
+        report.removed_blobs.len(),
+        report.removed_chunks.len(),
+        report.bytes_freed,
+        if options.dry_run { " [dry run]" } else { "" }
+    );
+    Ok(report)
+}
+
+/// Objects without a modification time are treated as recent.
+fn is_recent(modified: Option<DateTime<Utc>>, cutoff: DateTime<Utc>) -> bool {
+    modified.is_none_or(|modified| modified > cutoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Blob, MemoryObjectStore};
+
+    #[tokio::test]
+    async fn sweeps_only_old_unreachable_blobs() {
+        let store = MemoryObjectStore::new();
+        let live = Blob::from_content("live.txt", b"live".to_vec());
+        let dead = Blob::from_content("dead.txt", b"dead".to_vec());
+        store.put_blob(&live).await.unwrap();
+        store.put_blob(&dead).await.unwrap();
+
+        let mut roots = GcRoots::default();
+        roots.blobs.insert(live.hash().to_string());
+
+        // Everything was just written: the grace period protects it
+        let report = sweep_blobs(&store, &roots, &GcOptions::default())
+            .await
+            .unwrap();
+        assert_eq!((report.reachable_blobs, report.kept_recent), (1, 1));
+        assert!(report.removed_blobs.is_empty());
+
+        // A grace period beyond the calendar keeps everything too
+        let forever = GcOptions {
+            grace_period: GcOptions::grace_days(u32::MAX),
+            dry_run: false,
+        };
+        let report = sweep_blobs(&store, &roots, &forever).await.unwrap();
+        assert_eq!(report.kept_recent, 1);
+        let forever = GcOptions {
+            grace_period: Duration::MAX,
+            ..forever
+        };
+        assert_eq!(forever.cutoff(), DateTime::<Utc>::MIN_UTC);
+
+        let now = GcOptions {
+            grace_period: Duration::zero(),
+            dry_run: true,
+        };
+        let report = sweep_blobs(&store, &roots, &now).await.unwrap();
+        assert_eq!(report.removed_blobs, vec![dead.hash().to_string()]);
+        assert!(store.has_blob(dead.hash()).await.unwrap());
+
+        let now = GcOptions {
+            dry_run: false,
+            ..now
+        };
+        sweep_blobs(&store, &roots, &now).await.unwrap();
+        assert!(!store.has_blob(dead.hash()).await.unwrap());
+        assert!(store.has_blob(live.hash()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sweeps_chunks_of_removed_blobs() {
+        use crate::storage::chunking::{put_chunked, ChunkingConfig};
+
+        let store = MemoryObjectStore::new();
+        let config = ChunkingConfig {
+            threshold: 0,
+            min_size: 64,
+            avg_size: 256,
+            max_size: 1024,
+        };
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let live = put_chunked(
+            &store,
+            &Blob::from_content("a.bin", content.clone()),
+            &config,
+        )
+        .await
+        .unwrap();
+        let mut other = content;
+        other.extend_from_slice(b"a different tail that only the dead blob has");
+        let dead = put_chunked(&store, &Blob::from_content("b.bin", other), &config)
+            .await
+            .unwrap();
+        assert!(dead.chunks_reused > 0);
+
+        let mut roots = GcRoots::default();
+        roots.blobs.insert(live.manifest.hash().to_string());
+        let options = GcOptions {
+            grace_period: Duration::zero(),
+            dry_run: false,
+        };
+        let report = sweep_blobs(&store, &roots, &options).await.unwrap();
+        assert_eq!(report.removed_blobs, vec![dead.manifest.hash().to_string()]);
+        assert_eq!(report.removed_chunks.len(), dead.chunks_uploaded);
+
+        let manifest = store.get_blob(live.manifest.hash()).await.unwrap().unwrap();
+        chunking::verify_chunks(&store, &manifest.metadata)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_gc_keeps_checkpoint_and_snapshot_blobs() {
+        use crate::storage::checkpoint::Checkpoint;
+        use crate::storage::BlobRepository;
+
+        let dir = tempfile::tempdir().unwrap();
+        let forge = dir.path();
+        let blobs = BlobRepository::new(forge).unwrap();
+
+        let checkpointed = Blob::from_content("a.rs", b"checkpointed".to_vec());
+        let orphan = Blob::from_content("b.rs", b"orphan".to_vec());
+        blobs.store_local(&checkpointed).await.unwrap();
+        blobs.store_local(&orphan).await.unwrap();
+        PackedObjectStore::open(forge)
+            .unwrap()
+            .repack()
+            .await
+            .unwrap();
+
+        let db = Database::new(forge).unwrap();
+        db.initialize().unwrap();
+        db.store_checkpoint(&Checkpoint {
+            id: uuid::Uuid::new_v4(),
+            file_path: "a.rs".into(),
+            op_id: uuid::Uuid::new_v4(),
+            timestamp: Utc::now(),
+            blob_hash: checkpointed.hash().to_string(),
+            created_at: Utc::now(),
+            covers_seq: 0,
+        })
+        .unwrap();
+
+        let tracked = forge.join("tracked.txt");
+        std::fs::write(&tracked, b"snapshotted").unwrap();
+        let mut snapshots = SnapshotManager::new(forge).unwrap();
+        snapshots
+            .create_snapshot("init", Default::default(), vec![tracked])
+            .await
+            .unwrap();
+        let snapshotted = Blob::from_content("tracked.txt", b"snapshotted".to_vec());
+        blobs.store_local(&snapshotted).await.unwrap();
+
+        let roots = GcRoots::load(forge).unwrap();
+        let options = GcOptions {
+            grace_period: Duration::zero(),
+            dry_run: false,
+        };
+        let report = collect_local(forge, &roots, &options).await.unwrap();
+        assert_eq!(report.removed_blobs, vec![orphan.hash().to_string()]);
+        assert!(report.removed_snapshots.is_empty());
+
+        let blobs = BlobRepository::new(forge).unwrap();
+        assert!(blobs.exists_local(checkpointed.hash()).await);
+        assert!(blobs.exists_local(snapshotted.hash()).await);
+        assert!(!blobs.exists_local(orphan.hash()).await);
+    }
+}
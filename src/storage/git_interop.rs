@@ -1,20 +1,625 @@
-use anyhow::Result;
-use std::path::Path;
+//! Git interop: import Git history as forge snapshots and export snapshots
+//! back as Git commits.
+//!
+//! - Import walks a Git branch and turns every commit into a snapshot whose
+//!   files point at forge blobs, storing each distinct Git blob once.
+//! - Export turns the snapshots of a forge branch into commits on
+//!   `refs/forge/<branch>`, leaving the user's own branches alone.
+//! - The `git_commits` table maps snapshots to commits in both directions,
+//!   so repeated syncs only convert what is new on either side and never
+//!   re-export an imported commit (or re-import an exported one).
+//!
+//! Imports also move a tracking branch `git/<branch>`, and fast-forward the
+//! forge branch of the same name when it hasn't diverged.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use git2::{FileMode, ObjectType, Oid, Repository, Signature, Sort, TreeWalkMode, TreeWalkResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-pub async fn sync_with_git(path: &Path) -> Result<()> {
-    // Check if Forge is already initialized
-    if path.join(".dx").exists() {
-        println!("✓ Forge repository already exists.");
-        return Ok(());
+use super::blob::{Blob, BlobRepository};
+use super::Database;
+use crate::version::{FileSnapshot, Snapshot, SnapshotId, SnapshotManager};
+
+/// Ref namespace exported snapshots are written to
+pub const EXPORT_REF_PREFIX: &str = "refs/forge/";
+
+/// Forge branch that tracks an imported Git branch
+pub fn tracking_branch(git_branch: &str) -> String {
+    format!("git/{}", git_branch)
+}
+
+/// Outcome of importing a Git branch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub branch: String,
+    /// Commits turned into new snapshots
+    pub imported: usize,
+    /// Commits that were already mapped to snapshots
+    pub already_mapped: usize,
+    /// Git blobs stored as new forge blobs
+    pub blobs_stored: usize,
+    /// Snapshot of the branch tip
+    pub head: Option<String>,
+    /// Whether the forge branch was moved to the imported tip
+    pub fast_forwarded: bool,
+    /// The forge branch has snapshots the Git branch lacks and vice versa
+    pub diverged: bool,
+}
+
+/// Outcome of exporting a forge branch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub branch: String,
+    /// Snapshots turned into new commits
+    pub exported: usize,
+    /// Snapshots that were already mapped to commits
+    pub already_mapped: usize,
+    /// Ref that now points at the exported head
+    pub reference: String,
+    pub commit: Option<String>,
+}
+
+/// Outcome of a two-way sync
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GitSyncReport {
+    /// `None` when the Git branch doesn't exist
+    pub import: Option<ImportReport>,
+    /// `None` when the forge branch doesn't exist
+    pub export: Option<ExportReport>,
+}
+
+/// Import the commits of `git_branch` that aren't mapped yet.
+pub async fn import_branch(
+    forge_dir: &Path,
+    repo_path: &Path,
+    git_branch: &str,
+) -> Result<ImportReport> {
+    let repo = Repository::discover(repo_path)
+        .with_context(|| format!("No Git repository at {}", repo_path.display()))?;
+    let tip = repo
+        .find_reference(&format!("refs/heads/{}", git_branch))
+        .with_context(|| format!("Git branch {} not found", git_branch))?
+        .peel_to_commit()?
+        .id();
+
+    let mut walk = repo.revwalk()?;
+    walk.push(tip)?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    let commits = walk.collect::<Result<Vec<Oid>, _>>()?;
+
+    std::fs::create_dir_all(forge_dir)?;
+    let db = Database::new(forge_dir)?;
+    db.initialize()?;
+    let mut snapshots = SnapshotManager::new(forge_dir)?;
+    let blobs = BlobRepository::new(forge_dir)?;
+
+    let mut report = ImportReport {
+        branch: git_branch.to_string(),
+        ..Default::default()
+    };
+    let mut known_blobs = HashMap::new();
+
+    for oid in commits {
+        if db.snapshot_for_git_commit(&oid.to_string())?.is_some() {
+            report.already_mapped += 1;
+            continue;
+        }
+
+        let (snapshot, contents) = read_commit(&repo, oid, &db, &mut known_blobs)?;
+        for blob in contents {
+            if !blobs.exists_local(blob.hash()).await {
+                blobs.store_local(&blob).await?;
+                report.blobs_stored += 1;
+            }
+        }
+
+        snapshots.import_snapshot(&snapshot)?;
+        db.map_git_commit(snapshot.id.as_str(), &oid.to_string(), "git")?;
+        report.imported += 1;
+    }
+
+    let head = db
+        .snapshot_for_git_commit(&tip.to_string())?
+        .map(SnapshotId::from_str)
+        .context("Imported branch tip has no snapshot")?;
+    snapshots.set_branch_head(&tracking_branch(git_branch), head.clone(), "git import")?;
+
+    match snapshots.branch_head(git_branch)? {
+        Some(current) if snapshots.is_ancestor(&head, &current)? => {}
+        Some(current) if !snapshots.is_ancestor(&current, &head)? => report.diverged = true,
+        _ => {
+            snapshots.set_branch_head(git_branch, head.clone(), "git import: fast-forward")?;
+            report.fast_forwarded = true;
+        }
+    }
+
+    report.head = Some(head.as_str().to_string());
+    tracing::info!(
+        "Imported {} commits from Git branch {} ({} already mapped)",
+        report.imported,
+        git_branch,
+        report.already_mapped
+    );
+    Ok(report)
+}
+
+/// Build the snapshot for one commit, whose parents must already be
+/// mapped. Returns the snapshot and the contents of Git blobs not seen
+/// before in `known_blobs` (Git blob id to forge hash and size).
+fn read_commit(
+    repo: &Repository,
+    oid: Oid,
+    db: &Database,
+    known_blobs: &mut HashMap<Oid, (String, u64)>,
+) -> Result<(Snapshot, Vec<Blob>)> {
+    let commit = repo.find_commit(oid)?;
+    let timestamp =
+        DateTime::<Utc>::from_timestamp(commit.time().seconds(), 0).unwrap_or_else(Utc::now);
+
+    let mut entries = Vec::new();
+    commit.tree()?.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            if let Some(name) = entry.name() {
+                entries.push((format!("{}{}", root, name), entry.id()));
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let mut files = HashMap::new();
+    let mut contents = Vec::new();
+    for (path, blob_oid) in entries {
+        let (hash, size) = match known_blobs.get(&blob_oid) {
+            Some(known) => known.clone(),
+            None => {
+                let blob = Blob::from_content(&path, repo.find_blob(blob_oid)?.content().to_vec());
+                let known = (blob.hash().to_string(), blob.metadata.size);
+                known_blobs.insert(blob_oid, known.clone());
+                contents.push(blob);
+                known
+            }
+        };
+        files.insert(
+            PathBuf::from(&path),
+            FileSnapshot {
+                path: PathBuf::from(path),
+                hash,
+                size,
+                modified: timestamp,
+            },
+        );
+    }
+
+    let mut parents = Vec::new();
+    for parent in commit.parent_ids() {
+        // Parents outside a shallow clone's history have no snapshot
+        if let Some(id) = db.snapshot_for_git_commit(&parent.to_string())? {
+            parents.push(SnapshotId::from_str(id));
+        }
+    }
+
+    let author = commit.author();
+    let mut metadata = HashMap::new();
+    metadata.insert("git_commit".to_string(), oid.to_string());
+    if let Some(email) = author.email() {
+        metadata.insert("git_author_email".to_string(), email.to_string());
+    }
+
+    let snapshot = Snapshot {
+        id: SnapshotId::from_hash(format!("git:{}", oid).as_bytes()),
+        parents,
+        message: commit.message().unwrap_or_default().trim_end().to_string(),
+        author: author.name().unwrap_or("unknown").to_string(),
+        timestamp,
+        tool_states: HashMap::new(),
+        files,
+        metadata,
+    };
+    Ok((snapshot, contents))
+}
+
+/// Export the snapshots of `forge_branch` that aren't mapped yet as Git
+/// commits, and point `refs/forge/<forge_branch>` at its head.
+pub async fn export_branch(
+    forge_dir: &Path,
+    repo_path: &Path,
+    forge_branch: &str,
+) -> Result<ExportReport> {
+    let repo = Repository::discover(repo_path)
+        .with_context(|| format!("No Git repository at {}", repo_path.display()))?;
+    let db = Database::new(forge_dir)?;
+    db.initialize()?;
+    let snapshots = SnapshotManager::new(forge_dir)?;
+    let blobs = BlobRepository::new(forge_dir)?;
+
+    let head = snapshots
+        .branch_head(forge_branch)?
+        .with_context(|| format!("Forge branch {} has no snapshots", forge_branch))?;
+
+    let mut report = ExportReport {
+        branch: forge_branch.to_string(),
+        reference: format!("{}{}", EXPORT_REF_PREFIX, forge_branch),
+        ..Default::default()
+    };
+    let mut exported_blobs = HashMap::new();
+
+    for id in unexported_ancestry(&repo, &db, &snapshots, &head, &mut report)? {
+        let snapshot = snapshots
+            .get_snapshot(&id)?
+            .with_context(|| format!("Snapshot {} not found", id))?;
+
+        let mut tree_entries = Vec::with_capacity(snapshot.files.len());
+        for (path, file) in &snapshot.files {
+            let git_path = repo_relative(&repo, path)?;
+            let blob_oid = match exported_blobs.get(&file.hash) {
+                Some(oid) => *oid,
+                None => {
+                    let content = file_content(&blobs, &repo, file).await?;
+                    let oid = repo.blob(&content)?;
+                    exported_blobs.insert(file.hash.clone(), oid);
+                    oid
+                }
+            };
+            tree_entries.push((git_path, blob_oid));
+        }
+
+        let commit = write_commit(&repo, &db, &snapshot, tree_entries)?;
+        db.map_git_commit(id.as_str(), &commit.to_string(), "forge")?;
+        report.exported += 1;
     }
 
-    println!("🔄 Initializing Forge repository...");
+    let commit = mapped_commit(&repo, &db, &head)?.context("Exported head has no commit")?;
+    repo.reference(&report.reference, commit, true, "forge export")?;
+    report.commit = Some(commit.to_string());
+
+    tracing::info!(
+        "Exported {} snapshots of {} to {}",
+        report.exported,
+        forge_branch,
+        report.reference
+    );
+    Ok(report)
+}
+
+/// Snapshots reachable from `head` without a commit in `repo`, parents
+/// before children.
+fn unexported_ancestry(
+    repo: &Repository,
+    db: &Database,
+    snapshots: &SnapshotManager,
+    head: &SnapshotId,
+    report: &mut ExportReport,
+) -> Result<Vec<SnapshotId>> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![(head.clone(), false)];
 
-    // Initialize Forge repository
-    crate::storage::init(path).await?;
+    while let Some((id, parents_done)) = pending.pop() {
+        if parents_done {
+            order.push(id);
+            continue;
+        }
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        if mapped_commit(repo, db, &id)?.is_some() {
+            report.already_mapped += 1;
+            continue;
+        }
 
-    println!("✓ Forge repository initialized successfully.");
-    println!("💡 You can now use Forge for operation-level version control.");
+        let snapshot = snapshots
+            .get_snapshot(&id)?
+            .with_context(|| format!("Snapshot {} not found", id))?;
+        pending.push((id, true));
+        pending.extend(snapshot.parents.into_iter().map(|parent| (parent, false)));
+    }
+
+    Ok(order)
+}
+
+/// The commit a snapshot is mapped to, if it exists in `repo`
+fn mapped_commit(repo: &Repository, db: &Database, id: &SnapshotId) -> Result<Option<Oid>> {
+    let Some(oid) = db.git_commit_for_snapshot(id.as_str())? else {
+        return Ok(None);
+    };
+    let oid = Oid::from_str(&oid)?;
+    Ok(repo.find_commit(oid).is_ok().then_some(oid))
+}
+
+fn write_commit(
+    repo: &Repository,
+    db: &Database,
+    snapshot: &Snapshot,
+    tree_entries: Vec<(String, Oid)>,
+) -> Result<Oid> {
+    let empty = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+    let mut update = git2::build::TreeUpdateBuilder::new();
+    for (path, oid) in &tree_entries {
+        update.upsert(path.as_str(), *oid, FileMode::Blob);
+    }
+    let tree = repo.find_tree(update.create_updated(repo, &empty)?)?;
+
+    let mut parents = Vec::new();
+    for parent in &snapshot.parents {
+        let oid = mapped_commit(repo, db, parent)?
+            .with_context(|| format!("Parent snapshot {} was not exported", parent))?;
+        parents.push(repo.find_commit(oid)?);
+    }
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+    let name = if snapshot.author.trim().is_empty() {
+        "forge"
+    } else {
+        snapshot.author.as_str()
+    };
+    // Git requires an email: prefer the imported one, then the configured one
+    let email = snapshot
+        .metadata
+        .get("git_author_email")
+        .filter(|email| !email.is_empty())
+        .cloned()
+        .or_else(|| repo.config().ok()?.get_string("user.email").ok())
+        .unwrap_or_else(|| format!("{}@localhost", name));
+    let signature = Signature::new(
+        name,
+        &email,
+        &git2::Time::new(snapshot.timestamp.timestamp(), 0),
+    )?;
+
+    Ok(repo.commit(
+        None,
+        &signature,
+        &signature,
+        &snapshot.message,
+        &tree,
+        &parents,
+    )?)
+}
+
+/// Path of a snapshot file inside the repository, `/`-separated
+fn repo_relative(repo: &Repository, path: &Path) -> Result<String> {
+    let relative = if path.is_absolute() {
+        let workdir = repo
+            .workdir()
+            .context("Cannot export absolute paths from a bare repository")?;
+        let canonical = workdir.canonicalize().ok();
+        path.strip_prefix(workdir)
+            .ok()
+            .or_else(|| path.strip_prefix(canonical.as_deref()?).ok())
+            .map(Path::to_path_buf)
+            .with_context(|| format!("{} is outside the Git repository", path.display()))?
+    } else {
+        path.to_path_buf()
+    };
+
+    let parts: Vec<String> = relative
+        .components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    if parts.is_empty() {
+        anyhow::bail!("{} is not a file path", path.display());
+    }
+    Ok(parts.join("/"))
+}
+
+/// Content of a snapshot file: its forge blob, or the file on disk if it
+/// still has the snapshotted content
+async fn file_content(
+    blobs: &BlobRepository,
+    repo: &Repository,
+    file: &FileSnapshot,
+) -> Result<Vec<u8>> {
+    if blobs.exists_local(&file.hash).await {
+        return Ok(blobs.load_local(&file.hash).await?.content);
+    }
+
+    let on_disk = match (file.path.is_absolute(), repo.workdir()) {
+        (false, Some(workdir)) => workdir.join(&file.path),
+        _ => file.path.clone(),
+    };
+    if let Ok(content) = tokio::fs::read(&on_disk).await {
+        if format!("{:x}", Sha256::digest(&content)) == file.hash {
+            return Ok(content);
+        }
+    }
+    anyhow::bail!(
+        "Content of {} ({}) is neither stored nor on disk",
+        file.path.display(),
+        &file.hash[..file.hash.len().min(8)]
+    )
+}
+
+/// Import `branch` from Git, then export the forge branch of the same name.
+pub async fn sync(forge_dir: &Path, repo_path: &Path, branch: &str) -> Result<GitSyncReport> {
+    let repo = Repository::discover(repo_path)
+        .with_context(|| format!("No Git repository at {}", repo_path.display()))?;
+    let has_git_branch = repo
+        .find_reference(&format!("refs/heads/{}", branch))
+        .is_ok();
+    drop(repo);
+
+    let mut report = GitSyncReport::default();
+    if has_git_branch {
+        report.import = Some(import_branch(forge_dir, repo_path, branch).await?);
+    }
+    if SnapshotManager::new(forge_dir)?
+        .branch_head(branch)?
+        .is_some()
+    {
+        report.export = Some(export_branch(forge_dir, repo_path, branch).await?);
+    }
+    Ok(report)
+}
+
+/// Initialize forge in `path` if needed, then sync it with the Git
+/// repository there (`branch` defaults to Git's current branch).
+pub async fn sync_with_git(path: &Path, branch: Option<&str>) -> Result<()> {
+    if !path.join(".dx").exists() {
+        println!("🔄 Initializing Forge repository...");
+        crate::storage::init(path).await?;
+        println!("✓ Forge repository initialized successfully.");
+    }
+
+    let Ok(repo) = Repository::discover(path) else {
+        println!("💡 Not a Git repository; nothing to sync.");
+        return Ok(());
+    };
+    let branch = match branch {
+        Some(branch) => branch.to_string(),
+        None => repo
+            .head()
+            .ok()
+            .and_then(|head| head.shorthand().map(str::to_string))
+            .unwrap_or_else(|| "main".to_string()),
+    };
+    drop(repo);
+
+    let forge_dir = path.join(".dx/forge");
+    let report = sync(&forge_dir, path, &branch).await?;
+
+    match &report.import {
+        Some(import) => {
+            println!(
+                "{} Imported {} commits from {} ({} already known, {} new blobs)",
+                "✓".green(),
+                import.imported.to_string().bright_white(),
+                branch.cyan(),
+                import.already_mapped,
+                import.blobs_stored
+            );
+            if import.diverged {
+                println!(
+                    "{} Forge branch {} has diverged from Git; the Git history is on {}",
+                    "⚠".yellow(),
+                    branch.cyan(),
+                    tracking_branch(&branch).cyan()
+                );
+            }
+        }
+        None => println!("💡 Git branch {} doesn't exist; nothing to import.", branch),
+    }
+    if let Some(export) = &report.export {
+        println!(
+            "{} Exported {} snapshots to {}",
+            "✓".green(),
+            export.exported.to_string().bright_white(),
+            export.reference.cyan()
+        );
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit_file(repo: &Repository, path: &str, content: &str, message: &str) -> Oid {
+        std::fs::write(repo.workdir().unwrap().join(path), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Ada", "ada@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    fn init_repo(path: &Path) -> Repository {
+        let mut options = git2::RepositoryInitOptions::new();
+        options.initial_head("main");
+        Repository::init_opts(path, &options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn imports_history_incrementally() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path());
+        let forge = dir.path().join(".dx/forge");
+        commit_file(&repo, "a.txt", "one", "first");
+        commit_file(&repo, "b.txt", "one", "same content, new file");
+
+        let report = import_branch(&forge, dir.path(), "main").await.unwrap();
+        assert_eq!((report.imported, report.blobs_stored), (2, 1));
+        assert!(report.fast_forwarded);
+
+        let tip = commit_file(&repo, "a.txt", "two", "second");
+        let report = import_branch(&forge, dir.path(), "main").await.unwrap();
+        assert_eq!((report.imported, report.already_mapped), (1, 2));
+
+        let snapshots = SnapshotManager::new(&forge).unwrap();
+        let head = snapshots.branch_head("main").unwrap().unwrap();
+        assert_eq!(
+            snapshots.branch_head("git/main").unwrap(),
+            Some(head.clone())
+        );
+        let snapshot = snapshots.get_snapshot(&head).unwrap().unwrap();
+        assert_eq!(snapshot.message, "second");
+        assert_eq!(snapshot.metadata["git_commit"], tip.to_string());
+        assert_eq!(snapshot.parents.len(), 1);
+
+        let blobs = BlobRepository::new(&forge).unwrap();
+        let a = &snapshot.files[Path::new("a.txt")];
+        assert_eq!(blobs.load_local(&a.hash).await.unwrap().content, b"two");
+    }
+
+    #[tokio::test]
+    async fn exports_new_snapshots_onto_imported_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path());
+        let forge = dir.path().join(".dx/forge");
+        let base = commit_file(&repo, "a.txt", "one", "from git");
+
+        let report = sync(&forge, dir.path(), "main").await.unwrap();
+        let export = report.export.unwrap();
+        assert_eq!((export.exported, export.already_mapped), (0, 1));
+        assert_eq!(export.commit, Some(base.to_string()));
+
+        // A snapshot taken in forge on top of the imported one
+        let mut snapshots = SnapshotManager::new(&forge).unwrap();
+        snapshots.checkout_branch("main").await.unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "two").unwrap();
+        snapshots
+            .create_snapshot("from forge", HashMap::new(), vec![file])
+            .await
+            .unwrap();
+
+        let report = sync(&forge, dir.path(), "main").await.unwrap();
+        assert_eq!(report.import.unwrap().imported, 0);
+        let export = report.export.unwrap();
+        assert_eq!(export.exported, 1);
+
+        let commit = repo
+            .find_reference("refs/forge/main")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(commit.message(), Some("from forge"));
+        assert_eq!(commit.parent_id(0).unwrap(), base);
+        let entry = commit.tree().unwrap().get_path(Path::new("a.txt")).unwrap();
+        let blob = repo.find_blob(entry.id()).unwrap();
+        assert_eq!(blob.content(), b"two");
+
+        // Exported commits aren't imported back, nor exported twice
+        let again = export_branch(&forge, dir.path(), "main").await.unwrap();
+        assert_eq!(again.exported, 0);
+        assert_eq!(again.commit, Some(commit.id().to_string()));
+    }
+}
@@ -0,0 +1,140 @@
+//! Object store backed by a forge server's `/api/v1/objects` endpoints, so
+//! a remote forge can be used anywhere an `ObjectStore` is expected (e.g.
+//! as a push/pull remote).
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+
+use super::object_store::{ListPage, ObjectMeta, ObjectStore};
+use super::sigv4::uri_encode;
+
+/// Header the server reports object sizes in on `HEAD` requests
+pub const OBJECT_SIZE_HEADER: &str = "X-Object-Size";
+
+pub struct HttpObjectStore {
+    base_url: String,
+    client: Client,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListResponse {
+    objects: Vec<ObjectMeta>,
+    next_token: Option<String>,
+}
+
+impl HttpObjectStore {
+    /// Store on the server at `base_url`, authenticating with `FORGE_TOKEN`
+    /// when it is set
+    pub fn new(base_url: &str) -> Result<Self> {
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Client::builder()
+                .build()
+                .context("Failed to build HTTP client")?,
+            token: std::env::var("FORGE_TOKEN").ok(),
+        })
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: Method, key: &str) -> RequestBuilder {
+        let url = format!(
+            "{}/api/v1/objects/{}",
+            self.base_url,
+            uri_encode(key, false)
+        );
+        self.authorize(self.client.request(method, url))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for HttpObjectStore {
+    fn name(&self) -> String {
+        format!("forge:{}", self.base_url)
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.request(Method::PUT, key)
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Failed to upload {}", key))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.request(Method::GET, key).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", key))?;
+        Ok(Some(response.bytes().await?.to_vec()))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let response = self.request(Method::HEAD, key).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("Failed to stat {}", key))?;
+        let size = response
+            .headers()
+            .get(OBJECT_SIZE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        Ok(Some(ObjectMeta {
+            key: key.to_string(),
+            size,
+            etag: None,
+            last_modified: None,
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self.request(Method::DELETE, key).send().await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            response
+                .error_for_status()
+                .with_context(|| format!("Failed to delete {}", key))?;
+        }
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage> {
+        let mut query = vec![("prefix", prefix.to_string()), ("limit", limit.to_string())];
+        if let Some(token) = token {
+            query.push(("token", token.to_string()));
+        }
+        let url = format!("{}/api/v1/objects", self.base_url);
+        let page: ListResponse = self
+            .authorize(self.client.get(url).query(&query))
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Failed to list {}", prefix))?
+            .json()
+            .await?;
+        Ok(ListPage {
+            objects: page.objects,
+            next_token: page.next_token,
+        })
+    }
+}
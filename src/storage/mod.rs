@@ -1,18 +1,38 @@
 pub mod blob;
+pub mod checkpoint;
+pub mod chunking;
 pub mod db;
+pub mod fsck;
+pub mod gc;
 pub mod git_interop;
+pub mod http_store;
+pub mod multipart;
+pub mod object_store;
+pub mod op_query;
 pub mod oplog;
+pub mod pack;
 pub mod r2;
+pub mod sigv4;
 
 use anyhow::Result;
 use colored::*;
-use ropey::Rope;
 use std::path::Path;
 
-pub use blob::{Blob, BlobMetadata, BlobRepository};
+pub use blob::{Blob, BlobMetadata, BlobRepository, Compression};
+pub use checkpoint::{Checkpoint, CompactionReport};
+pub use chunking::ChunkingConfig;
 pub use db::Database;
+pub use fsck::{FsckIssue, FsckReport};
+pub use gc::{GcOptions, GcReport, GcRoots};
+pub use http_store::HttpObjectStore;
+pub use op_query::{OpKind, OpOrder, OpPage, OpQuery};
 pub use oplog::OperationLog;
-pub use r2::{batch_upload_blobs, R2Config, R2Storage, SyncResult};
+pub use pack::{PackedObjectStore, RepackReport};
+pub use object_store::{
+    batch_upload_blobs, missing_blobs, open_remote, sync_down, sync_up, sync_up_with, LocalObjectStore, MemoryObjectStore, ObjectMeta,
+    ObjectStore, SyncResult,
+};
+pub use r2::{R2Config, R2Storage};
 
 const FORGE_DIR: &str = ".dx/forge";
 
@@ -35,6 +55,7 @@ pub async fn init(path: &Path) -> Result<()> {
         "actor_id": uuid::Uuid::new_v4().to_string(),
         "repo_id": uuid::Uuid::new_v4().to_string(),
         "git_interop": true,
+        "compression": "lz4",
         "real_time_sync": false,
     });
 
@@ -47,14 +68,14 @@ pub async fn init(path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub async fn show_log(file: Option<std::path::PathBuf>, limit: usize) -> Result<()> {
+pub async fn show_log(query: OpQuery) -> Result<()> {
     let db = Database::open(".dx/forge")?;
-    let operations = db.get_operations(file.as_deref(), limit)?;
+    let page = db.query_operations(&query)?;
 
     println!("{}", "Operation Log".cyan().bold());
     println!("{}", "═".repeat(80).bright_black());
 
-    for op in operations {
+    for op in page.operations {
         let time = op.timestamp.format("%Y-%m-%d %H:%M:%S%.3f");
         let op_type = match &op.op_type {
             crate::crdt::OperationType::Insert { length, .. } => {
@@ -82,11 +103,18 @@ pub async fn show_log(file: Option<std::path::PathBuf>, limit: usize) -> Result<
         );
     }
 
+    if let Some(cursor) = page.next_cursor {
+        println!(
+            "{}",
+            format!("More operations: --cursor {}", cursor).bright_black()
+        );
+    }
+
     Ok(())
 }
 
-pub async fn git_sync(path: &Path) -> Result<()> {
-    git_interop::sync_with_git(path).await
+pub async fn git_sync(path: &Path, branch: Option<&str>) -> Result<()> {
+    git_interop::sync_with_git(path, branch).await
 }
 
 pub async fn time_travel(file: &Path, timestamp: Option<String>) -> Result<()> {
@@ -109,8 +137,6 @@ pub async fn time_travel(file: &Path, timestamp: Option<String>) -> Result<()> {
     };
     let target_canon = normalize_path(&target_path);
 
-    let mut operations = db.get_operations(None, 2000)?;
-
     // Reconstruct file state at timestamp
     let target_time = if let Some(ts) = timestamp {
         chrono::DateTime::parse_from_rfc3339(&ts)?.with_timezone(&chrono::Utc)
@@ -118,55 +144,35 @@ pub async fn time_travel(file: &Path, timestamp: Option<String>) -> Result<()> {
         chrono::Utc::now()
     };
 
-    operations.retain(|op| {
-        op.timestamp <= target_time
-            && normalize_path(std::path::Path::new(&op.file_path)) == target_canon
-    });
-    operations.sort_by_key(|op| op.timestamp);
+    // Operations are keyed by the path string the watcher recorded.
+    let target_display = target_path.display().to_string();
+    let recorded = db.operation_files()?;
+    let Some(file_path) = recorded
+        .iter()
+        .find(|p| **p == target_display)
+        .or_else(|| {
+            recorded
+                .iter()
+                .find(|p| normalize_path(Path::new(p.as_str())) == target_canon)
+        })
+    else {
+        println!("{}", "No operations recorded for this file.".yellow());
+        return Ok(());
+    };
 
-    let mut rope = Rope::new();
+    let blobs = BlobRepository::new(&forge_path)?;
+    let state = checkpoint::reconstruct(&db, &blobs, file_path, target_time).await?;
+    let content = state.content;
 
-    for op in operations.iter() {
-        match &op.op_type {
-            crate::crdt::OperationType::FileCreate { content: c } => {
-                rope = Rope::from_str(c);
-            }
-            crate::crdt::OperationType::Insert {
-                position, content, ..
-            } => {
-                let char_idx = clamp_offset(&rope, position.offset);
-                rope.insert(char_idx, content);
-            }
-            crate::crdt::OperationType::Delete { position, length } => {
-                let start = clamp_offset(&rope, position.offset);
-                let end = clamp_offset(&rope, start + *length);
-                if start < end {
-                    rope.remove(start..end);
-                }
-            }
-            crate::crdt::OperationType::Replace {
-                position,
-                old_content,
-                new_content,
-            } => {
-                let start = clamp_offset(&rope, position.offset);
-                let end = clamp_offset(&rope, start + old_content.chars().count());
-                if start < end {
-                    rope.remove(start..end);
-                }
-                rope.insert(start, new_content);
-            }
-            crate::crdt::OperationType::FileDelete => {
-                rope = Rope::new();
-            }
-            crate::crdt::OperationType::FileRename { .. } => {
-                // Rename events are handled by resolving the target path above.
-            }
-        }
+    if let Some(cp) = &state.checkpoint {
+        println!(
+            "{} {} {}",
+            "↺".bright_blue(),
+            format!("from checkpoint {}", cp.timestamp.format("%Y-%m-%d %H:%M:%S")).bright_black(),
+            format!("+{} ops", state.replayed).bright_black()
+        );
     }
 
-    let content = rope.to_string();
-
     println!("\n{}", "─".repeat(80).bright_black());
     println!("{}", content);
     println!("{}", "─".repeat(80).bright_black());
@@ -174,10 +180,129 @@ pub async fn time_travel(file: &Path, timestamp: Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn normalize_path(path: &Path) -> std::path::PathBuf {
-    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+pub async fn compact(retention_days: u32, dry_run: bool) -> Result<()> {
+    let forge_path = std::env::current_dir()?.join(FORGE_DIR);
+    let db = Database::new(&forge_path)?;
+    db.initialize()?;
+    let blobs = BlobRepository::new(&forge_path)?;
+
+    let retention = chrono::Duration::days(i64::from(retention_days));
+    let report = checkpoint::compact(&db, &blobs, retention, dry_run).await?;
+
+    let verb = if report.dry_run { "Would remove" } else { "Removed" };
+    println!(
+        "{} {} operations across {} files (older than {} days)",
+        "✓".green(),
+        format!("{} {}", verb, report.operations_removed).bright_white(),
+        report.files_checkpointed.to_string().bright_white(),
+        retention_days
+    );
+    if report.operations_coalesced > 0 {
+        println!(
+            "{} Coalesced {} keystroke operations",
+            "✓".green(),
+            report.operations_coalesced.to_string().bright_white()
+        );
+    }
+
+    Ok(())
 }
 
-fn clamp_offset(rope: &Rope, offset: usize) -> usize {
-    offset.min(rope.len_chars())
+pub async fn repack() -> Result<()> {
+    let forge_path = std::env::current_dir()?.join(FORGE_DIR);
+    let store = PackedObjectStore::open(&forge_path)?;
+    let report = store.repack().await?;
+
+    if report.loose_packed == 0 && report.packs_replaced == 0 {
+        println!("{} Nothing to repack ({} objects packed)", "✓".green(), report.objects);
+        return Ok(());
+    }
+
+    println!(
+        "{} Packed {} loose blobs; pack now holds {} objects ({} bytes, replaced {} packs)",
+        "✓".green(),
+        report.loose_packed.to_string().bright_white(),
+        report.objects.to_string().bright_white(),
+        report.pack_size,
+        report.packs_replaced
+    );
+
+    Ok(())
+}
+
+pub async fn collect_garbage(grace_days: u32, dry_run: bool, remote: bool) -> Result<()> {
+    let forge_path = std::env::current_dir()?.join(FORGE_DIR);
+    let roots = GcRoots::load(&forge_path)?;
+    let options = GcOptions {
+        grace_period: GcOptions::grace_days(grace_days),
+        dry_run,
+    };
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    let report = gc::collect_local(&forge_path, &roots, &options).await?;
+    println!(
+        "{} {} unreachable blobs, {} chunks ({} bytes) and {} snapshots; {} reachable, {} within the {}-day grace period",
+        "✓".green(),
+        format!("{} {}", verb, report.removed_blobs.len()).bright_white(),
+        report.removed_chunks.len(),
+        report.bytes_freed,
+        report.removed_snapshots.len(),
+        report.reachable_blobs,
+        report.kept_recent,
+        grace_days
+    );
+
+    if remote {
+        let store = R2Storage::new(R2Config::from_env()?)?;
+        let report = gc::sweep_blobs(&store, &roots, &options).await?;
+        println!(
+            "{} {} unreachable blobs, {} chunks ({} bytes) from {}",
+            "✓".green(),
+            format!("{} {}", verb, report.removed_blobs.len()).bright_white(),
+            report.removed_chunks.len(),
+            report.bytes_freed,
+            store.name().bright_black()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn fsck(repair: bool, json: bool) -> Result<()> {
+    let forge_path = std::env::current_dir()?.join(FORGE_DIR);
+    let report = fsck::check(&forge_path, repair).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} Checked {} blobs, {} snapshots, {} operations",
+            "🔍".cyan(),
+            report.blobs_checked,
+            report.snapshots_checked,
+            report.operations_checked
+        );
+        for issue in &report.issues {
+            if report.repaired.contains(issue) {
+                println!("  {} {} {}", "✓".green(), issue, "(repaired)".bright_black());
+            } else if issue.repairable() {
+                println!("  {} {} {}", "✗".red(), issue, "(repairable with --repair)".bright_black());
+            } else {
+                println!("  {} {}", "✗".red(), issue);
+            }
+        }
+    }
+
+    let outstanding = report.outstanding().count();
+    if outstanding > 0 {
+        anyhow::bail!("{} integrity issues found", outstanding);
+    }
+    if !json {
+        println!("{} Repository is consistent", "✓".green());
+    }
+    Ok(())
+}
+
+fn normalize_path(path: &Path) -> std::path::PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
 }
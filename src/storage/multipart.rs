@@ -0,0 +1,270 @@
+/// S3 Multipart Upload Support
+///
+/// Large objects are uploaded in parts. Each uploaded part is recorded in a
+/// small JSON state file so an interrupted upload can resume: on retry the
+/// source is re-read, parts whose MD5 matches a recorded (and still
+/// present) part are skipped, and only the rest are sent.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Objects larger than this are uploaded in parts
+pub const MULTIPART_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Default part size
+pub const DEFAULT_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// S3's minimum size for every part but the last
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// S3's maximum number of parts per upload
+const MAX_PARTS: u64 = 10_000;
+
+/// A part as reported by ListParts: `(number, etag, size)`
+pub type ListedPartInfo = (u32, String, u64);
+
+/// A part the store has acknowledged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadedPart {
+    pub number: u32,
+    pub etag: String,
+    pub size: u64,
+    /// Hex MD5 of the part's bytes, to recognise it when resuming
+    pub md5: String,
+}
+
+/// Progress of one multipart upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartState {
+    pub key: String,
+    pub upload_id: String,
+    pub size: u64,
+    pub part_size: usize,
+    pub parts: Vec<UploadedPart>,
+}
+
+impl MultipartState {
+    pub fn new(key: &str, upload_id: String, size: u64, part_size: usize) -> Self {
+        Self {
+            key: key.to_string(),
+            upload_id,
+            size,
+            part_size,
+            parts: Vec::new(),
+        }
+    }
+
+    /// A previously recorded part that matches `number` and `md5`
+    pub fn completed_part(&self, number: u32, md5: &str) -> Option<&UploadedPart> {
+        self.parts
+            .iter()
+            .find(|part| part.number == number && part.md5 == md5)
+    }
+
+    pub fn record(&mut self, part: UploadedPart) {
+        self.parts.retain(|existing| existing.number != part.number);
+        self.parts.push(part);
+        self.parts.sort_by_key(|part| part.number);
+    }
+}
+
+/// Part size for an object: at least `preferred` (and `MIN_PART_SIZE`),
+/// grown as needed to stay within S3's part limit.
+pub fn part_size_for(size: u64, preferred: usize) -> usize {
+    let minimum = size.div_ceil(MAX_PARTS) as usize;
+    preferred.max(MIN_PART_SIZE).max(minimum)
+}
+
+/// Where resumable upload state is kept
+pub struct UploadTracker {
+    dir: PathBuf,
+}
+
+impl UploadTracker {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, scope: &str, key: &str) -> PathBuf {
+        let digest = Sha256::digest(format!("{}/{}", scope, key).as_bytes());
+        self.dir.join(format!("{:x}.json", digest))
+    }
+
+    /// State of an unfinished upload of `key`, if any
+    pub async fn load(&self, scope: &str, key: &str) -> Option<MultipartState> {
+        let bytes = tokio::fs::read(self.path(scope, key)).await.ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    pub async fn save(&self, scope: &str, state: &MultipartState) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.path(scope, &state.key);
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(state)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self, scope: &str, key: &str) {
+        let _ = tokio::fs::remove_file(self.path(scope, key)).await;
+    }
+}
+
+/// Read up to `part_size` bytes; shorter only at the end of the stream.
+pub async fn read_part<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+    part_size: usize,
+) -> Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(part_size);
+    let mut limited = reader.take(part_size as u64);
+    limited.read_to_end(&mut part).await?;
+    Ok(part)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult {
+    upload_id: String,
+}
+
+/// Upload id from a CreateMultipartUpload response
+pub fn parse_upload_id(xml: &str) -> Result<String> {
+    let parsed: InitiateMultipartUploadResult =
+        quick_xml::de::from_str(xml).context("Malformed CreateMultipartUpload response")?;
+    Ok(parsed.upload_id)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListPartsResult {
+    #[serde(default, rename = "Part")]
+    parts: Vec<ListedPart>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_part_number_marker: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedPart {
+    part_number: u32,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+}
+
+/// Parts from one ListParts page, plus the marker for the next page
+pub fn parse_list_parts(xml: &str) -> Result<(Vec<ListedPartInfo>, Option<u32>)> {
+    let parsed: ListPartsResult =
+        quick_xml::de::from_str(xml).context("Malformed ListParts response")?;
+    let parts = parsed
+        .parts
+        .into_iter()
+        .map(|part| (part.part_number, normalize_etag(&part.etag), part.size))
+        .collect();
+    let next = parsed
+        .next_part_number_marker
+        .filter(|_| parsed.is_truncated);
+    Ok((parts, next))
+}
+
+/// Request body for CompleteMultipartUpload
+pub fn complete_request_body(parts: &[UploadedPart]) -> String {
+    let mut body = String::from("<CompleteMultipartUpload>");
+    for part in parts {
+        body.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag></Part>",
+            part.number, part.etag
+        ));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+/// CompleteMultipartUpload can fail with a 200 status and an `<Error>` body
+pub fn check_complete_response(xml: &str) -> Result<()> {
+    if xml.contains("<Error>") {
+        anyhow::bail!("Multipart upload completion failed: {}", xml);
+    }
+    Ok(())
+}
+
+pub fn normalize_etag(etag: &str) -> String {
+    etag.trim().trim_matches('"').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_sizes_respect_s3_limits() {
+        assert_eq!(part_size_for(100, 1024), MIN_PART_SIZE);
+        assert_eq!(
+            part_size_for(200 * 1024 * 1024, DEFAULT_PART_SIZE),
+            DEFAULT_PART_SIZE
+        );
+        let huge = 400 * 1024 * 1024 * 1024u64;
+        assert!(part_size_for(huge, DEFAULT_PART_SIZE) as u64 * MAX_PARTS >= huge);
+    }
+
+    #[test]
+    fn parses_multipart_responses() {
+        let initiate = r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>forge</Bucket><Key>blobs/ab/cd</Key><UploadId>VXBsb2FkIElE</UploadId>
+</InitiateMultipartUploadResult>"#;
+        assert_eq!(parse_upload_id(initiate).unwrap(), "VXBsb2FkIElE");
+
+        let parts = r#"<ListPartsResult>
+  <UploadId>VXBsb2FkIElE</UploadId>
+  <IsTruncated>true</IsTruncated>
+  <NextPartNumberMarker>2</NextPartNumberMarker>
+  <Part><PartNumber>1</PartNumber><ETag>"7778AEF83F66ABC1FA1E8477F296D394"</ETag><Size>10485760</Size></Part>
+  <Part><PartNumber>2</PartNumber><ETag>&quot;aaaa&quot;</ETag><Size>10485760</Size></Part>
+</ListPartsResult>"#;
+        let (parts, next) = parse_list_parts(parts).unwrap();
+        assert_eq!(
+            parts[0],
+            (1, "7778aef83f66abc1fa1e8477f296d394".to_string(), 10485760)
+        );
+        assert_eq!(parts[1].1, "aaaa");
+        assert_eq!(next, Some(2));
+
+        assert!(check_complete_response("<CompleteMultipartUploadResult/>").is_ok());
+        assert!(check_complete_response("<Error><Code>InternalError</Code></Error>").is_err());
+    }
+
+    #[tokio::test]
+    async fn tracker_round_trips_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = UploadTracker::new(dir.path());
+        let mut state = MultipartState::new("big.bin", "upload-1".into(), 42, MIN_PART_SIZE);
+        state.record(UploadedPart {
+            number: 1,
+            etag: "e1".into(),
+            size: 42,
+            md5: "m1".into(),
+        });
+        tracker.save("bucket", &state).await.unwrap();
+
+        let loaded = tracker.load("bucket", "big.bin").await.unwrap();
+        assert_eq!(loaded.completed_part(1, "m1").unwrap().etag, "e1");
+        assert!(loaded.completed_part(1, "other").is_none());
+        assert!(tracker.load("other-bucket", "big.bin").await.is_none());
+
+        tracker.remove("bucket", "big.bin").await;
+        assert!(tracker.load("bucket", "big.bin").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_fixed_size_parts() {
+        let mut data: &[u8] = &[1, 2, 3, 4, 5];
+        assert_eq!(read_part(&mut data, 2).await.unwrap(), vec![1, 2]);
+        assert_eq!(read_part(&mut data, 2).await.unwrap(), vec![3, 4]);
+        assert_eq!(read_part(&mut data, 2).await.unwrap(), vec![5]);
+        assert!(read_part(&mut data, 2).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,870 @@
+/// Pluggable Object Storage
+///
+/// Everything forge keeps remotely or on disk as opaque objects (blobs,
+/// components, checkpoints) goes through the `ObjectStore` trait. Backends:
+/// the local `.dx/forge` directory, any S3-compatible endpoint (`R2Storage`)
+/// and an in-memory store for tests.
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use super::blob::Blob;
+use super::chunking::{self, ChunkingConfig};
+
+/// Page size used when a caller doesn't pick one (S3's own maximum)
+pub const DEFAULT_LIST_LIMIT: usize = 1000;
+
+/// Metadata for one stored object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// One page of a listing; pass `next_token` back to continue.
+#[derive(Debug, Clone, Default)]
+pub struct ListPage {
+    pub objects: Vec<ObjectMeta>,
+    pub next_token: Option<String>,
+}
+
+/// Key/value object storage with prefix listing.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Short backend description for logs and health output
+    fn name(&self) -> String;
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()>;
+
+    /// Fetch an object; `None` if it doesn't exist
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Object metadata without the body; `None` if it doesn't exist
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>>;
+
+    /// Delete an object; deleting a missing key is not an error
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// List keys starting with `prefix` in lexicographic order, at most
+    /// `limit` per page, resuming after a previous page's `next_token`.
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage>;
+
+    /// Upload `size` bytes from `reader` without buffering them all, where
+    /// the backend supports it.
+    async fn put_reader(&self, key: &str, mut reader: ObjectReader, size: u64) -> Result<()> {
+        let mut data = Vec::with_capacity(size as usize);
+        reader.read_to_end(&mut data).await?;
+        self.put(key, data).await
+    }
+
+    /// Upload a file from disk.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<()> {
+        let file = fs::File::open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let size = file.metadata().await?.len();
+        self.put_reader(key, Box::new(file), size).await
+    }
+
+    /// Open an object for streaming reads; `None` if it doesn't exist.
+    async fn get_reader(&self, key: &str) -> Result<Option<(ObjectReader, u64)>> {
+        Ok(self.get(key).await?.map(|data| {
+            let size = data.len() as u64;
+            (Box::new(std::io::Cursor::new(data)) as ObjectReader, size)
+        }))
+    }
+
+    /// Bytes `start..end` of an object (`end` exclusive, `None` for the
+    /// rest); `None` if the object doesn't exist.
+    async fn get_range(&self, key: &str, start: u64, end: Option<u64>) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key).await?.map(|data| {
+            let len = data.len() as u64;
+            let end = end.unwrap_or(len).min(len);
+            let start = start.min(end);
+            data[start as usize..end as usize].to_vec()
+        }))
+    }
+
+    /// Download an object into `dest`, returning its size; `None` if it
+    /// doesn't exist.
+    async fn get_to_file(&self, key: &str, dest: &Path) -> Result<Option<u64>> {
+        let Some((mut reader, _)) = self.get_reader(key).await? else {
+            return Ok(None);
+        };
+        let mut file = fs::File::create(dest)
+            .await
+            .with_context(|| format!("Failed to create {}", dest.display()))?;
+        let written = tokio::io::copy(&mut reader, &mut file).await?;
+        file.sync_all().await?;
+        Ok(Some(written))
+    }
+
+    /// Time-limited URL that lets a client `method` (GET or PUT) the object
+    /// directly, bypassing the server; `None` if the backend can't do that.
+    fn presigned_url(
+        &self,
+        _method: &str,
+        _key: &str,
+        _expires: std::time::Duration,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Follow `next_token` until the listing is exhausted.
+    async fn list_all(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let mut objects = Vec::new();
+        let mut token = None;
+        loop {
+            let page = self
+                .list(prefix, token.as_deref(), DEFAULT_LIST_LIMIT)
+                .await?;
+            objects.extend(page.objects);
+            match page.next_token {
+                Some(next) => token = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    /// Store a blob under its content-addressed key; returns the key.
+    async fn put_blob(&self, blob: &Blob) -> Result<String> {
+        let key = blob_key(blob.hash());
+        self.put(&key, blob.to_binary()?).await?;
+        Ok(key)
+    }
+
+    /// Fetch a blob as stored; its content may still be compressed.
+    async fn get_blob(&self, hash: &str) -> Result<Option<Blob>> {
+        match self.get(&blob_key(hash)).await? {
+            Some(binary) => Ok(Some(Blob::from_binary(&binary)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn has_blob(&self, hash: &str) -> Result<bool> {
+        Ok(self.head(&blob_key(hash)).await?.is_some())
+    }
+
+    /// Hashes of every blob in the store.
+    async fn list_blob_hashes(&self) -> Result<Vec<String>> {
+        Ok(self
+            .list_all(BLOB_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|meta| blob_hash_from_key(&meta.key))
+            .collect())
+    }
+}
+
+/// Streaming source or sink for object bodies
+pub type ObjectReader = Box<dyn AsyncRead + Send + Unpin>;
+
+pub(crate) const BLOB_PREFIX: &str = "blobs/";
+
+/// Content-addressed key for a blob, laid out like Git: `blobs/ab/cdef...`
+pub fn blob_key(hash: &str) -> String {
+    match (hash.get(..2), hash.get(2..)) {
+        (Some(fanout), Some(rest)) if !rest.is_empty() => {
+            format!("{}{}/{}", BLOB_PREFIX, fanout, rest)
+        }
+        _ => format!("{}{}", BLOB_PREFIX, hash),
+    }
+}
+
+/// Whether `hash` looks like a blob hash; check untrusted input with this
+/// before turning it into a key.
+pub fn is_blob_hash(hash: &str) -> bool {
+    hash.len() > 2 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Inverse of `blob_key`
+pub fn blob_hash_from_key(key: &str) -> Option<String> {
+    let (prefix, suffix) = key.strip_prefix(BLOB_PREFIX)?.split_once('/')?;
+    if prefix.len() != 2 || suffix.is_empty() || suffix.contains('/') {
+        return None;
+    }
+    Some(format!("{}{}", prefix, suffix))
+}
+
+/// Object key for a published component
+pub fn component_key(tool: &str, version: &str, component: &str) -> String {
+    format!("components/{}/{}/{}.tsx", tool, version, component)
+}
+
+/// Objects stored as plain files below a directory. With the forge
+/// directory as root, blob keys map onto `BlobRepository`'s layout.
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        let valid = !key.is_empty()
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            bail!("Invalid object key: {}", key);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn key_for(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let parts: Vec<&str> = relative
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<_>>()?;
+        Some(parts.join("/"))
+    }
+
+    fn meta_for(key: String, metadata: &std::fs::Metadata) -> ObjectMeta {
+        ObjectMeta {
+            key,
+            size: metadata.len(),
+            etag: None,
+            last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalObjectStore {
+    fn name(&self) -> String {
+        format!("local:{}", self.root.display())
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Write to a sibling and rename so readers never see partial objects
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::write(&tmp, data)
+            .await
+            .with_context(|| format!("Failed to write object {}", key))?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn put_reader(&self, key: &str, mut reader: ObjectReader, _size: u64) -> Result<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        let mut file = fs::File::create(&tmp).await?;
+        let copied = tokio::io::copy(&mut reader, &mut file).await;
+        if let Err(e) = copied {
+            drop(file);
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e).with_context(|| format!("Failed to write object {}", key));
+        }
+        file.sync_all().await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get_reader(&self, key: &str) -> Result<Option<(ObjectReader, u64)>> {
+        match fs::File::open(self.path_for(key)?).await {
+            Ok(file) => {
+                let size = file.metadata().await?.len();
+                Ok(Some((Box::new(file), size)))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read object {}", key)),
+        }
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: Option<u64>) -> Result<Option<Vec<u8>>> {
+        let mut file = match fs::File::open(self.path_for(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata().await?.len();
+        let end = end.unwrap_or(size).min(size);
+        let start = start.min(end);
+
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        file.take(end - start).read_to_end(&mut data).await?;
+        Ok(Some(data))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path_for(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read object {}", key)),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match fs::metadata(self.path_for(key)?).await {
+            Ok(metadata) if metadata.is_file() => {
+                Ok(Some(Self::meta_for(key.to_string(), &metadata)))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path_for(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage> {
+        // Only the directory holding the prefix can contain matching keys
+        let start = match prefix.rfind('/') {
+            Some(end) => self.path_for(&prefix[..end])?,
+            None => self.root.clone(),
+        };
+        let root = self.root.clone();
+        let prefix = prefix.to_string();
+        let token = token.map(str::to_string);
+        let wanted = limit.max(1) + 1;
+
+        let objects = tokio::task::spawn_blocking(move || {
+            let store = LocalObjectStore { root };
+            // Directories sort as `name/` so the walk yields keys in order
+            let walk = walkdir::WalkDir::new(&start)
+                .sort_by(|a, b| walk_order(a).cmp(&walk_order(b)))
+                .into_iter()
+                .filter_entry(|entry| {
+                    if entry.depth() == 0 || !entry.file_type().is_dir() {
+                        return true;
+                    }
+                    let Some(key) = store.key_for(entry.path()) else {
+                        return false;
+                    };
+                    let dir = format!("{}/", key);
+                    let overlaps = dir.starts_with(&prefix) || prefix.starts_with(&dir);
+                    // Everything below `dir` sorts before a token outside it
+                    let passed = token
+                        .as_deref()
+                        .is_some_and(|token| dir.as_str() < token && !token.starts_with(&dir));
+                    overlaps && !passed
+                });
+
+            let mut objects = Vec::new();
+            for entry in walk.filter_map(|entry| entry.ok()) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let Some(key) = store.key_for(entry.path()) else {
+                    continue;
+                };
+                let in_progress = entry
+                    .path()
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.starts_with("tmp-"));
+                let matches = key.starts_with(&prefix)
+                    && token.as_deref().is_none_or(|token| key.as_str() > token)
+                    && !in_progress;
+                if !matches {
+                    continue;
+                }
+                if let Ok(metadata) = entry.metadata() {
+                    objects.push(Self::meta_for(key, &metadata));
+                }
+                if objects.len() == wanted {
+                    break;
+                }
+            }
+            objects
+        })
+        .await?;
+
+        Ok(paginate(objects, limit))
+    }
+}
+
+/// Sort key for a directory entry: directories compare as `name/` so a
+/// pre-order walk visits keys in string order.
+fn walk_order(entry: &walkdir::DirEntry) -> Vec<u8> {
+    let mut name = entry.file_name().as_encoded_bytes().to_vec();
+    if entry.file_type().is_dir() {
+        name.push(b'/');
+    }
+    name
+}
+
+/// Object body and its modification time
+type MemoryObject = (Vec<u8>, DateTime<Utc>);
+
+/// Objects held in memory; for tests and ephemeral servers.
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: RwLock<BTreeMap<String, MemoryObject>>,
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.read().is_empty()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.objects
+            .write()
+            .insert(key.to_string(), (data, Utc::now()));
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.objects.read().get(key).map(|(data, _)| data.clone()))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        Ok(self
+            .objects
+            .read()
+            .get(key)
+            .map(|(data, modified)| ObjectMeta {
+                key: key.to_string(),
+                size: data.len() as u64,
+                etag: None,
+                last_modified: Some(*modified),
+            }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.objects.write().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage> {
+        let objects = self.objects.read();
+        let start = match token {
+            Some(token) if token > prefix => token,
+            _ => prefix,
+        };
+        let matching = objects
+            .range::<str, _>((std::ops::Bound::Included(start), std::ops::Bound::Unbounded))
+            .filter(|(key, _)| token.is_none_or(|token| key.as_str() > token))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit.max(1) + 1)
+            .map(|(key, (data, modified))| ObjectMeta {
+                key: key.clone(),
+                size: data.len() as u64,
+                etag: None,
+                last_modified: Some(*modified),
+            })
+            .collect();
+
+        Ok(paginate(matching, limit))
+    }
+}
+
+/// Cut a sorted listing down to one page.
+pub(crate) fn paginate(mut objects: Vec<ObjectMeta>, limit: usize) -> ListPage {
+    let limit = limit.max(1);
+    let next_token = if objects.len() > limit {
+        objects.truncate(limit);
+        objects.last().map(|meta| meta.key.clone())
+    } else {
+        None
+    };
+    ListPage {
+        objects,
+        next_token,
+    }
+}
+
+/// Open the store a remote URL names:
+/// - `http(s)://host[:port]` – a forge server (`HttpObjectStore`)
+/// - `r2://bucket` or `s3://bucket` – R2/S3 with credentials from the
+///   environment (`R2Config::from_env`) and the given bucket
+/// - `file:///path` or a plain path – a local directory
+pub fn open_remote(url: &str) -> Result<Arc<dyn ObjectStore>> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(Arc::new(super::http_store::HttpObjectStore::new(url)?));
+    }
+    if let Some(bucket) = url
+        .strip_prefix("r2://")
+        .or_else(|| url.strip_prefix("s3://"))
+    {
+        let bucket = bucket.trim_end_matches('/');
+        if bucket.is_empty() || bucket.contains('/') {
+            bail!("Expected a bucket name in {}", url);
+        }
+        let config = super::r2::R2Config {
+            bucket_name: bucket.to_string(),
+            ..super::r2::R2Config::from_env()?
+        };
+        return Ok(Arc::new(super::r2::R2Storage::new(config)?));
+    }
+    if url.contains("://") && !url.starts_with("file://") {
+        bail!("Unsupported remote {}", url);
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    Ok(Arc::new(LocalObjectStore::new(path)?))
+}
+
+/// Sync operation result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncResult {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+/// Upload local blobs the store doesn't have yet.
+pub async fn sync_up(
+    store: Arc<dyn ObjectStore>,
+    local_blobs: Vec<Blob>,
+    progress_callback: Option<impl Fn(usize, usize) + Send + Sync>,
+) -> Result<SyncResult> {
+    sync_up_with(store, local_blobs, None, progress_callback).await
+}
+
+/// Like `sync_up`, splitting blobs above the chunking threshold so that
+/// only chunks the store lacks are uploaded. Blobs must hold their
+/// content, as returned by `BlobRepository::load_local`.
+pub async fn sync_up_with(
+    store: Arc<dyn ObjectStore>,
+    local_blobs: Vec<Blob>,
+    chunking: Option<ChunkingConfig>,
+    progress_callback: Option<impl Fn(usize, usize) + Send + Sync>,
+) -> Result<SyncResult> {
+    use futures::stream::{self, StreamExt};
+
+    tracing::info!(
+        "🔄 Starting sync up to {}: {} local blobs",
+        store.name(),
+        local_blobs.len()
+    );
+
+    let mut uploaded = 0;
+    let mut skipped = 0;
+    let mut errors = Vec::new();
+    let total = local_blobs.len();
+
+    // Check which blobs already exist remotely
+    let mut to_upload = Vec::new();
+    for blob in local_blobs {
+        match store.has_blob(blob.hash()).await {
+            Ok(true) => skipped += 1,
+            Ok(false) => to_upload.push(blob),
+            Err(e) => {
+                errors.push(format!("Failed to check blob {}: {}", blob.hash(), e));
+                to_upload.push(blob); // Try to upload anyway
+            }
+        }
+    }
+
+    // Upload missing blobs in parallel (max 10 concurrent)
+    let mut stream = stream::iter(to_upload)
+        .map(|blob| {
+            let store = store.clone();
+            async move {
+                let uploaded = match chunking.filter(|c| c.applies_to(blob.metadata.size)) {
+                    Some(config) if !blob.is_chunked() && blob.metadata.compression.is_none() => {
+                        chunking::put_chunked(store.as_ref(), &blob, &config)
+                            .await
+                            .map(|_| ())
+                    }
+                    _ => store.put_blob(&blob).await.map(|_| ()),
+                };
+                match uploaded {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(format!("Failed to upload {}: {}", blob.hash(), e)),
+                }
+            }
+        })
+        .buffer_unordered(10);
+
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(()) => {
+                uploaded += 1;
+                if let Some(cb) = &progress_callback {
+                    cb(uploaded + skipped, total);
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    tracing::info!(
+        "✅ Sync up complete: {} uploaded, {} skipped, {} errors",
+        uploaded,
+        skipped,
+        errors.len()
+    );
+
+    Ok(SyncResult {
+        uploaded,
+        downloaded: 0,
+        skipped,
+        errors,
+    })
+}
+
+/// Hashes of blobs present in `source` but not in `target`, e.g. the
+/// remote blobs a `sync_down` into the local repository should fetch.
+pub async fn missing_blobs(
+    source: &dyn ObjectStore,
+    target: &dyn ObjectStore,
+) -> Result<Vec<String>> {
+    let present: std::collections::HashSet<String> =
+        target.list_blob_hashes().await?.into_iter().collect();
+    Ok(source
+        .list_blob_hashes()
+        .await?
+        .into_iter()
+        .filter(|hash| !present.contains(hash))
+        .collect())
+}
+/// Download the given blobs from the store, reassembling chunked ones.
+/// Download the given blobs from the store.
+pub async fn sync_down(
+    store: Arc<dyn ObjectStore>,
+    remote_hashes: Vec<String>,
+    progress_callback: Option<impl Fn(usize, usize) + Send + Sync>,
+) -> Result<Vec<Blob>> {
+    use futures::stream::{self, StreamExt};
+
+    tracing::info!(
+        "🔄 Starting sync down from {}: {} remote blobs",
+        store.name(),
+        remote_hashes.len()
+    );
+
+    let total = remote_hashes.len();
+    let mut downloaded_blobs = Vec::new();
+
+    // Download blobs in parallel (max 10 concurrent)
+    let mut stream = stream::iter(remote_hashes)
+        .map(|hash| {
+            let store = store.clone();
+            async move {
+                match store.get_blob(&hash).await {
+                    Ok(Some(mut blob)) if blob.is_chunked() => {
+                        chunking::resolve(store.as_ref(), &mut blob)
+                            .await
+                            .map(|_| blob)
+                            .map_err(|e| format!("Failed to reassemble {}: {}", hash, e))
+                    }
+                    Ok(Some(blob)) => Ok(blob),
+                    Ok(None) => Err(format!("Blob not found: {}", hash)),
+                    Err(e) => Err(format!("Failed to download {}: {}", hash, e)),
+                }
+            }
+        })
+        .buffer_unordered(10);
+
+    let mut errors = Vec::new();
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(blob) => {
+                downloaded_blobs.push(blob);
+                if let Some(cb) = &progress_callback {
+                    cb(downloaded_blobs.len(), total);
+                }
+            }
+            Err(e) => {
+                tracing::warn!("⚠️ {}", e);
+                errors.push(e);
+            }
+        }
+    }
+
+    tracing::info!(
+        "✅ Sync down complete: {} downloaded, {} errors",
+        downloaded_blobs.len(),
+        errors.len()
+    );
+
+    Ok(downloaded_blobs)
+}
+
+/// Batch upload blobs with progress tracking
+pub async fn batch_upload_blobs(
+    store: &dyn ObjectStore,
+    blobs: Vec<Blob>,
+    progress_callback: impl Fn(usize, usize),
+) -> Result<Vec<String>> {
+    use futures::stream::{self, StreamExt};
+
+    let total = blobs.len();
+    let mut keys = Vec::with_capacity(total);
+
+    // Upload in parallel (max 10 concurrent)
+    let mut stream = stream::iter(blobs)
+        .map(|blob| async move { store.put_blob(&blob).await })
+        .buffer_unordered(10);
+
+    while let Some(result) = stream.next().await {
+        keys.push(result?);
+        progress_callback(keys.len(), total);
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exercise(store: &dyn ObjectStore) {
+        for key in ["a/1", "a/2", "a/3", "b/1"] {
+            store.put(key, key.as_bytes().to_vec()).await.unwrap();
+        }
+        assert_eq!(store.get("a/2").await.unwrap(), Some(b"a/2".to_vec()));
+        assert_eq!(store.get("missing").await.unwrap(), None);
+        assert_eq!(store.head("b/1").await.unwrap().unwrap().size, 3);
+
+        let first = store.list("a/", None, 2).await.unwrap();
+        let keys: Vec<_> = first.objects.iter().map(|m| m.key.as_str()).collect();
+        assert_eq!(keys, vec!["a/1", "a/2"]);
+        let rest = store
+            .list("a/", first.next_token.as_deref(), 2)
+            .await
+            .unwrap();
+        let keys: Vec<_> = rest.objects.iter().map(|m| m.key.as_str()).collect();
+        assert_eq!(keys, vec!["a/3"]);
+        assert!(rest.next_token.is_none());
+
+        store.delete("a/1").await.unwrap();
+        store.delete("a/1").await.unwrap();
+        assert_eq!(store.list_all("a/").await.unwrap().len(), 2);
+
+        let blob = Blob::from_content("x.txt", b"hello".to_vec());
+        store.put_blob(&blob).await.unwrap();
+        assert!(store.has_blob(blob.hash()).await.unwrap());
+        assert_eq!(
+            store.list_blob_hashes().await.unwrap(),
+            vec![blob.hash().to_string()]
+        );
+
+        let data: Vec<u8> = (0..=255).collect();
+        let reader: ObjectReader = Box::new(std::io::Cursor::new(data.clone()));
+        store.put_reader("big", reader, 256).await.unwrap();
+        assert_eq!(
+            store.get_range("big", 10, Some(20)).await.unwrap(),
+            Some(data[10..20].to_vec())
+        );
+        assert_eq!(
+            store.get_range("big", 250, None).await.unwrap(),
+            Some(data[250..].to_vec())
+        );
+        let (mut reader, size) = store.get_reader("big").await.unwrap().unwrap();
+        let mut streamed = Vec::new();
+        reader.read_to_end(&mut streamed).await.unwrap();
+        assert_eq!((streamed, size), (data, 256));
+    }
+
+    #[tokio::test]
+    async fn local_and_memory_stores_behave_alike() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&LocalObjectStore::new(dir.path()).unwrap()).await;
+        exercise(&MemoryObjectStore::new()).await;
+
+        let local = LocalObjectStore::new(dir.path()).unwrap();
+        assert!(local.put("../escape", vec![]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn local_listing_pages_within_the_prefix_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+        for key in ["blobs/ab/1", "blobs/ab/2", "blobs/ab-c", "blobs/ac/3", "other/x"] {
+            store.put(key, b"x".to_vec()).await.unwrap();
+        }
+
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let page = store.list("blobs/a", token.as_deref(), 1).await.unwrap();
+            keys.extend(page.objects.into_iter().map(|meta| meta.key));
+            token = page.next_token;
+            if token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(keys, ["blobs/ab-c", "blobs/ab/1", "blobs/ab/2", "blobs/ac/3"]);
+
+        let page = store.list("missing/", None, 10).await.unwrap();
+        assert!(page.objects.is_empty());
+    }
+
+    #[test]
+    fn blob_keys_tolerate_non_hex_input() {
+        assert_eq!(blob_key("abcdef"), "blobs/ab/cdef");
+        assert_eq!(blob_key("aé1"), "blobs/aé1");
+        assert!(is_blob_hash("abcdef0123"));
+        assert!(!is_blob_hash("../config.json"));
+        assert!(!is_blob_hash("éa1"));
+    }
+
+    #[tokio::test]
+    async fn sync_up_skips_existing_blobs() {
+        let store: Arc<dyn ObjectStore> = Arc::new(MemoryObjectStore::new());
+        let a = Blob::from_content("a.txt", b"a".to_vec());
+        store.put_blob(&a).await.unwrap();
+
+        let blobs = vec![a, Blob::from_content("b.txt", b"b".to_vec())];
+        let result = sync_up(store.clone(), blobs, None::<fn(usize, usize)>)
+            .await
+            .unwrap();
+        assert_eq!((result.uploaded, result.skipped), (1, 1));
+
+        let local = MemoryObjectStore::new();
+        local
+            .put_blob(&Blob::from_content("b.txt", b"b".to_vec()))
+            .await
+            .unwrap();
+        let hashes = missing_blobs(store.as_ref(), &local).await.unwrap();
+        let downloaded = sync_down(store, hashes, None::<fn(usize, usize)>)
+            .await
+            .unwrap();
+        assert_eq!(downloaded.len(), 1);
+        assert_eq!(downloaded[0].content, b"a");
+    }
+}
@@ -0,0 +1,568 @@
+//! Typed queries over the operations table.
+//!
+//! `OpQuery` collects filters and turns them into one parameterized
+//! statement; no caller-supplied value is ever spliced into SQL. Results
+//! come back in pages: `OpPage::next_cursor` is an opaque token that resumes
+//! right after the last operation returned, even while new operations are
+//! being written.
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::crdt::{Operation, OperationType};
+
+/// Page size when none is given
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Largest page a client may ask for
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Kind of an operation, as stored in the `op_type` column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OpKind {
+    Insert,
+    Delete,
+    Replace,
+    FileCreate,
+    FileDelete,
+    FileRename,
+}
+
+impl OpKind {
+    pub fn of(op_type: &OperationType) -> Self {
+        match op_type {
+            OperationType::Insert { .. } => OpKind::Insert,
+            OperationType::Delete { .. } => OpKind::Delete,
+            OperationType::Replace { .. } => OpKind::Replace,
+            OperationType::FileCreate { .. } => OpKind::FileCreate,
+            OperationType::FileDelete => OpKind::FileDelete,
+            OperationType::FileRename { .. } => OpKind::FileRename,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpKind::Insert => "Insert",
+            OpKind::Delete => "Delete",
+            OpKind::Replace => "Replace",
+            OpKind::FileCreate => "FileCreate",
+            OpKind::FileDelete => "FileDelete",
+            OpKind::FileRename => "FileRename",
+        }
+    }
+}
+
+impl std::str::FromStr for OpKind {
+    type Err = anyhow::Error;
+
+    /// Accepts the stored names and snake/kebab case (`file-create`)
+    fn from_str(s: &str) -> Result<Self> {
+        let normalized: String = s
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match normalized.as_str() {
+            "insert" => Ok(OpKind::Insert),
+            "delete" => Ok(OpKind::Delete),
+            "replace" => Ok(OpKind::Replace),
+            "filecreate" | "create" => Ok(OpKind::FileCreate),
+            "filedelete" => Ok(OpKind::FileDelete),
+            "filerename" | "rename" => Ok(OpKind::FileRename),
+            _ => anyhow::bail!("Unknown operation type: {}", s),
+        }
+    }
+}
+
+/// Order of results; cursors only resume in the order they were made in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+/// Position after the last operation of a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cursor {
+    timestamp: String,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}\n{}", self.timestamp, self.id))
+    }
+
+    fn decode(token: &str) -> Result<Self> {
+        let raw = URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()
+            .and_then(|raw| String::from_utf8(raw).ok())
+            .context("Invalid cursor")?;
+        let (timestamp, id) = raw.split_once('\n').context("Invalid cursor")?;
+        Ok(Self {
+            timestamp: timestamp.to_string(),
+            id: id.to_string(),
+        })
+    }
+}
+
+/// Filters, order and page of an operations query
+#[derive(Debug, Clone)]
+pub struct OpQuery {
+    file: Option<String>,
+    file_glob: Option<String>,
+    actors: Vec<String>,
+    kinds: Vec<OpKind>,
+    since: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    beyond: Option<(DateTime<Utc>, i64)>,
+    received_through: Option<i64>,
+    parent: Option<Uuid>,
+    order: OpOrder,
+    limit: Option<usize>,
+    cursor: Option<Cursor>,
+}
+
+impl Default for OpQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpQuery {
+    /// All operations, newest first, one default-sized page
+    pub fn new() -> Self {
+        Self {
+            file: None,
+            file_glob: None,
+            actors: Vec::new(),
+            kinds: Vec::new(),
+            since: None,
+            after: None,
+            until: None,
+            beyond: None,
+            received_through: None,
+            parent: None,
+            order: OpOrder::default(),
+            limit: Some(DEFAULT_PAGE_SIZE),
+            cursor: None,
+        }
+    }
+
+    /// Operations on exactly this path
+    pub fn file(mut self, path: impl Into<String>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Operations on paths matching a glob (`*`, `?`, `[...]`, with `*`
+    /// also matching `/`)
+    pub fn file_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.file_glob = Some(pattern.into());
+        self
+    }
+
+    /// Operations by this actor; repeat to allow several
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.actors.push(actor.into());
+        self
+    }
+
+    /// Operations of this kind; repeat to allow several
+    pub fn kind(mut self, kind: OpKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Operations at or after `time`
+    pub fn since(mut self, time: DateTime<Utc>) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Operations strictly after `time`
+    pub fn after(mut self, time: DateTime<Utc>) -> Self {
+        self.after = Some(time);
+        self
+    }
+
+    /// Operations at or before `time`
+    pub fn until(mut self, time: DateTime<Utc>) -> Self {
+        self.until = Some(time);
+        self
+    }
+
+    /// Operations a checkpoint at `time` covering arrivals up to `seq`
+    /// doesn't include: newer than `time`, or arrived after `seq`
+    pub fn beyond(mut self, time: DateTime<Utc>, seq: i64) -> Self {
+        self.beyond = Some((time, seq));
+        self
+    }
+
+    /// Operations that arrived no later than sequence number `seq`
+    pub fn received_through(mut self, seq: i64) -> Self {
+        self.received_through = Some(seq);
+        self
+    }
+
+    /// Operations that list `parent` among their parents
+    pub fn parent(mut self, parent: Uuid) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn order(mut self, order: OpOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Page size (at least one)
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit.max(1));
+        self
+    }
+
+    /// Return every match in one page
+    pub fn unlimited(mut self) -> Self {
+        self.limit = None;
+        self
+    }
+
+    /// Resume after the page that returned `token`
+    pub fn cursor(mut self, token: &str) -> Result<Self> {
+        self.cursor = Some(Cursor::decode(token)?);
+        Ok(self)
+    }
+
+    /// The SQL and its parameters. One row more than the page size is
+    /// requested to tell whether another page follows.
+    pub(crate) fn to_sql(&self) -> (String, Vec<Value>) {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        let mut bind = |clauses: &mut Vec<String>, clause: &str, value: Value| {
+            params.push(value);
+            clauses.push(clause.replace('?', &format!("?{}", params.len())));
+        };
+
+        if let Some(file) = &self.file {
+            bind(&mut clauses, "file_path = ?", Value::Text(file.clone()));
+        }
+        if let Some(pattern) = &self.file_glob {
+            bind(
+                &mut clauses,
+                "file_path GLOB ?",
+                Value::Text(pattern.clone()),
+            );
+        }
+        if !self.actors.is_empty() {
+            let mut alternatives = Vec::new();
+            for actor in &self.actors {
+                bind(
+                    &mut alternatives,
+                    "actor_id = ?",
+                    Value::Text(actor.clone()),
+                );
+            }
+            clauses.push(format!("({})", alternatives.join(" OR ")));
+        }
+        if !self.kinds.is_empty() {
+            // Older rows stored the kind with a trailing space
+            let mut alternatives = Vec::new();
+            for kind in &self.kinds {
+                bind(
+                    &mut alternatives,
+                    "TRIM(op_type) = ?",
+                    Value::Text(kind.as_str().to_string()),
+                );
+            }
+            clauses.push(format!("({})", alternatives.join(" OR ")));
+        }
+        if let Some(since) = self.since {
+            bind(
+                &mut clauses,
+                "timestamp >= ?",
+                Value::Text(since.to_rfc3339()),
+            );
+        }
+        if let Some(after) = self.after {
+            bind(
+                &mut clauses,
+                "timestamp > ?",
+                Value::Text(after.to_rfc3339()),
+            );
+        }
+        if let Some(until) = self.until {
+            bind(
+                &mut clauses,
+                "timestamp <= ?",
+                Value::Text(until.to_rfc3339()),
+            );
+        }
+        if let Some((time, seq)) = self.beyond {
+            let mut alternatives = Vec::new();
+            bind(
+                &mut alternatives,
+                "timestamp > ?",
+                Value::Text(time.to_rfc3339()),
+            );
+            bind(
+                &mut alternatives,
+                "COALESCE(seq, 0) > ?",
+                Value::Integer(seq),
+            );
+            clauses.push(format!("({})", alternatives.join(" OR ")));
+        }
+        if let Some(seq) = self.received_through {
+            bind(
+                &mut clauses,
+                "COALESCE(seq, 0) <= ?",
+                Value::Integer(seq),
+            );
+        }
+        if let Some(parent) = self.parent {
+            bind(
+                &mut clauses,
+                "EXISTS (SELECT 1 FROM json_each(parent_ops) WHERE value = ?)",
+                Value::Text(parent.to_string()),
+            );
+        }
+
+        let (direction, beyond) = match self.order {
+            OpOrder::NewestFirst => ("DESC", "<"),
+            OpOrder::OldestFirst => ("ASC", ">"),
+        };
+        if let Some(cursor) = &self.cursor {
+            let n = params.len();
+            params.push(Value::Text(cursor.timestamp.clone()));
+            params.push(Value::Text(cursor.id.clone()));
+            clauses.push(format!(
+                "(timestamp {beyond} ?{a} OR (timestamp = ?{a} AND id {beyond} ?{b}))",
+                a = n + 1,
+                b = n + 2,
+            ));
+        }
+
+        let mut sql = String::from(
+            "SELECT id, timestamp, actor_id, file_path, op_data, parent_ops FROM operations",
+        );
+        if !clauses.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&clauses.join(" AND "));
+        }
+        sql.push_str(&format!(" ORDER BY timestamp {direction}, id {direction}"));
+        if let Some(limit) = self.limit {
+            params.push(Value::Integer(limit as i64 + 1));
+            sql.push_str(&format!(" LIMIT ?{}", params.len()));
+        }
+
+        (sql, params)
+    }
+
+    pub(crate) fn page_size(&self) -> Option<usize> {
+        self.limit
+    }
+}
+
+/// One page of query results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpPage {
+    pub operations: Vec<Operation>,
+    /// Pass to `OpQuery::cursor` for the next page; `None` on the last
+    pub next_cursor: Option<String>,
+}
+
+impl OpPage {
+    /// Build a page from rows with their stored timestamps, of which one
+    /// more than `page_size` may have been fetched.
+    pub(crate) fn from_rows(mut rows: Vec<(String, Operation)>, page_size: Option<usize>) -> Self {
+        let next_cursor = match page_size {
+            Some(size) if rows.len() > size => {
+                rows.truncate(size);
+                rows.last().map(|(timestamp, op)| {
+                    Cursor {
+                        timestamp: timestamp.clone(),
+                        id: op.id.to_string(),
+                    }
+                    .encode()
+                })
+            }
+            _ => None,
+        };
+        Self {
+            operations: rows.into_iter().map(|(_, op)| op).collect(),
+            next_cursor,
+        }
+    }
+}
+
+/// Parse a time given on the command line or in a request: RFC 3339, a
+/// local `YYYY-MM-DD[ HH:MM[:SS]]`, or an age such as `90m`, `6h`, `2d`.
+pub fn parse_time(input: &str) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(input, format) {
+            return local_to_utc(naive);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return local_to_utc(date.and_hms_opt(0, 0, 0).expect("midnight is valid"));
+    }
+
+    if let Some(unit) = input.chars().last() {
+        if let Ok(amount) = input[..input.len() - unit.len_utf8()].parse::<i64>() {
+            let age = match unit {
+                's' => TimeDelta::try_seconds(amount),
+                'm' => TimeDelta::try_minutes(amount),
+                'h' => TimeDelta::try_hours(amount),
+                'd' => TimeDelta::try_days(amount),
+                'w' => TimeDelta::try_weeks(amount),
+                _ => anyhow::bail!("Unknown time unit in {}", input),
+            };
+            return age
+                .and_then(|age| Utc::now().checked_sub_signed(age))
+                .with_context(|| format!("Time out of range: {}", input));
+        }
+    }
+
+    anyhow::bail!("Unrecognized time: {}", input)
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Result<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .with_context(|| format!("{} does not exist in the local time zone", naive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::Position;
+    use crate::storage::Database;
+
+    fn op(file: &str, actor: &str, op_type: OperationType, minutes_ago: i64) -> Operation {
+        let mut op = Operation::new(file.to_string(), op_type, actor.to_string());
+        op.timestamp = Utc::now() - chrono::Duration::minutes(minutes_ago);
+        op
+    }
+
+    fn insert() -> OperationType {
+        OperationType::Insert {
+            position: Position::new(1, 1, 0, "a".into(), 1),
+            content: "x".into(),
+            length: 1,
+        }
+    }
+
+    #[test]
+    fn filters_are_parameterized_and_combined() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+
+        let first = op("src/it's.rs", "alice", insert(), 120);
+        let mut second = op("src/lib.rs", "bob", OperationType::FileDelete, 60);
+        second.parent_ops = vec![first.id];
+        let third = op("docs/a.md", "alice", insert(), 10);
+        for op in [&first, &second, &third] {
+            db.store_operation(op).unwrap();
+        }
+
+        let ids = |query: OpQuery| -> Vec<Uuid> {
+            db.query_operations(&query)
+                .unwrap()
+                .operations
+                .iter()
+                .map(|op| op.id)
+                .collect()
+        };
+
+        assert_eq!(ids(OpQuery::new().file("src/it's.rs")), vec![first.id]);
+        assert_eq!(
+            ids(OpQuery::new().file_glob("src/*")),
+            vec![second.id, first.id]
+        );
+        assert_eq!(ids(OpQuery::new().actor("alice")), vec![third.id, first.id]);
+        assert_eq!(
+            ids(OpQuery::new().kind(OpKind::FileDelete)),
+            vec![second.id]
+        );
+        assert_eq!(ids(OpQuery::new().parent(first.id)), vec![second.id]);
+        assert_eq!(
+            ids(OpQuery::new()
+                .actor("alice")
+                .since(Utc::now() - chrono::Duration::minutes(90))),
+            vec![third.id]
+        );
+        assert_eq!(
+            ids(OpQuery::new()
+                .until(Utc::now() - chrono::Duration::minutes(30))
+                .order(OpOrder::OldestFirst)),
+            vec![first.id, second.id]
+        );
+    }
+
+    #[test]
+    fn cursors_page_through_everything_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+
+        // Several operations share a timestamp, so the cursor needs the id
+        let at = Utc::now();
+        let mut stored = Vec::new();
+        for i in 0..7 {
+            let mut op = op("a.rs", "alice", insert(), 0);
+            op.timestamp = at - chrono::Duration::seconds(i / 3);
+            db.store_operation(&op).unwrap();
+            stored.push(op.id);
+        }
+
+        let mut seen = Vec::new();
+        let mut query = OpQuery::new().limit(3);
+        loop {
+            let page = db.query_operations(&query).unwrap();
+            assert!(page.operations.len() <= 3);
+            seen.extend(page.operations.iter().map(|op| op.id));
+            match page.next_cursor {
+                Some(cursor) => query = query.cursor(&cursor).unwrap(),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen.len(), stored.len());
+        seen.sort();
+        stored.sort();
+        assert_eq!(seen, stored);
+        assert!(OpQuery::new().cursor("not a cursor").is_err());
+    }
+
+    #[test]
+    fn parses_absolute_and_relative_times() {
+        let exact = parse_time("2024-05-01T14:30:00Z").unwrap();
+        assert_eq!(exact.to_rfc3339(), "2024-05-01T14:30:00+00:00");
+        assert!(parse_time("2024-05-01 14:30").is_ok());
+        assert!(parse_time("2024-05-01").is_ok());
+
+        let hours_ago = parse_time("6h").unwrap();
+        let expected = Utc::now() - chrono::Duration::hours(6);
+        assert!((hours_ago - expected).num_seconds().abs() < 5);
+        assert!(parse_time("yesterday-ish").is_err());
+        assert!(parse_time("9999999999999d").is_err());
+        assert!(parse_time("9223372036854775807s").is_err());
+        assert!(parse_time("-9999999999w").is_err());
+    }
+}
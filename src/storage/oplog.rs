@@ -1,13 +1,21 @@
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
 use crossbeam::channel::{self, Sender};
 use dashmap::DashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::thread;
 use uuid::Uuid;
 
+use super::op_query::{OpOrder, OpQuery};
 use super::Database;
+use crate::crdt::operations::OperationBatch;
 use crate::crdt::Operation;
 
+/// Operations younger than this are still being relayed to peers and are
+/// never coalesced.
+pub const SETTLE_WINDOW: Duration = Duration::minutes(10);
+
 pub struct OperationLog {
     // In-memory cache for fast lookups and deduplication
     cache: DashMap<Uuid, Operation>,
@@ -53,3 +61,156 @@ impl OperationLog {
         self.cache.get(id).map(|op| op.clone())
     }
 }
+
+/// Coalesce keystroke runs among the stored operations older than
+/// `settled_before` and return how many rows were removed.
+///
+/// Operations are persisted exactly as they were cached and relayed, so ids
+/// referenced by other operations stay resolvable; only settled runs whose
+/// intermediate ids nothing else depends on are merged afterwards. Operations
+/// a checkpoint may already reflect are left alone, so a merged run is never
+/// replayed on top of part of itself.
+pub fn coalesce_settled(db: &Database, settled_before: DateTime<Utc>) -> Result<usize> {
+    let parents = db.operation_parents()?;
+    let checkpoints = db.checkpoints()?;
+    let mut removed_total = 0;
+
+    for file_path in db.operation_files()? {
+        let mut query = OpQuery::new()
+            .file(file_path.as_str())
+            .until(settled_before)
+            .order(OpOrder::OldestFirst)
+            .unlimited();
+        let newest = checkpoints
+            .iter()
+            .filter(|cp| cp.file_path == file_path)
+            .fold(None, |bound: Option<(DateTime<Utc>, i64)>, cp| {
+                Some(match bound {
+                    Some((time, seq)) => (time.max(cp.timestamp), seq.max(cp.covers_seq)),
+                    None => (cp.timestamp, cp.covers_seq),
+                })
+            });
+        if let Some((time, seq)) = newest {
+            query = query.beyond(time, seq);
+        }
+        let operations = db.query_operations(&query)?.operations;
+        if operations.len() < 2 {
+            continue;
+        }
+
+        let in_batch: HashSet<Uuid> = operations.iter().map(|op| op.id).collect();
+        let referenced: HashSet<Uuid> = parents
+            .iter()
+            .filter(|(id, _)| !in_batch.contains(id))
+            .flat_map(|(_, parent_ops)| parent_ops.iter().copied())
+            .collect();
+
+        let originals: HashMap<Uuid, Operation> =
+            operations.iter().map(|op| (op.id, op.clone())).collect();
+        let mut batch = OperationBatch::new(operations);
+        batch.optimize_keeping(&referenced);
+
+        let kept: HashSet<Uuid> = batch.operations.iter().map(|op| op.id).collect();
+        let removed: Vec<Uuid> = in_batch.difference(&kept).copied().collect();
+        if removed.is_empty() {
+            continue;
+        }
+        let changed: Vec<Operation> = batch
+            .operations
+            .into_iter()
+            .filter(|op| {
+                originals.get(&op.id).is_none_or(|original| {
+                    original.parent_ops != op.parent_ops
+                        || bincode::serialize(&original.op_type).ok()
+                            != bincode::serialize(&op.op_type).ok()
+                })
+            })
+            .collect();
+
+        db.rewrite_operations(&removed, &changed)?;
+        removed_total += removed.len();
+    }
+
+    Ok(removed_total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{OperationType, Position};
+
+    fn insert(offset: usize, text: &str, age: Duration) -> Operation {
+        let mut op = Operation::new(
+            "notes.txt".into(),
+            OperationType::Insert {
+                position: Position::new(1, offset + 1, offset, "actor".into(), 0),
+                content: text.into(),
+                length: text.chars().count(),
+            },
+            "actor".into(),
+        );
+        op.timestamp = Utc::now() - age;
+        op
+    }
+
+    #[tokio::test]
+    async fn appended_operations_are_persisted_under_their_own_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(Database::new(dir.path()).unwrap());
+        db.initialize().unwrap();
+        let log = OperationLog::new(db.clone());
+
+        let first = insert(0, "a", Duration::zero());
+        let second = insert(1, "b", Duration::zero()).with_parents(vec![first.id]);
+        log.append(first.clone()).unwrap();
+        log.append(second.clone()).unwrap();
+
+        let mut stored = Vec::new();
+        for _ in 0..100 {
+            stored = db.get_operations(None, 10).unwrap();
+            if stored.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let mut ids: Vec<Uuid> = stored.iter().map(|op| op.id).collect();
+        ids.sort();
+        let mut expected = vec![first.id, second.id];
+        expected.sort();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn settled_runs_coalesce_unless_referenced() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path()).unwrap();
+        db.initialize().unwrap();
+
+        let age = Duration::hours(1);
+        let f = insert(0, "f", age);
+        let n = insert(1, "n", age).with_parents(vec![f.id]);
+        let space = insert(2, " ", age).with_parents(vec![n.id]);
+        let x = insert(3, "x", age).with_parents(vec![space.id]);
+        // A recent operation from a peer builds on `space`
+        let mut peer = insert(3, "!", Duration::zero()).with_parents(vec![space.id]);
+        peer.actor_id = "peer".into();
+        for op in [&f, &n, &space, &x, &peer] {
+            db.store_operation(op).unwrap();
+        }
+
+        let removed = coalesce_settled(&db, Utc::now() - SETTLE_WINDOW).unwrap();
+        assert_eq!(removed, 2);
+
+        let stored = db
+            .query_operations(&OpQuery::new().order(OpOrder::OldestFirst).unlimited())
+            .unwrap()
+            .operations;
+        let ids: Vec<Uuid> = stored.iter().map(|op| op.id).collect();
+        assert_eq!(ids, vec![space.id, x.id, peer.id]);
+        assert!(matches!(
+            &stored[0].op_type,
+            OperationType::Insert { content, .. } if content == "fn "
+        ));
+        assert!(stored[0].parent_ops.is_empty());
+    }
+}
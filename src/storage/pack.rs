@@ -0,0 +1,536 @@
+/// Packfiles
+///
+/// Every blob starts out as its own file under `blobs/ab/...`. Repos with
+/// tens of thousands of small snapshots run out of inodes that way, so
+/// `repack` consolidates small loose blobs into a single packfile:
+///
+///   packs/pack-<sha256>.pack   `PACK_MAGIC` followed by object bodies
+///   packs/pack-<sha256>.idx    JSON `PackIndex`: key, offset, size, hash
+///
+/// `PackedObjectStore` serves loose and packed objects through one
+/// `ObjectStore`. New objects are always written loose; deleting a packed
+/// object drops it from the index and its bytes are reclaimed by the next
+/// repack.
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::object_store::{
+    paginate, ListPage, LocalObjectStore, ObjectMeta, ObjectReader, ObjectStore, BLOB_PREFIX,
+};
+
+/// Directory below the store root that holds packfiles
+pub const PACKS_DIR: &str = "packs";
+
+/// Loose blobs up to this size are moved into packs; larger ones gain
+/// little from packing and stay loose so they can be streamed.
+pub const PACK_OBJECT_LIMIT: u64 = 1024 * 1024;
+
+/// First bytes of every packfile
+const PACK_MAGIC: &[u8; 8] = b"DXPACK01";
+
+const INDEX_VERSION: u32 = 1;
+
+/// One object inside a packfile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackEntry {
+    pub key: String,
+    pub offset: u64,
+    pub size: u64,
+    /// SHA-256 of the stored bytes
+    pub sha256: String,
+}
+
+/// Contents of a `.idx` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackIndex {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub entries: Vec<PackEntry>,
+}
+
+/// Where a packed object lives
+#[derive(Debug, Clone)]
+struct PackedObject {
+    pack: Arc<str>,
+    entry: PackEntry,
+    created_at: DateTime<Utc>,
+}
+
+/// Outcome of a repack
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepackReport {
+    /// Loose objects moved into the new pack
+    pub loose_packed: usize,
+    /// Objects in the new pack
+    pub objects: usize,
+    /// Old packs folded into the new one
+    pub packs_replaced: usize,
+    /// Size of the new pack in bytes
+    pub pack_size: u64,
+}
+
+/// Loose objects in a `LocalObjectStore` plus packfiles under `packs/`
+pub struct PackedObjectStore {
+    loose: LocalObjectStore,
+    packs_dir: PathBuf,
+    packed: RwLock<BTreeMap<String, PackedObject>>,
+    /// Serialises repacks and index rewrites
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl PackedObjectStore {
+    /// Open the store rooted at `root`, loading every pack index.
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let loose = LocalObjectStore::new(root)?;
+        let packs_dir = loose.root().join(PACKS_DIR);
+        std::fs::create_dir_all(&packs_dir)?;
+
+        let mut packed = BTreeMap::new();
+        for entry in std::fs::read_dir(&packs_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("idx") {
+                continue;
+            }
+            let Some(pack) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let index: PackIndex = serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("Corrupt pack index {}", path.display()))?;
+            if index.version != INDEX_VERSION {
+                bail!(
+                    "Unsupported pack index version {} in {}",
+                    index.version,
+                    path.display()
+                );
+            }
+
+            let pack: Arc<str> = Arc::from(pack);
+            for entry in index.entries {
+                packed.insert(
+                    entry.key.clone(),
+                    PackedObject {
+                        pack: pack.clone(),
+                        entry,
+                        created_at: index.created_at,
+                    },
+                );
+            }
+        }
+
+        Ok(Self {
+            loose,
+            packs_dir,
+            packed: RwLock::new(packed),
+            write_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        self.loose.root()
+    }
+
+    /// Names of the packs currently in use
+    pub fn packs(&self) -> Vec<String> {
+        let mut packs: Vec<String> = self
+            .packed
+            .read()
+            .values()
+            .map(|object| object.pack.to_string())
+            .collect();
+        packs.sort();
+        packs.dedup();
+        packs
+    }
+
+    /// Every packed object with the pack that holds it
+    pub fn packed_entries(&self) -> Vec<(String, PackEntry)> {
+        self.packed
+            .read()
+            .values()
+            .map(|object| (object.pack.to_string(), object.entry.clone()))
+            .collect()
+    }
+
+    /// Path of a pack's data (`.pack`) or index (`.idx`) file
+    pub fn pack_path(&self, pack: &str, extension: &str) -> PathBuf {
+        self.packs_dir.join(format!("{}.{}", pack, extension))
+    }
+
+    fn packed_object(&self, key: &str) -> Option<PackedObject> {
+        self.packed.read().get(key).cloned()
+    }
+
+    async fn read_packed(&self, object: &PackedObject) -> Result<Vec<u8>> {
+        let path = self.pack_path(&object.pack, "pack");
+        let mut file = fs::File::open(&path)
+            .await
+            .with_context(|| format!("Missing packfile {}", path.display()))?;
+        file.seek(std::io::SeekFrom::Start(object.entry.offset))
+            .await?;
+        let mut data = vec![0u8; object.entry.size as usize];
+        file.read_exact(&mut data)
+            .await
+            .with_context(|| format!("Truncated packfile {}", path.display()))?;
+        Ok(data)
+    }
+
+    /// Move small loose blobs and all existing packs into one new pack.
+    pub async fn repack(&self) -> Result<RepackReport> {
+        self.repack_retaining(|_| true).await
+    }
+
+    /// Like `repack`, but objects for which `keep` returns false are left
+    /// out of the new pack and deleted, whether loose or packed.
+    pub async fn repack_retaining(&self, keep: impl Fn(&str) -> bool) -> Result<RepackReport> {
+        let _guard = self.write_lock.lock().await;
+
+        let loose: Vec<ObjectMeta> = self
+            .loose
+            .list_all(BLOB_PREFIX)
+            .await?
+            .into_iter()
+            .filter(|meta| meta.size <= PACK_OBJECT_LIMIT || !keep(&meta.key))
+            .collect();
+        let packed: Vec<PackedObject> = self.packed.read().values().cloned().collect();
+        let old_packs = self.packs();
+
+        if loose.is_empty() && old_packs.len() <= 1 && packed.iter().all(|o| keep(&o.entry.key)) {
+            return Ok(RepackReport {
+                objects: packed.len(),
+                ..Default::default()
+            });
+        }
+
+        // Gather the new pack's contents, loose copies taking precedence
+        let mut sources: BTreeMap<String, Option<PackedObject>> = BTreeMap::new();
+        for object in packed {
+            if keep(&object.entry.key) {
+                sources.insert(object.entry.key.clone(), Some(object));
+            }
+        }
+        for meta in &loose {
+            if keep(&meta.key) {
+                sources.insert(meta.key.clone(), None);
+            }
+        }
+
+        let tmp = self
+            .packs_dir
+            .join(format!("tmp-{}.pack", uuid::Uuid::new_v4()));
+        let written = self.write_pack(&tmp, &sources).await;
+        let (name, entries, pack_size) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp).await;
+                return Err(e);
+            }
+        };
+
+        let report = RepackReport {
+            loose_packed: loose.iter().filter(|meta| keep(&meta.key)).count(),
+            objects: entries.len(),
+            packs_replaced: old_packs.iter().filter(|pack| **pack != name).count(),
+            pack_size,
+        };
+
+        if entries.is_empty() {
+            let _ = fs::remove_file(&tmp).await;
+            self.packed.write().clear();
+        } else {
+            fs::rename(&tmp, self.pack_path(&name, "pack")).await?;
+            let index = PackIndex {
+                version: INDEX_VERSION,
+                created_at: Utc::now(),
+                entries,
+            };
+            write_index(&self.pack_path(&name, "idx"), &index).await?;
+            self.install(&name, index);
+        }
+
+        for pack in old_packs.iter().filter(|pack| **pack != name) {
+            // Index first: a pack without an index is ignored
+            let _ = fs::remove_file(self.pack_path(pack, "idx")).await;
+            let _ = fs::remove_file(self.pack_path(pack, "pack")).await;
+        }
+        for meta in &loose {
+            self.loose.delete(&meta.key).await?;
+            self.remove_empty_dirs(&meta.key).await;
+        }
+
+        Ok(report)
+    }
+
+    /// Write the objects in `sources` (loose if `None`) to a packfile at
+    /// `path`, returning the pack's name, entries and size.
+    async fn write_pack(
+        &self,
+        path: &Path,
+        sources: &BTreeMap<String, Option<PackedObject>>,
+    ) -> Result<(String, Vec<PackEntry>, u64)> {
+        let mut file = fs::File::create(path).await?;
+        let mut pack_hasher = Sha256::new();
+        file.write_all(PACK_MAGIC).await?;
+        pack_hasher.update(PACK_MAGIC);
+        let mut offset = PACK_MAGIC.len() as u64;
+
+        let mut entries = Vec::with_capacity(sources.len());
+        for (key, source) in sources {
+            let data = match source {
+                Some(object) => self.read_packed(object).await?,
+                None => self
+                    .loose
+                    .get(key)
+                    .await?
+                    .with_context(|| format!("Object {} vanished during repack", key))?,
+            };
+
+            file.write_all(&data).await?;
+            pack_hasher.update(&data);
+            entries.push(PackEntry {
+                key: key.clone(),
+                offset,
+                size: data.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(&data)),
+            });
+            offset += data.len() as u64;
+        }
+        file.sync_all().await?;
+
+        Ok((
+            format!("pack-{:x}", pack_hasher.finalize()),
+            entries,
+            offset,
+        ))
+    }
+
+    /// Make `index` the live index for `pack`, replacing every other pack.
+    fn install(&self, pack: &str, index: PackIndex) {
+        let pack: Arc<str> = Arc::from(pack);
+        let mut packed = self.packed.write();
+        packed.clear();
+        for entry in index.entries {
+            packed.insert(
+                entry.key.clone(),
+                PackedObject {
+                    pack: pack.clone(),
+                    entry,
+                    created_at: index.created_at,
+                },
+            );
+        }
+    }
+
+    /// Drop `key` from its pack's index; the bytes stay until the next repack.
+    async fn remove_packed(&self, key: &str) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let Some(object) = self.packed.write().remove(key) else {
+            return Ok(());
+        };
+
+        let entries: Vec<PackEntry> = self
+            .packed
+            .read()
+            .values()
+            .filter(|other| other.pack == object.pack)
+            .map(|other| other.entry.clone())
+            .collect();
+
+        if entries.is_empty() {
+            let _ = fs::remove_file(self.pack_path(&object.pack, "idx")).await;
+            let _ = fs::remove_file(self.pack_path(&object.pack, "pack")).await;
+            return Ok(());
+        }
+
+        let index = PackIndex {
+            version: INDEX_VERSION,
+            created_at: object.created_at,
+            entries,
+        };
+        write_index(&self.pack_path(&object.pack, "idx"), &index).await
+    }
+
+    /// Remove the fan-out directory left empty after deleting a loose blob.
+    async fn remove_empty_dirs(&self, key: &str) {
+        if let Some((dir, _)) = key.rsplit_once('/') {
+            let _ = fs::remove_dir(self.root().join(dir)).await;
+        }
+    }
+}
+
+async fn write_index(path: &Path, index: &PackIndex) -> Result<()> {
+    let tmp = path.with_extension("idx.tmp");
+    fs::write(&tmp, serde_json::to_vec(index)?).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[async_trait]
+impl ObjectStore for PackedObjectStore {
+    fn name(&self) -> String {
+        format!("packed:{}", self.root().display())
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.loose.put(key, data).await
+    }
+
+    async fn put_reader(&self, key: &str, reader: ObjectReader, size: u64) -> Result<()> {
+        self.loose.put_reader(key, reader, size).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.loose.get(key).await? {
+            return Ok(Some(data));
+        }
+        match self.packed_object(key) {
+            Some(object) => Ok(Some(self.read_packed(&object).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_reader(&self, key: &str) -> Result<Option<(ObjectReader, u64)>> {
+        if let Some(found) = self.loose.get_reader(key).await? {
+            return Ok(Some(found));
+        }
+        match self.packed_object(key) {
+            Some(object) => {
+                let data = self.read_packed(&object).await?;
+                let size = data.len() as u64;
+                Ok(Some((Box::new(std::io::Cursor::new(data)), size)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        if let Some(meta) = self.loose.head(key).await? {
+            return Ok(Some(meta));
+        }
+        Ok(self.packed_object(key).map(|object| ObjectMeta {
+            key: key.to_string(),
+            size: object.entry.size,
+            etag: Some(object.entry.sha256),
+            last_modified: Some(object.created_at),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.loose.delete(key).await?;
+        self.remove_packed(key).await
+    }
+
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage> {
+        let packs_prefix = format!("{}/", PACKS_DIR);
+        let mut objects: BTreeMap<String, ObjectMeta> = self
+            .packed
+            .read()
+            .range::<str, _>((
+                std::ops::Bound::Included(prefix),
+                std::ops::Bound::Unbounded,
+            ))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| {
+                let meta = ObjectMeta {
+                    key: key.clone(),
+                    size: object.entry.size,
+                    etag: Some(object.entry.sha256.clone()),
+                    last_modified: Some(object.created_at),
+                };
+                (key.clone(), meta)
+            })
+            .collect();
+        for meta in self.loose.list_all(prefix).await? {
+            if !meta.key.starts_with(&packs_prefix) {
+                objects.insert(meta.key.clone(), meta);
+            }
+        }
+
+        let objects = objects
+            .into_values()
+            .filter(|meta| token.is_none_or(|token| meta.key.as_str() > token))
+            .collect();
+        Ok(paginate(objects, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn repack_moves_small_blobs_into_one_pack() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PackedObjectStore::open(dir.path()).unwrap();
+        for i in 0..20u8 {
+            store
+                .put(&format!("blobs/{:02x}/{}", i, i), vec![i; 100])
+                .await
+                .unwrap();
+        }
+        store.put("components/a.tsx", b"x".to_vec()).await.unwrap();
+
+        let report = store.repack().await.unwrap();
+        assert_eq!((report.loose_packed, report.objects), (20, 20));
+        assert_eq!(store.packs().len(), 1);
+        assert!(!dir.path().join("blobs/03").exists());
+
+        // Packed objects read, list and survive a reopen
+        let reopened = PackedObjectStore::open(dir.path()).unwrap();
+        assert_eq!(
+            reopened.get("blobs/03/3").await.unwrap(),
+            Some(vec![3; 100])
+        );
+        assert_eq!(
+            reopened.head("blobs/04/4").await.unwrap().unwrap().size,
+            100
+        );
+        assert_eq!(reopened.list_all("blobs/").await.unwrap().len(), 20);
+        assert_eq!(reopened.list_all("").await.unwrap().len(), 21);
+
+        // New writes are loose; the next repack folds the old pack in
+        reopened.put("blobs/ff/new", vec![9; 10]).await.unwrap();
+        reopened.delete("blobs/00/0").await.unwrap();
+        assert_eq!(reopened.get("blobs/00/0").await.unwrap(), None);
+        let report = reopened.repack().await.unwrap();
+        assert_eq!((report.loose_packed, report.objects), (1, 20));
+        assert_eq!(report.packs_replaced, 1);
+        assert_eq!(reopened.packs().len(), 1);
+        assert_eq!(
+            reopened.get("blobs/ff/new").await.unwrap(),
+            Some(vec![9; 10])
+        );
+    }
+
+    #[tokio::test]
+    async fn repack_retaining_drops_unwanted_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PackedObjectStore::open(dir.path()).unwrap();
+        store.put("blobs/aa/keep", vec![1]).await.unwrap();
+        store.put("blobs/bb/drop", vec![2]).await.unwrap();
+        store.repack().await.unwrap();
+        store.put("blobs/cc/drop", vec![3]).await.unwrap();
+
+        let report = store
+            .repack_retaining(|key| !key.ends_with("drop"))
+            .await
+            .unwrap();
+        assert_eq!(report.objects, 1);
+        let keys: Vec<_> = store
+            .list_all("blobs/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|meta| meta.key)
+            .collect();
+        assert_eq!(keys, vec!["blobs/aa/keep"]);
+    }
+}
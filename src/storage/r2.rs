@@ -3,12 +3,24 @@
 /// This module provides integration with Cloudflare R2 for blob storage.
 /// Zero egress fees make it perfect for code hosting platforms.
 use anyhow::{Context, Result};
-use reqwest::{header, Client, StatusCode};
+use async_trait::async_trait;
+use reqwest::{header, Client, Method, StatusCode};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
 
 use super::blob::Blob;
+use super::multipart::{
+    check_complete_response, complete_request_body, normalize_etag, parse_list_parts,
+    parse_upload_id, part_size_for, read_part, ListedPartInfo, MultipartState, UploadTracker,
+    UploadedPart, DEFAULT_PART_SIZE, MIN_PART_SIZE, MULTIPART_THRESHOLD,
+};
+use super::object_store::{
+    blob_key, component_key, ListPage, ObjectMeta, ObjectReader, ObjectStore, DEFAULT_LIST_LIMIT,
+};
+use super::sigv4::{self, CanonicalRequest, Credentials, SigningScope, UNSIGNED_PAYLOAD};
 
 /// R2 configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -27,6 +39,14 @@ pub struct R2Config {
 
     /// Custom domain (optional)
     pub custom_domain: Option<String>,
+
+    /// Full endpoint URL of another S3-compatible service, e.g. MinIO
+    #[serde(default)]
+    pub endpoint: Option<String>,
+
+    /// Signing region; R2 uses `auto`, most other services `us-east-1`
+    #[serde(default)]
+    pub region: Option<String>,
 }
 
 impl R2Config {
@@ -42,6 +62,8 @@ impl R2Config {
         let secret_access_key = std::env::var("R2_SECRET_ACCESS_KEY")
             .context("R2_SECRET_ACCESS_KEY not set in .env")?;
         let custom_domain = std::env::var("R2_CUSTOM_DOMAIN").ok();
+        let endpoint = std::env::var("R2_ENDPOINT").ok();
+        let region = std::env::var("R2_REGION").ok();
 
         Ok(Self {
             account_id,
@@ -49,12 +71,21 @@ impl R2Config {
             access_key_id,
             secret_access_key,
             custom_domain,
+            endpoint,
+            region,
         })
     }
 
+    /// Region used in request signatures
+    pub fn region(&self) -> &str {
+        self.region.as_deref().unwrap_or("auto")
+    }
+
     /// Get R2 endpoint URL
     pub fn endpoint_url(&self) -> String {
-        if let Some(domain) = &self.custom_domain {
+        if let Some(endpoint) = &self.endpoint {
+            endpoint.trim_end_matches('/').to_string()
+        } else if let Some(domain) = &self.custom_domain {
             format!("https://{}", domain)
         } else {
             format!("https://{}.r2.cloudflarestorage.com", self.account_id)
@@ -62,152 +93,452 @@ impl R2Config {
     }
 }
 
+/// SigV4 caps presigned URL lifetimes at 7 days
+const MAX_PRESIGN_SECS: u64 = 7 * 24 * 60 * 60;
+
 /// R2 storage client
+///
+/// Speaks the S3 API, so it also works against MinIO or any other
+/// S3-compatible endpoint configured through `R2Config::endpoint`.
 pub struct R2Storage {
     config: R2Config,
     client: Client,
+    multipart_threshold: u64,
+    part_size: usize,
+    uploads: UploadTracker,
 }
 
 impl R2Storage {
     /// Create new R2 storage client
     pub fn new(config: R2Config) -> Result<Self> {
-        let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+        // No overall timeout: large transfers legitimately take minutes
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .read_timeout(Duration::from_secs(60))
+            .build()?;
 
-        Ok(Self { config, client })
+        Ok(Self {
+            config,
+            client,
+            multipart_threshold: MULTIPART_THRESHOLD,
+            part_size: DEFAULT_PART_SIZE,
+            uploads: UploadTracker::new(std::env::temp_dir().join("dx-forge-uploads")),
+        })
     }
 
-    /// Upload blob to R2
-    pub async fn upload_blob(&self, blob: &Blob) -> Result<String> {
-        let hash = blob.hash();
-        let key = format!("blobs/{}/{}", &hash[..2], &hash[2..]);
-
-        let binary = blob.to_binary()?;
-        let content_hash = compute_sha256_hex(&binary);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    /// Upload objects larger than `threshold` in parts of `part_size`
+    pub fn with_multipart(mut self, threshold: u64, part_size: usize) -> Self {
+        self.multipart_threshold = threshold;
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
 
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
+    /// Keep resumable multipart state in `dir` (e.g. under `.dx/forge`)
+    pub fn with_upload_state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.uploads = UploadTracker::new(dir);
+        self
+    }
 
-        // Create AWS Signature V4 (simplified - in production use aws-sigv4 crate)
-        let authorization = self.create_auth_header("PUT", &key, &binary)?;
+    /// Path of `key` on the endpoint. A custom domain is bound to the
+    /// bucket itself; other endpoints are addressed path-style.
+    fn object_path(&self, key: &str) -> String {
+        if self.config.endpoint.is_none() && self.config.custom_domain.is_some() {
+            format!("/{}", key)
+        } else {
+            format!("/{}/{}", self.config.bucket_name, key)
+        }
+    }
 
-        let response = self
-            .client
-            .put(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header(header::CONTENT_TYPE, "application/octet-stream")
-            .header("x-amz-content-sha256", content_hash)
-            .header("x-amz-date", date)
-            .body(binary)
-            .send()
-            .await?;
+    /// `host[:port]` exactly as the HTTP client will send it
+    fn host(&self) -> Result<String> {
+        let endpoint = Url::parse(&self.config.endpoint_url())
+            .with_context(|| format!("Invalid endpoint {}", self.config.endpoint_url()))?;
+        let host = endpoint
+            .host_str()
+            .with_context(|| format!("Endpoint {} has no host", endpoint))?;
+        Ok(match endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        })
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 upload failed: {} - {}", status, body);
+    fn credentials(&self) -> Credentials {
+        Credentials {
+            access_key_id: self.config.access_key_id.clone(),
+            secret_access_key: self.config.secret_access_key.clone(),
         }
+    }
 
-        Ok(key)
+    fn url(&self, request: &CanonicalRequest) -> String {
+        let query = request.canonical_query();
+        let path = sigv4::uri_encode(&request.path, false);
+        if query.is_empty() {
+            format!("{}{}", self.config.endpoint_url(), path)
+        } else {
+            format!("{}{}?{}", self.config.endpoint_url(), path, query)
+        }
     }
 
-    /// Download blob from R2
-    pub async fn download_blob(&self, hash: &str) -> Result<Blob> {
-        let key = format!("blobs/{}/{}", &hash[..2], &hash[2..]);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    /// Send a SigV4-signed request for `key`.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, String)],
+        body: Option<(Vec<u8>, &str)>,
+    ) -> Result<reqwest::Response> {
+        self.send_with_headers(method, key, query, &[], body).await
+    }
 
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
+    /// `send` with extra signed headers such as `range` or `content-md5`.
+    async fn send_with_headers(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, String)],
+        headers: &[(&str, String)],
+        body: Option<(Vec<u8>, &str)>,
+    ) -> Result<reqwest::Response> {
+        let scope = SigningScope {
+            region: self.config.region(),
+            service: "s3",
+            time: chrono::Utc::now(),
+        };
+        let amz_date = scope.time.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = match &body {
+            Some((bytes, _)) => sigv4::sha256_hex(bytes),
+            None => UNSIGNED_PAYLOAD.to_string(),
+        };
 
-        let authorization = self.create_auth_header("GET", &key, &[])?;
+        let mut canonical =
+            CanonicalRequest::new(method.as_str(), &self.object_path(key), &self.host()?)
+                .header("x-amz-content-sha256", &payload_hash)
+                .header("x-amz-date", &amz_date);
+        canonical.payload_hash = payload_hash.clone();
+        for (name, value) in query {
+            canonical = canonical.query(name, value);
+        }
+        if let Some((_, content_type)) = &body {
+            canonical = canonical.header("content-type", content_type);
+        }
+        for (name, value) in headers {
+            canonical = canonical.header(name, value);
+        }
+        let authorization = sigv4::authorization_header(&self.credentials(), &scope, &canonical)?;
 
-        let response = self
+        let mut request = self
             .client
-            .get(&url)
+            .request(method, self.url(&canonical))
             .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .header("x-amz-content-sha256", "UNSIGNED-PAYLOAD")
-            .send()
-            .await?;
-
-        if response.status() == StatusCode::NOT_FOUND {
-            anyhow::bail!("Blob not found: {}", hash);
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date);
+        for (name, value) in headers {
+            request = request.header(*name, value);
         }
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 download failed: {} - {}", status, body);
+        if let Some((bytes, content_type)) = body {
+            request = request
+                .header(header::CONTENT_TYPE, content_type)
+                .body(bytes);
         }
 
-        let binary = response.bytes().await?;
-        Blob::from_binary(&binary)
+        Ok(request.send().await?)
     }
 
-    /// Check if blob exists in R2
-    pub async fn blob_exists(&self, hash: &str) -> Result<bool> {
-        let key = format!("blobs/{}/{}", &hash[..2], &hash[2..]);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    /// Presigned URL granting `method` on `key` to anyone holding it until
+    /// it expires (at most 7 days, the SigV4 limit).
+    pub fn presign(&self, method: Method, key: &str, expires: Duration) -> Result<String> {
+        let expires_in = expires.as_secs();
+        if !(1..=MAX_PRESIGN_SECS).contains(&expires_in) {
+            anyhow::bail!(
+                "Presigned URLs must expire within 1s..7 days, got {}s",
+                expires_in
+            );
+        }
 
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
+        let scope = SigningScope {
+            region: self.config.region(),
+            service: "s3",
+            time: chrono::Utc::now(),
+        };
+        let request = CanonicalRequest::new(method.as_str(), &self.object_path(key), &self.host()?);
+        let path = sigv4::uri_encode(&request.path, false);
+        let query = sigv4::presign_query(&self.credentials(), &scope, request, expires_in)?;
 
-        let authorization = self.create_auth_header("HEAD", &key, &[])?;
+        Ok(format!("{}{}?{}", self.config.endpoint_url(), path, query))
+    }
 
+    async fn put_object(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<()> {
         let response = self
-            .client
-            .head(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .send()
+            .send(Method::PUT, key, &[], Some((data, content_type)))
             .await?;
 
-        Ok(response.status().is_success())
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("R2 upload of {} failed: {} - {}", key, status, body);
+        }
+        Ok(())
     }
 
-    /// Delete blob from R2
-    pub async fn delete_blob(&self, hash: &str) -> Result<()> {
-        let key = format!("blobs/{}/{}", &hash[..2], &hash[2..]);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    /// Upload `size` bytes from `reader` in parts, resuming a previous
+    /// attempt for the same key and size when its parts are still present.
+    pub async fn upload_multipart(
+        &self,
+        key: &str,
+        reader: &mut ObjectReader,
+        size: u64,
+    ) -> Result<()> {
+        let scope = self.name();
+        let part_size = part_size_for(size, self.part_size);
+
+        let mut state = match self.resume_upload(&scope, key, size, part_size).await {
+            Some(state) => state,
+            None => {
+                let upload_id = self.create_multipart_upload(key).await?;
+                let state = MultipartState::new(key, upload_id, size, part_size);
+                self.uploads.save(&scope, &state).await?;
+                state
+            }
+        };
 
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
+        let mut number = 1u32;
+        let mut sent = 0u64;
+        loop {
+            let data = read_part(reader, part_size).await?;
+            if data.is_empty() {
+                break;
+            }
+            sent += data.len() as u64;
+
+            let md5 = format!("{:x}", md5::compute(&data));
+            if state.completed_part(number, &md5).is_none() {
+                let part_len = data.len() as u64;
+                let etag = self
+                    .upload_part(key, &state.upload_id, number, data, &md5)
+                    .await?;
+                state.record(UploadedPart {
+                    number,
+                    etag,
+                    size: part_len,
+                    md5,
+                });
+                self.uploads.save(&scope, &state).await?;
+            }
+            number += 1;
+        }
+
+        if sent != size {
+            anyhow::bail!(
+                "Source for {} changed during upload: expected {} bytes, read {}",
+                key,
+                size,
+                sent
+            );
+        }
+
+        state.parts.retain(|part| part.number < number);
+        self.complete_multipart_upload(key, &state).await?;
+        self.uploads.remove(&scope, key).await;
+        Ok(())
+    }
 
-        let authorization = self.create_auth_header("DELETE", &key, &[])?;
+    /// Saved state for an interrupted upload, limited to the parts the store
+    /// still has. Stale uploads are discarded.
+    async fn resume_upload(
+        &self,
+        scope: &str,
+        key: &str,
+        size: u64,
+        part_size: usize,
+    ) -> Option<MultipartState> {
+        let mut state = self.uploads.load(scope, key).await?;
+        if state.size != size || state.part_size != part_size {
+            let _ = self.abort_multipart_upload(key, &state.upload_id).await;
+            self.uploads.remove(scope, key).await;
+            return None;
+        }
 
+        match self.list_parts(key, &state.upload_id).await {
+            Ok(Some(remote)) => {
+                state.parts.retain(|part| {
+                    remote.iter().any(|(number, etag, size)| {
+                        *number == part.number && *etag == part.etag && *size == part.size
+                    })
+                });
+                Some(state)
+            }
+            _ => {
+                self.uploads.remove(scope, key).await;
+                None
+            }
+        }
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
         let response = self
-            .client
-            .delete(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .send()
+            .send(Method::POST, key, &[("uploads", String::new())], None)
             .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Starting multipart upload of {} failed: {} - {}",
+                key,
+                status,
+                body
+            );
+        }
+        parse_upload_id(&response.text().await?)
+    }
 
+    /// Upload one part; the store verifies it against `content-md5` and
+    /// the signed SHA-256, and we check the returned ETag.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+        md5_hex: &str,
+    ) -> Result<String> {
+        use base64::Engine;
+        let content_md5 = base64::engine::general_purpose::STANDARD.encode(hex::decode(md5_hex)?);
+        let query = [
+            ("partNumber", number.to_string()),
+            ("uploadId", upload_id.to_string()),
+        ];
+        let response = self
+            .send_with_headers(
+                Method::PUT,
+                key,
+                &query,
+                &[("content-md5", content_md5)],
+                Some((data, "application/octet-stream")),
+            )
+            .await?;
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 delete failed: {} - {}", status, body);
+            anyhow::bail!(
+                "Uploading part {} of {} failed: {} - {}",
+                number,
+                key,
+                status,
+                body
+            );
+        }
+
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(normalize_etag)
+            .with_context(|| format!("Part {} of {} has no ETag", number, key))?;
+        if etag != md5_hex {
+            anyhow::bail!(
+                "Part {} of {} was corrupted in transit (ETag {}, expected {})",
+                number,
+                key,
+                etag,
+                md5_hex
+            );
+        }
+        Ok(etag)
+    }
+
+    /// Parts of an upload; `None` if the upload no longer exists.
+    async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Option<Vec<ListedPartInfo>>> {
+        let mut parts = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut query = vec![("uploadId", upload_id.to_string())];
+            if let Some(marker) = marker {
+                query.push(("part-number-marker", format!("{}", marker)));
+            }
+            let response = self.send(Method::GET, key, &query, None).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            if !response.status().is_success() {
+                anyhow::bail!("Listing parts of {} failed: {}", key, response.status());
+            }
+
+            let (page, next) = parse_list_parts(&response.text().await?)?;
+            parts.extend(page);
+            match next {
+                Some(next) => marker = Some(next),
+                None => return Ok(Some(parts)),
+            }
         }
+    }
 
+    async fn complete_multipart_upload(&self, key: &str, state: &MultipartState) -> Result<()> {
+        let body = complete_request_body(&state.parts).into_bytes();
+        let response = self
+            .send(
+                Method::POST,
+                key,
+                &[("uploadId", state.upload_id.clone())],
+                Some((body, "application/xml")),
+            )
+            .await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            anyhow::bail!(
+                "Completing multipart upload of {} failed: {} - {}",
+                key,
+                status,
+                text
+            );
+        }
+        check_complete_response(&text)
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        let response = self
+            .send(
+                Method::DELETE,
+                key,
+                &[("uploadId", upload_id.to_string())],
+                None,
+            )
+            .await?;
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            anyhow::bail!(
+                "Aborting multipart upload of {} failed: {}",
+                key,
+                response.status()
+            );
+        }
         Ok(())
     }
 
+    /// Upload blob to R2
+    pub async fn upload_blob(&self, blob: &Blob) -> Result<String> {
+        self.put_blob(blob).await
+    }
+
+    /// Download blob from R2, decompressed and reassembled
+    pub async fn download_blob(&self, hash: &str) -> Result<Blob> {
+        let mut blob = self
+            .get_blob(hash)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Blob not found: {}", hash))?;
+        super::chunking::resolve(self, &mut blob).await?;
+        Ok(blob)
+    }
+
+    /// Check if blob exists in R2
+    pub async fn blob_exists(&self, hash: &str) -> Result<bool> {
+        self.has_blob(hash).await
+    }
+
+    /// Delete blob from R2
+    pub async fn delete_blob(&self, hash: &str) -> Result<()> {
+        self.delete(&blob_key(hash)).await
+    }
+
     /// Download component from R2
     pub async fn download_component(
         &self,
@@ -216,39 +547,12 @@ impl R2Storage {
         version: Option<&str>,
     ) -> Result<String> {
         let version = version.unwrap_or("latest");
-        let key = format!("components/{}/{}/{}.tsx", tool, version, component);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
-
-        let authorization = self.create_auth_header("GET", &key, &[])?;
-
-        let response = self
-            .client
-            .get(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .header("x-amz-content-sha256", "UNSIGNED-PAYLOAD")
-            .send()
-            .await?;
+        let key = component_key(tool, version, component);
 
-        if response.status() == StatusCode::NOT_FOUND {
-            anyhow::bail!("Component not found: {}/{} v{}", tool, component, version);
-        }
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 component download failed: {} - {}", status, body);
+        match self.get(&key).await? {
+            Some(content) => Ok(String::from_utf8(content)?),
+            None => anyhow::bail!("Component not found: {}/{} v{}", tool, component, version),
         }
-
-        let content = response.text().await?;
-        Ok(content)
     }
 
     /// Upload component to R2
@@ -259,37 +563,13 @@ impl R2Storage {
         version: &str,
         content: &str,
     ) -> Result<String> {
-        let key = format!("components/{}/{}/{}.tsx", tool, version, component);
-        let binary = content.as_bytes();
-        let content_hash = compute_sha256_hex(binary);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
-
-        let authorization = self.create_auth_header("PUT", &key, binary)?;
-
-        let response = self
-            .client
-            .put(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
-            .header("x-amz-content-sha256", content_hash)
-            .header("x-amz-date", date)
-            .body(content.to_string())
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 component upload failed: {} - {}", status, body);
-        }
-
+        let key = component_key(tool, version, component);
+        self.put_object(
+            &key,
+            content.as_bytes().to_vec(),
+            "text/plain; charset=utf-8",
+        )
+        .await?;
         Ok(key)
     }
 
@@ -301,105 +581,63 @@ impl R2Storage {
         version: Option<&str>,
     ) -> Result<bool> {
         let version = version.unwrap_or("latest");
-        let key = format!("components/{}/{}/{}.tsx", tool, version, component);
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-
-        let url = format!(
-            "{}/{}/{}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            key
-        );
-
-        let authorization = self.create_auth_header("HEAD", &key, &[])?;
-
-        let response = self
-            .client
-            .head(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .send()
-            .await?;
-
-        Ok(response.status().is_success())
+        Ok(self
+            .head(&component_key(tool, version, component))
+            .await?
+            .is_some())
     }
 
     /// List all components in R2
     pub async fn list_components(&self, tool: &str) -> Result<Vec<String>> {
         let prefix = format!("components/{}/", tool);
-        let url = format!(
-            "{}/{}/?list-type=2&prefix={}",
-            self.config.endpoint_url(),
-            self.config.bucket_name,
-            prefix
-        );
-
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-        let authorization = self.create_auth_header("GET", &format!("?list-type=2&prefix={}", prefix), &[])?;
-
-        let response = self
-            .client
-            .get(&url)
-            .header(header::AUTHORIZATION, authorization)
-            .header("x-amz-date", date)
-            .header("x-amz-content-sha256", "UNSIGNED-PAYLOAD")
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("R2 list failed: {} - {}", status, body);
-        }
-
-        // Parse XML response (simplified - in production use proper XML parser)
-        let body = response.text().await?;
-        let mut components = Vec::new();
-        
-        for line in body.lines() {
-            if line.contains("<Key>") {
-                let key = line.replace("<Key>", "").replace("</Key>", "").trim().to_string();
-                if let Some(name) = key.split('/').last() {
-                    if let Some(component_name) = name.strip_suffix(".tsx") {
-                        components.push(component_name.to_string());
-                    }
-                }
-            }
-        }
+        let components = self
+            .list_all(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|meta| {
+                let name = meta.key.rsplit('/').next()?;
+                name.strip_suffix(".tsx").map(str::to_string)
+            })
+            .collect();
 
         Ok(components)
     }
 
     /// Sync components (bidirectional)
     pub async fn sync_components(
-        &self, 
-        tool: &str, 
+        &self,
+        tool: &str,
         local_components: &[String],
         on_download: impl Fn(&str),
-        on_upload: impl Fn(&str)
+        on_upload: impl Fn(&str),
     ) -> Result<()> {
         // 1. List remote components
         let remote_components = self.list_components(tool).await?;
-        
+
         // 2. Calculate sync actions
-        let (to_download, to_upload) = self.calculate_sync_actions(&remote_components, local_components);
-        
+        let (to_download, to_upload) =
+            self.calculate_sync_actions(&remote_components, local_components);
+
         // 3. Execute actions
         for remote in to_download {
             on_download(&remote);
         }
-        
+
         for local in to_upload {
             on_upload(&local);
         }
-        
+
         Ok(())
     }
 
     /// Calculate what needs to be downloaded and uploaded
     /// Returns (to_download, to_upload)
     #[cfg_attr(test, allow(dead_code))]
-    pub(crate) fn calculate_sync_actions(&self, remote_components: &[String], local_components: &[String]) -> (Vec<String>, Vec<String>) {
+    pub(crate) fn calculate_sync_actions(
+        &self,
+        remote_components: &[String],
+        local_components: &[String],
+    ) -> (Vec<String>, Vec<String>) {
         let mut to_download = Vec::new();
         let mut to_upload = Vec::new();
 
@@ -418,246 +656,305 @@ impl R2Storage {
         (to_download, to_upload)
     }
 
-    /// Create AWS Signature V4 authorization header (simplified)
-    fn create_auth_header(&self, method: &str, key: &str, body: &[u8]) -> Result<String> {
-        // Simplified auth - in production, use aws-sigv4 crate for proper signing
-        // For R2, you can also use S3-compatible libraries
+    /// List all blob hashes in R2 bucket
+    pub async fn list_blobs(&self) -> Result<Vec<String>> {
+        self.list_blob_hashes().await
+    }
 
-        use hmac::{Hmac, Mac};
-        use sha2::Sha256;
+    /// One ListObjectsV2 call. With a `delimiter`, keys sharing the part
+    /// of their name up to the next delimiter are grouped into
+    /// `common_prefixes` instead of being returned individually.
+    pub async fn list_objects(
+        &self,
+        prefix: &str,
+        delimiter: Option<&str>,
+        token: Option<&str>,
+        max_keys: usize,
+    ) -> Result<ListObjectsResult> {
+        let mut params = vec![
+            ("list-type", "2".to_string()),
+            (
+                "max-keys",
+                max_keys.clamp(1, DEFAULT_LIST_LIMIT).to_string(),
+            ),
+            ("prefix", prefix.to_string()),
+        ];
+        if let Some(delimiter) = delimiter {
+            params.push(("delimiter", delimiter.to_string()));
+        }
+        if let Some(token) = token {
+            params.push(("continuation-token", token.to_string()));
+        }
+        let response = self.send(Method::GET, "", &params, None).await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("R2 list failed: {} - {}", status, body);
+        }
 
-        type HmacSha256 = Hmac<Sha256>;
+        parse_list_objects(&response.text().await?)
+    }
 
-        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-        let date_short = &date[..8];
+    /// Every common prefix below `prefix`, following continuation tokens.
+    pub async fn list_common_prefixes(&self, prefix: &str, delimiter: &str) -> Result<Vec<String>> {
+        let mut prefixes = Vec::new();
+        let mut token = None;
+        loop {
+            let page = self
+                .list_objects(
+                    prefix,
+                    Some(delimiter),
+                    token.as_deref(),
+                    DEFAULT_LIST_LIMIT,
+                )
+                .await?;
+            prefixes.extend(page.common_prefixes);
+            match page.next_token {
+                Some(next) => token = Some(next),
+                None => return Ok(prefixes),
+            }
+        }
+    }
+}
 
-        let body_hash = compute_sha256_hex(body);
-        let host = format!("{}.r2.cloudflarestorage.com", self.config.account_id);
+#[async_trait]
+impl ObjectStore for R2Storage {
+    fn name(&self) -> String {
+        format!(
+            "s3:{}/{}",
+            self.config.endpoint_url(),
+            self.config.bucket_name
+        )
+    }
 
-        // Canonical request
-        let canonical_request = format!(
-            "{}\n/{}/{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
-            method,
-            self.config.bucket_name,
-            key,
-            host,
-            body_hash,
-            date,
-            body_hash
-        );
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.put_object(key, data, "application/octet-stream").await
+    }
 
-        let canonical_request_hash = compute_sha256_hex(canonical_request.as_bytes());
+    async fn put_reader(&self, key: &str, mut reader: ObjectReader, size: u64) -> Result<()> {
+        if size <= self.multipart_threshold {
+            let mut data = Vec::with_capacity(size as usize);
+            reader.read_to_end(&mut data).await?;
+            return self.put(key, data).await;
+        }
+        self.upload_multipart(key, &mut reader, size).await
+    }
 
-        // String to sign
-        let string_to_sign = format!(
-            "AWS4-HMAC-SHA256\n{}\n{}/auto/s3/aws4_request\n{}",
-            date, date_short, canonical_request_hash
-        );
+    async fn get_range(&self, key: &str, start: u64, end: Option<u64>) -> Result<Option<Vec<u8>>> {
+        if end.is_some_and(|end| end <= start) {
+            return Ok(self.head(key).await?.map(|_| Vec::new()));
+        }
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end - 1),
+            None => format!("bytes={}-", start),
+        };
+        let response = self
+            .send_with_headers(Method::GET, key, &[], &[("range", range)], None)
+            .await?;
 
-        // Signing key
-        let mut mac = HmacSha256::new_from_slice(
-            format!("AWS4{}", self.config.secret_access_key).as_bytes(),
-        )?;
-        mac.update(date_short.as_bytes());
-        let date_key = mac.finalize().into_bytes();
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            // Asking for bytes past the end of the object
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Some(Vec::new())),
+            StatusCode::PARTIAL_CONTENT => Ok(Some(response.bytes().await?.to_vec())),
+            // Range ignored: trim the full body ourselves
+            status if status.is_success() => {
+                let data = response.bytes().await?;
+                let len = data.len() as u64;
+                let end = end.unwrap_or(len).min(len);
+                Ok(Some(data[start.min(end) as usize..end as usize].to_vec()))
+            }
+            status => anyhow::bail!("R2 ranged download of {} failed: {}", key, status),
+        }
+    }
 
-        let mut mac = HmacSha256::new_from_slice(&date_key)?;
-        mac.update(b"auto");
-        let region_key = mac.finalize().into_bytes();
+    /// Streams the body to disk. An interrupted download leaves
+    /// `<dest>.part` behind and the next call continues from its end.
+    async fn get_to_file(&self, key: &str, dest: &Path) -> Result<Option<u64>> {
+        let partial = dest.with_extension("part");
+        let offset = match tokio::fs::metadata(&partial).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
 
-        let mut mac = HmacSha256::new_from_slice(&region_key)?;
-        mac.update(b"s3");
-        let service_key = mac.finalize().into_bytes();
+        let headers: Vec<(&str, String)> = if offset > 0 {
+            vec![("range", format!("bytes={}-", offset))]
+        } else {
+            Vec::new()
+        };
+        let mut response = self
+            .send_with_headers(Method::GET, key, &[], &headers, None)
+            .await?;
 
-        let mut mac = HmacSha256::new_from_slice(&service_key)?;
-        mac.update(b"aws4_request");
-        let signing_key = mac.finalize().into_bytes();
+        let mut file = match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::PARTIAL_CONTENT => {
+                tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&partial)
+                    .await?
+            }
+            // The partial file already holds the whole object
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                tokio::fs::rename(&partial, dest).await?;
+                return Ok(Some(offset));
+            }
+            status if status.is_success() => tokio::fs::File::create(&partial).await?,
+            status => anyhow::bail!("R2 download of {} failed: {}", key, status),
+        };
 
-        // Signature
-        let mut mac = HmacSha256::new_from_slice(&signing_key)?;
-        mac.update(string_to_sign.as_bytes());
-        let signature = hex::encode(mac.finalize().into_bytes());
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+        drop(file);
 
-        Ok(format!(
-            "AWS4-HMAC-SHA256 Credential={}/{}/auto/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
-            self.config.access_key_id,
-            date_short,
-            signature
-        ))
+        let size = tokio::fs::metadata(&partial).await?.len();
+        tokio::fs::rename(&partial, dest).await?;
+        Ok(Some(size))
     }
 
-    /// Sync local blobs up to R2 (upload missing blobs)
-    pub async fn sync_up(
-        &self,
-        local_blobs: Vec<Blob>,
-        progress_callback: Option<impl Fn(usize, usize) + Send + Sync>,
-    ) -> Result<SyncResult> {
-        use futures::stream::{self, StreamExt};
-        
-        tracing::info!("ðŸ”„ Starting R2 sync up: {} local blobs", local_blobs.len());
-        
-        let mut uploaded = 0;
-        let mut skipped = 0;
-        let mut errors = Vec::new();
-        let total = local_blobs.len();
-
-        // Check which blobs already exist in R2
-        let mut to_upload = Vec::new();
-        for blob in local_blobs {
-            match self.blob_exists(&blob.hash()).await {
-                Ok(exists) => {
-                    if exists {
-                        skipped += 1;
-                    } else {
-                        to_upload.push(blob);
-                    }
-                }
-                Err(e) => {
-                    errors.push(format!("Failed to check blob {}: {}", blob.hash(), e));
-                    to_upload.push(blob); // Try to upload anyway
-                }
-            }
-        }
+    fn presigned_url(&self, method: &str, key: &str, expires: Duration) -> Result<Option<String>> {
+        let method = Method::from_bytes(method.to_uppercase().as_bytes())?;
+        self.presign(method, key, expires).map(Some)
+    }
 
-        // Upload missing blobs in parallel (max 10 concurrent)
-        let mut stream = stream::iter(to_upload.into_iter().enumerate())
-            .map(|(idx, blob)| async move {
-                let hash = blob.hash();
-                match self.upload_blob(&blob).await {
-                    Ok(_) => Ok::<(usize, String), String>((idx, hash.to_string())),
-                    Err(e) => Err(format!("Failed to upload {}: {}", hash, e)),
-                }
-            })
-            .buffer_unordered(10);
-
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok((_idx, _hash)) => {
-                    uploaded += 1;
-                    if let Some(cb) = &progress_callback {
-                        cb(uploaded + skipped, total);
-                    }
-                }
-                Err(e) => {
-                    errors.push(e);
-                }
-            }
-        }
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.send(Method::GET, key, &[], None).await?;
 
-        tracing::info!(
-            "âœ… Sync up complete: {} uploaded, {} skipped, {} errors",
-            uploaded,
-            skipped,
-            errors.len()
-        );
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("R2 download of {} failed: {} - {}", key, status, body);
+        }
 
-        Ok(SyncResult {
-            uploaded,
-            downloaded: 0,
-            skipped,
-            errors,
-        })
+        Ok(Some(response.bytes().await?.to_vec()))
     }
 
-    /// Sync remote blobs down from R2 (download missing blobs)
-    pub async fn sync_down(
-        &self,
-        remote_hashes: Vec<String>,
-        progress_callback: Option<impl Fn(usize, usize) + Send + Sync>,
-    ) -> Result<Vec<Blob>> {
-        use futures::stream::{self, StreamExt};
-        
-        tracing::info!("ðŸ”„ Starting R2 sync down: {} remote blobs", remote_hashes.len());
-        
-        let total = remote_hashes.len();
-        let mut downloaded_blobs = Vec::new();
-
-        // Download blobs in parallel (max 10 concurrent)
-        let mut stream = stream::iter(remote_hashes.into_iter().enumerate())
-            .map(|(idx, hash)| async move {
-                match self.download_blob(&hash).await {
-                    Ok(blob) => Ok::<(usize, Blob), String>((idx, blob)),
-                    Err(e) => Err(format!("Failed to download {}: {}", hash, e)),
-                }
-            })
-            .buffer_unordered(10);
-
-        let mut errors = Vec::new();
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok((idx, blob)) => {
-                    downloaded_blobs.push(blob);
-                    if let Some(cb) = &progress_callback {
-                        cb(idx + 1, total);
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("âš ï¸ {}", e);
-                    errors.push(e);
-                }
-            }
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let response = self.send(Method::HEAD, key, &[], None).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("R2 head of {} failed: {}", key, response.status());
         }
 
-        tracing::info!(
-            "âœ… Sync down complete: {} downloaded, {} errors", downloaded_blobs.len(),
-            errors.len()
-        );
+        let headers = response.headers();
+        let text = |name: header::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(Some(ObjectMeta {
+            key: key.to_string(),
+            size: response.content_length().unwrap_or_default(),
+            etag: text(header::ETAG).map(|etag| etag.trim_matches('"').to_string()),
+            last_modified: text(header::LAST_MODIFIED)
+                .and_then(|date| chrono::DateTime::parse_from_rfc2822(&date).ok())
+                .map(|date| date.with_timezone(&chrono::Utc)),
+        }))
+    }
 
-        Ok(downloaded_blobs)
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self.send(Method::DELETE, key, &[], None).await?;
+
+        // S3 answers 204 for missing keys too; some stand-ins say 404
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("R2 delete of {} failed: {} - {}", key, status, body);
+        }
+        Ok(())
     }
 
-    /// List all blob hashes in R2 bucket (simplified - in production use pagination)
-    pub async fn list_blobs(&self, _prefix: Option<&str>) -> Result<Vec<String>> {
-        // This is a simplified version. In production, use S3 ListObjects API
-        // For now, return empty list as listing requires more complex S3 API integration
-        tracing::warn!("R2 list_blobs not fully implemented - requires S3 ListObjects API");
-        Ok(Vec::new())
+    async fn list(&self, prefix: &str, token: Option<&str>, limit: usize) -> Result<ListPage> {
+        let result = self.list_objects(prefix, None, token, limit).await?;
+        Ok(ListPage {
+            objects: result.objects,
+            next_token: result.next_token,
+        })
     }
 }
 
-/// Sync operation result
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SyncResult {
-    pub uploaded: usize,
-    pub downloaded: usize,
-    pub skipped: usize,
-    pub errors: Vec<String>,
+/// One page of an S3 ListObjectsV2 response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListObjectsResult {
+    pub objects: Vec<ObjectMeta>,
+    /// Keys rolled up by the delimiter, e.g. `components/dx-ui/`
+    pub common_prefixes: Vec<String>,
+    /// Continuation token for the next page, if the listing was truncated
+    pub next_token: Option<String>,
 }
 
-/// Compute SHA-256 hex string
-fn compute_sha256_hex(data: &[u8]) -> String {
-    use sha2::Digest;
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    format!("{:x}", hasher.finalize())
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    common_prefixes: Vec<ListedPrefix>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
 }
 
-/// Batch upload blobs with progress tracking
-pub async fn batch_upload_blobs(
-    storage: &R2Storage,
-    blobs: Vec<Blob>,
-    progress_callback: impl Fn(usize, usize),
-) -> Result<Vec<String>> {
-    use futures::stream::{self, StreamExt};
-
-    let total = blobs.len();
-    let mut keys = Vec::with_capacity(total);
-
-    // Upload in parallel (max 10 concurrent)
-    let mut stream = stream::iter(blobs.into_iter().enumerate())
-        .map(|(idx, blob)| async move {
-            let key = storage.upload_blob(&blob).await?;
-            Ok::<(usize, String), anyhow::Error>((idx, key))
-        })
-        .buffer_unordered(10);
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(rename = "ETag")]
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedPrefix {
+    prefix: String,
+}
+
+/// Parse a ListObjectsV2 response body
+fn parse_list_objects(xml: &str) -> Result<ListObjectsResult> {
+    let parsed: ListBucketResult =
+        quick_xml::de::from_str(xml).context("Malformed ListObjectsV2 response")?;
 
-    while let Some(result) = stream.next().await {
-        let (idx, key) = result?;
-        keys.push(key);
-        progress_callback(idx + 1, total);
+    if parsed.is_truncated && parsed.next_continuation_token.is_none() {
+        anyhow::bail!("Truncated ListObjectsV2 response without a continuation token");
     }
 
-    Ok(keys)
+    let objects = parsed
+        .contents
+        .into_iter()
+        .map(|object| ObjectMeta {
+            key: object.key,
+            size: object.size,
+            etag: object.etag.map(|etag| etag.trim_matches('"').to_string()),
+            last_modified: object
+                .last_modified
+                .and_then(|date| chrono::DateTime::parse_from_rfc3339(&date).ok())
+                .map(|date| date.with_timezone(&chrono::Utc)),
+        })
+        .collect();
+
+    Ok(ListObjectsResult {
+        objects,
+        common_prefixes: parsed
+            .common_prefixes
+            .into_iter()
+            .map(|prefix| prefix.prefix)
+            .collect(),
+        next_token: parsed
+            .next_continuation_token
+            .filter(|_| parsed.is_truncated),
+    })
 }
 
 #[cfg(test)]
@@ -672,36 +969,125 @@ mod tests {
             access_key_id: "test-key".to_string(),
             secret_access_key: "test-secret".to_string(),
             custom_domain: None,
+            endpoint: None,
+            region: None,
         };
 
         assert!(config.endpoint_url().contains("test-account"));
         assert!(config.endpoint_url().contains("r2.cloudflarestorage.com"));
     }
 
+    #[test]
+    fn test_presigned_urls_use_the_configured_endpoint() {
+        let storage = R2Storage::new(R2Config {
+            bucket_name: "forge".to_string(),
+            access_key_id: "minio".to_string(),
+            secret_access_key: "minio-secret".to_string(),
+            endpoint: Some("http://localhost:9000/".to_string()),
+            region: Some("us-east-1".to_string()),
+            ..R2Config::default()
+        })
+        .unwrap();
+
+        assert_eq!(storage.host().unwrap(), "localhost:9000");
+        let url = storage
+            .presign(Method::PUT, "uploads/a b.txt", Duration::from_secs(900))
+            .unwrap();
+        assert!(url.starts_with("http://localhost:9000/forge/uploads/a%20b.txt?X-Amz-Algorithm="));
+        assert!(url.contains("%2Fus-east-1%2Fs3%2Faws4_request"));
+        assert!(url.contains("X-Amz-Expires=900&X-Amz-SignedHeaders=host&X-Amz-Signature="));
+        assert!(storage
+            .presign(Method::GET, "x", Duration::from_secs(8 * 24 * 3600))
+            .is_err());
+
+        let custom = R2Storage::new(R2Config {
+            account_id: "acct".to_string(),
+            bucket_name: "forge".to_string(),
+            custom_domain: Some("blobs.example.com".to_string()),
+            ..R2Config::default()
+        })
+        .unwrap();
+        assert_eq!(custom.host().unwrap(), "blobs.example.com");
+        assert_eq!(custom.object_path("blobs/ab/cd"), "/blobs/ab/cd");
+        assert_eq!(custom.config.region(), "auto");
+    }
+
+    #[test]
+    fn test_parse_list_objects() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>forge</Name>
+  <Prefix>components/</Prefix>
+  <KeyCount>2</KeyCount>
+  <MaxKeys>2</MaxKeys>
+  <Delimiter>/</Delimiter>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents>
+    <Key>components/readme &amp; notes.md</Key>
+    <LastModified>2024-03-01T12:30:00.000Z</LastModified>
+    <ETag>&quot;9b2cf535f27731c974343645a3985328&quot;</ETag>
+    <Size>434234</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+  <CommonPrefixes><Prefix>components/dx-icons/</Prefix></CommonPrefixes>
+  <CommonPrefixes><Prefix>components/dx-ui/</Prefix></CommonPrefixes>
+</ListBucketResult>"#;
+
+        let page = parse_list_objects(xml).unwrap();
+        assert_eq!(page.objects.len(), 1);
+        let object = &page.objects[0];
+        assert_eq!(object.key, "components/readme & notes.md");
+        assert_eq!(object.size, 434234);
+        assert_eq!(
+            object.etag.as_deref(),
+            Some("9b2cf535f27731c974343645a3985328")
+        );
+        assert_eq!(
+            object.last_modified.unwrap().to_rfc3339(),
+            "2024-03-01T12:30:00+00:00"
+        );
+        assert_eq!(
+            page.common_prefixes,
+            vec!["components/dx-icons/", "components/dx-ui/"]
+        );
+        assert_eq!(
+            page.next_token.as_deref(),
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=")
+        );
+
+        let last_page = "<ListBucketResult><IsTruncated>false</IsTruncated><KeyCount>0</KeyCount></ListBucketResult>";
+        let page = parse_list_objects(last_page).unwrap();
+        assert!(page.objects.is_empty() && page.next_token.is_none());
+
+        let broken = "<ListBucketResult><IsTruncated>true</IsTruncated></ListBucketResult>";
+        assert!(parse_list_objects(broken).is_err());
+    }
+
     #[test]
     fn test_sync_calculation() {
         let config = R2Config::default();
         let storage = R2Storage::new(config).unwrap();
-        
+
         let remote = vec!["comp1.tsx".to_string(), "comp2.tsx".to_string()];
         let local = vec!["comp2.tsx".to_string(), "comp3.tsx".to_string()];
-        
+
         let (download, upload) = storage.calculate_sync_actions(&remote, &local);
-        
+
         assert_eq!(download, vec!["comp1.tsx".to_string()]);
         assert_eq!(upload, vec!["comp3.tsx".to_string()]);
     }
-    
+
     #[test]
     fn test_sync_empty() {
         let config = R2Config::default();
         let storage = R2Storage::new(config).unwrap();
-        
+
         let remote = vec![];
         let local = vec![];
-        
+
         let (download, upload) = storage.calculate_sync_actions(&remote, &local);
-        
+
         assert!(download.is_empty());
         assert!(upload.is_empty());
     }
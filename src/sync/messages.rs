@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+use super::presence::Presence;
 use crate::crdt::Operation;
 
 /// Wire format for sync messages exchanged over WebSockets.
@@ -8,6 +9,12 @@ use crate::crdt::Operation;
 pub enum SyncMessage {
     Handshake { actor_id: String, repo_id: String },
     Operation { operation: Operation },
+    /// Ephemeral presence; relayed to peers, never persisted.
+    Presence { presence: Presence },
+    /// The actor disconnected or timed out.
+    PresenceLeft { actor_id: String },
+    /// Sent by a server that refuses to route a session's messages.
+    Rejected { repo_id: Option<String>, reason: String },
 }
 
 impl SyncMessage {
@@ -18,4 +25,15 @@ impl SyncMessage {
     pub fn operation(operation: Operation) -> Self {
         Self::Operation { operation }
     }
+
+    pub fn presence(presence: Presence) -> Self {
+        Self::Presence { presence }
+    }
+
+    pub fn rejected(repo_id: Option<String>, reason: impl Into<String>) -> Self {
+        Self::Rejected {
+            repo_id,
+            reason: reason.into(),
+        }
+    }
 }
@@ -1,10 +1,12 @@
 pub mod clock;
 pub mod messages;
+pub mod presence;
 pub mod protocol;
 pub mod remote;
 
 pub use clock::GLOBAL_CLOCK;
 pub use messages::SyncMessage;
+pub use presence::{Presence, PresenceEvent, Selection};
 pub use protocol::SyncManager;
 
 // Real-time sync protocol: in-process broadcast-based sync manager
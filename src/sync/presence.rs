@@ -0,0 +1,183 @@
+// Ephemeral presence: who is connected, which file they have open and
+// where their cursors are. Presence is relayed between peers but never
+// written to the operation log; entries expire when a peer goes quiet.
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Peers that haven't refreshed their presence within this window are
+/// considered gone.
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A line/column location in a file (both 1-based, like `Position`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CursorPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A selection; `anchor == head` for a plain cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: CursorPosition,
+    pub head: CursorPosition,
+}
+
+/// What one actor is currently doing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub actor_id: String,
+    pub display_name: String,
+    /// File the actor has focused, if any
+    pub file: Option<String>,
+    #[serde(default)]
+    pub selections: Vec<Selection>,
+    /// When the holder of this presence last heard from the actor. Registries
+    /// set it on arrival; the value a peer sends is ignored.
+    pub last_active: DateTime<Utc>,
+}
+
+impl Presence {
+    pub fn new(actor_id: String, display_name: String) -> Self {
+        Self {
+            actor_id,
+            display_name,
+            file: None,
+            selections: Vec::new(),
+            last_active: Utc::now(),
+        }
+    }
+
+    pub fn with_file(mut self, file: impl Into<String>, selections: Vec<Selection>) -> Self {
+        self.file = Some(file.into());
+        self.selections = selections;
+        self
+    }
+
+    /// Whether the actor has been quiet for longer than `timeout`.
+    pub fn is_stale(&self, now: DateTime<Utc>, timeout: Duration) -> bool {
+        let quiet = now.signed_duration_since(self.last_active);
+        quiet.to_std().map(|quiet| quiet > timeout).unwrap_or(false)
+    }
+}
+
+/// Change notifications delivered to presence subscribers.
+#[derive(Debug, Clone)]
+pub enum PresenceEvent {
+    Updated(Arc<Presence>),
+    Left(String),
+}
+
+/// Latest presence per actor plus a broadcast channel of changes.
+#[derive(Clone)]
+pub struct PresenceRegistry {
+    peers: Arc<DashMap<String, Peer>>,
+    tx: broadcast::Sender<PresenceEvent>,
+}
+
+struct Peer {
+    presence: Arc<Presence>,
+    /// Connection that claimed the actor, if it arrived over one
+    owner: Option<Uuid>,
+}
+
+impl PresenceRegistry {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(256);
+        Self {
+            peers: Arc::new(DashMap::new()),
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Record a presence update, stamped with the time it arrived. Actors
+    /// claimed by a connection (see [`PresenceRegistry::update_from`]) can't
+    /// be updated this way; returns whether it was accepted.
+    pub fn update(&self, presence: Presence) -> bool {
+        self.record(None, presence)
+    }
+
+    /// Record a presence update received over connection `owner`. The first
+    /// connection to publish an actor owns it until the actor is removed;
+    /// updates for it from any other connection are refused.
+    pub fn update_from(&self, owner: Uuid, presence: Presence) -> bool {
+        self.record(Some(owner), presence)
+    }
+
+    fn record(&self, owner: Option<Uuid>, mut presence: Presence) -> bool {
+        presence.last_active = Utc::now();
+        let presence = Arc::new(presence);
+
+        match self.peers.entry(presence.actor_id.clone()) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().owner.is_some_and(|current| Some(current) != owner) {
+                    return false;
+                }
+                entry.get_mut().presence = presence.clone();
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Peer {
+                    presence: presence.clone(),
+                    owner,
+                });
+            }
+        }
+
+        let _ = self.tx.send(PresenceEvent::Updated(presence));
+        true
+    }
+
+    /// Forget an actor, e.g. when its connection closes.
+    pub fn remove(&self, actor_id: &str) -> bool {
+        let removed = self.peers.remove(actor_id).is_some();
+        if removed {
+            let _ = self.tx.send(PresenceEvent::Left(actor_id.to_string()));
+        }
+        removed
+    }
+
+    /// Actors that refreshed their presence within `PRESENCE_TIMEOUT`.
+    pub fn active(&self) -> Vec<Arc<Presence>> {
+        let now = Utc::now();
+        let mut peers: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|entry| !entry.presence.is_stale(now, PRESENCE_TIMEOUT))
+            .map(|entry| entry.presence.clone())
+            .collect();
+        peers.sort_by(|a, b| a.actor_id.cmp(&b.actor_id));
+        peers
+    }
+
+    /// Drop peers quiet for longer than `timeout`, notifying subscribers.
+    pub fn prune_stale(&self, timeout: Duration) -> Vec<String> {
+        let now = Utc::now();
+        let stale: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|entry| entry.presence.is_stale(now, timeout))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        stale
+            .into_iter()
+            .filter(|actor_id| self.remove(actor_id))
+            .collect()
+    }
+}
+
+impl Default for PresenceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
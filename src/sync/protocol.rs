@@ -1,21 +1,39 @@
+use dashmap::DashMap;
+use parking_lot::Mutex;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 
-use crate::crdt::Operation;
+use super::presence::{Presence, PresenceEvent, PresenceRegistry};
+use crate::crdt::{Operation, OperationType, Transformer};
 
 /// Lightweight in-process sync manager using a tokio broadcast channel.
 /// Components can `publish` operations and other components can `subscribe`
 /// to receive live updates. Messages are wrapped in `Arc` to make cloning cheap.
+///
+/// Every published operation, local or received from a peer, is applied to
+/// a per-file [`Transformer`] so concurrent edits are rebased rather than
+/// applied at their raw offsets. Subscribers still receive the original
+/// operation, which is what gets persisted and relayed.
+///
+/// Presence travels on a separate channel: it is relayed to peers like
+/// operations but never reaches the operation log.
 #[derive(Clone)]
 pub struct SyncManager {
     tx: broadcast::Sender<Arc<Operation>>,
+    presence: PresenceRegistry,
+    documents: Arc<DashMap<String, Mutex<Transformer>>>,
 }
 
 impl SyncManager {
     /// Create a new SyncManager with a reasonable buffer size.
     pub fn new() -> Self {
         let (tx, _) = broadcast::channel(256);
-        Self { tx }
+        Self {
+            tx,
+            presence: PresenceRegistry::new(),
+            documents: Arc::new(DashMap::new()),
+        }
     }
 
     /// Subscribe to live operations. The receiver will receive only
@@ -24,14 +42,73 @@ impl SyncManager {
         self.tx.subscribe()
     }
 
-    /// Publish an operation to all subscribers. Returns Err if there are
-    /// no subscribers or the buffer is full.
+    /// Apply an operation to its file and publish it to all subscribers.
+    /// Returns Err if there are no subscribers or the buffer is full; the
+    /// operation is applied either way.
     pub fn publish(
         &self,
         op: Arc<Operation>,
     ) -> Result<usize, broadcast::error::SendError<Arc<Operation>>> {
+        self.apply(&op);
         self.tx.send(op)
     }
+
+    /// Rebase `op` onto the current text of its file and apply it. Returns
+    /// the equivalent edits against the text as it was just before, or
+    /// nothing when the operation was already applied.
+    pub fn apply(&self, op: &Operation) -> Vec<OperationType> {
+        if let OperationType::FileRename { old_path, new_path } = &op.op_type {
+            if let Some((_, document)) = self.documents.remove(old_path) {
+                self.documents.insert(new_path.clone(), document);
+            }
+            return vec![op.op_type.clone()];
+        }
+
+        self.documents
+            .entry(op.file_path.clone())
+            .or_insert_with(|| Mutex::new(Transformer::new("")))
+            .lock()
+            .rebase(op)
+    }
+
+    /// Text of a file after every operation published so far.
+    pub fn document(&self, file_path: &str) -> Option<String> {
+        self.documents
+            .get(file_path)
+            .map(|document| document.lock().text())
+    }
+
+    /// Subscribe to presence changes of local and remote peers.
+    pub fn subscribe_presence(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.presence.subscribe()
+    }
+
+    /// Publish a presence update (local or received from a peer). Returns
+    /// false when a connection has claimed the actor.
+    pub fn publish_presence(&self, presence: Presence) -> bool {
+        self.presence.update(presence)
+    }
+
+    /// Publish a presence update received over connection `owner`, which
+    /// claims the actor; refused when another connection already owns it.
+    pub fn publish_presence_from(&self, owner: uuid::Uuid, presence: Presence) -> bool {
+        self.presence.update_from(owner, presence)
+    }
+
+    /// Remove an actor's presence, notifying subscribers that it left.
+    pub fn remove_presence(&self, actor_id: &str) -> bool {
+        self.presence.remove(actor_id)
+    }
+
+    /// Peers whose presence is still fresh.
+    pub fn active_peers(&self) -> Vec<Arc<Presence>> {
+        self.presence.active()
+    }
+
+    /// Expire peers that have been quiet for longer than `timeout`.
+    pub fn prune_stale_presence(&self, timeout: Duration) -> Vec<String> {
+        self.presence.prune_stale(timeout)
+    }
 }
 
 #[cfg(test)]
@@ -54,6 +131,79 @@ mod tests {
 
         let got = rx.recv().await.unwrap();
         assert_eq!(got.id, op.id);
+        assert_eq!(mgr.document("/tmp/x").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn concurrent_operations_are_rebased_on_apply() {
+        use crate::crdt::Position;
+
+        let mgr = SyncManager::new();
+        let insert = |actor: &str, lamport: u64, offset: usize, text: &str| {
+            Operation::new(
+                "doc.txt".into(),
+                OperationType::Insert {
+                    position: Position::new(1, offset + 1, offset, actor.into(), lamport),
+                    content: text.into(),
+                    length: text.chars().count(),
+                },
+                actor.into(),
+            )
+        };
+
+        let base = insert("a", 1, 0, "hello world");
+        let left = insert("a", 2, 5, ",").with_parents(vec![base.id]);
+        let right = insert("b", 3, 11, "!").with_parents(vec![base.id]);
+        for op in [base, left, right.clone()] {
+            let _ = mgr.publish(Arc::new(op));
+        }
+        // Redelivered operations are not applied twice
+        assert!(mgr.apply(&right).is_empty());
+
+        assert_eq!(mgr.document("doc.txt").as_deref(), Some("hello, world!"));
+    }
+
+    #[tokio::test]
+    async fn presence_updates_and_expiry() {
+        let mgr = SyncManager::new();
+        let mut rx = mgr.subscribe_presence();
+
+        let alice = Presence::new("alice".into(), "Alice".into()).with_file("src/lib.rs", vec![]);
+        assert!(mgr.publish_presence(alice.clone()));
+        assert!(matches!(rx.recv().await.unwrap(), PresenceEvent::Updated(p) if p.actor_id == "alice"));
+
+        // Timestamps sent by peers are replaced by the arrival time, so a
+        // future one neither keeps a peer alive nor blocks later updates
+        let mut ahead = alice.clone();
+        ahead.last_active += chrono::Duration::days(365);
+        assert!(mgr.publish_presence(ahead));
+        assert!(mgr.active_peers()[0].last_active <= chrono::Utc::now());
+        let mut moved = alice.clone();
+        moved.file = Some("src/main.rs".into());
+        assert!(mgr.publish_presence(moved));
+        assert_eq!(mgr.active_peers()[0].file.as_deref(), Some("src/main.rs"));
+
+        // A peer that stopped sending heartbeats is pruned
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let mut pruned = mgr.prune_stale_presence(Duration::ZERO);
+        pruned.sort();
+        assert_eq!(pruned, vec!["alice".to_string()]);
+        assert!(mgr.active_peers().is_empty());
+    }
+
+    #[test]
+    fn connections_cannot_take_over_other_actors() {
+        let mgr = SyncManager::new();
+        let (first, second) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+        let alice = Presence::new("alice".into(), "Alice".into());
+        assert!(mgr.publish_presence_from(first, alice.clone()));
+        assert!(mgr.publish_presence_from(first, alice.clone().with_file("a.rs", vec![])));
+
+        let mut spoofed = alice.clone();
+        spoofed.display_name = "Mallory".into();
+        assert!(!mgr.publish_presence_from(second, spoofed.clone()));
+        assert!(!mgr.publish_presence(spoofed));
+        assert_eq!(mgr.active_peers()[0].display_name, "Alice");
     }
 }
-// Future: WebSocket-based sync protocol for real-time collaboration
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use futures::{SinkExt, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::Message;
 use url::Url;
@@ -9,7 +10,7 @@ use url::Url;
 use super::protocol::SyncManager;
 use crate::crdt::Operation;
 use crate::storage::OperationLog;
-use crate::sync::{SyncMessage, GLOBAL_CLOCK};
+use crate::sync::{PresenceEvent, SyncMessage, GLOBAL_CLOCK};
 use colored::*;
 use dashmap::DashSet;
 use reqwest::Client;
@@ -37,7 +38,7 @@ pub async fn connect_peer(
     ws_tx.send(Message::Text(handshake_json.into())).await?;
 
     // Initial cold start sync via HTTP
-    if let Some(ops_url) = derive_ops_url(&url) {
+    if let Some(ops_url) = derive_ops_url(&url, &repo_id) {
         if let Ok(ops) = fetch_initial_ops(ops_url).await {
             for op in ops.into_iter().rev() {
                 if insert_seen(&seen, op.id) {
@@ -52,28 +53,40 @@ pub async fn connect_peer(
         }
     }
 
-    // Subscribe to local ops to forward to remote
+    // Subscribe to local ops and presence to forward to remote
     let mut rx = sync.subscribe();
+    let mut presence_rx = sync.subscribe_presence();
 
     // Spawn forwarder for local -> remote
     let actor_id_clone = actor_id.clone();
     let seen_forward = seen.clone();
     let forward = tokio::spawn(async move {
         loop {
-            match rx.recv().await {
-                Ok(op_arc) => {
+            let msg = tokio::select! {
+                received = rx.recv() => match received {
                     // Only forward our own actor's ops to reduce echo, server will broadcast
-                    if op_arc.actor_id == actor_id_clone && insert_seen(&seen_forward, op_arc.id) {
-                        if let Ok(json) =
-                            serde_json::to_string(&SyncMessage::operation((*op_arc).clone()))
-                        {
-                            if ws_tx.send(Message::Text(json.into())).await.is_err() {
-                                break;
-                            }
-                        }
+                    Ok(op_arc) if op_arc.actor_id == actor_id_clone && insert_seen(&seen_forward, op_arc.id) => {
+                        SyncMessage::operation((*op_arc).clone())
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                },
+                event = presence_rx.recv() => match event {
+                    Ok(PresenceEvent::Updated(presence)) if presence.actor_id == actor_id_clone => {
+                        SyncMessage::presence((*presence).clone())
+                    }
+                    Ok(PresenceEvent::Left(actor)) if actor == actor_id_clone => {
+                        SyncMessage::PresenceLeft { actor_id: actor }
                     }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                },
+            };
+
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if ws_tx.send(Message::Text(json.into())).await.is_err() {
+                    break;
                 }
-                Err(_) => break,
             }
         }
     });
@@ -98,6 +111,25 @@ pub async fn connect_peer(
                                     repo_id.bright_white()
                                 );
                             }
+                            SyncMessage::Rejected { repo_id, reason } => {
+                                println!(
+                                    "{} Peer rejected session (repo={}): {}",
+                                    "✗".red(),
+                                    repo_id.unwrap_or_default().bright_white(),
+                                    reason
+                                );
+                                break;
+                            }
+                            SyncMessage::Presence { presence } => {
+                                if presence.actor_id != actor_id_clone2 {
+                                    sync_clone.publish_presence(presence);
+                                }
+                            }
+                            SyncMessage::PresenceLeft { actor_id } => {
+                                if actor_id != actor_id_clone2 {
+                                    sync_clone.remove_presence(&actor_id);
+                                }
+                            }
                             SyncMessage::Operation { operation: op } => {
                                 if op.actor_id != actor_id_clone2 && insert_seen(&seen_recv, op.id)
                                 {
@@ -169,7 +201,7 @@ fn enforce_seen_limit(cache: &DashSet<Uuid>) {
     }
 }
 
-fn derive_ops_url(ws_url: &Url) -> Option<Url> {
+fn derive_ops_url(ws_url: &Url, repo_id: &str) -> Option<Url> {
     let mut http = ws_url.clone();
     let scheme = match ws_url.scheme() {
         "ws" => "http",
@@ -181,8 +213,22 @@ fn derive_ops_url(ws_url: &Url) -> Option<Url> {
         return None;
     }
 
+    // Keep any credentials passed on the WebSocket URL
+    let token = ws_url
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned());
+
     http.set_path("/ops");
-    http.set_query(Some("limit=200"));
+    http.set_query(None);
+    {
+        let mut query = http.query_pairs_mut();
+        query.append_pair("limit", "200");
+        query.append_pair("repo", repo_id);
+        if let Some(token) = token {
+            query.append_pair("token", &token);
+        }
+    }
     Some(http)
 }
 
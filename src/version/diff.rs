@@ -0,0 +1,448 @@
+//! Content-level diffs between snapshots.
+//!
+//! Built from the file contents stored with each snapshot: modified text
+//! files get line hunks, files that only moved are reported as renames,
+//! and tool states are compared by version and config key. A diff renders
+//! as unified text, as a word diff, or serializes to JSON as is.
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, DiffOp, TextDiff};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use super::snapshot::{SnapshotId, ToolState};
+use super::types::Version;
+
+/// Unchanged lines shown around each hunk
+pub const CONTEXT_LINES: usize = 3;
+
+/// How to render a [`ContentDiff`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffFormat {
+    #[default]
+    Unified,
+    /// Changed lines merged inline as `[-removed-]{+added+}`
+    Word,
+    Json,
+}
+
+impl std::str::FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "unified" => Ok(Self::Unified),
+            "word" => Ok(Self::Word),
+            "json" => Ok(Self::Json),
+            other => anyhow::bail!(
+                "Unknown diff format '{}' (expected unified, word or json)",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+    /// Same content under a new path
+    Renamed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineTag {
+    Context,
+    Delete,
+    Insert,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub tag: LineTag,
+    /// Line content including its newline, if it had one
+    pub text: String,
+}
+
+/// A run of changes with surrounding context. Starts are 1-based, as in
+/// unified diff headers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// What changed inside a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileContentDiff {
+    Text {
+        hunks: Vec<DiffHunk>,
+    },
+    /// Either side isn't text; only the sizes are compared
+    Binary {
+        old_size: u64,
+        new_size: u64,
+    },
+    /// Content is identical (renames)
+    Unchanged,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    /// Previous path of a renamed file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<PathBuf>,
+    pub status: FileStatus,
+    pub content: FileContentDiff,
+}
+
+/// A config key that differs between two tool states
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+}
+
+/// A tool whose state differs. A missing version means the tool is absent
+/// on that side.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDiff {
+    pub tool: String,
+    pub old_version: Option<Version>,
+    pub new_version: Option<Version>,
+    pub config: Vec<ConfigChange>,
+    pub added_outputs: Vec<PathBuf>,
+    pub removed_outputs: Vec<PathBuf>,
+}
+
+/// Everything that changed between two snapshots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentDiff {
+    pub from: SnapshotId,
+    pub to: SnapshotId,
+    /// Sorted by path
+    pub files: Vec<FileDiff>,
+    /// Sorted by tool name
+    pub tools: Vec<ToolDiff>,
+}
+
+impl ContentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.tools.is_empty()
+    }
+
+    pub fn render(&self, format: DiffFormat) -> anyhow::Result<String> {
+        Ok(match format {
+            DiffFormat::Unified => self.unified(),
+            DiffFormat::Word => self.word_diff(),
+            DiffFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    /// Unified diff text, with tool changes listed after the files
+    pub fn unified(&self) -> String {
+        self.render_text(|out, hunk| {
+            for line in &hunk.lines {
+                let prefix = match line.tag {
+                    LineTag::Context => ' ',
+                    LineTag::Delete => '-',
+                    LineTag::Insert => '+',
+                };
+                out.push(prefix);
+                push_line(out, &line.text);
+            }
+        })
+    }
+
+    /// Like [`Self::unified`], but each run of changed lines is shown once
+    /// with removed and added words marked inline
+    pub fn word_diff(&self) -> String {
+        self.render_text(|out, hunk| {
+            let (mut old, mut new) = (String::new(), String::new());
+            for line in &hunk.lines {
+                match line.tag {
+                    LineTag::Delete => old.push_str(&line.text),
+                    LineTag::Insert => new.push_str(&line.text),
+                    LineTag::Context => {
+                        push_words(out, &old, &new);
+                        old.clear();
+                        new.clear();
+                        out.push(' ');
+                        push_line(out, &line.text);
+                    }
+                }
+            }
+            push_words(out, &old, &new);
+        })
+    }
+
+    fn render_text(&self, render_hunk: impl Fn(&mut String, &DiffHunk)) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            let new = format!("b/{}", file.path.display());
+            let old = format!(
+                "a/{}",
+                file.old_path.as_ref().unwrap_or(&file.path).display()
+            );
+            let _ = writeln!(out, "diff --forge {} {}", old, new);
+            match file.status {
+                FileStatus::Added => out.push_str("new file\n"),
+                FileStatus::Deleted => out.push_str("deleted file\n"),
+                FileStatus::Renamed => {
+                    let _ = writeln!(out, "rename from {}", &old[2..]);
+                    let _ = writeln!(out, "rename to {}", &new[2..]);
+                }
+                FileStatus::Modified => {}
+            }
+
+            let old = if file.status == FileStatus::Added {
+                "/dev/null".to_string()
+            } else {
+                old
+            };
+            let new = if file.status == FileStatus::Deleted {
+                "/dev/null".to_string()
+            } else {
+                new
+            };
+            match &file.content {
+                FileContentDiff::Unchanged => {}
+                FileContentDiff::Binary { .. } => {
+                    let _ = writeln!(out, "Binary files {} and {} differ", old, new);
+                }
+                FileContentDiff::Text { hunks } => {
+                    let _ = writeln!(out, "--- {}\n+++ {}", old, new);
+                    for hunk in hunks {
+                        let _ = writeln!(
+                            out,
+                            "@@ -{},{} +{},{} @@",
+                            hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len
+                        );
+                        render_hunk(&mut out, hunk);
+                    }
+                }
+            }
+        }
+
+        for tool in &self.tools {
+            let version =
+                |v: &Option<Version>| v.as_ref().map_or("(none)".to_string(), |v| v.to_string());
+            let _ = writeln!(
+                out,
+                "tool {}: {} -> {}",
+                tool.tool,
+                version(&tool.old_version),
+                version(&tool.new_version)
+            );
+            for change in &tool.config {
+                let value = |v: &Option<serde_json::Value>| {
+                    v.as_ref().map_or("(unset)".to_string(), |v| v.to_string())
+                };
+                let _ = writeln!(
+                    out,
+                    "  config {}: {} -> {}",
+                    change.key,
+                    value(&change.old),
+                    value(&change.new)
+                );
+            }
+            for path in &tool.removed_outputs {
+                let _ = writeln!(out, "  -output {}", path.display());
+            }
+            for path in &tool.added_outputs {
+                let _ = writeln!(out, "  +output {}", path.display());
+            }
+        }
+        out
+    }
+}
+
+fn push_line(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+fn push_words(out: &mut String, old: &str, new: &str) {
+    if old.is_empty() && new.is_empty() {
+        return;
+    }
+    let diff = TextDiff::from_words(old, new);
+    for change in diff.iter_all_changes() {
+        let text = change.value();
+        match change.tag() {
+            ChangeTag::Equal => out.push_str(text),
+            ChangeTag::Delete => {
+                let _ = write!(out, "[-{}-]", text);
+            }
+            ChangeTag::Insert => {
+                let _ = write!(out, "{{+{}+}}", text);
+            }
+        }
+    }
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Whether content should be diffed as text
+pub fn is_text(content: &[u8]) -> bool {
+    !content.contains(&0) && std::str::from_utf8(content).is_ok()
+}
+
+/// Line hunks turning `old` into `new`
+pub fn text_hunks(old: &str, new: &str) -> Vec<DiffHunk> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.grouped_ops(CONTEXT_LINES)
+        .iter()
+        .filter_map(|group| {
+            let (first, last) = (group.first()?, group.last()?);
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let lines = group
+                .iter()
+                .flat_map(|op: &DiffOp| diff.iter_changes(op))
+                .map(|change| DiffLine {
+                    tag: match change.tag() {
+                        ChangeTag::Equal => LineTag::Context,
+                        ChangeTag::Delete => LineTag::Delete,
+                        ChangeTag::Insert => LineTag::Insert,
+                    },
+                    text: change.value().to_string(),
+                })
+                .collect();
+            // An empty range is anchored at the line before it
+            let start = |range: &std::ops::Range<usize>| {
+                if range.is_empty() {
+                    range.start
+                } else {
+                    range.start + 1
+                }
+            };
+            Some(DiffHunk {
+                old_start: start(&old_range),
+                old_len: old_range.len(),
+                new_start: start(&new_range),
+                new_len: new_range.len(),
+                lines,
+            })
+        })
+        .collect()
+}
+
+/// Differences between two sets of tool states, sorted by tool
+pub fn tool_diffs(
+    old: &HashMap<String, ToolState>,
+    new: &HashMap<String, ToolState>,
+) -> Vec<ToolDiff> {
+    let tools: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    tools
+        .into_iter()
+        .filter_map(|tool| {
+            let (before, after) = (old.get(tool), new.get(tool));
+            let config = |state: Option<&ToolState>| -> BTreeMap<String, serde_json::Value> {
+                state
+                    .map(|s| s.config.clone().into_iter().collect())
+                    .unwrap_or_default()
+            };
+            let (old_config, new_config) = (config(before), config(after));
+            let keys: BTreeSet<&String> = old_config.keys().chain(new_config.keys()).collect();
+            let config = keys
+                .into_iter()
+                .filter(|key| old_config.get(*key) != new_config.get(*key))
+                .map(|key| ConfigChange {
+                    key: key.clone(),
+                    old: old_config.get(key).cloned(),
+                    new: new_config.get(key).cloned(),
+                })
+                .collect();
+
+            let outputs = |state: Option<&ToolState>| -> BTreeSet<PathBuf> {
+                state
+                    .map(|s| s.output_files.iter().cloned().collect())
+                    .unwrap_or_default()
+            };
+            let (old_outputs, new_outputs) = (outputs(before), outputs(after));
+
+            let diff = ToolDiff {
+                tool: tool.clone(),
+                old_version: before.map(|s| s.version.clone()),
+                new_version: after.map(|s| s.version.clone()),
+                config,
+                added_outputs: new_outputs.difference(&old_outputs).cloned().collect(),
+                removed_outputs: old_outputs.difference(&new_outputs).cloned().collect(),
+            };
+            let changed = diff.old_version != diff.new_version
+                || !diff.config.is_empty()
+                || !diff.added_outputs.is_empty()
+                || !diff.removed_outputs.is_empty();
+            changed.then_some(diff)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hunks_follow_unified_conventions() {
+        let hunks = text_hunks("a\nb\nc\n", "a\nB\nc\nd");
+        assert_eq!(hunks.len(), 1);
+        let hunk = &hunks[0];
+        assert_eq!(
+            (hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len),
+            (1, 3, 1, 4)
+        );
+
+        let diff = ContentDiff {
+            from: SnapshotId::from_str("a"),
+            to: SnapshotId::from_str("b"),
+            files: vec![FileDiff {
+                path: "notes.txt".into(),
+                old_path: None,
+                status: FileStatus::Modified,
+                content: FileContentDiff::Text { hunks },
+            }],
+            tools: vec![],
+        };
+        assert_eq!(
+            diff.unified(),
+            "diff --forge a/notes.txt b/notes.txt\n--- a/notes.txt\n+++ b/notes.txt\n\
+             @@ -1,3 +1,4 @@\n a\n-b\n+B\n c\n+d\n\\ No newline at end of file\n"
+        );
+        assert!(diff.word_diff().contains(" a\n[-b-]{+B+}\n c\n"));
+
+        // Adding to an empty file anchors the old side at line 0
+        assert_eq!(text_hunks("", "x\n")[0].old_start, 0);
+    }
+
+    #[test]
+    fn tool_diffs_report_versions_and_config() {
+        let state = |major, level: i64| ToolState {
+            tool_name: "fmt".into(),
+            version: Version::new(major, 0, 0),
+            config: HashMap::from([("level".to_string(), serde_json::json!(level))]),
+            output_files: vec![],
+        };
+        let old = HashMap::from([("fmt".to_string(), state(1, 1))]);
+        let new = HashMap::from([("fmt".to_string(), state(2, 3))]);
+
+        assert!(tool_diffs(&old, &old).is_empty());
+        let diffs = tool_diffs(&old, &new);
+        assert_eq!(diffs[0].new_version, Some(Version::new(2, 0, 0)));
+        assert_eq!(diffs[0].config[0].new, Some(serde_json::json!(3)));
+        assert_eq!(tool_diffs(&old, &HashMap::new())[0].new_version, None);
+    }
+}
@@ -0,0 +1,155 @@
+//! `dx.lock`: the exact versions a resolution picked, so later installs
+//! get the same ones.
+//!
+//! ```toml
+//! version = 1
+//!
+//! [[package]]
+//! name = "dx-style"
+//! version = "1.2.0"
+//! dependencies = ["dx-core"]
+//! ```
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::resolver::Resolution;
+use super::types::{version_string, Version};
+
+pub const LOCKFILE_NAME: &str = "dx.lock";
+
+/// Format version written to new lockfiles
+pub const LOCKFILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+    /// Sorted by name
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    #[serde(with = "version_string")]
+    pub version: Version,
+    /// Names of the locked packages it depends on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Kept at this version even when updating
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            packages: Vec::new(),
+        }
+    }
+}
+
+impl Lockfile {
+    pub fn from_resolution(resolution: &Resolution) -> Self {
+        let packages = resolution
+            .packages
+            .iter()
+            .map(|(name, resolved)| {
+                let mut dependencies: Vec<String> = resolved
+                    .dependencies
+                    .keys()
+                    .filter(|dependency| resolution.packages.contains_key(*dependency))
+                    .cloned()
+                    .collect();
+                dependencies.sort();
+                LockedPackage {
+                    name: name.clone(),
+                    version: resolved.version.clone(),
+                    dependencies,
+                    pinned: false,
+                }
+            })
+            .collect();
+        Self {
+            version: LOCKFILE_VERSION,
+            packages,
+        }
+    }
+
+    /// Read the lockfile at `path`, or `None` if there isn't one
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let lockfile: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if lockfile.version > LOCKFILE_VERSION {
+            anyhow::bail!(
+                "{} has format version {}, but only {} is supported",
+                path.display(),
+                lockfile.version,
+                LOCKFILE_VERSION
+            );
+        }
+        Ok(Some(lockfile))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = format!(
+            "# Generated by forge; do not edit by hand.\n{}",
+            toml::to_string(self)?
+        );
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|package| package.name == name)
+    }
+
+    pub fn is_pinned(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|package| package.pinned)
+    }
+
+    /// Keep `name` at exactly `version`, also through updates
+    pub fn pin(&mut self, name: &str, version: Version) {
+        match self.packages.iter_mut().find(|package| package.name == name) {
+            Some(package) => {
+                package.version = version;
+                package.pinned = true;
+            }
+            None => {
+                self.packages.push(LockedPackage {
+                    name: name.to_string(),
+                    version,
+                    dependencies: Vec::new(),
+                    pinned: true,
+                });
+                self.packages.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+    }
+
+    /// Carry the pins of `previous` over, e.g. into a fresh resolution
+    pub fn keep_pins(&mut self, previous: &Lockfile) {
+        for package in &mut self.packages {
+            package.pinned = previous.is_pinned(&package.name);
+        }
+    }
+
+    /// The lockfile without `name`, so resolving picks it afresh
+    pub fn without(&self, name: &str) -> Lockfile {
+        Lockfile {
+            version: self.version,
+            packages: self
+                .packages
+                .iter()
+                .filter(|package| package.name != name)
+                .cloned()
+                .collect(),
+        }
+    }
+}
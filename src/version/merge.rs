@@ -0,0 +1,323 @@
+//! Three-way merging of snapshots.
+//!
+//! Files are classified against the merge base: a side that left a file as
+//! it was in the base yields to the other side, and only files changed on
+//! both sides are merged line by line (diff3). Anything that can't be
+//! merged is reported as a conflict instead of silently picking a side.
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::snapshot::{SnapshotId, ToolState};
+use super::RestoreReport;
+
+/// How a merge ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeOutcome {
+    /// The source is already part of the target's history
+    UpToDate,
+    /// The target was behind and now points at the source head
+    FastForward,
+    /// A merge snapshot with both heads as parents was created
+    Merged,
+    /// Nothing was changed; see the conflicts
+    Conflicted,
+}
+
+/// Which side of a merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeSide {
+    /// The branch being merged into (the current branch)
+    Ours,
+    /// The branch being merged
+    Theirs,
+}
+
+/// Result for one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileMerge {
+    /// Identical on both sides
+    Unchanged,
+    /// Only changed on one side, whose version is taken
+    FastForward {
+        from: MergeSide,
+    },
+    /// Changed on both sides and merged line by line without conflicts
+    Clean,
+    /// Removed on one side, untouched on the other
+    Deleted {
+        by: MergeSide,
+    },
+    Conflict(MergeConflict),
+}
+
+/// Why a file couldn't be merged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "conflict", rename_all = "snake_case")]
+pub enum MergeConflict {
+    /// Overlapping edits; `merged` holds the text with conflict markers
+    Content { merged: String, regions: usize },
+    /// Deleted on one side and modified on the other
+    DeleteModify { deleted_by: MergeSide },
+    /// Added on both sides with different content
+    AddAdd { merged: String, regions: usize },
+    /// Changed on both sides and not text
+    Binary,
+}
+
+/// A tool whose state changed differently on both sides
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolConflict {
+    pub tool: String,
+    pub base: Option<ToolState>,
+    pub ours: Option<ToolState>,
+    pub theirs: Option<ToolState>,
+}
+
+/// Outcome of `SnapshotManager::merge`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeResult {
+    pub outcome: MergeOutcome,
+    /// Common ancestor the sides were compared against
+    pub base: Option<SnapshotId>,
+    /// New head of the current branch (`None` when conflicted)
+    pub head: Option<SnapshotId>,
+    /// Every file that exists on any side
+    pub files: BTreeMap<PathBuf, FileMerge>,
+    pub tool_conflicts: Vec<ToolConflict>,
+    /// Changes made to the working tree
+    pub restore: Option<RestoreReport>,
+}
+
+impl MergeResult {
+    pub fn has_conflicts(&self) -> bool {
+        !self.tool_conflicts.is_empty() || self.conflicts().next().is_some()
+    }
+
+    /// Files that need resolving
+    pub fn conflicts(&self) -> impl Iterator<Item = (&PathBuf, &MergeConflict)> {
+        self.files.iter().filter_map(|(path, merge)| match merge {
+            FileMerge::Conflict(conflict) => Some((path, conflict)),
+            _ => None,
+        })
+    }
+}
+
+/// Three-way pick for a value that is either kept whole or not at all:
+/// the side to take, or `None` when both sides changed it differently.
+pub fn pick<T: PartialEq>(
+    base: Option<&T>,
+    ours: Option<&T>,
+    theirs: Option<&T>,
+) -> Option<MergeSide> {
+    if ours == theirs || theirs == base {
+        Some(MergeSide::Ours)
+    } else if ours == base {
+        Some(MergeSide::Theirs)
+    } else {
+        None
+    }
+}
+
+/// Result of a line-based merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMerge {
+    pub content: String,
+    /// Number of conflicting regions marked in `content`
+    pub conflicts: usize,
+}
+
+impl TextMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// A change one side made to the base: base lines `start..end` replaced
+#[derive(Debug)]
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: &'a [&'a str],
+}
+
+fn hunks<'a>(base: &[&str], side: &'a [&'a str]) -> Vec<Hunk<'a>> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter_map(|op| match op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Delete {
+                old_index,
+                old_len,
+                new_index,
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index + old_len,
+                lines: &side[new_index..new_index],
+            }),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index,
+                lines: &side[new_index..new_index + new_len],
+            }),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index + old_len,
+                lines: &side[new_index..new_index + new_len],
+            }),
+        })
+        .collect()
+}
+
+/// One side's version of base lines `start..end`
+fn apply<'a>(base: &[&'a str], start: usize, end: usize, hunks: &[Hunk<'a>]) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        out.extend_from_slice(&base[pos..hunk.start]);
+        out.extend_from_slice(hunk.lines);
+        pos = hunk.end;
+    }
+    out.extend_from_slice(&base[pos..end]);
+    out
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+}
+
+/// Push a side of a conflict, making sure the next marker starts a line
+fn push_side(out: &mut String, lines: &[&str]) {
+    push_lines(out, lines);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Merge `ours` and `theirs` line by line against `base`. Changes to
+/// separate regions are combined; overlapping or adjacent changes that
+/// differ are marked as conflicts labelled with `labels`.
+pub fn merge_text(base: &str, ours: &str, theirs: &str, labels: (&str, &str)) -> TextMerge {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_hunks = hunks(&base, &ours);
+    let theirs_hunks = hunks(&base, &theirs);
+
+    let mut content = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a region at the earliest remaining hunk and grow it while
+        // hunks from either side overlap or touch it
+        let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(a), Some(b)) => a.start.min(b.start),
+            (Some(a), None) => a.start,
+            (None, Some(b)) => b.start,
+            (None, None) => unreachable!(),
+        };
+        let mut end = start;
+        let (first_ours, first_theirs) = (i, j);
+        loop {
+            if let Some(hunk) = ours_hunks.get(i).filter(|h| h.start <= end) {
+                end = end.max(hunk.end);
+                i += 1;
+            } else if let Some(hunk) = theirs_hunks.get(j).filter(|h| h.start <= end) {
+                end = end.max(hunk.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        push_lines(&mut content, &base[pos..start]);
+        let ours_region = apply(&base, start, end, &ours_hunks[first_ours..i]);
+        let theirs_region = apply(&base, start, end, &theirs_hunks[first_theirs..j]);
+        if first_theirs == j || ours_region == theirs_region {
+            push_lines(&mut content, &ours_region);
+        } else if first_ours == i {
+            push_lines(&mut content, &theirs_region);
+        } else {
+            conflicts += 1;
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&format!("<<<<<<< {}\n", labels.0));
+            push_side(&mut content, &ours_region);
+            content.push_str("=======\n");
+            push_side(&mut content, &theirs_region);
+            content.push_str(&format!(">>>>>>> {}\n", labels.1));
+        }
+        pos = end;
+    }
+    push_lines(&mut content, &base[pos..]);
+
+    TextMerge { content, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: (&str, &str) = ("main", "feature");
+
+    #[test]
+    fn combines_changes_to_separate_lines() {
+        let base = "a\nb\nc\nd\ne\n";
+        let merged = merge_text(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n", LABELS);
+        assert!(merged.is_clean());
+        assert_eq!(merged.content, "A\nb\nc\nd\nE\n");
+
+        // The same change on both sides is not a conflict
+        let merged = merge_text(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n", LABELS);
+        assert_eq!(merged.content, "a\nB\nc\nd\ne\n");
+
+        // Insertions and deletions
+        let merged = merge_text(base, "a\nb\nc\nd\ne\nf\n", "b\nc\nd\ne\n", LABELS);
+        assert_eq!(merged.content, "b\nc\nd\ne\nf\n");
+    }
+
+    #[test]
+    fn marks_overlapping_edits() {
+        let merged = merge_text("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n", LABELS);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.content,
+            "a\n<<<<<<< main\nours\n=======\ntheirs\n>>>>>>> feature\nc\n"
+        );
+
+        // Missing trailing newlines don't glue markers onto content
+        let merged = merge_text("x", "y", "z", LABELS);
+        assert_eq!(
+            merged.content,
+            "<<<<<<< main\ny\n=======\nz\n>>>>>>> feature\n"
+        );
+    }
+
+    #[test]
+    fn picks_the_side_that_changed() {
+        assert_eq!(pick(Some(&1), Some(&1), Some(&2)), Some(MergeSide::Theirs));
+        assert_eq!(pick(Some(&1), Some(&2), Some(&1)), Some(MergeSide::Ours));
+        assert_eq!(pick(Some(&1), None, Some(&1)), Some(MergeSide::Ours));
+        assert_eq!(pick(None, Some(&2), Some(&2)), Some(MergeSide::Ours));
+        assert_eq!(pick(Some(&1), Some(&2), Some(&3)), None);
+        assert_eq!(pick(Some(&1), None, Some(&3)), None);
+    }
+}
@@ -4,12 +4,32 @@
 //! and Git-like version control with snapshots and branching.
 
 pub mod types;
+pub mod diff;
+pub mod lockfile;
+pub mod merge;
+pub mod package_index;
 pub mod registry;
+pub mod remote;
+pub mod resolver;
 pub mod snapshot;
 
-pub use types::{Version, VersionReq};
+pub use types::{Comparator, Op, Version, VersionReq};
 pub use registry::{ToolInfo, ToolRegistry, ToolSource};
+pub use lockfile::{LockedPackage, Lockfile, LOCKFILE_NAME};
+pub use package_index::{
+    FileEntry, IndexEntry, IndexedVersion, PackageIndex, VariantManifest, DEFAULT_VARIANT,
+};
+pub use resolver::{
+    resolve_locked, MemorySource, PackageSource, Resolution, ResolutionConflict, ResolvedPackage,
+    Resolver,
+};
+pub use remote::{FetchReport, PullOutcome, PullReport, PushReport};
+pub use diff::{
+    ConfigChange, ContentDiff, DiffFormat, DiffHunk, DiffLine, FileContentDiff, FileDiff,
+    FileStatus, LineTag, ToolDiff,
+};
+pub use merge::{FileMerge, MergeConflict, MergeOutcome, MergeResult, MergeSide, ToolConflict};
 pub use snapshot::{
     Snapshot, SnapshotId, SnapshotManager, Branch, ToolState, FileSnapshot,
-    SnapshotDiff,
+    SnapshotDiff, RestoreMode, RestoreReport, STASH_BRANCH, Head, Tag, ReflogEntry, HEAD,
 };
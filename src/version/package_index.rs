@@ -0,0 +1,180 @@
+//! Package registry index: which packages exist, their versions, variants
+//! and the files each variant installs.
+//!
+//! A registry is any [`ObjectStore`], so a plain local directory works as
+//! well as R2 or an HTTP remote:
+//!
+//! ```text
+//! index/<package-id>.json   one IndexEntry per package
+//! files/<sha256>            file contents, addressed by hash
+//! ```
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use super::resolver::PackageSource;
+use super::types::{version_string, Version, VersionReq};
+use crate::storage::object_store::DEFAULT_LIST_LIMIT;
+use crate::storage::ObjectStore;
+
+/// Variant installed when the caller doesn't ask for one
+pub const DEFAULT_VARIANT: &str = "default";
+
+const INDEX_PREFIX: &str = "index/";
+const FILES_PREFIX: &str = "files/";
+
+/// Everything the registry knows about one package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub versions: Vec<IndexedVersion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedVersion {
+    #[serde(with = "version_string")]
+    pub version: Version,
+    #[serde(default)]
+    pub dependencies: HashMap<String, VersionReq>,
+    /// Variant name to the files it installs
+    pub variants: BTreeMap<String, VariantManifest>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariantManifest {
+    pub files: Vec<FileEntry>,
+}
+
+/// One installed file, relative to the project root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String,
+    /// SHA-256 of the contents, hex encoded
+    pub hash: String,
+    pub size: u64,
+}
+
+impl IndexEntry {
+    pub fn version(&self, version: &Version) -> Option<&IndexedVersion> {
+        self.versions
+            .iter()
+            .find(|indexed| &indexed.version == version)
+    }
+
+    pub fn latest(&self) -> Option<&IndexedVersion> {
+        self.versions
+            .iter()
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        query.is_empty()
+            || self.id.to_lowercase().contains(&query)
+            || self.name.to_lowercase().contains(&query)
+            || self.description.to_lowercase().contains(&query)
+            || self
+                .keywords
+                .iter()
+                .any(|keyword| keyword.to_lowercase().contains(&query))
+    }
+}
+
+/// The index of a registry, loaded once and queried in memory
+pub struct PackageIndex {
+    store: Arc<dyn ObjectStore>,
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+impl PackageIndex {
+    /// Read every index entry in `store`
+    pub async fn load(store: Arc<dyn ObjectStore>) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut token: Option<String> = None;
+        loop {
+            let page = store
+                .list(INDEX_PREFIX, token.as_deref(), DEFAULT_LIST_LIMIT)
+                .await?;
+            for object in page.objects {
+                if !object.key.ends_with(".json") {
+                    continue;
+                }
+                let Some(data) = store.get(&object.key).await? else {
+                    continue;
+                };
+                let entry: IndexEntry = serde_json::from_slice(&data)
+                    .with_context(|| format!("Invalid registry index entry {}", object.key))?;
+                entries.insert(entry.id.clone(), entry);
+            }
+            match page.next_token {
+                Some(next) => token = Some(next),
+                None => break,
+            }
+        }
+        Ok(Self { store, entries })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&IndexEntry> {
+        self.entries.get(id)
+    }
+
+    /// Packages whose id, name, description or keywords contain `query`,
+    /// ignoring case; an empty query lists everything
+    pub fn search(&self, query: &str) -> Vec<&IndexEntry> {
+        self.entries
+            .values()
+            .filter(|entry| entry.matches(query))
+            .collect()
+    }
+
+    pub fn version(&self, id: &str, version: &Version) -> Option<&IndexedVersion> {
+        self.get(id)?.version(version)
+    }
+
+    /// Contents of a manifest file, checked against its hash
+    pub async fn fetch_file(&self, file: &FileEntry) -> Result<Vec<u8>> {
+        let key = format!("{}{}", FILES_PREFIX, file.hash);
+        let data = self
+            .store
+            .get(&key)
+            .await?
+            .with_context(|| format!("Registry is missing {} ({})", file.path, file.hash))?;
+        let actual = format!("{:x}", Sha256::digest(&data));
+        if actual != file.hash {
+            bail!(
+                "Registry file {} is corrupt: expected sha256 {}, got {}",
+                file.path,
+                file.hash,
+                actual
+            );
+        }
+        Ok(data)
+    }
+}
+
+impl PackageSource for PackageIndex {
+    fn versions(&self, package: &str) -> Result<Vec<Version>> {
+        Ok(self
+            .get(package)
+            .map(|entry| entry.versions.iter().map(|v| v.version.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    fn dependencies(
+        &self,
+        package: &str,
+        version: &Version,
+    ) -> Result<HashMap<String, VersionReq>> {
+        Ok(self
+            .version(package, version)
+            .map(|indexed| indexed.dependencies.clone())
+            .unwrap_or_default())
+    }
+}
@@ -30,6 +30,32 @@ pub enum ToolSource {
     R2 { bucket: String, key: String },
 }
 
+impl ToolSource {
+    /// Where `new` of a tool installed from this source at `current` comes
+    /// from: crates by version, local paths as they are unless they name
+    /// the version, and git revisions and R2 keys when they name the
+    /// version. `None` when the new location can't be known.
+    pub fn for_version(&self, current: &Version, new: &Version) -> Option<ToolSource> {
+        let (current, new) = (current.to_string(), new.to_string());
+        match self {
+            ToolSource::Crate { .. } => Some(ToolSource::Crate { version: new }),
+            ToolSource::Local(path) => {
+                let path = path.to_string_lossy().replace(&current, &new);
+                Some(ToolSource::Local(PathBuf::from(path)))
+            }
+            ToolSource::Git { url, rev } if rev.contains(&current) => Some(ToolSource::Git {
+                url: url.clone(),
+                rev: rev.replace(&current, &new),
+            }),
+            ToolSource::R2 { bucket, key } if key.contains(&current) => Some(ToolSource::R2 {
+                bucket: bucket.clone(),
+                key: key.replace(&current, &new),
+            }),
+            ToolSource::Git { .. } | ToolSource::R2 { .. } => None,
+        }
+    }
+}
+
 /// DX Tool Version Registry
 ///
 /// Manages installed tool versions, dependencies, and compatibility
@@ -131,6 +157,13 @@ impl ToolRegistry {
         Ok(())
     }
 
+    /// Put back an entry exactly as it was saved, e.g. after a rollback
+    pub fn restore(&mut self, info: ToolInfo) -> Result<()> {
+        self.tools.insert(info.name.clone(), info);
+        self.save()?;
+        Ok(())
+    }
+
     /// Check if an update is available
     pub fn needs_update(&self, name: &str, latest: &Version) -> bool {
         if let Some(info) = self.tools.get(name) {
@@ -0,0 +1,492 @@
+//! Pushing and pulling snapshots to and from a remote object store.
+//!
+//! A remote keeps snapshots under fixed keys, so any `ObjectStore` (a local
+//! directory, R2/S3 or a forge server) can serve as one:
+//!
+//! - `blobs/..`, `chunks/..`: file contents, as in the blob store
+//! - `snapshots/<id>.json`: snapshot metadata
+//! - `refs/heads/<branch>`: id of the branch head
+//! - `refs/tags/<tag>`: the tag as JSON
+//!
+//! Contents are written before the snapshots that use them, ancestors
+//! before descendants and refs last, so a remote never names anything it
+//! doesn't hold, even after an interrupted push.
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path};
+
+use super::merge::{MergeOutcome, MergeResult};
+use super::snapshot::{RestoreMode, Snapshot, SnapshotId, SnapshotManager, Tag};
+use crate::storage::object_store::is_blob_hash;
+use crate::storage::ObjectStore;
+
+pub const SNAPSHOT_PREFIX: &str = "snapshots/";
+pub const HEADS_PREFIX: &str = "refs/heads/";
+pub const TAGS_PREFIX: &str = "refs/tags/";
+
+/// Local branch that records where the remote's `branch` was last seen
+pub fn remote_branch(branch: &str) -> String {
+    format!("remote/{}", branch)
+}
+
+fn snapshot_key(id: &SnapshotId) -> String {
+    format!("{}{}.json", SNAPSHOT_PREFIX, id.as_str())
+}
+
+/// Outcome of [`push`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PushReport {
+    pub branch: String,
+    pub head: Option<SnapshotId>,
+    /// Snapshots the remote didn't have yet
+    pub snapshots: usize,
+    pub blobs: usize,
+    pub tags: usize,
+}
+
+/// Outcome of [`fetch`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FetchReport {
+    pub branch: String,
+    /// The remote branch head, now also at `remote/<branch>`
+    pub head: Option<SnapshotId>,
+    /// Snapshots that weren't available locally
+    pub snapshots: usize,
+    pub blobs: usize,
+    pub tags: usize,
+}
+
+/// What [`pull`] did to the local branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullOutcome {
+    /// The local branch already contains the remote head
+    UpToDate,
+    /// The branch didn't exist locally and now points at the remote head
+    Created,
+    FastForward,
+    Merged,
+    /// The merge stopped on conflicts; see `merge`
+    Conflicted,
+}
+
+/// Outcome of [`pull`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullReport {
+    pub fetched: FetchReport,
+    pub outcome: PullOutcome,
+    /// Present when the checked out branch was merged
+    pub merge: Option<MergeResult>,
+}
+
+/// Order snapshots so that parents in the set come before their children
+fn ancestors_first(snapshots: Vec<Snapshot>) -> Vec<Snapshot> {
+    let mut by_id: HashMap<SnapshotId, Snapshot> = snapshots
+        .into_iter()
+        .map(|snapshot| (snapshot.id.clone(), snapshot))
+        .collect();
+    let mut stack: Vec<(SnapshotId, bool)> = by_id.keys().map(|id| (id.clone(), false)).collect();
+    let mut ordered = Vec::with_capacity(by_id.len());
+
+    while let Some((id, parents_done)) = stack.pop() {
+        if parents_done {
+            ordered.extend(by_id.remove(&id));
+            continue;
+        }
+        let Some(snapshot) = by_id.get(&id) else {
+            continue;
+        };
+        let parents: Vec<SnapshotId> = snapshot
+            .parents
+            .iter()
+            .filter(|parent| by_id.contains_key(*parent))
+            .cloned()
+            .collect();
+        stack.push((id, true));
+        stack.extend(parents.into_iter().map(|parent| (parent, false)));
+    }
+
+    ordered
+}
+
+/// Whether `path` stays below the directory it is joined to
+fn is_tree_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Check a snapshot read from the remote under `id` before any of it is
+/// stored or restored: it must be the snapshot the key names and its files
+/// must be blobs at relative paths inside the working tree. Blob contents
+/// are checked against their hashes as they are downloaded.
+fn check_remote_snapshot(id: &SnapshotId, snapshot: &Snapshot) -> Result<()> {
+    if snapshot.id != *id {
+        bail!(
+            "Remote snapshot {} claims to be {}",
+            id.as_str(),
+            snapshot.id.as_str()
+        );
+    }
+    if let Some(parent) = snapshot.parents.iter().find(|parent| !parent.is_well_formed()) {
+        bail!(
+            "Remote snapshot {} has a malformed parent {}",
+            id.as_str(),
+            parent.as_str()
+        );
+    }
+    for (path, file) in &snapshot.files {
+        if !is_tree_path(path) || file.path != *path {
+            bail!(
+                "Remote snapshot {} has a file outside the working tree: {}",
+                id.as_str(),
+                path.display()
+            );
+        }
+        if !is_blob_hash(&file.hash) {
+            bail!(
+                "Remote snapshot {} has a malformed hash for {}",
+                id.as_str(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Head of `branch` on the remote
+pub async fn remote_head(remote: &dyn ObjectStore, branch: &str) -> Result<Option<SnapshotId>> {
+    let Some(data) = remote.get(&format!("{}{}", HEADS_PREFIX, branch)).await? else {
+        return Ok(None);
+    };
+    let id = String::from_utf8(data).context("Malformed remote branch head")?;
+    let id = SnapshotId::from_str(id.trim());
+    if !id.is_well_formed() {
+        bail!("Malformed remote branch head {}", id.as_str());
+    }
+    Ok(Some(id))
+}
+
+/// Send `branch` with every snapshot and file content the remote lacks,
+/// plus tags on pushed snapshots. Refuses when the remote branch has
+/// snapshots that aren't in the local one.
+pub async fn push(
+    snapshots: &SnapshotManager,
+    remote: &dyn ObjectStore,
+    branch: &str,
+) -> Result<PushReport> {
+    let head = snapshots
+        .branch_head(branch)?
+        .with_context(|| format!("Branch {} has no snapshots", branch))?;
+    let mut report = PushReport {
+        branch: branch.to_string(),
+        head: Some(head.clone()),
+        ..Default::default()
+    };
+
+    if let Some(theirs) = remote_head(remote, branch).await? {
+        if !snapshots.is_ancestor(&theirs, &head)? {
+            anyhow::bail!(
+                "{} on {} has snapshots that {} doesn't; pull first",
+                branch,
+                remote.name(),
+                branch
+            );
+        }
+    }
+
+    // Everything from the head back to what the remote already has
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![head.clone()];
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id.clone()) || remote.head(&snapshot_key(&id)).await?.is_some() {
+            continue;
+        }
+        let snapshot = snapshots
+            .get_snapshot(&id)?
+            .with_context(|| format!("Snapshot {} not found", id.as_str()))?;
+        pending.extend(snapshot.parents.iter().cloned());
+        missing.push(snapshot);
+    }
+
+    let mut sent_blobs = HashSet::new();
+    for snapshot in &ancestors_first(missing) {
+        for file in snapshot.files.values() {
+            if sent_blobs.insert(file.hash.clone()) && !remote.has_blob(&file.hash).await? {
+                snapshots.blobs().upload_to(remote, &file.hash).await?;
+                report.blobs += 1;
+            }
+        }
+        remote
+            .put(
+                &snapshot_key(&snapshot.id),
+                serde_json::to_vec_pretty(snapshot)?,
+            )
+            .await?;
+        report.snapshots += 1;
+    }
+
+    remote
+        .put(
+            &format!("{}{}", HEADS_PREFIX, branch),
+            head.as_str().as_bytes().to_vec(),
+        )
+        .await?;
+
+    for tag in snapshots.list_tags()? {
+        let key = format!("{}{}", TAGS_PREFIX, tag.name);
+        if remote.head(&key).await?.is_none()
+            && remote.head(&snapshot_key(&tag.target)).await?.is_some()
+        {
+            remote.put(&key, serde_json::to_vec_pretty(&tag)?).await?;
+            report.tags += 1;
+        }
+    }
+
+    tracing::info!(
+        "Pushed {} to {}: {} snapshots, {} blobs",
+        branch,
+        remote.name(),
+        report.snapshots,
+        report.blobs
+    );
+    Ok(report)
+}
+
+/// Download the remote `branch` with the snapshots and contents missing
+/// locally, recording its head at `remote/<branch>`, and import remote
+/// tags on known snapshots. Local branches are left alone.
+pub async fn fetch(
+    snapshots: &mut SnapshotManager,
+    remote: &dyn ObjectStore,
+    branch: &str,
+) -> Result<FetchReport> {
+    let head = remote_head(remote, branch)
+        .await?
+        .with_context(|| format!("{} has no branch {}", remote.name(), branch))?;
+    let mut report = FetchReport {
+        branch: branch.to_string(),
+        head: Some(head.clone()),
+        ..Default::default()
+    };
+
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![head.clone()];
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id.clone()) || snapshots.get_snapshot(&id)?.is_some() {
+            continue;
+        }
+        let data = remote
+            .get(&snapshot_key(&id))
+            .await?
+            .with_context(|| format!("{} is missing snapshot {}", remote.name(), id.as_str()))?;
+        let snapshot: Snapshot = serde_json::from_slice(&data)
+            .with_context(|| format!("Malformed remote snapshot {}", id.as_str()))?;
+        check_remote_snapshot(&id, &snapshot)?;
+        pending.extend(snapshot.parents.iter().cloned());
+        missing.push(snapshot);
+    }
+
+    // Contents first, so a snapshot is only imported once it can be restored
+    for snapshot in &ancestors_first(missing) {
+        for file in snapshot.files.values() {
+            if !snapshots.blobs().exists_local(&file.hash).await {
+                snapshots.blobs().download_from(remote, &file.hash).await?;
+                report.blobs += 1;
+            }
+        }
+        snapshots.import_snapshot(snapshot)?;
+        report.snapshots += 1;
+    }
+
+    snapshots.set_branch_head(
+        &remote_branch(branch),
+        head,
+        &format!("fetch: {} from {}", branch, remote.name()),
+    )?;
+
+    for meta in remote.list_all(TAGS_PREFIX).await? {
+        let Some(data) = remote.get(&meta.key).await? else {
+            continue;
+        };
+        let tag: Tag = serde_json::from_slice(&data)
+            .with_context(|| format!("Malformed remote tag {}", meta.key))?;
+        if tag.target.is_well_formed()
+            && snapshots.get_snapshot(&tag.target)?.is_some()
+            && snapshots.import_tag(&tag)?
+        {
+            report.tags += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Fetch `branch` and bring the local branch up to date with it: create
+/// it, fast-forward it, or merge when it's checked out and both sides have
+/// new snapshots. The working tree follows when the branch is checked out.
+pub async fn pull(
+    snapshots: &mut SnapshotManager,
+    remote: &dyn ObjectStore,
+    branch: &str,
+) -> Result<PullReport> {
+    let fetched = fetch(snapshots, remote, branch).await?;
+    let theirs = fetched.head.clone().context("Fetch returned no head")?;
+    let checked_out = snapshots.current_branch() == Some(branch);
+
+    let (outcome, merge) = match snapshots.branch_head(branch)? {
+        None => {
+            if checked_out {
+                snapshots.restore(&theirs, &[], RestoreMode::Refuse).await?;
+            }
+            snapshots.set_branch_head(branch, theirs, "pull: created")?;
+            (PullOutcome::Created, None)
+        }
+        Some(ours) if snapshots.is_ancestor(&theirs, &ours)? => (PullOutcome::UpToDate, None),
+        Some(_) if checked_out => {
+            let message = format!("Merge {} from {}", branch, remote.name());
+            let result = snapshots.merge(remote_branch(branch), message).await?;
+            let outcome = match result.outcome {
+                MergeOutcome::UpToDate => PullOutcome::UpToDate,
+                MergeOutcome::FastForward => PullOutcome::FastForward,
+                MergeOutcome::Merged => PullOutcome::Merged,
+                MergeOutcome::Conflicted => PullOutcome::Conflicted,
+            };
+            (outcome, Some(result))
+        }
+        Some(ours) if snapshots.is_ancestor(&ours, &theirs)? => {
+            snapshots.set_branch_head(branch, theirs, "pull: fast-forward")?;
+            (PullOutcome::FastForward, None)
+        }
+        Some(_) => anyhow::bail!(
+            "{} has diverged from {}; check it out to merge",
+            branch,
+            remote.name()
+        ),
+    };
+
+    Ok(PullReport {
+        fetched,
+        outcome,
+        merge,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryObjectStore;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn pull_fast_forwards_and_merges_from_a_remote() {
+        let remote = MemoryObjectStore::new();
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut alice = SnapshotManager::new(a.path()).unwrap();
+        let mut bob = SnapshotManager::new(b.path()).unwrap();
+        let notes = PathBuf::from("notes.txt");
+
+        std::fs::write(a.path().join(&notes), "one\ntwo\nthree\n").unwrap();
+        let base = alice
+            .create_snapshot("base", HashMap::new(), vec![notes.clone()])
+            .await
+            .unwrap();
+        alice.create_tag("v1", &base, Some("First")).unwrap();
+        let pushed = push(&alice, &remote, "main").await.unwrap();
+        assert_eq!((pushed.snapshots, pushed.blobs, pushed.tags), (1, 1, 1));
+
+        // A fresh checkout gets the files and tags
+        let pulled = pull(&mut bob, &remote, "main").await.unwrap();
+        assert_eq!(pulled.outcome, PullOutcome::Created);
+        assert_eq!(
+            std::fs::read_to_string(b.path().join(&notes)).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+        assert_eq!(bob.resolve("v1").unwrap(), base);
+
+        // Both sides edit; alice pushes first, so bob has to merge
+        std::fs::write(a.path().join(&notes), "ONE\ntwo\nthree\n").unwrap();
+        alice
+            .create_snapshot("alice", HashMap::new(), vec![notes.clone()])
+            .await
+            .unwrap();
+        push(&alice, &remote, "main").await.unwrap();
+
+        std::fs::write(b.path().join(&notes), "one\ntwo\nTHREE\n").unwrap();
+        bob.create_snapshot("bob", HashMap::new(), vec![notes.clone()])
+            .await
+            .unwrap();
+        assert!(push(&bob, &remote, "main").await.is_err());
+
+        let pulled = pull(&mut bob, &remote, "main").await.unwrap();
+        assert_eq!(pulled.outcome, PullOutcome::Merged);
+        assert_eq!(
+            std::fs::read_to_string(b.path().join(&notes)).unwrap(),
+            "ONE\ntwo\nTHREE\n"
+        );
+        push(&bob, &remote, "main").await.unwrap();
+
+        let pulled = pull(&mut alice, &remote, "main").await.unwrap();
+        assert_eq!(pulled.outcome, PullOutcome::FastForward);
+        assert_eq!(
+            alice.branch_head("main").unwrap(),
+            bob.branch_head("main").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_refuses_snapshots_that_escape_the_tree_or_their_key() {
+        let remote = MemoryObjectStore::new();
+        let (a, outer) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        // Bob's checkout sits inside a directory of its own, which a
+        // successful escape would write to
+        let b = outer.path().join("bob");
+        std::fs::create_dir(&b).unwrap();
+        let mut alice = SnapshotManager::new(a.path()).unwrap();
+        let mut bob = SnapshotManager::new(&b).unwrap();
+        let notes = PathBuf::from("notes.txt");
+        std::fs::write(a.path().join(&notes), "notes\n").unwrap();
+        let id = alice
+            .create_snapshot("base", HashMap::new(), vec![notes.clone()])
+            .await
+            .unwrap();
+        push(&alice, &remote, "main").await.unwrap();
+
+        let key = snapshot_key(&id);
+        let original: serde_json::Value =
+            serde_json::from_slice(&remote.get(&key).await.unwrap().unwrap()).unwrap();
+        let escape = outer.path().join("escape.txt");
+        for target in [escape.display().to_string(), "../escape.txt".to_string()] {
+            let mut tampered = original.clone();
+            let mut file = tampered["files"]["notes.txt"].take();
+            file["path"] = target.clone().into();
+            tampered["files"] = serde_json::json!({ target: file });
+            remote
+                .put(&key, serde_json::to_vec(&tampered).unwrap())
+                .await
+                .unwrap();
+            assert!(pull(&mut bob, &remote, "main").await.is_err());
+        }
+        assert!(!escape.exists());
+
+        // A snapshot stored under another snapshot's key
+        let mut renamed = original.clone();
+        renamed["id"] = "0".repeat(64).into();
+        remote.put(&key, serde_json::to_vec(&renamed).unwrap()).await.unwrap();
+        assert!(fetch(&mut bob, &remote, "main").await.is_err());
+        assert!(bob.get_snapshot(&id).unwrap().is_none());
+
+        remote.put("refs/heads/main", b"../../HEAD".to_vec()).await.unwrap();
+        assert!(fetch(&mut bob, &remote, "main").await.is_err());
+
+        remote.put(&key, serde_json::to_vec(&original).unwrap()).await.unwrap();
+        remote.put("refs/heads/main", id.as_str().as_bytes().to_vec()).await.unwrap();
+        pull(&mut bob, &remote, "main").await.unwrap();
+        assert_eq!(std::fs::read_to_string(b.join(&notes)).unwrap(), "notes\n");
+    }
+}
@@ -0,0 +1,1121 @@
+//! Dependency resolution for DX tools.
+//!
+//! A PubGrub-style solver: every forced choice is backed by an
+//! incompatibility (a set of terms that can't all hold), each conflict
+//! teaches it a new incompatibility and it jumps back to the decision that
+//! caused the conflict, so unsatisfiable requirements come out as a chain
+//! of reasons instead of an exhausted search.
+//!
+//! Each package has a finite set of known versions (its universe, from a
+//! [`PackageSource`]), so a term is simply the outcomes it allows: some of
+//! those versions, and whether leaving the package out is allowed.
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use super::lockfile::{Lockfile, LOCKFILE_NAME};
+use super::registry::ToolRegistry;
+use super::types::{Version, VersionReq};
+
+/// Where the resolver learns which versions exist and what they need
+pub trait PackageSource {
+    /// Available versions of `package`; empty when it is unknown
+    fn versions(&self, package: &str) -> Result<Vec<Version>>;
+
+    /// Requirements of one version of `package`
+    fn dependencies(&self, package: &str, version: &Version)
+        -> Result<HashMap<String, VersionReq>>;
+}
+
+/// Packages held in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    packages: BTreeMap<String, BTreeMap<Version, HashMap<String, VersionReq>>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `version` of `package` available with `dependencies`
+    pub fn add(
+        &mut self,
+        package: &str,
+        version: Version,
+        dependencies: HashMap<String, VersionReq>,
+    ) -> &mut Self {
+        self.packages
+            .entry(package.to_string())
+            .or_default()
+            .insert(version, dependencies);
+        self
+    }
+}
+
+impl PackageSource for MemorySource {
+    fn versions(&self, package: &str) -> Result<Vec<Version>> {
+        Ok(self
+            .packages
+            .get(package)
+            .map(|versions| versions.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn dependencies(
+        &self,
+        package: &str,
+        version: &Version,
+    ) -> Result<HashMap<String, VersionReq>> {
+        Ok(self
+            .packages
+            .get(package)
+            .and_then(|versions| versions.get(version))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Installed tools, each available at its installed version only
+impl PackageSource for ToolRegistry {
+    fn versions(&self, package: &str) -> Result<Vec<Version>> {
+        Ok(self
+            .get(package)
+            .map(|tool| vec![tool.version.clone()])
+            .unwrap_or_default())
+    }
+
+    fn dependencies(
+        &self,
+        package: &str,
+        version: &Version,
+    ) -> Result<HashMap<String, VersionReq>> {
+        Ok(self
+            .get(package)
+            .filter(|tool| &tool.version == version)
+            .map(|tool| tool.dependencies.clone())
+            .unwrap_or_default())
+    }
+}
+
+/// A package picked by the resolver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedPackage {
+    pub version: Version,
+    pub dependencies: HashMap<String, VersionReq>,
+}
+
+/// A consistent set of versions for the requirements and everything they
+/// depend on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolution {
+    pub packages: BTreeMap<String, ResolvedPackage>,
+}
+
+impl Resolution {
+    pub fn version(&self, package: &str) -> Option<&Version> {
+        self.packages.get(package).map(|resolved| &resolved.version)
+    }
+}
+
+/// Requirements that can't all be met; the explanation walks through why
+#[derive(Debug, Clone)]
+pub struct ResolutionConflict {
+    pub explanation: String,
+}
+
+impl fmt::Display for ResolutionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dependencies can't be resolved:\n{}", self.explanation)
+    }
+}
+
+impl std::error::Error for ResolutionConflict {}
+
+pub struct Resolver<'a> {
+    source: &'a dyn PackageSource,
+    locked: BTreeMap<String, Version>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(source: &'a dyn PackageSource) -> Self {
+        Self {
+            source,
+            locked: BTreeMap::new(),
+        }
+    }
+
+    /// Keep every package the lockfile names at exactly its locked version
+    pub fn with_lockfile(mut self, lockfile: &Lockfile) -> Self {
+        self.locked = lockfile
+            .packages
+            .iter()
+            .map(|package| (package.name.clone(), package.version.clone()))
+            .collect();
+        self
+    }
+
+    /// Pick versions for `requirements` and their dependencies, preferring
+    /// the newest. Fails with a [`ResolutionConflict`] when no consistent
+    /// set exists.
+    pub fn resolve(&self, requirements: &HashMap<String, VersionReq>) -> Result<Resolution> {
+        let mut state = State::new(self.source, requirements);
+        state.add_root(&self.locked)?;
+
+        let mut next = ROOT.to_string();
+        loop {
+            if let Err(failure) = state.propagate(next) {
+                return Err(ResolutionConflict {
+                    explanation: state.explain(failure),
+                }
+                .into());
+            }
+            match state.decide()? {
+                Some(package) => next = package,
+                None => break,
+            }
+        }
+
+        let mut resolution = Resolution::default();
+        for (package, version) in state.decisions() {
+            if package == ROOT {
+                continue;
+            }
+            let dependencies = state.dependencies(&package, &version)?;
+            resolution.packages.insert(
+                package,
+                ResolvedPackage {
+                    version,
+                    dependencies,
+                },
+            );
+        }
+        Ok(resolution)
+    }
+}
+
+/// Resolve honoring `dx.lock` in `dir` if there is one, then write the
+/// result to it
+pub fn resolve_locked(
+    source: &dyn PackageSource,
+    requirements: &HashMap<String, VersionReq>,
+    dir: &Path,
+) -> Result<Resolution> {
+    let path = dir.join(LOCKFILE_NAME);
+    let previous = Lockfile::load(&path)?.unwrap_or_default();
+    let resolution = Resolver::new(source)
+        .with_lockfile(&previous)
+        .resolve(requirements)?;
+    let mut lockfile = Lockfile::from_resolution(&resolution);
+    lockfile.keep_pins(&previous);
+    lockfile.save(&path)?;
+    Ok(resolution)
+}
+
+/// Package standing for the requirements themselves; no real package can
+/// have an empty name
+const ROOT: &str = "";
+
+fn root_version() -> Version {
+    Version::new(0, 0, 0)
+}
+
+/// The outcomes a term allows for one package
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    versions: BTreeSet<Version>,
+    /// Whether the package may be left out
+    absent: bool,
+}
+
+impl Term {
+    /// The package is selected at one of `versions`
+    fn positive(versions: BTreeSet<Version>) -> Self {
+        Self {
+            versions,
+            absent: false,
+        }
+    }
+
+    fn any(universe: &BTreeSet<Version>) -> Self {
+        Self {
+            versions: universe.clone(),
+            absent: true,
+        }
+    }
+
+    fn negate(&self, universe: &BTreeSet<Version>) -> Self {
+        Self {
+            versions: universe.difference(&self.versions).cloned().collect(),
+            absent: !self.absent,
+        }
+    }
+
+    fn intersect(&self, other: &Term) -> Self {
+        Self {
+            versions: self
+                .versions
+                .intersection(&other.versions)
+                .cloned()
+                .collect(),
+            absent: self.absent && other.absent,
+        }
+    }
+
+    fn union(&self, other: &Term) -> Self {
+        Self {
+            versions: self.versions.union(&other.versions).cloned().collect(),
+            absent: self.absent || other.absent,
+        }
+    }
+
+    fn is_subset(&self, other: &Term) -> bool {
+        self.versions.is_subset(&other.versions) && (!self.absent || other.absent)
+    }
+
+    fn is_disjoint(&self, other: &Term) -> bool {
+        self.versions.is_disjoint(&other.versions) && !(self.absent && other.absent)
+    }
+
+    fn is_positive(&self) -> bool {
+        !self.absent
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Cause {
+    /// The requirements must be resolved
+    Root,
+    /// `package` `version` requires `dependency` at `req`
+    Dependency {
+        package: String,
+        version: Version,
+        dependency: String,
+        req: VersionReq,
+    },
+    /// The lockfile pins `package` at `version`
+    Locked { package: String, version: Version },
+    /// Nothing is left to choose for `package`
+    NoVersions { package: String },
+    /// Learned from a conflict between two incompatibilities
+    Derived(usize, usize),
+}
+
+/// Terms that can't all hold at once
+#[derive(Debug, Clone)]
+struct Incompatibility {
+    terms: BTreeMap<String, Term>,
+    cause: Cause,
+}
+
+#[derive(Debug, Clone)]
+struct Assignment {
+    package: String,
+    term: Term,
+    level: usize,
+    /// Incompatibility that forced it; `None` for decisions
+    cause: Option<usize>,
+}
+
+enum Relation {
+    Satisfied,
+    Contradicted,
+    /// Every term but the one for this package is satisfied
+    AlmostSatisfied(String),
+    Inconclusive,
+}
+
+struct State<'a> {
+    source: &'a dyn PackageSource,
+    requirements: &'a HashMap<String, VersionReq>,
+    universes: BTreeMap<String, BTreeSet<Version>>,
+    dependencies: BTreeMap<(String, Version), HashMap<String, VersionReq>>,
+    incompatibilities: Vec<Incompatibility>,
+    by_package: BTreeMap<String, Vec<usize>>,
+    assignments: Vec<Assignment>,
+    level: usize,
+    locked: BTreeMap<String, Version>,
+}
+
+impl<'a> State<'a> {
+    fn new(source: &'a dyn PackageSource, requirements: &'a HashMap<String, VersionReq>) -> Self {
+        Self {
+            source,
+            requirements,
+            universes: BTreeMap::new(),
+            dependencies: BTreeMap::new(),
+            incompatibilities: Vec::new(),
+            by_package: BTreeMap::new(),
+            assignments: Vec::new(),
+            level: 0,
+            locked: BTreeMap::new(),
+        }
+    }
+
+    /// Require the root and forbid every version the lockfile doesn't pin
+    fn add_root(&mut self, locked: &BTreeMap<String, Version>) -> Result<()> {
+        self.universes
+            .insert(ROOT.to_string(), BTreeSet::from([root_version()]));
+        let not_selected = Term {
+            versions: BTreeSet::new(),
+            absent: true,
+        };
+        self.add(
+            BTreeMap::from([(ROOT.to_string(), not_selected)]),
+            Cause::Root,
+        );
+        for (package, version) in locked {
+            let universe = self.universe(package)?.clone();
+            let others = universe.iter().filter(|v| *v != version).cloned().collect();
+            self.add(
+                BTreeMap::from([
+                    (
+                        ROOT.to_string(),
+                        Term::positive(BTreeSet::from([root_version()])),
+                    ),
+                    (package.clone(), Term::positive(others)),
+                ]),
+                Cause::Locked {
+                    package: package.clone(),
+                    version: version.clone(),
+                },
+            );
+        }
+        self.locked = locked.clone();
+        Ok(())
+    }
+
+    fn universe(&mut self, package: &str) -> Result<&BTreeSet<Version>> {
+        if !self.universes.contains_key(package) {
+            let versions = self.source.versions(package)?.into_iter().collect();
+            self.universes.insert(package.to_string(), versions);
+        }
+        Ok(&self.universes[package])
+    }
+
+    fn dependencies(
+        &mut self,
+        package: &str,
+        version: &Version,
+    ) -> Result<HashMap<String, VersionReq>> {
+        if package == ROOT {
+            return Ok(self.requirements.clone());
+        }
+        let key = (package.to_string(), version.clone());
+        if !self.dependencies.contains_key(&key) {
+            let dependencies = self.source.dependencies(package, version)?;
+            self.dependencies.insert(key.clone(), dependencies);
+        }
+        Ok(self.dependencies[&key].clone())
+    }
+
+    /// Record an incompatibility, leaving out terms that always hold
+    fn add(&mut self, terms: BTreeMap<String, Term>, cause: Cause) -> usize {
+        let terms: BTreeMap<String, Term> = terms
+            .into_iter()
+            .filter(|(package, term)| *term != Term::any(&self.universes[package]))
+            .collect();
+        let id = self.incompatibilities.len();
+        for package in terms.keys() {
+            self.by_package.entry(package.clone()).or_default().push(id);
+        }
+        self.incompatibilities
+            .push(Incompatibility { terms, cause });
+        id
+    }
+
+    /// Everything the current assignments say about `package`
+    fn accumulated(&self, package: &str) -> Term {
+        self.assignments
+            .iter()
+            .filter(|assignment| assignment.package == package)
+            .fold(Term::any(&self.universes[package]), |term, assignment| {
+                term.intersect(&assignment.term)
+            })
+    }
+
+    fn relation(&self, id: usize) -> Relation {
+        let mut unsatisfied = None;
+        for (package, term) in &self.incompatibilities[id].terms {
+            let current = self.accumulated(package);
+            if current.is_subset(term) {
+                continue;
+            }
+            if current.is_disjoint(term) {
+                return Relation::Contradicted;
+            }
+            if unsatisfied.is_some() {
+                return Relation::Inconclusive;
+            }
+            unsatisfied = Some(package.clone());
+        }
+        match unsatisfied {
+            Some(package) => Relation::AlmostSatisfied(package),
+            None => Relation::Satisfied,
+        }
+    }
+
+    /// Assign the opposite of `package`'s term in incompatibility `id`
+    fn derive(&mut self, package: &str, id: usize) {
+        let term = self.incompatibilities[id].terms[package].negate(&self.universes[package]);
+        self.assignments.push(Assignment {
+            package: package.to_string(),
+            term,
+            level: self.level,
+            cause: Some(id),
+        });
+    }
+
+    /// Unit propagation: derive everything the incompatibilities force
+    /// after `package` changed. Errs with the incompatibility proving the
+    /// requirements unsatisfiable.
+    fn propagate(&mut self, package: String) -> std::result::Result<(), usize> {
+        let mut changed = vec![package];
+        while let Some(package) = changed.pop() {
+            let ids = self.by_package.get(&package).cloned().unwrap_or_default();
+            for id in ids.into_iter().rev() {
+                match self.relation(id) {
+                    Relation::Satisfied => {
+                        let root_cause = self.resolve_conflict(id)?;
+                        let Relation::AlmostSatisfied(package) = self.relation(root_cause) else {
+                            return Err(root_cause);
+                        };
+                        self.derive(&package, root_cause);
+                        changed.clear();
+                        changed.push(package);
+                        break;
+                    }
+                    Relation::AlmostSatisfied(package) => {
+                        self.derive(&package, id);
+                        if !changed.contains(&package) {
+                            changed.push(package);
+                        }
+                    }
+                    Relation::Contradicted | Relation::Inconclusive => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_failure(&self, id: usize) -> bool {
+        let terms = &self.incompatibilities[id].terms;
+        terms.is_empty() || (terms.len() == 1 && terms.get(ROOT).is_some_and(Term::is_positive))
+    }
+
+    /// Learn from a satisfied incompatibility until it points at a
+    /// decision to undo, backtrack there and return what was learned
+    fn resolve_conflict(&mut self, mut id: usize) -> std::result::Result<usize, usize> {
+        loop {
+            if self.is_failure(id) {
+                return Err(id);
+            }
+            let (satisfier, previous_level) = self.satisfier(id);
+            let assignment = self.assignments[satisfier].clone();
+            let Some(cause) = assignment
+                .cause
+                .filter(|_| previous_level >= assignment.level)
+            else {
+                self.backtrack(previous_level);
+                return Ok(id);
+            };
+
+            // Everything in both incompatibilities except the satisfier's
+            // package, which the satisfier's cause already accounts for
+            let package = &assignment.package;
+            let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+            let sides = [&self.incompatibilities[id], &self.incompatibilities[cause]];
+            for (name, term) in sides.iter().flat_map(|incompat| &incompat.terms) {
+                if name != package {
+                    let merged = match terms.get(name) {
+                        Some(existing) => existing.intersect(term),
+                        None => term.clone(),
+                    };
+                    terms.insert(name.clone(), merged);
+                }
+            }
+            let term = &self.incompatibilities[id].terms[package];
+            if !assignment.term.is_subset(term) {
+                let universe = &self.universes[package];
+                terms.insert(
+                    package.clone(),
+                    assignment.term.negate(universe).union(term),
+                );
+            }
+            id = self.add(terms, Cause::Derived(id, cause));
+        }
+    }
+
+    /// The assignment that first makes incompatibility `id` satisfied, and
+    /// the decision level at which it would be almost satisfied without it
+    fn satisfier(&self, id: usize) -> (usize, usize) {
+        let terms = &self.incompatibilities[id].terms;
+        let mut accumulated: BTreeMap<&str, Term> = BTreeMap::new();
+        let mut satisfied_at: BTreeMap<&str, usize> = BTreeMap::new();
+        for (index, assignment) in self.assignments.iter().enumerate() {
+            let package = assignment.package.as_str();
+            let Some(term) = terms.get(package) else {
+                continue;
+            };
+            if satisfied_at.contains_key(package) {
+                continue;
+            }
+            let current = accumulated
+                .remove(package)
+                .unwrap_or_else(|| Term::any(&self.universes[package]))
+                .intersect(&assignment.term);
+            if current.is_subset(term) {
+                satisfied_at.insert(package, index);
+            }
+            accumulated.insert(package, current);
+        }
+
+        let (&package, &satisfier) = satisfied_at
+            .iter()
+            .max_by_key(|(_, index)| **index)
+            .expect("a satisfied incompatibility has a satisfier");
+        let mut previous_level = satisfied_at
+            .iter()
+            .filter(|(name, _)| **name != package)
+            .map(|(_, index)| self.assignments[*index].level)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+
+        // The earliest assignment that, together with the satisfier, still
+        // satisfies the satisfier's term
+        let satisfier_term = &self.assignments[satisfier].term;
+        let term = &terms[package];
+        let mut current = Term::any(&self.universes[package]);
+        if !current.intersect(satisfier_term).is_subset(term) {
+            for assignment in &self.assignments[..satisfier] {
+                if assignment.package != package {
+                    continue;
+                }
+                current = current.intersect(&assignment.term);
+                if current.intersect(satisfier_term).is_subset(term) {
+                    previous_level = previous_level.max(assignment.level);
+                    break;
+                }
+            }
+        }
+        (satisfier, previous_level)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        self.assignments
+            .retain(|assignment| assignment.level <= level);
+        self.level = level;
+    }
+
+    fn decisions(&self) -> Vec<(String, Version)> {
+        self.assignments
+            .iter()
+            .filter(|assignment| assignment.cause.is_none())
+            .filter_map(|assignment| {
+                let version = assignment.term.versions.iter().next()?;
+                Some((assignment.package.clone(), version.clone()))
+            })
+            .collect()
+    }
+
+    /// Pick a version for the undecided package with the fewest candidates
+    /// (the locked one, else the newest) and add its dependencies. Returns
+    /// the package to propagate next, or `None` when everything is decided.
+    fn decide(&mut self) -> Result<Option<String>> {
+        let decided: BTreeSet<String> = self
+            .decisions()
+            .into_iter()
+            .map(|(package, _)| package)
+            .collect();
+        let pending: BTreeSet<&String> = self
+            .assignments
+            .iter()
+            .map(|assignment| &assignment.package)
+            .filter(|package| !decided.contains(*package))
+            .collect();
+        let next = pending
+            .into_iter()
+            .map(|package| (package.clone(), self.accumulated(package)))
+            .filter(|(_, term)| term.is_positive())
+            .min_by_key(|(_, term)| term.versions.len());
+        let Some((package, term)) = next else {
+            return Ok(None);
+        };
+
+        let version = match self.locked.get(&package) {
+            Some(locked) if term.versions.contains(locked) => locked.clone(),
+            _ => match term.versions.last() {
+                Some(newest) => newest.clone(),
+                None => {
+                    self.add(
+                        BTreeMap::from([(package.clone(), term)]),
+                        Cause::NoVersions {
+                            package: package.clone(),
+                        },
+                    );
+                    return Ok(Some(package));
+                }
+            },
+        };
+
+        let mut conflict = false;
+        let dependencies: BTreeMap<String, VersionReq> =
+            self.dependencies(&package, &version)?.into_iter().collect();
+        for (dependency, req) in dependencies {
+            let universe = self.universe(&dependency)?.clone();
+            let matching = universe
+                .iter()
+                .filter(|v| req.matches(v))
+                .cloned()
+                .collect();
+            let id = self.add(
+                BTreeMap::from([
+                    (
+                        package.clone(),
+                        Term::positive(BTreeSet::from([version.clone()])),
+                    ),
+                    (
+                        dependency.clone(),
+                        Term::positive(matching).negate(&universe),
+                    ),
+                ]),
+                Cause::Dependency {
+                    package: package.clone(),
+                    version: version.clone(),
+                    dependency,
+                    req,
+                },
+            );
+            // Deciding would immediately violate this dependency
+            conflict |= self.incompatibilities[id]
+                .terms
+                .iter()
+                .filter(|(name, _)| **name != package)
+                .all(|(name, term)| self.accumulated(name).is_subset(term));
+        }
+
+        if !conflict {
+            self.level += 1;
+            self.assignments.push(Assignment {
+                package: package.clone(),
+                term: Term::positive(BTreeSet::from([version])),
+                level: self.level,
+                cause: None,
+            });
+        }
+        Ok(Some(package))
+    }
+
+    /// Walk the derivation of `failure` back to the facts it came from
+    fn explain(&self, failure: usize) -> String {
+        let mut explainer = Explainer {
+            state: self,
+            lines: Vec::new(),
+            labels: 0,
+        };
+        explainer.explain(failure);
+        explainer.lines.join("\n")
+    }
+}
+
+struct Explainer<'s, 'a> {
+    state: &'s State<'a>,
+    lines: Vec<String>,
+    labels: usize,
+}
+
+impl Explainer<'_, '_> {
+    fn explain(&mut self, id: usize) {
+        let incompat = &self.state.incompatibilities[id];
+        let conclusion = self.describe(id);
+        let Cause::Derived(left, right) = incompat.cause else {
+            let fact = capitalize(&self.external(id));
+            self.lines.push(format!("{}, so {}.", fact, conclusion));
+            return;
+        };
+        let derived =
+            |id: usize| matches!(self.state.incompatibilities[id].cause, Cause::Derived(..));
+        let line = match (derived(left), derived(right)) {
+            (false, false) => format!(
+                "Because {} and {}, {}.",
+                self.external(left),
+                self.external(right),
+                conclusion
+            ),
+            (true, false) | (false, true) => {
+                let (inner, outer) = if derived(left) {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                self.explain(inner);
+                format!("And because {}, {}.", self.external(outer), conclusion)
+            }
+            (true, true) => {
+                let first = self.explain_labelled(left);
+                let second = self.explain_labelled(right);
+                format!("Because of ({}) and ({}), {}.", first, second, conclusion)
+            }
+        };
+        self.lines.push(line);
+    }
+
+    fn explain_labelled(&mut self, id: usize) -> usize {
+        self.explain(id);
+        self.labels += 1;
+        if let Some(line) = self.lines.last_mut() {
+            line.push_str(&format!(" ({})", self.labels));
+        }
+        self.labels
+    }
+
+    /// A fact that wasn't derived
+    fn external(&self, id: usize) -> String {
+        match &self.state.incompatibilities[id].cause {
+            Cause::Root => "the requirements must be met".to_string(),
+            Cause::Dependency {
+                package,
+                version,
+                dependency,
+                req,
+            } => {
+                let who = if package == ROOT {
+                    "the project requires".to_string()
+                } else {
+                    format!("{} {} depends on", package, version)
+                };
+                let universe = &self.state.universes[dependency];
+                let note = if universe.is_empty() {
+                    format!(", but no versions of {} are available", dependency)
+                } else if !universe.iter().any(|v| req.matches(v)) {
+                    format!(", but no available version of {} matches it", dependency)
+                } else {
+                    String::new()
+                };
+                format!("{} {} {}{}", who, dependency, req, note)
+            }
+            Cause::Locked { package, version } => {
+                format!("{} pins {} at {}", LOCKFILE_NAME, package, version)
+            }
+            Cause::NoVersions { package } => format!("no version of {} is left", package),
+            Cause::Derived(..) => self.describe(id),
+        }
+    }
+
+    /// What an incompatibility says, in words
+    fn describe(&self, id: usize) -> String {
+        let terms: Vec<(&String, &Term)> = self.state.incompatibilities[id]
+            .terms
+            .iter()
+            .filter(|(package, _)| package.as_str() != ROOT)
+            .collect();
+        let positive: Vec<String> = terms
+            .iter()
+            .filter(|(_, term)| term.is_positive())
+            .map(|(package, term)| self.versions(package, &term.versions))
+            .collect();
+        let required: Vec<String> = terms
+            .iter()
+            .filter(|(_, term)| !term.is_positive())
+            .map(|(package, term)| {
+                let universe = &self.state.universes[package.as_str()];
+                self.versions(package, &term.negate(universe).versions)
+            })
+            .collect();
+
+        match (positive.as_slice(), required.as_slice()) {
+            ([], []) => "version solving failed".to_string(),
+            ([one], []) => format!("{} is forbidden", one),
+            ([], [one]) => format!("{} is required", one),
+            ([one], [other]) => format!("{} requires {}", one, other),
+            (_, []) => format!("{} are incompatible", join(&positive, "and")),
+            ([], _) => format!("one of {} is required", join(&required, "or")),
+            (_, _) => format!(
+                "{} require {}",
+                join(&positive, "and"),
+                join(&required, "and")
+            ),
+        }
+    }
+
+    /// `package` limited to `versions`, out of its known versions
+    fn versions(&self, package: &str, versions: &BTreeSet<Version>) -> String {
+        let universe = &self.state.universes[package];
+        if versions == universe {
+            return package.to_string();
+        }
+        let listed: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+        match listed.len() {
+            0 => format!("no version of {}", package),
+            1..=3 => format!("{} {}", package, join(&listed, "or")),
+            n => format!(
+                "{} {}, {} or {} other versions",
+                package,
+                listed[0],
+                listed[1],
+                n - 2
+            ),
+        }
+    }
+}
+
+fn join(items: &[String], last: &str) -> String {
+    match items {
+        [] => String::new(),
+        [one] => one.clone(),
+        [rest @ .., final_item] => format!("{} {} {}", rest.join(", "), last, final_item),
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(version: &str) -> Version {
+        version.parse().unwrap()
+    }
+
+    fn reqs(requirements: &[(&str, &str)]) -> HashMap<String, VersionReq> {
+        requirements
+            .iter()
+            .map(|(name, req)| (name.to_string(), req.parse().unwrap()))
+            .collect()
+    }
+
+    /// A published package: name, version and its dependencies
+    type Published<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+    fn source(packages: &[Published]) -> MemorySource {
+        let mut source = MemorySource::new();
+        for (name, version, dependencies) in packages {
+            source.add(name, v(version), reqs(dependencies));
+        }
+        source
+    }
+
+    fn conflict(error: anyhow::Error) -> String {
+        error
+            .downcast::<ResolutionConflict>()
+            .expect("a resolution conflict")
+            .explanation
+    }
+
+    #[test]
+    fn picks_newest_versions_and_backtracks_on_conflicts() {
+        let source = source(&[
+            ("style", "1.0.0", &[("core", "^1")]),
+            ("style", "2.0.0", &[("core", "^2"), ("icons", "^1")]),
+            ("core", "1.4.0", &[]),
+            ("core", "2.1.0", &[]),
+            ("icons", "1.0.0", &[("core", "^1")]),
+            ("icons", "1.1.0", &[("core", "^2")]),
+            ("forms", "1.0.0", &[("core", ">=1.2, <2")]),
+        ]);
+
+        let resolution = Resolver::new(&source)
+            .resolve(&reqs(&[("style", "*")]))
+            .unwrap();
+        assert_eq!(resolution.version("style"), Some(&v("2.0.0")));
+        assert_eq!(resolution.version("icons"), Some(&v("1.1.0")));
+        assert_eq!(resolution.version("core"), Some(&v("2.1.0")));
+        assert_eq!(resolution.version("forms"), None);
+
+        // forms needs core 1.x, which rules out style 2 and icons 1.1
+        let resolution = Resolver::new(&source)
+            .resolve(&reqs(&[("style", "*"), ("forms", "^1")]))
+            .unwrap();
+        assert_eq!(resolution.version("style"), Some(&v("1.0.0")));
+        assert_eq!(resolution.version("core"), Some(&v("1.4.0")));
+        assert_eq!(resolution.packages.len(), 3);
+    }
+
+    #[test]
+    fn explains_unsatisfiable_requirements() {
+        let source = source(&[
+            ("style", "1.0.0", &[("core", "^1")]),
+            ("forms", "1.0.0", &[("core", "^2")]),
+            ("core", "1.0.0", &[]),
+            ("core", "2.0.0", &[]),
+        ]);
+
+        let error = Resolver::new(&source)
+            .resolve(&reqs(&[("style", "^1"), ("forms", "^1")]))
+            .unwrap_err();
+        let explanation = conflict(error);
+        assert!(
+            explanation.contains("style 1.0.0 depends on core ^1"),
+            "{}",
+            explanation
+        );
+        assert!(
+            explanation.contains("forms 1.0.0 depends on core ^2"),
+            "{}",
+            explanation
+        );
+        assert!(
+            explanation.ends_with("version solving failed."),
+            "{}",
+            explanation
+        );
+
+        let error = Resolver::new(&source)
+            .resolve(&reqs(&[("missing", "^1")]))
+            .unwrap_err();
+        assert_eq!(
+            conflict(error),
+            "The project requires missing ^1, but no versions of missing are available, \
+             so version solving failed."
+        );
+    }
+
+    #[test]
+    fn honors_the_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source = source(&[
+            ("style", "1.0.0", &[("core", "^1")]),
+            ("core", "1.0.0", &[]),
+        ]);
+        let requirements = reqs(&[("style", "^1")]);
+
+        resolve_locked(&source, &requirements, dir.path()).unwrap();
+        source.add("core", v("1.1.0"), HashMap::new());
+        source.add("style", v("1.2.0"), reqs(&[("core", "^1.1")]));
+
+        // Newer versions are ignored while the lock stands
+        let locked = resolve_locked(&source, &requirements, dir.path()).unwrap();
+        assert_eq!(locked.version("style"), Some(&v("1.0.0")));
+        assert_eq!(locked.version("core"), Some(&v("1.0.0")));
+        assert_eq!(
+            Resolver::new(&source)
+                .resolve(&requirements)
+                .unwrap()
+                .version("core"),
+            Some(&v("1.1.0"))
+        );
+
+        // And requirements the lock can't meet say so
+        let error = resolve_locked(&source, &reqs(&[("style", "^1.2")]), dir.path()).unwrap_err();
+        assert!(conflict(error).contains("dx.lock pins style at 1.0.0"));
+    }
+
+    #[test]
+    fn backtracks_past_a_decision_that_only_fails_later() {
+        // Picking app 2 looks fine until its ui dependency turns out to need
+        // a core that db rules out, two levels below the decision
+        let source = source(&[
+            ("app", "1.0.0", &[("ui", "^1")]),
+            ("app", "2.0.0", &[("ui", "^2")]),
+            ("ui", "1.0.0", &[("core", "^1")]),
+            ("ui", "2.0.0", &[("render", "^1")]),
+            ("render", "1.0.0", &[("core", "^2")]),
+            ("db", "1.0.0", &[("core", "^1")]),
+            ("core", "1.3.0", &[]),
+            ("core", "2.0.0", &[]),
+        ]);
+
+        let resolution = Resolver::new(&source)
+            .resolve(&reqs(&[("app", "*"), ("db", "^1")]))
+            .unwrap();
+        assert_eq!(resolution.version("app"), Some(&v("1.0.0")));
+        assert_eq!(resolution.version("ui"), Some(&v("1.0.0")));
+        assert_eq!(resolution.version("core"), Some(&v("1.3.0")));
+        assert_eq!(resolution.version("render"), None);
+    }
+
+    #[test]
+    fn explains_conflicts_through_transitive_dependencies() {
+        let source = source(&[
+            ("app", "1.0.0", &[("ui", "^1")]),
+            ("ui", "1.0.0", &[("core", "^2")]),
+            ("db", "1.0.0", &[("core", "^1")]),
+            ("core", "1.0.0", &[]),
+            ("core", "2.0.0", &[]),
+        ]);
+
+        let error = Resolver::new(&source)
+            .resolve(&reqs(&[("app", "^1"), ("db", "^1")]))
+            .unwrap_err();
+        let explanation = conflict(error);
+        for step in [
+            "app 1.0.0 depends on ui ^1",
+            "ui 1.0.0 depends on core ^2",
+            "db 1.0.0 depends on core ^1",
+        ] {
+            assert!(explanation.contains(step), "{}", explanation);
+        }
+        assert!(
+            explanation.ends_with("version solving failed."),
+            "{}",
+            explanation
+        );
+    }
+
+    #[test]
+    fn keeps_pins_that_satisfy_new_requirements() {
+        let source = source(&[
+            ("style", "1.0.0", &[("core", "^1")]),
+            ("style", "1.1.0", &[("core", "^1")]),
+            ("style", "2.0.0", &[("core", "^1")]),
+            ("core", "1.0.0", &[]),
+            ("core", "1.2.0", &[]),
+        ]);
+        let mut lockfile = Lockfile::default();
+        lockfile.pin("style", v("1.1.0"));
+
+        let resolution = Resolver::new(&source)
+            .with_lockfile(&lockfile)
+            .resolve(&reqs(&[("style", ">=1.1")]))
+            .unwrap();
+        assert_eq!(resolution.version("style"), Some(&v("1.1.0")));
+        // Packages the lockfile doesn't name still get their newest version
+        assert_eq!(resolution.version("core"), Some(&v("1.2.0")));
+
+        let error = Resolver::new(&source)
+            .with_lockfile(&lockfile)
+            .resolve(&reqs(&[("style", "^2")]))
+            .unwrap_err();
+        let explanation = conflict(error);
+        assert!(
+            explanation.contains("dx.lock pins style at 1.1.0"),
+            "{}",
+            explanation
+        );
+        assert!(
+            explanation.ends_with("version solving failed."),
+            "{}",
+            explanation
+        );
+    }
+
+    #[test]
+    fn skips_prereleases_unless_asked_for() {
+        let source = source(&[("style", "1.0.0", &[]), ("style", "1.1.0-beta.1", &[])]);
+
+        let resolution = Resolver::new(&source)
+            .resolve(&reqs(&[("style", "^1")]))
+            .unwrap();
+        assert_eq!(resolution.version("style"), Some(&v("1.0.0")));
+
+        let resolution = Resolver::new(&source)
+            .resolve(&reqs(&[("style", ">=1.1.0-beta")]))
+            .unwrap();
+        assert_eq!(resolution.version("style"), Some(&v("1.1.0-beta.1")));
+
+        // A prerelease is never pulled in to satisfy a requirement that
+        // doesn't name one
+        let error = Resolver::new(&source)
+            .resolve(&reqs(&[("style", "^1.1")]))
+            .unwrap_err();
+        assert!(conflict(error).ends_with("version solving failed."));
+    }
+}
@@ -5,17 +5,34 @@
 //! - Branching and merging
 //! - Version history
 //! - Diff computation
+//! - Restoring files and checking out branches
+//!
+//! File contents go to the blob store when a snapshot is taken, so any
+//! snapshot can later be written back to the working tree.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 
-use crate::storage::Database;
+use crate::storage::{BlobRepository, Database};
+use super::diff::{
+    is_text, text_hunks, tool_diffs, ContentDiff, FileContentDiff, FileDiff, FileStatus,
+};
+use super::merge::{
+    merge_text, pick, FileMerge, MergeConflict, MergeOutcome, MergeResult, MergeSide, ToolConflict,
+};
 use super::types::Version;
 
+/// Branch that `RestoreMode::Stash` saves local changes on
+pub const STASH_BRANCH: &str = "stash";
+
+/// Files up to this size are read whole and stored compressed; larger ones
+/// are streamed into the blob store
+const INLINE_FILE_LIMIT: u64 = 8 * 1024 * 1024;
+
 /// Unique identifier for a snapshot
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SnapshotId(String);
@@ -31,6 +48,12 @@ impl SnapshotId {
         Self(s.into())
     }
 
+    /// Whether this has the shape of an id `from_hash` produces. Ids name
+    /// files, so check ones read from elsewhere before looking them up.
+    pub fn is_well_formed(&self) -> bool {
+        self.0.len() == 64 && self.0.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
     /// Get the hash string
     pub fn as_str(&self) -> &str {
         &self.0
@@ -109,11 +132,17 @@ impl FileSnapshot {
 
     /// Check if file has changed since snapshot
     pub fn has_changed(&self) -> Result<bool> {
-        if !self.path.exists() {
+        self.has_changed_at(&self.path)
+    }
+
+    /// Check if the file at `location` differs from this snapshot, e.g.
+    /// when the snapshot's path is relative to a work tree
+    pub fn has_changed_at(&self, location: &Path) -> Result<bool> {
+        if !location.exists() {
             return Ok(true);
         }
 
-        let content = std::fs::read(&self.path)?;
+        let content = std::fs::read(location)?;
         let current_hash = format!("{:x}", Sha256::digest(&content));
         Ok(current_hash != self.hash)
     }
@@ -128,12 +157,87 @@ pub struct Branch {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Name HEAD goes by in the reflog and in revisions
+pub const HEAD: &str = "HEAD";
+
+/// What HEAD points at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Head {
+    /// On a branch; new snapshots advance the branch
+    Branch(String),
+    /// On a snapshot directly; new snapshots only advance HEAD
+    Detached(SnapshotId),
+}
+
+/// A stable name for a snapshot. Annotated tags also record a message and
+/// who created them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub target: SnapshotId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tagger: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Tag {
+    pub fn is_annotated(&self) -> bool {
+        self.message.is_some()
+    }
+}
+
+/// One movement of a branch or of HEAD
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflogEntry {
+    /// Branch name, or `HEAD`
+    pub ref_name: String,
+    /// `None` when the ref was created
+    pub old: Option<SnapshotId>,
+    /// `None` when the ref was deleted
+    pub new: Option<SnapshotId>,
+    pub reason: String,
+    pub actor: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// What to do when a restore would overwrite local changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestoreMode {
+    /// Fail without touching anything
+    #[default]
+    Refuse,
+    /// Snapshot the changed files on the `stash` branch first
+    Stash,
+    /// Overwrite them
+    Force,
+}
+
+/// Outcome of a restore or checkout
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Files written to disk
+    pub written: Vec<PathBuf>,
+    /// Files removed because the target no longer tracks them
+    pub removed: Vec<PathBuf>,
+    /// Files that already had the right content
+    pub unchanged: usize,
+    /// Snapshot holding the local changes, with `RestoreMode::Stash`
+    pub stash: Option<SnapshotId>,
+}
+
 /// Snapshot manager for version control
 pub struct SnapshotManager {
-    _db: Database,
+    db: Database,
+    blobs: BlobRepository,
+    work_dir: PathBuf,
     snapshots_path: PathBuf,
     branches_path: PathBuf,
-    current_branch: String,
+    tags_path: PathBuf,
+    head_path: PathBuf,
+    head: Head,
 }
 
 impl SnapshotManager {
@@ -142,54 +246,122 @@ impl SnapshotManager {
         // Ensure forge directory exists and open the shared forge database
         std::fs::create_dir_all(forge_dir)?;
         let db = Database::new(forge_dir)?;
+        db.initialize()?;
 
         let snapshots_path = forge_dir.join("snapshots");
         let branches_path = forge_dir.join("branches.json");
+        let head_path = forge_dir.join("HEAD");
 
         std::fs::create_dir_all(&snapshots_path)?;
 
-        // Initialize with main branch if needed
-        let current_branch = if branches_path.exists() {
-            let content = std::fs::read_to_string(&branches_path)?;
-            let branches: HashMap<String, Branch> = serde_json::from_str(&content)?;
-            branches.keys().next().cloned().unwrap_or_else(|| "main".to_string())
+        let head = if head_path.exists() {
+            let content = std::fs::read_to_string(&head_path)?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Malformed HEAD file {}", head_path.display()))?
+        } else {
+            // Repositories from before HEAD was stored: main if it exists,
+            // otherwise the first branch by name
+            let branches: BTreeMap<String, Branch> = if branches_path.exists() {
+                serde_json::from_str(&std::fs::read_to_string(&branches_path)?)?
+            } else {
+                BTreeMap::new()
+            };
+            let name = match branches.keys().next() {
+                Some(first) if !branches.contains_key("main") => first.clone(),
+                _ => "main".to_string(),
+            };
+            Head::Branch(name)
+        };
+
+        // `<root>/.dx/forge` belongs to the work tree at `<root>`
+        let work_dir = if forge_dir.ends_with(".dx/forge") {
+            forge_dir
+                .parent()
+                .and_then(Path::parent)
+                .unwrap_or(forge_dir)
+                .to_path_buf()
         } else {
-            "main".to_string()
+            forge_dir.to_path_buf()
         };
 
         Ok(Self {
-            _db: db,
+            db,
+            blobs: BlobRepository::new(forge_dir)?,
+            work_dir,
             snapshots_path,
             branches_path,
-            current_branch,
+            tags_path: forge_dir.join("tags.json"),
+            head_path,
+            head,
         })
     }
 
-    /// Create a new snapshot
-    pub fn create_snapshot(
+    /// Resolve relative snapshot paths against this work tree instead of
+    /// the one derived from the forge directory
+    pub fn with_work_dir(mut self, work_dir: impl Into<PathBuf>) -> Self {
+        self.work_dir = work_dir.into();
+        self
+    }
+
+    /// Work tree that relative snapshot paths are resolved against
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    /// Blob store holding snapshot file contents
+    pub fn blobs(&self) -> &BlobRepository {
+        &self.blobs
+    }
+
+    /// Where a snapshot path lives on disk
+    pub fn location(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.work_dir.join(path)
+        }
+    }
+
+    /// Create a new snapshot on the current branch (or on a detached
+    /// HEAD), storing the files' contents in the blob store
+    pub async fn create_snapshot(
         &mut self,
         message: impl Into<String>,
         tool_states: HashMap<String, ToolState>,
         files: Vec<PathBuf>,
+    ) -> Result<SnapshotId> {
+        self.snapshot_on(None, message.into(), tool_states, files)
+            .await
+    }
+
+    /// Snapshot onto `branch`, or onto whatever HEAD points at
+    async fn snapshot_on(
+        &mut self,
+        branch: Option<&str>,
+        message: String,
+        tool_states: HashMap<String, ToolState>,
+        files: Vec<PathBuf>,
     ) -> Result<SnapshotId> {
         let author = whoami::username();
         let timestamp = Utc::now();
-        let message: String = message.into();
 
         // Create file snapshots
         let mut file_snapshots = HashMap::new();
         for file in files {
-            if file.exists() {
-                let snapshot = FileSnapshot::from_path(&file)?;
+            let location = self.location(&file);
+            if location.exists() {
+                let snapshot = self.store_file(&file, &location).await?;
                 file_snapshots.insert(file, snapshot);
             }
         }
 
-        // Get parent snapshot from current branch
-        let parents = self
-            .get_branch_head(&self.current_branch)?
-            .map(|head| vec![head])
-            .unwrap_or_default();
+        // Get parent snapshot from the branch
+        let parent = match branch {
+            Some(branch) => self.get_branch_head(branch)?,
+            None => self.head_id()?,
+        };
+        let parents: Vec<SnapshotId> = parent.into_iter().collect();
+        let reason = format!("snapshot: {}", message.lines().next().unwrap_or_default());
 
         // Compute snapshot ID from full commit-like content so that
         // even identical tool states taken at different times produce
@@ -219,14 +391,43 @@ impl SnapshotManager {
         // Save snapshot
         self.save_snapshot(&snapshot)?;
 
-        // Update branch head (clone current_branch to avoid borrow conflict)
-        let current_branch = self.current_branch.clone();
-        self.update_branch_head(&current_branch, id.clone())?;
+        match branch {
+            Some(branch) => self.update_branch_head(branch, id.clone(), &reason)?,
+            None => self.advance_head(id.clone(), &reason)?,
+        }
 
-        tracing::info!("Created snapshot {} on branch {}", id, self.current_branch);
+        tracing::info!("Created snapshot {} on {}", id, branch.unwrap_or(&self.head_label()));
         Ok(id)
     }
 
+    /// Store a file's content and describe it
+    async fn store_file(&self, path: &Path, location: &Path) -> Result<FileSnapshot> {
+        let metadata = std::fs::metadata(location)?;
+        let modified = metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+
+        let (hash, size) = if metadata.len() > INLINE_FILE_LIMIT {
+            let stored = self.blobs.store_file(location).await?;
+            (stored.hash, stored.size)
+        } else {
+            let content = tokio::fs::read(location).await?;
+            let blob = crate::storage::Blob::from_content(&path.display().to_string(), content);
+            if !self.blobs.exists_local(blob.hash()).await {
+                self.blobs.store_local(&blob).await?;
+            }
+            (blob.metadata.hash, blob.metadata.size)
+        };
+
+        Ok(FileSnapshot {
+            path: path.to_path_buf(),
+            hash,
+            size,
+            modified,
+        })
+    }
+
     /// Get a snapshot by ID
     pub fn get_snapshot(&self, id: &SnapshotId) -> Result<Option<Snapshot>> {
         let snapshot_file = self.snapshots_path.join(format!("{}.json", id.as_str()));
@@ -240,57 +441,398 @@ impl SnapshotManager {
         Ok(Some(snapshot))
     }
 
-    /// Create a new branch
+    /// Create a new branch at HEAD
     pub fn create_branch(&mut self, name: impl Into<String>) -> Result<()> {
         let name = name.into();
-        
-        let head = self.get_branch_head(&self.current_branch)?
+        validate_ref_name(&name)?;
+        if self.branch_exists(&name) {
+            anyhow::bail!("Branch {} already exists", name);
+        }
+
+        let head = self.head_id()?
             .ok_or_else(|| anyhow::anyhow!("Current branch has no commits"))?;
 
         let branch = Branch {
             name: name.clone(),
-            head,
+            head: head.clone(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         };
 
         self.save_branch(&branch)?;
+        self.log_ref(&name, None, Some(head), &format!("branch: created from {}", self.head_label()))?;
         tracing::info!("Created branch {}", name);
         Ok(())
     }
 
-    /// Switch to a different branch
-    pub fn checkout_branch(&mut self, name: impl Into<String>) -> Result<()> {
+    /// Delete a branch. Its snapshots stay until garbage collected.
+    pub fn delete_branch(&mut self, name: &str) -> Result<()> {
+        if self.current_branch() == Some(name) {
+            anyhow::bail!("Cannot delete the checked out branch {}", name);
+        }
+
+        let mut branches = self.read_branches()?;
+        let branch = branches
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Branch {} does not exist", name))?;
+        self.write_branches(&branches)?;
+
+        self.log_ref(name, Some(branch.head), None, "branch: deleted")?;
+        tracing::info!("Deleted branch {}", name);
+        Ok(())
+    }
+
+    /// Rename a branch, keeping its reflog and following it with HEAD
+    pub fn rename_branch(&mut self, old: &str, new: impl Into<String>) -> Result<()> {
+        let new = new.into();
+        validate_ref_name(&new)?;
+
+        let mut branches = self.read_branches()?;
+        if branches.contains_key(&new) {
+            anyhow::bail!("Branch {} already exists", new);
+        }
+        let mut branch = branches
+            .remove(old)
+            .ok_or_else(|| anyhow::anyhow!("Branch {} does not exist", old))?;
+        branch.name = new.clone();
+        branch.updated_at = Utc::now();
+        let head = branch.head.clone();
+        branches.insert(new.clone(), branch);
+        self.write_branches(&branches)?;
+
+        self.db.rename_reflog(old, &new)?;
+        let reason = format!("branch: renamed {} to {}", old, new);
+        self.log_ref(&new, Some(head.clone()), Some(head), &reason)?;
+        if self.current_branch() == Some(old) {
+            self.head = Head::Branch(new.clone());
+            self.write_head()?;
+        }
+
+        tracing::info!("Renamed branch {} to {}", old, new);
+        Ok(())
+    }
+
+    /// Tag a snapshot. With a message the tag is annotated with it and the
+    /// current user.
+    pub fn create_tag(
+        &self,
+        name: impl Into<String>,
+        target: &SnapshotId,
+        message: Option<&str>,
+    ) -> Result<Tag> {
+        let name = name.into();
+        validate_ref_name(&name)?;
+        self.load_snapshot(target)?;
+
+        let mut tags = self.read_tags()?;
+        if tags.contains_key(&name) {
+            anyhow::bail!("Tag {} already exists", name);
+        }
+
+        let tag = Tag {
+            name: name.clone(),
+            target: target.clone(),
+            message: message.map(str::to_string),
+            tagger: message.map(|_| whoami::username()),
+            created_at: Utc::now(),
+        };
+        tags.insert(name, tag.clone());
+        self.write_tags(&tags)?;
+
+        tracing::info!("Tagged {} as {}", target, tag.name);
+        Ok(tag)
+    }
+
+    pub fn get_tag(&self, name: &str) -> Result<Option<Tag>> {
+        Ok(self.read_tags()?.remove(name))
+    }
+
+    /// All tags, by name
+    pub fn list_tags(&self) -> Result<Vec<Tag>> {
+        Ok(self.read_tags()?.into_values().collect())
+    }
+
+    pub fn delete_tag(&self, name: &str) -> Result<()> {
+        let mut tags = self.read_tags()?;
+        if tags.remove(name).is_none() {
+            anyhow::bail!("Tag {} does not exist", name);
+        }
+        self.write_tags(&tags)
+    }
+
+    /// Add a tag made elsewhere, e.g. fetched from a remote, as is. An
+    /// existing tag of the same name is kept; returns whether it was added.
+    pub fn import_tag(&self, tag: &Tag) -> Result<bool> {
+        let mut tags = self.read_tags()?;
+        if tags.contains_key(&tag.name) {
+            return Ok(false);
+        }
+        tags.insert(tag.name.clone(), tag.clone());
+        self.write_tags(&tags)?;
+        Ok(true)
+    }
+
+    /// Resolve `HEAD`, a branch, a tag or a snapshot id (or a unique
+    /// prefix of one) to a snapshot
+    pub fn resolve(&self, rev: &str) -> Result<SnapshotId> {
+        if rev == HEAD {
+            return self.head_id()?
+                .ok_or_else(|| anyhow::anyhow!("HEAD has no snapshots yet"));
+        }
+        if let Some(head) = self.get_branch_head(rev)? {
+            return Ok(head);
+        }
+        if let Some(tag) = self.get_tag(rev)? {
+            return Ok(tag.target);
+        }
+
+        let mut matches = self
+            .all_snapshot_ids()?
+            .into_iter()
+            .filter(|id| id.as_str().starts_with(rev));
+        match (matches.next(), matches.next()) {
+            (Some(id), None) if !rev.is_empty() => Ok(id),
+            (Some(_), Some(_)) => anyhow::bail!("Snapshot prefix {} is ambiguous", rev),
+            _ => anyhow::bail!("Unknown revision {}", rev),
+        }
+    }
+
+    /// Movements of a branch or `HEAD`, newest first
+    pub fn reflog(&self, ref_name: &str, limit: usize) -> Result<Vec<ReflogEntry>> {
+        self.db.reflog(ref_name, limit)
+    }
+
+    /// Switch to a different branch, updating the working tree to its
+    /// head. Refuses if that would overwrite local changes.
+    pub async fn checkout_branch(&mut self, name: impl Into<String>) -> Result<RestoreReport> {
+        self.checkout_branch_with(name, RestoreMode::Refuse).await
+    }
+
+    /// `checkout_branch` with a choice of what happens to local changes
+    pub async fn checkout_branch_with(
+        &mut self,
+        name: impl Into<String>,
+        mode: RestoreMode,
+    ) -> Result<RestoreReport> {
         let name = name.into();
         
         if !self.branch_exists(&name) {
             anyhow::bail!("Branch {} does not exist", name);
         }
 
-        self.current_branch = name.clone();
+        let target = match self.get_branch_head(&name)? {
+            Some(head) => self.load_snapshot(&head)?.files,
+            None => HashMap::new(),
+        };
+        let report = self.switch_to(target, mode).await?;
+
+        let reason = format!("checkout: moving from {} to {}", self.head_label(), name);
+        self.set_head(Head::Branch(name.clone()), &reason)?;
         tracing::info!("Switched to branch {}", name);
-        Ok(())
+        Ok(report)
     }
 
-    /// Get current branch name
-    pub fn current_branch(&self) -> &str {
-        &self.current_branch
+    /// Check out a snapshot (any revision `resolve` accepts) without a
+    /// branch. New snapshots then only move HEAD until a branch is created
+    /// or checked out.
+    pub async fn checkout_detached(&mut self, rev: &str, mode: RestoreMode) -> Result<RestoreReport> {
+        let id = self.resolve(rev)?;
+        let target = self.load_snapshot(&id)?.files;
+        let report = self.switch_to(target, mode).await?;
+
+        let reason = format!("checkout: moving from {} to {}", self.head_label(), rev);
+        self.set_head(Head::Detached(id.clone()), &reason)?;
+        tracing::info!("HEAD is now at {}", id);
+        Ok(report)
     }
 
-    /// List all branches
-    pub fn list_branches(&self) -> Result<Vec<Branch>> {
-        if !self.branches_path.exists() {
-            return Ok(vec![]);
+    /// Make the working tree match `target`, starting from HEAD
+    async fn switch_to(
+        &mut self,
+        target: HashMap<PathBuf, FileSnapshot>,
+        mode: RestoreMode,
+    ) -> Result<RestoreReport> {
+        let current = self.head_files()?;
+
+        // Files the current head tracks and the target doesn't go away
+        let removals: Vec<PathBuf> = current
+            .keys()
+            .filter(|path| !target.contains_key(*path))
+            .cloned()
+            .collect();
+
+        self.materialize(target, removals, mode).await
+    }
+
+    /// Write the given paths (all files when empty) of a snapshot to the
+    /// working tree. Paths may name directories. Files the snapshot
+    /// doesn't track are left alone.
+    pub async fn restore(
+        &mut self,
+        id: &SnapshotId,
+        paths: &[PathBuf],
+        mode: RestoreMode,
+    ) -> Result<RestoreReport> {
+        let snapshot = self.load_snapshot(id)?;
+        let files: HashMap<PathBuf, FileSnapshot> = snapshot
+            .files
+            .into_iter()
+            .filter(|(path, _)| paths.is_empty() || paths.iter().any(|p| path.starts_with(p)))
+            .collect();
+        if files.is_empty() && !paths.is_empty() {
+            anyhow::bail!("Snapshot {} has none of the requested paths", id);
         }
 
-        let content = std::fs::read_to_string(&self.branches_path)?;
-        let branches: HashMap<String, Branch> = serde_json::from_str(&content)?;
-        Ok(branches.into_values().collect())
+        self.materialize(files, Vec::new(), mode).await
+    }
+
+    /// Paths whose on-disk content differs from the current head: modified
+    /// or deleted tracked files. Untracked files aren't reported.
+    pub fn changed_files(&self) -> Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        for (path, file) in self.head_files()? {
+            if file.has_changed_at(&self.location(&path))? {
+                changed.push(path);
+            }
+        }
+        changed.sort();
+        Ok(changed)
+    }
+
+    async fn materialize(
+        &mut self,
+        target: HashMap<PathBuf, FileSnapshot>,
+        removals: Vec<PathBuf>,
+        mode: RestoreMode,
+    ) -> Result<RestoreReport> {
+        let current = self.head_files()?;
+        let mut report = RestoreReport::default();
+
+        // Local changes that writing or removing would lose
+        let mut to_write = Vec::new();
+        let mut dirty = Vec::new();
+        for (path, file) in &target {
+            let location = self.location(path);
+            let Some(on_disk) = hash_file(&location)? else {
+                to_write.push((path.clone(), file.clone()));
+                continue;
+            };
+            if on_disk == file.hash {
+                report.unchanged += 1;
+                continue;
+            }
+            if current.get(path).is_none_or(|head| head.hash != on_disk) {
+                dirty.push(path.clone());
+            }
+            to_write.push((path.clone(), file.clone()));
+        }
+        let mut to_remove = Vec::new();
+        for path in removals {
+            let Some(head) = current.get(&path) else {
+                continue;
+            };
+            let location = self.location(&path);
+            if !location.exists() {
+                continue;
+            }
+            if head.has_changed_at(&location)? {
+                dirty.push(path.clone());
+            }
+            to_remove.push(path);
+        }
+
+        if !dirty.is_empty() {
+            dirty.sort();
+            match mode {
+                RestoreMode::Refuse => anyhow::bail!(
+                    "Local changes would be overwritten: {}",
+                    dirty
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                RestoreMode::Stash => {
+                    let message = format!("Stash of {} changed files", dirty.len());
+                    let stash = self
+                        .snapshot_on(Some(STASH_BRANCH), message, HashMap::new(), dirty)
+                        .await?;
+                    report.stash = Some(stash);
+                }
+                RestoreMode::Force => {}
+            }
+        }
+
+        for (path, file) in to_write {
+            let content = self
+                .blobs
+                .load_local(&file.hash)
+                .await
+                .with_context(|| format!("Content of {} is not in the blob store", path.display()))?
+                .content;
+            write_atomically(&self.location(&path), &content)?;
+            report.written.push(path);
+        }
+        for path in to_remove {
+            std::fs::remove_file(self.location(&path))?;
+            report.removed.push(path);
+        }
+
+        report.written.sort();
+        report.removed.sort();
+        Ok(report)
+    }
+
+    fn load_snapshot(&self, id: &SnapshotId) -> Result<Snapshot> {
+        self.get_snapshot(id)?
+            .ok_or_else(|| anyhow::anyhow!("Snapshot {} not found", id.as_str()))
     }
 
-    /// Get commit history for current branch
+    /// Files tracked by HEAD
+    fn head_files(&self) -> Result<HashMap<PathBuf, FileSnapshot>> {
+        match self.head_id()? {
+            Some(head) => Ok(self.load_snapshot(&head)?.files),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Get current branch name, or `None` when HEAD is detached
+    pub fn current_branch(&self) -> Option<&str> {
+        match &self.head {
+            Head::Branch(name) => Some(name),
+            Head::Detached(_) => None,
+        }
+    }
+
+    /// What HEAD points at
+    pub fn head(&self) -> &Head {
+        &self.head
+    }
+
+    /// Snapshot HEAD points at, if any
+    pub fn head_id(&self) -> Result<Option<SnapshotId>> {
+        match &self.head {
+            Head::Branch(name) => self.get_branch_head(name),
+            Head::Detached(id) => Ok(Some(id.clone())),
+        }
+    }
+
+    /// Branch name, or the short snapshot id when detached
+    fn head_label(&self) -> String {
+        match &self.head {
+            Head::Branch(name) => name.clone(),
+            Head::Detached(id) => id.to_string(),
+        }
+    }
+
+    /// List all branches
+    pub fn list_branches(&self) -> Result<Vec<Branch>> {
+        Ok(self.read_branches()?.into_values().collect())
+    }
+
+    /// Get commit history from HEAD
     pub fn history(&self, limit: usize) -> Result<Vec<Snapshot>> {
-        let head = self.get_branch_head(&self.current_branch)?;
+        let head = self.head_id()?;
         
         if head.is_none() {
             return Ok(vec![]);
@@ -315,35 +857,189 @@ impl SnapshotManager {
         Ok(history)
     }
 
-    /// Merge a branch into current branch
-    pub fn merge(&mut self, source_branch: impl Into<String>, message: impl Into<String>) -> Result<SnapshotId> {
+    /// Best common ancestor of two snapshots: the common ancestor closest
+    /// to `b`, found by walking back from `b` breadth-first
+    pub fn merge_base(&self, a: &SnapshotId, b: &SnapshotId) -> Result<Option<SnapshotId>> {
+        let mut ancestors = std::collections::HashSet::new();
+        let mut pending = vec![a.clone()];
+        while let Some(id) = pending.pop() {
+            if ancestors.insert(id.clone()) {
+                if let Some(snapshot) = self.get_snapshot(&id)? {
+                    pending.extend(snapshot.parents);
+                }
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = std::collections::VecDeque::from([b.clone()]);
+        while let Some(id) = queue.pop_front() {
+            if ancestors.contains(&id) {
+                return Ok(Some(id));
+            }
+            if seen.insert(id.clone()) {
+                if let Some(snapshot) = self.get_snapshot(&id)? {
+                    queue.extend(snapshot.parents);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Merge a branch (or any revision `resolve` accepts) into HEAD against
+    /// their merge base, fast-forwarding when possible and updating the
+    /// working tree. When anything conflicts nothing is changed and the
+    /// conflicts are returned.
+    pub async fn merge(
+        &mut self,
+        source_branch: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Result<MergeResult> {
         let source_branch = source_branch.into();
-        
-        let source_head = self.get_branch_head(&source_branch)?
-            .ok_or_else(|| anyhow::anyhow!("Source branch has no commits"))?;
+        let target_branch = self.head_label();
+
+        let source_head = self.resolve(&source_branch)?;
 
-        let target_head = self.get_branch_head(&self.current_branch)?
+        let target_head = self.head_id()?
             .ok_or_else(|| anyhow::anyhow!("Current branch has no commits"))?;
 
-        // Get snapshots
-        let source_snap = self.get_snapshot(&source_head)?
-            .ok_or_else(|| anyhow::anyhow!("Source snapshot not found"))?;
+        let base = self.merge_base(&target_head, &source_head)?;
+        let mut result = MergeResult {
+            outcome: MergeOutcome::UpToDate,
+            base: base.clone(),
+            head: Some(target_head.clone()),
+            files: BTreeMap::new(),
+            tool_conflicts: Vec::new(),
+            restore: None,
+        };
 
-        let target_snap = self.get_snapshot(&target_head)?
-            .ok_or_else(|| anyhow::anyhow!("Target snapshot not found"))?;
+        if base.as_ref() == Some(&source_head) {
+            return Ok(result);
+        }
+
+        let source_snap = self.load_snapshot(&source_head)?;
+        let target_snap = self.load_snapshot(&target_head)?;
+
+        if base.as_ref() == Some(&target_head) {
+            let removals = target_snap
+                .files
+                .keys()
+                .filter(|path| !source_snap.files.contains_key(*path))
+                .cloned()
+                .collect();
+            result.restore = Some(
+                self.materialize(source_snap.files, removals, RestoreMode::Refuse)
+                    .await?,
+            );
+            self.advance_head(source_head.clone(), &format!("merge {}: fast-forward", source_branch))?;
+            result.outcome = MergeOutcome::FastForward;
+            result.head = Some(source_head);
+            tracing::info!("Fast-forwarded {} to {}", target_branch, source_branch);
+            return Ok(result);
+        }
+
+        let base_snap = match &base {
+            Some(id) => Some(self.load_snapshot(id)?),
+            None => None,
+        };
+        let base_files = base_snap.as_ref().map(|s| s.files.clone()).unwrap_or_default();
+        let base_states = base_snap.map(|s| s.tool_states).unwrap_or_default();
+
+        // Tool states, compared by value
+        let mut merged_states = HashMap::new();
+        let tools: BTreeSet<&String> = base_states
+            .keys()
+            .chain(target_snap.tool_states.keys())
+            .chain(source_snap.tool_states.keys())
+            .collect();
+        for tool in tools {
+            let (b, o, t) = (
+                base_states.get(tool),
+                target_snap.tool_states.get(tool),
+                source_snap.tool_states.get(tool),
+            );
+            let value = |state: Option<&ToolState>| state.map(serde_json::to_value).transpose();
+            match pick(value(b)?.as_ref(), value(o)?.as_ref(), value(t)?.as_ref()) {
+                Some(MergeSide::Ours) => merged_states.extend(o.map(|s| (tool.clone(), s.clone()))),
+                Some(MergeSide::Theirs) => merged_states.extend(t.map(|s| (tool.clone(), s.clone()))),
+                None => result.tool_conflicts.push(ToolConflict {
+                    tool: tool.clone(),
+                    base: b.cloned(),
+                    ours: o.cloned(),
+                    theirs: t.cloned(),
+                }),
+            }
+        }
 
-        // Merge tool states (target takes precedence for conflicts)
-        let mut merged_states = target_snap.tool_states.clone();
-        for (name, state) in source_snap.tool_states {
-            merged_states.entry(name).or_insert(state);
+        // Files, compared by content hash
+        let mut merged_files = HashMap::new();
+        let paths: BTreeSet<&PathBuf> = base_files
+            .keys()
+            .chain(target_snap.files.keys())
+            .chain(source_snap.files.keys())
+            .collect();
+        for path in paths {
+            let (b, o, t) = (
+                base_files.get(path),
+                target_snap.files.get(path),
+                source_snap.files.get(path),
+            );
+            let hash = |file: Option<&FileSnapshot>| file.map(|f| f.hash.clone());
+            let picked = pick(hash(b).as_ref(), hash(o).as_ref(), hash(t).as_ref());
+            let merge = match (picked, o, t) {
+                (Some(MergeSide::Ours), Some(file), _) => {
+                    merged_files.insert(path.clone(), file.clone());
+                    if hash(o) == hash(t) {
+                        FileMerge::Unchanged
+                    } else {
+                        FileMerge::FastForward { from: MergeSide::Ours }
+                    }
+                }
+                (Some(MergeSide::Ours), None, _) => FileMerge::Deleted { by: MergeSide::Ours },
+                (Some(MergeSide::Theirs), _, Some(file)) => {
+                    merged_files.insert(path.clone(), file.clone());
+                    FileMerge::FastForward { from: MergeSide::Theirs }
+                }
+                (Some(MergeSide::Theirs), _, None) => FileMerge::Deleted { by: MergeSide::Theirs },
+                (None, Some(ours), Some(theirs)) => {
+                    let labels = (target_branch.as_str(), source_branch.as_str());
+                    match self.merge_contents(path, b, ours, theirs, labels).await? {
+                        Ok(file) => {
+                            merged_files.insert(path.clone(), file);
+                            FileMerge::Clean
+                        }
+                        Err(conflict) => FileMerge::Conflict(conflict),
+                    }
+                }
+                (None, None, _) => FileMerge::Conflict(MergeConflict::DeleteModify {
+                    deleted_by: MergeSide::Ours,
+                }),
+                (None, _, None) => FileMerge::Conflict(MergeConflict::DeleteModify {
+                    deleted_by: MergeSide::Theirs,
+                }),
+            };
+            result.files.insert(path.clone(), merge);
         }
 
-        // Merge files
-        let mut merged_files = target_snap.files.clone();
-        for (path, file) in source_snap.files {
-            merged_files.entry(path).or_insert(file);
+        if result.has_conflicts() {
+            result.outcome = MergeOutcome::Conflicted;
+            result.head = None;
+            return Ok(result);
         }
 
+        // Bring the working tree in line before moving the branch, so a
+        // refusal leaves everything as it was
+        let removals = target_snap
+            .files
+            .keys()
+            .filter(|path| !merged_files.contains_key(*path))
+            .cloned()
+            .collect();
+        result.restore = Some(
+            self.materialize(merged_files.clone(), removals, RestoreMode::Refuse)
+                .await?,
+        );
+
         // Create merge snapshot with both parents
         let author = whoami::username();
         let timestamp = Utc::now();
@@ -372,13 +1068,57 @@ impl SnapshotManager {
         };
 
         self.save_snapshot(&snapshot)?;
-        
-        // Update branch head (clone to avoid borrow conflict)
-        let current_branch = self.current_branch.clone();
-        self.update_branch_head(&current_branch, id.clone())?;
+        self.advance_head(id.clone(), &format!("merge {}", source_branch))?;
 
-        tracing::info!("Merged {} into {} ({})", source_branch, self.current_branch, id);
-        Ok(id)
+        tracing::info!("Merged {} into {} ({})", source_branch, target_branch, id);
+        result.outcome = MergeOutcome::Merged;
+        result.head = Some(id);
+        Ok(result)
+    }
+
+    /// Line-merge a file changed on both sides. A clean result is stored
+    /// as a new blob.
+    async fn merge_contents(
+        &self,
+        path: &Path,
+        base: Option<&FileSnapshot>,
+        ours: &FileSnapshot,
+        theirs: &FileSnapshot,
+        labels: (&str, &str),
+    ) -> Result<std::result::Result<FileSnapshot, MergeConflict>> {
+        let text = |hash: String| async move {
+            let blob = self.blobs.load_local(&hash).await?;
+            Ok::<_, anyhow::Error>(String::from_utf8(blob.content).ok())
+        };
+        let base_text = match base {
+            Some(file) => text(file.hash.clone()).await?,
+            None => Some(String::new()),
+        };
+        let (Some(base_text), Some(ours_text), Some(theirs_text)) = (
+            base_text,
+            text(ours.hash.clone()).await?,
+            text(theirs.hash.clone()).await?,
+        ) else {
+            return Ok(Err(MergeConflict::Binary));
+        };
+
+        let merged = merge_text(&base_text, &ours_text, &theirs_text, labels);
+        if !merged.is_clean() {
+            let (content, regions) = (merged.content, merged.conflicts);
+            return Ok(Err(match base {
+                Some(_) => MergeConflict::Content { merged: content, regions },
+                None => MergeConflict::AddAdd { merged: content, regions },
+            }));
+        }
+
+        let blob = crate::storage::Blob::from_content(&path.display().to_string(), merged.content.into_bytes());
+        self.blobs.store_local(&blob).await?;
+        Ok(Ok(FileSnapshot {
+            path: path.to_path_buf(),
+            hash: blob.metadata.hash,
+            size: blob.metadata.size,
+            modified: Utc::now(),
+        }))
     }
 
     /// Compute diff between two snapshots
@@ -423,6 +1163,177 @@ impl SnapshotManager {
         })
     }
 
+    /// Line-level diff between two snapshots using their stored contents,
+    /// with files that only moved reported as renames
+    pub async fn diff_contents(&self, from: &SnapshotId, to: &SnapshotId) -> Result<ContentDiff> {
+        let from_snap = self.load_snapshot(from)?;
+        let to_snap = self.load_snapshot(to)?;
+        let summary = self.diff(from, to)?;
+
+        // Pair deleted and added paths with the same content
+        let mut added: Vec<&PathBuf> = summary.added_files.iter().collect();
+        let mut renames = HashMap::new();
+        for old_path in &summary.deleted_files {
+            let hash = &from_snap.files[old_path].hash;
+            if let Some(i) = added.iter().position(|p| &to_snap.files[*p].hash == hash) {
+                renames.insert(added.remove(i).clone(), old_path.clone());
+            }
+        }
+        let renamed: BTreeSet<&PathBuf> = renames.values().collect();
+
+        let mut files = Vec::new();
+        for (path, old_path) in &renames {
+            files.push(FileDiff {
+                path: path.clone(),
+                old_path: Some(old_path.clone()),
+                status: FileStatus::Renamed,
+                content: FileContentDiff::Unchanged,
+            });
+        }
+        for path in added {
+            let content = self.content_diff(None, Some(&to_snap.files[path])).await?;
+            files.push(FileDiff { path: path.clone(), old_path: None, status: FileStatus::Added, content });
+        }
+        for path in &summary.modified_files {
+            let content = self
+                .content_diff(Some(&from_snap.files[path]), Some(&to_snap.files[path]))
+                .await?;
+            files.push(FileDiff { path: path.clone(), old_path: None, status: FileStatus::Modified, content });
+        }
+        for path in summary.deleted_files.iter().filter(|p| !renamed.contains(p)) {
+            let content = self.content_diff(Some(&from_snap.files[path]), None).await?;
+            files.push(FileDiff { path: path.clone(), old_path: None, status: FileStatus::Deleted, content });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(ContentDiff {
+            from: from.clone(),
+            to: to.clone(),
+            files,
+            tools: tool_diffs(&from_snap.tool_states, &to_snap.tool_states),
+        })
+    }
+
+    async fn content_diff(
+        &self,
+        old: Option<&FileSnapshot>,
+        new: Option<&FileSnapshot>,
+    ) -> Result<FileContentDiff> {
+        let mut contents = Vec::new();
+        for file in [old, new] {
+            contents.push(match file {
+                Some(file) => self
+                    .blobs
+                    .load_local(&file.hash)
+                    .await
+                    .with_context(|| format!("Missing content for {}", file.path.display()))?
+                    .content,
+                None => Vec::new(),
+            });
+        }
+
+        let (old, new) = (&contents[0], &contents[1]);
+        if !(is_text(old) && is_text(new)) {
+            return Ok(FileContentDiff::Binary {
+                old_size: old.len() as u64,
+                new_size: new.len() as u64,
+            });
+        }
+        let hunks = text_hunks(
+            std::str::from_utf8(old).unwrap_or_default(),
+            std::str::from_utf8(new).unwrap_or_default(),
+        );
+        Ok(FileContentDiff::Text { hunks })
+    }
+
+    /// IDs of every snapshot on disk, reachable or not
+    pub fn all_snapshot_ids(&self) -> Result<Vec<SnapshotId>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.snapshots_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                ids.push(SnapshotId::from_str(stem));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Every snapshot reachable from a branch head, tag or HEAD through
+    /// any parent
+    pub fn reachable_snapshots(&self) -> Result<Vec<Snapshot>> {
+        let mut seen = std::collections::HashSet::new();
+        let mut pending: Vec<SnapshotId> = self
+            .list_branches()?
+            .into_iter()
+            .map(|branch| branch.head)
+            .chain(self.list_tags()?.into_iter().map(|tag| tag.target))
+            .chain(self.head_id()?)
+            .collect();
+        let mut reachable = Vec::new();
+
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            if let Some(snapshot) = self.get_snapshot(&id)? {
+                pending.extend(snapshot.parents.iter().cloned());
+                reachable.push(snapshot);
+            }
+        }
+
+        Ok(reachable)
+    }
+
+    /// Remove a snapshot file; used by garbage collection
+    pub fn delete_snapshot(&self, id: &SnapshotId) -> Result<()> {
+        let snapshot_file = self.snapshots_path.join(format!("{}.json", id.as_str()));
+        match std::fs::remove_file(snapshot_file) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a snapshot built elsewhere (e.g. imported from Git) without
+    /// moving any branch.
+    pub fn import_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        self.save_snapshot(snapshot)
+    }
+
+    /// Head of a branch, if it exists
+    pub fn branch_head(&self, name: &str) -> Result<Option<SnapshotId>> {
+        self.get_branch_head(name)
+    }
+
+    /// Point a branch at `head`, creating the branch if needed. `reason`
+    /// goes to the reflog.
+    pub fn set_branch_head(&mut self, name: &str, head: SnapshotId, reason: &str) -> Result<()> {
+        self.update_branch_head(name, head, reason)
+    }
+
+    /// Whether `ancestor` is `descendant` or reachable from it through parents
+    pub fn is_ancestor(&self, ancestor: &SnapshotId, descendant: &SnapshotId) -> Result<bool> {
+        let mut seen = std::collections::HashSet::new();
+        let mut pending = vec![descendant.clone()];
+
+        while let Some(id) = pending.pop() {
+            if &id == ancestor {
+                return Ok(true);
+            }
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            if let Some(snapshot) = self.get_snapshot(&id)? {
+                pending.extend(snapshot.parents);
+            }
+        }
+
+        Ok(false)
+    }
+
     // Private helper methods
 
     fn save_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
@@ -432,72 +1343,156 @@ impl SnapshotManager {
         Ok(())
     }
 
-    fn save_branch(&self, branch: &Branch) -> Result<()> {
-        let mut branches = if self.branches_path.exists() {
-            let content = std::fs::read_to_string(&self.branches_path)?;
-            serde_json::from_str(&content)?
-        } else {
-            HashMap::new()
-        };
+    fn read_branches(&self) -> Result<BTreeMap<String, Branch>> {
+        if !self.branches_path.exists() {
+            return Ok(BTreeMap::new());
+        }
 
-        branches.insert(branch.name.clone(), branch.clone());
+        let content = std::fs::read_to_string(&self.branches_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
 
-        let content = serde_json::to_string_pretty(&branches)?;
+    fn write_branches(&self, branches: &BTreeMap<String, Branch>) -> Result<()> {
+        let content = serde_json::to_string_pretty(branches)?;
         std::fs::write(&self.branches_path, content)?;
         Ok(())
     }
 
-    fn get_branch_head(&self, name: &str) -> Result<Option<SnapshotId>> {
-        if !self.branches_path.exists() {
-            return Ok(None);
-        }
+    fn save_branch(&self, branch: &Branch) -> Result<()> {
+        let mut branches = self.read_branches()?;
+        branches.insert(branch.name.clone(), branch.clone());
+        self.write_branches(&branches)
+    }
 
-        let content = std::fs::read_to_string(&self.branches_path)?;
-        let branches: HashMap<String, Branch> = serde_json::from_str(&content)?;
-        
-        Ok(branches.get(name).map(|b| b.head.clone()))
+    fn get_branch_head(&self, name: &str) -> Result<Option<SnapshotId>> {
+        Ok(self.read_branches()?.remove(name).map(|b| b.head))
     }
 
-    fn update_branch_head(&mut self, name: &str, head: SnapshotId) -> Result<()> {
-        let mut branches: HashMap<String, Branch> = if self.branches_path.exists() {
-            let content = std::fs::read_to_string(&self.branches_path)?;
-            serde_json::from_str(&content)?
-        } else {
-            HashMap::new()
+    fn update_branch_head(&mut self, name: &str, head: SnapshotId, reason: &str) -> Result<()> {
+        let mut branches = self.read_branches()?;
+
+        let old = match branches.get_mut(name) {
+            Some(branch) => {
+                branch.updated_at = Utc::now();
+                Some(std::mem::replace(&mut branch.head, head.clone()))
+            }
+            None => {
+                branches.insert(name.to_string(), Branch {
+                    name: name.to_string(),
+                    head: head.clone(),
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                });
+                None
+            }
         };
 
-        if let Some(branch) = branches.get_mut(name) {
-            branch.head = head;
-            branch.updated_at = Utc::now();
-        } else {
-            branches.insert(name.to_string(), Branch {
-                name: name.to_string(),
-                head,
-                created_at: Utc::now(),
-                updated_at: Utc::now(),
-            });
+        self.write_branches(&branches)?;
+        self.log_ref(name, old.clone(), Some(head.clone()), reason)?;
+        if self.current_branch() == Some(name) {
+            self.log_ref(HEAD, old, Some(head), reason)?;
         }
-
-        let content = serde_json::to_string_pretty(&branches)?;
-        std::fs::write(&self.branches_path, content)?;
         Ok(())
     }
 
     fn branch_exists(&self, name: &str) -> bool {
-        if !self.branches_path.exists() {
-            return false;
+        self.read_branches()
+            .map(|branches| branches.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Move whatever HEAD points at: the current branch, or HEAD itself
+    /// when detached
+    fn advance_head(&mut self, id: SnapshotId, reason: &str) -> Result<()> {
+        match self.head.clone() {
+            Head::Branch(name) => self.update_branch_head(&name, id, reason),
+            Head::Detached(_) => self.set_head(Head::Detached(id), reason),
         }
+    }
 
-        if let Ok(content) = std::fs::read_to_string(&self.branches_path) {
-            if let Ok(branches) = serde_json::from_str::<HashMap<String, Branch>>(&content) {
-                return branches.contains_key(name);
-            }
+    /// Point HEAD somewhere else and persist it
+    fn set_head(&mut self, head: Head, reason: &str) -> Result<()> {
+        let old = self.head_id()?;
+        self.head = head;
+        self.write_head()?;
+        let new = self.head_id()?;
+        self.log_ref(HEAD, old, new, reason)
+    }
+
+    fn write_head(&self) -> Result<()> {
+        std::fs::write(&self.head_path, serde_json::to_string(&self.head)?)?;
+        Ok(())
+    }
+
+    fn log_ref(
+        &self,
+        ref_name: &str,
+        old: Option<SnapshotId>,
+        new: Option<SnapshotId>,
+        reason: &str,
+    ) -> Result<()> {
+        self.db.append_reflog(&ReflogEntry {
+            ref_name: ref_name.to_string(),
+            old,
+            new,
+            reason: reason.to_string(),
+            actor: whoami::username(),
+            timestamp: Utc::now(),
+        })
+    }
+
+    fn read_tags(&self) -> Result<BTreeMap<String, Tag>> {
+        if !self.tags_path.exists() {
+            return Ok(BTreeMap::new());
         }
 
-        false
+        let content = std::fs::read_to_string(&self.tags_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn write_tags(&self, tags: &BTreeMap<String, Tag>) -> Result<()> {
+        let content = serde_json::to_string_pretty(tags)?;
+        std::fs::write(&self.tags_path, content)?;
+        Ok(())
+    }
+}
+
+/// Branch and tag names must be usable as revisions
+fn validate_ref_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == HEAD
+        || name.starts_with('-')
+        || name.contains("..")
+        || name.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        anyhow::bail!("Invalid branch or tag name '{}'", name);
+    }
+    Ok(())
+}
+
+/// SHA-256 of a file, or `None` if it doesn't exist
+fn hash_file(path: &Path) -> Result<Option<String>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(Some(format!("{:x}", Sha256::digest(&content)))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// Replace `path` with `content` without leaving a partial file behind
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file path", path.display()))?;
+    let staging = path.with_file_name(format!(".{}.forge-restore", name.to_string_lossy()));
+    std::fs::write(&staging, content)?;
+    std::fs::rename(&staging, path)?;
+    Ok(())
+}
+
 /// Diff between two snapshots
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotDiff {
@@ -527,8 +1522,8 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
-    #[test]
-    fn test_create_snapshot() {
+    #[tokio::test]
+    async fn test_create_snapshot() {
         let temp_dir = TempDir::new().unwrap();
         let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
 
@@ -540,30 +1535,300 @@ mod tests {
             output_files: vec![],
         });
 
-        let id = manager.create_snapshot("Initial commit", tool_states, vec![]).unwrap();
+        let id = manager.create_snapshot("Initial commit", tool_states, vec![]).await.unwrap();
         
         let snapshot = manager.get_snapshot(&id).unwrap().unwrap();
         assert_eq!(snapshot.message, "Initial commit");
         assert_eq!(snapshot.tool_states.len(), 1);
     }
 
-    #[test]
-    fn test_branching() {
+    #[tokio::test]
+    async fn test_branching() {
         let temp_dir = TempDir::new().unwrap();
         let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
 
         // Create initial snapshot
-        manager.create_snapshot("Initial", HashMap::new(), vec![]).unwrap();
+        manager.create_snapshot("Initial", HashMap::new(), vec![]).await.unwrap();
 
         // Create a new branch
         manager.create_branch("feature").unwrap();
         
         // Switch to new branch
-        manager.checkout_branch("feature").unwrap();
-        assert_eq!(manager.current_branch(), "feature");
+        manager.checkout_branch("feature").await.unwrap();
+        assert_eq!(manager.current_branch(), Some("feature"));
 
         // List branches
         let branches = manager.list_branches().unwrap();
         assert!(branches.iter().any(|b| b.name == "feature"));
     }
+
+    #[tokio::test]
+    async fn restore_writes_back_stored_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let file = PathBuf::from("src/lib.rs");
+        let location = temp_dir.path().join(&file);
+        std::fs::create_dir_all(location.parent().unwrap()).unwrap();
+
+        std::fs::write(&location, "v1").unwrap();
+        let v1 = manager.create_snapshot("v1", HashMap::new(), vec![file.clone()]).await.unwrap();
+        std::fs::write(&location, "v2").unwrap();
+        manager.create_snapshot("v2", HashMap::new(), vec![file.clone()]).await.unwrap();
+
+        // Committed content can be restored even after the file is gone
+        std::fs::remove_file(&location).unwrap();
+        let report = manager.restore(&v1, &[PathBuf::from("src")], RestoreMode::Refuse).await.unwrap();
+        assert_eq!(report.written, vec![file.clone()]);
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "v1");
+
+        // "v1" on disk is now a local change relative to the v2 head
+        assert_eq!(manager.changed_files().unwrap(), vec![file.clone()]);
+        let head = manager.history(1).unwrap()[0].id.clone();
+        assert!(manager.restore(&head, &[], RestoreMode::Refuse).await.is_err());
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "v1");
+
+        let report = manager.restore(&head, &[], RestoreMode::Stash).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "v2");
+        let stash = manager.get_snapshot(&report.stash.unwrap()).unwrap().unwrap();
+        assert_eq!(stash.files[&file].hash, manager.get_snapshot(&v1).unwrap().unwrap().files[&file].hash);
+        assert_eq!(manager.current_branch(), Some("main"));
+    }
+
+    #[tokio::test]
+    async fn checkout_switches_the_working_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let shared = PathBuf::from("shared.txt");
+        let extra = PathBuf::from("feature.txt");
+        std::fs::write(temp_dir.path().join(&shared), "base").unwrap();
+        manager.create_snapshot("base", HashMap::new(), vec![shared.clone()]).await.unwrap();
+
+        manager.create_branch("feature").unwrap();
+        manager.checkout_branch("feature").await.unwrap();
+        std::fs::write(temp_dir.path().join(&shared), "changed on feature").unwrap();
+        std::fs::write(temp_dir.path().join(&extra), "new").unwrap();
+        manager
+            .create_snapshot("feature work", HashMap::new(), vec![shared.clone(), extra.clone()])
+            .await
+            .unwrap();
+
+        let report = manager.checkout_branch("main").await.unwrap();
+        assert_eq!(report.written, vec![shared.clone()]);
+        assert_eq!(report.removed, vec![extra.clone()]);
+        assert_eq!(std::fs::read_to_string(temp_dir.path().join(&shared)).unwrap(), "base");
+        assert!(!temp_dir.path().join(&extra).exists());
+
+        // Uncommitted edits block switching back
+        std::fs::write(temp_dir.path().join(&shared), "uncommitted").unwrap();
+        assert!(manager.checkout_branch("feature").await.is_err());
+        assert_eq!(manager.current_branch(), Some("main"));
+
+        let report = manager.checkout_branch_with("feature", RestoreMode::Force).await.unwrap();
+        assert_eq!(report.written, vec![extra.clone(), shared.clone()]);
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join(&shared)).unwrap(),
+            "changed on feature"
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_combines_edits_and_fast_forwards() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let notes = PathBuf::from("notes.txt");
+        let location = temp_dir.path().join(&notes);
+        std::fs::write(&location, "one\ntwo\nthree\n").unwrap();
+        let base = manager.create_snapshot("base", HashMap::new(), vec![notes.clone()]).await.unwrap();
+
+        manager.create_branch("feature").unwrap();
+        manager.checkout_branch("feature").await.unwrap();
+        std::fs::write(&location, "one\ntwo\nTHREE\n").unwrap();
+        let feature = manager.create_snapshot("feature", HashMap::new(), vec![notes.clone()]).await.unwrap();
+
+        // Both branches edit different lines of the same file
+        manager.checkout_branch("main").await.unwrap();
+        assert_eq!(manager.merge_base(&base, &feature).unwrap(), Some(base.clone()));
+        std::fs::write(&location, "ONE\ntwo\nthree\n").unwrap();
+        manager.create_snapshot("main", HashMap::new(), vec![notes.clone()]).await.unwrap();
+
+        let result = manager.merge("feature", "Merge feature").await.unwrap();
+        assert_eq!(result.outcome, MergeOutcome::Merged);
+        assert_eq!(result.base, Some(base));
+        assert_eq!(result.files[&notes], FileMerge::Clean);
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "ONE\ntwo\nTHREE\n");
+        let merged = manager.get_snapshot(result.head.as_ref().unwrap()).unwrap().unwrap();
+        assert_eq!(merged.parents.len(), 2);
+
+        manager.checkout_branch("feature").await.unwrap();
+        // feature is behind the merge, so it just moves forward
+        let result = manager.merge("main", "Catch up").await.unwrap();
+        assert_eq!(result.outcome, MergeOutcome::FastForward);
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "ONE\ntwo\nTHREE\n");
+        assert_eq!(manager.merge("main", "Again").await.unwrap().outcome, MergeOutcome::UpToDate);
+    }
+
+    #[tokio::test]
+    async fn merge_reports_conflicts_without_touching_anything() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let notes = PathBuf::from("notes.txt");
+        let location = temp_dir.path().join(&notes);
+        let tool = |major| {
+            HashMap::from([("fmt".to_string(), ToolState {
+                tool_name: "fmt".to_string(),
+                version: Version::new(major, 0, 0),
+                config: HashMap::new(),
+                output_files: vec![],
+            })])
+        };
+        std::fs::write(&location, "line\n").unwrap();
+        manager.create_snapshot("base", tool(1), vec![notes.clone()]).await.unwrap();
+
+        manager.create_branch("feature").unwrap();
+        manager.checkout_branch("feature").await.unwrap();
+        std::fs::write(&location, "feature line\n").unwrap();
+        manager.create_snapshot("feature", tool(2), vec![notes.clone()]).await.unwrap();
+
+        manager.checkout_branch("main").await.unwrap();
+        std::fs::write(&location, "main line\n").unwrap();
+        let head = manager.create_snapshot("main", tool(3), vec![notes.clone()]).await.unwrap();
+
+        let result = manager.merge("feature", "Merge feature").await.unwrap();
+        assert_eq!(result.outcome, MergeOutcome::Conflicted);
+        assert_eq!(result.tool_conflicts.len(), 1);
+        let conflicts: Vec<_> = result.conflicts().collect();
+        assert_eq!(
+            conflicts,
+            vec![(
+                &notes,
+                &MergeConflict::Content {
+                    merged: "<<<<<<< main\nmain line\n=======\nfeature line\n>>>>>>> feature\n"
+                        .to_string(),
+                    regions: 1,
+                }
+            )]
+        );
+        assert_eq!(std::fs::read_to_string(&location).unwrap(), "main line\n");
+        assert_eq!(manager.get_branch_head("main").unwrap(), Some(head));
+    }
+
+    #[tokio::test]
+    async fn content_diff_reports_hunks_renames_and_binaries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let write = |name: &str, content: &[u8]| std::fs::write(temp_dir.path().join(name), content).unwrap();
+        let files = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        write("notes.txt", b"one\ntwo\n");
+        write("old.txt", b"moved\n");
+        write("logo.png", b"\x89PNG\0\x01");
+        let from = manager
+            .create_snapshot("from", HashMap::new(), files(&["notes.txt", "old.txt", "logo.png"]))
+            .await
+            .unwrap();
+
+        write("notes.txt", b"one\nTWO\n");
+        std::fs::rename(temp_dir.path().join("old.txt"), temp_dir.path().join("new.txt")).unwrap();
+        write("logo.png", b"\x89PNG\0\x02\x03");
+        let to = manager
+            .create_snapshot("to", HashMap::new(), files(&["notes.txt", "new.txt", "logo.png"]))
+            .await
+            .unwrap();
+
+        let diff = manager.diff_contents(&from, &to).await.unwrap();
+        let statuses: Vec<_> = diff.files.iter().map(|f| (f.path.to_str().unwrap(), f.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("logo.png", FileStatus::Modified),
+                ("new.txt", FileStatus::Renamed),
+                ("notes.txt", FileStatus::Modified),
+            ]
+        );
+        assert_eq!(diff.files[0].content, FileContentDiff::Binary { old_size: 6, new_size: 7 });
+        assert_eq!(diff.files[1].old_path, Some(PathBuf::from("old.txt")));
+
+        let text = diff.unified();
+        assert!(text.contains("rename from old.txt\nrename to new.txt\n"));
+        assert!(text.contains("@@ -1,2 +1,2 @@\n one\n-two\n+TWO\n"));
+        assert!(text.contains("Binary files a/logo.png and b/logo.png differ"));
+    }
+
+    #[tokio::test]
+    async fn head_tags_and_reflog_survive_reopening() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let v1 = manager.create_snapshot("v1", HashMap::new(), vec![]).await.unwrap();
+        manager.create_tag("release-1", &v1, Some("First release")).unwrap();
+        manager.create_tag("light", &v1, None).unwrap();
+        assert!(manager.create_tag("light", &v1, None).is_err());
+
+        // Branches sort before "main", which used to make HEAD flip
+        manager.create_branch("alpha").unwrap();
+        manager.create_branch("feature").unwrap();
+        manager.checkout_branch("feature").await.unwrap();
+        let v2 = manager.create_snapshot("v2", HashMap::new(), vec![]).await.unwrap();
+
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        assert_eq!(manager.current_branch(), Some("feature"));
+        let tag = manager.get_tag("release-1").unwrap().unwrap();
+        assert!(tag.is_annotated());
+        assert!(!manager.get_tag("light").unwrap().unwrap().is_annotated());
+        assert_eq!(manager.resolve("release-1").unwrap(), v1);
+        assert_eq!(manager.resolve(&v2.as_str()[..12]).unwrap(), v2);
+
+        // A detached HEAD moves on its own and leaves branches alone
+        manager.checkout_detached("release-1", RestoreMode::Refuse).await.unwrap();
+        assert_eq!(manager.head(), &Head::Detached(v1.clone()));
+        let v3 = manager.create_snapshot("hotfix", HashMap::new(), vec![]).await.unwrap();
+        assert_eq!(manager.head_id().unwrap(), Some(v3.clone()));
+        assert_eq!(manager.branch_head("feature").unwrap(), Some(v2.clone()));
+        assert_eq!(
+            SnapshotManager::new(temp_dir.path()).unwrap().head(),
+            &Head::Detached(v3.clone())
+        );
+
+        let reasons: Vec<_> = manager
+            .reflog(HEAD, 10)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "snapshot: hotfix",
+                "checkout: moving from feature to release-1",
+                "snapshot: v2",
+                "checkout: moving from main to feature",
+                "snapshot: v1",
+            ]
+        );
+        assert!(manager.reachable_snapshots().unwrap().iter().any(|s| s.id == v3));
+    }
+
+    #[tokio::test]
+    async fn branches_can_be_renamed_and_deleted() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager = SnapshotManager::new(temp_dir.path()).unwrap();
+        let head = manager.create_snapshot("base", HashMap::new(), vec![]).await.unwrap();
+        manager.create_branch("topic").unwrap();
+        assert!(manager.create_branch("topic").is_err());
+        assert!(manager.create_branch("bad name").is_err());
+
+        manager.rename_branch("main", "trunk").unwrap();
+        assert_eq!(manager.current_branch(), Some("trunk"));
+        assert_eq!(manager.branch_head("main").unwrap(), None);
+        assert_eq!(manager.reflog("trunk", 10).unwrap().len(), 2);
+        assert_eq!(
+            SnapshotManager::new(temp_dir.path()).unwrap().current_branch(),
+            Some("trunk")
+        );
+
+        assert!(manager.delete_branch("trunk").is_err());
+        manager.delete_branch("topic").unwrap();
+        assert!(manager.delete_branch("topic").is_err());
+        let deleted = &manager.reflog("topic", 1).unwrap()[0];
+        assert_eq!((deleted.old.clone(), deleted.new.clone()), (Some(head), None));
+    }
 }
@@ -7,7 +7,10 @@ use std::fmt;
 use std::str::FromStr;
 
 /// Semantic version following semver 2.0.0 specification
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Equality and ordering follow the spec: build metadata is ignored and
+/// pre-release identifiers are compared field by field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Version {
     pub major: u64,
     pub minor: u64,
@@ -35,15 +38,7 @@ impl Version {
 
     /// Check if this version satisfies a requirement
     pub fn satisfies(&self, req: &VersionReq) -> bool {
-        match req {
-            VersionReq::Exact(v) => self == v,
-            VersionReq::GreaterThan(v) => self > v,
-            VersionReq::GreaterOrEqual(v) => self >= v,
-            VersionReq::LessThan(v) => self < v,
-            VersionReq::LessOrEqual(v) => self <= v,
-            VersionReq::Compatible(v) => self.is_compatible_with(v) && self >= v,
-            VersionReq::Any => true,
-        }
+        req.matches(self)
     }
 
     /// Check if this is a pre-release version
@@ -74,36 +69,39 @@ impl FromStr for Version {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
+        let input = s.trim();
         // Remove 'v' prefix if present
-        let s = s.strip_prefix('v').unwrap_or(s);
+        let s = input.strip_prefix('v').unwrap_or(input);
 
         // Split on '+' for build metadata
         let (version_pre, build) = match s.split_once('+') {
-            Some((v, b)) => (v, Some(b.to_string())),
+            Some((v, b)) => {
+                validate_identifiers(b, false)
+                    .with_context(|| format!("Invalid build metadata in {}", input))?;
+                (v, Some(b.to_string()))
+            }
             None => (s, None),
         };
 
         // Split on '-' for pre-release
         let (version, pre_release) = match version_pre.split_once('-') {
-            Some((v, p)) => (v, Some(p.to_string())),
+            Some((v, p)) => {
+                validate_identifiers(p, true)
+                    .with_context(|| format!("Invalid pre-release in {}", input))?;
+                (v, Some(p.to_string()))
+            }
             None => (version_pre, None),
         };
 
         // Parse major.minor.patch
         let parts: Vec<&str> = version.split('.').collect();
         if parts.len() != 3 {
-            return Err(anyhow!("Invalid version format: {}", s));
+            return Err(anyhow!("Invalid version format: {}", input));
         }
 
-        let major = parts[0]
-            .parse()
-            .context("Failed to parse major version")?;
-        let minor = parts[1]
-            .parse()
-            .context("Failed to parse minor version")?;
-        let patch = parts[2]
-            .parse()
-            .context("Failed to parse patch version")?;
+        let major = parse_number(parts[0]).context("Failed to parse major version")?;
+        let minor = parse_number(parts[1]).context("Failed to parse minor version")?;
+        let patch = parse_number(parts[2]).context("Failed to parse patch version")?;
 
         Ok(Self {
             major,
@@ -115,6 +113,14 @@ impl FromStr for Version {
     }
 }
 
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
 impl PartialOrd for Version {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -123,60 +129,354 @@ impl PartialOrd for Version {
 
 impl Ord for Version {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Compare major.minor.patch
-        match self.major.cmp(&other.major) {
-            Ordering::Equal => {}
-            ord => return ord,
+        self.major
+            .cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+            .then_with(|| {
+                compare_pre_release(self.pre_release.as_deref(), other.pre_release.as_deref())
+            })
+    }
+}
+
+/// A version number component: digits only, without leading zeros
+fn parse_number(s: &str) -> Result<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!("'{}' is not a number", s));
+    }
+    if s.len() > 1 && s.starts_with('0') {
+        return Err(anyhow!("'{}' has a leading zero", s));
+    }
+    Ok(s.parse()?)
+}
+
+/// Dot-separated identifiers of `[0-9A-Za-z-]`; numeric pre-release
+/// identifiers may not have leading zeros
+fn validate_identifiers(s: &str, pre_release: bool) -> Result<()> {
+    for identifier in s.split('.') {
+        if identifier.is_empty() {
+            return Err(anyhow!("empty identifier in '{}'", s));
         }
-        match self.minor.cmp(&other.minor) {
-            Ordering::Equal => {}
-            ord => return ord,
+        if !identifier
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        {
+            return Err(anyhow!("invalid character in identifier '{}'", identifier));
         }
-        match self.patch.cmp(&other.patch) {
-            Ordering::Equal => {}
-            ord => return ord,
+        let numeric = identifier.bytes().all(|b| b.is_ascii_digit());
+        if pre_release && numeric && identifier.len() > 1 && identifier.starts_with('0') {
+            return Err(anyhow!("identifier '{}' has a leading zero", identifier));
         }
+    }
+    Ok(())
+}
 
-        // Pre-release versions have lower precedence
-        match (&self.pre_release, &other.pre_release) {
-            (None, None) => Ordering::Equal,
-            (Some(_), None) => Ordering::Less,
-            (None, Some(_)) => Ordering::Greater,
-            (Some(a), Some(b)) => a.cmp(b),
+/// Pre-release precedence: a release ranks above any of its pre-releases;
+/// numeric identifiers compare numerically and below alphanumeric ones,
+/// and a shorter set of identifiers ranks below a longer one it prefixes.
+fn compare_pre_release(a: Option<&str>, b: Option<&str>) -> Ordering {
+    let (a, b) = match (a, b) {
+        (None, None) => return Ordering::Equal,
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (Some(a), Some(b)) => (a, b),
+    };
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let ord = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => x.cmp(y),
+            },
+        };
+        if ord != Ordering::Equal {
+            return ord;
         }
     }
 }
 
 /// Version requirement for dependency resolution
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub enum VersionReq {
-    /// Exact version match (=1.2.3)
-    Exact(Version),
-    /// Greater than (>1.2.3)
-    GreaterThan(Version),
-    /// Greater or equal (>=1.2.3)
-    GreaterOrEqual(Version),
-    /// Less than (<1.2.3)
-    LessThan(Version),
-    /// Less or equal (<=1.2.3)
-    LessOrEqual(Version),
-    /// Compatible (^1.2.3 - same major, >= minor.patch)
-    Compatible(Version),
-    /// Any version (*)
-    Any,
+///
+/// A union (`||`) of comparator sets; a version matches when it satisfies
+/// every comparator of at least one set. Accepted syntax covers both Cargo
+/// and npm styles:
+///
+/// - comparators `=1.2.3`, `>1.2`, `>=1.2.3`, `<2`, `<=1.4`, with partial
+///   versions (`<=1.4` means below 1.5.0)
+/// - caret `^1.2.3` (`>=1.2.3, <2.0.0`; `^0.2.3` stays below 0.3.0 and
+///   `^0.0.3` is exact), which is also the meaning of a bare version
+/// - tilde `~1.2.3` (`>=1.2.3, <1.3.0`) and `~1` (`>=1.0.0, <2.0.0`)
+/// - wildcards `*`, `1.x`, `1.2.*`
+/// - hyphen ranges `1.2.3 - 2.3` (inclusive; a partial upper bound covers
+///   its whole range)
+/// - comparators in a set separated by commas or spaces, sets by `||`
+///
+/// A pre-release version only matches a set that has a comparator with a
+/// pre-release on the same `major.minor.patch`, so `>=1.2.3-beta` admits
+/// `1.2.3-rc.1` but not `1.3.0-alpha`. Build metadata is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    /// Comparator sets, any of which may match; an empty set matches
+    /// every release
+    pub alternatives: Vec<Vec<Comparator>>,
+}
+
+/// One comparison against a possibly partial version (`1`, `1.2`, `1.2.3`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    pub major: u64,
+    pub minor: Option<u64>,
+    pub patch: Option<u64>,
+    /// Only set together with `patch`
+    pub pre_release: Option<String>,
+}
+
+/// Comparison operator of a [`Comparator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `=1.2.3`; `=1.2` matches any 1.2.x
+    Exact,
+    /// `>1.2.3`
+    Greater,
+    /// `>=1.2.3`
+    GreaterEq,
+    /// `<1.2.3`
+    Less,
+    /// `<=1.2.3`
+    LessEq,
+    /// `~1.2.3`: patch updates only (minor updates for `~1`)
+    Tilde,
+    /// `^1.2.3`: updates that don't change the leftmost non-zero component
+    Caret,
+    /// `1.*`, `1.2.x`: any value for the missing components
+    Wildcard,
+}
+
+impl VersionReq {
+    /// Requirement matched by every release (`*`)
+    pub fn any() -> Self {
+        Self {
+            alternatives: vec![Vec::new()],
+        }
+    }
+
+    /// Requirement matched by exactly `version` (`=1.2.3`)
+    pub fn exact(version: &Version) -> Self {
+        Self {
+            alternatives: vec![vec![Comparator {
+                op: Op::Exact,
+                major: version.major,
+                minor: Some(version.minor),
+                patch: Some(version.patch),
+                pre_release: version.pre_release.clone(),
+            }]],
+        }
+    }
+
+    /// Whether `version` satisfies the requirement
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|set| {
+            set.iter().all(|comparator| comparator.matches(version))
+                && (!version.is_prerelease()
+                    || set
+                        .iter()
+                        .any(|comparator| comparator.admits_prerelease_of(version)))
+        })
+    }
+}
+
+impl Default for VersionReq {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+impl Comparator {
+    /// Whether `version` satisfies this comparator alone, ignoring the
+    /// pre-release rule applied by [`VersionReq::matches`]
+    pub fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Exact | Op::Wildcard => self.matches_exact(version),
+            Op::Greater => self.matches_greater(version),
+            Op::GreaterEq => self.matches_exact(version) || self.matches_greater(version),
+            Op::Less => self.matches_less(version),
+            Op::LessEq => self.matches_exact(version) || self.matches_less(version),
+            Op::Tilde => self.matches_tilde(version),
+            Op::Caret => self.matches_caret(version),
+        }
+    }
+
+    /// Pre-releases are opted into per `major.minor.patch`
+    fn admits_prerelease_of(&self, version: &Version) -> bool {
+        self.pre_release.is_some()
+            && self.major == version.major
+            && self.minor == Some(version.minor)
+            && self.patch == Some(version.patch)
+    }
+
+    fn compare_pre_release(&self, version: &Version) -> Ordering {
+        compare_pre_release(version.pre_release.as_deref(), self.pre_release.as_deref())
+    }
+
+    fn matches_exact(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return false;
+        }
+        match self.minor {
+            Some(minor) if version.minor != minor => return false,
+            None => return true,
+            _ => {}
+        }
+        match self.patch {
+            Some(patch) if version.patch != patch => false,
+            None => true,
+            _ => self.compare_pre_release(version) == Ordering::Equal,
+        }
+    }
+
+    fn matches_greater(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return version.major > self.major;
+        }
+        let Some(minor) = self.minor else {
+            return false;
+        };
+        if version.minor != minor {
+            return version.minor > minor;
+        }
+        let Some(patch) = self.patch else {
+            return false;
+        };
+        if version.patch != patch {
+            return version.patch > patch;
+        }
+        self.compare_pre_release(version) == Ordering::Greater
+    }
+
+    fn matches_less(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return version.major < self.major;
+        }
+        let Some(minor) = self.minor else {
+            return false;
+        };
+        if version.minor != minor {
+            return version.minor < minor;
+        }
+        let Some(patch) = self.patch else {
+            return false;
+        };
+        if version.patch != patch {
+            return version.patch < patch;
+        }
+        self.compare_pre_release(version) == Ordering::Less
+    }
+
+    fn matches_tilde(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return false;
+        }
+        match self.minor {
+            Some(minor) if version.minor != minor => return false,
+            None => return true,
+            _ => {}
+        }
+        match self.patch {
+            Some(patch) if version.patch != patch => version.patch > patch,
+            None => true,
+            _ => self.compare_pre_release(version) != Ordering::Less,
+        }
+    }
+
+    fn matches_caret(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return false;
+        }
+        let Some(minor) = self.minor else {
+            return true;
+        };
+        let Some(patch) = self.patch else {
+            return if self.major > 0 {
+                version.minor >= minor
+            } else {
+                version.minor == minor
+            };
+        };
+        if self.major > 0 {
+            if version.minor != minor {
+                return version.minor > minor;
+            }
+            if version.patch != patch {
+                return version.patch > patch;
+            }
+        } else if minor > 0 {
+            if version.minor != minor {
+                return false;
+            }
+            if version.patch != patch {
+                return version.patch > patch;
+            }
+        } else if version.minor != minor || version.patch != patch {
+            return false;
+        }
+        self.compare_pre_release(version) != Ordering::Less
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Tilde => "~",
+            Op::Caret => "^",
+            Op::Wildcard => "",
+        };
+        write!(f, "{}{}", op, self.major)?;
+        match self.minor {
+            Some(minor) => write!(f, ".{}", minor)?,
+            None if self.op == Op::Wildcard => return write!(f, ".*"),
+            None => return Ok(()),
+        }
+        match self.patch {
+            Some(patch) => write!(f, ".{}", patch)?,
+            None if self.op == Op::Wildcard => return write!(f, ".*"),
+            None => return Ok(()),
+        }
+        if let Some(pre) = &self.pre_release {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for VersionReq {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            VersionReq::Exact(v) => write!(f, "={}", v),
-            VersionReq::GreaterThan(v) => write!(f, ">{}", v),
-            VersionReq::GreaterOrEqual(v) => write!(f, ">={}", v),
-            VersionReq::LessThan(v) => write!(f, "<{}", v),
-            VersionReq::LessOrEqual(v) => write!(f, "<={}", v),
-            VersionReq::Compatible(v) => write!(f, "^{}", v),
-            VersionReq::Any => write!(f, "*"),
+        for (i, set) in self.alternatives.iter().enumerate() {
+            if i > 0 {
+                write!(f, " || ")?;
+            }
+            if set.is_empty() {
+                write!(f, "*")?;
+            }
+            for (j, comparator) in set.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", comparator)?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -184,33 +484,214 @@ impl FromStr for VersionReq {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let s = s.trim();
+        let alternatives = s
+            .split("||")
+            .map(parse_comparator_set)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid version requirement '{}'", s.trim()))?;
+        Ok(Self { alternatives })
+    }
+}
 
-        if s == "*" {
-            return Ok(VersionReq::Any);
+const OPERATORS: [&str; 7] = [">=", "<=", ">", "<", "=", "~", "^"];
+
+/// Comparators separated by commas or whitespace, e.g. `>=1.2, <2` or
+/// `>= 1.2.0 <2.0.0`, or a hyphen range `1.2 - 1.4`
+fn parse_comparator_set(s: &str) -> Result<Vec<Comparator>> {
+    let tokens: Vec<&str> = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let mut set = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if tokens.get(i + 1) == Some(&"-") {
+            let upper = tokens
+                .get(i + 2)
+                .ok_or_else(|| anyhow!("hyphen range '{} -' has no upper bound", tokens[i]))?;
+            set.extend(parse_partial(tokens[i], Op::GreaterEq)?);
+            set.extend(parse_partial(upper, Op::LessEq)?);
+            i += 3;
+            continue;
         }
 
-        if let Some(v) = s.strip_prefix(">=") {
-            return Ok(VersionReq::GreaterOrEqual(v.trim().parse()?));
+        // Allow whitespace between an operator and its version
+        let comparator = if OPERATORS.contains(&tokens[i]) {
+            let version = tokens
+                .get(i + 1)
+                .ok_or_else(|| anyhow!("operator '{}' has no version", tokens[i]))?;
+            i += 1;
+            format!("{}{}", tokens[i - 1], version)
+        } else {
+            tokens[i].to_string()
+        };
+        set.extend(parse_comparator(&comparator)?);
+        i += 1;
+    }
+    Ok(set)
+}
+
+/// A single comparator; `None` when it matches everything (`*`, `>=*`)
+fn parse_comparator(s: &str) -> Result<Option<Comparator>> {
+    let (op, version) = match OPERATORS.iter().find(|op| s.starts_with(**op)) {
+        Some(&op) => {
+            let version = &s[op.len()..];
+            match op {
+                ">=" => (Op::GreaterEq, version),
+                "<=" => (Op::LessEq, version),
+                ">" => (Op::Greater, version),
+                "<" => (Op::Less, version),
+                "=" => (Op::Exact, version),
+                "~" => (Op::Tilde, version),
+                _ => (Op::Caret, version),
+            }
         }
-        if let Some(v) = s.strip_prefix('>') {
-            return Ok(VersionReq::GreaterThan(v.trim().parse()?));
+        // A bare version means caret, a bare wildcard means wildcard
+        None if s.split(['-', '+']).next().is_some_and(has_wildcard) => (Op::Wildcard, s),
+        None => (Op::Caret, s),
+    };
+    parse_partial(version, op)
+}
+
+fn has_wildcard(version: &str) -> bool {
+    version.split('.').any(is_wildcard)
+}
+
+fn is_wildcard(part: &str) -> bool {
+    matches!(part, "*" | "x" | "X")
+}
+
+/// A possibly partial version with wildcards for `op`. Trailing wildcards
+/// just drop the component (`>=1.x` is `>=1`); a leading one matches all.
+fn parse_partial(s: &str, op: Op) -> Result<Option<Comparator>> {
+    let s = s.strip_prefix('v').unwrap_or(s);
+    // Build metadata doesn't take part in matching
+    let s = match s.split_once('+') {
+        Some((version, build)) => {
+            validate_identifiers(build, false)
+                .with_context(|| format!("Invalid build metadata in '{}'", s))?;
+            version
         }
-        if let Some(v) = s.strip_prefix("<=") {
-            return Ok(VersionReq::LessOrEqual(v.trim().parse()?));
+        None => s,
+    };
+    let (version, pre_release) = match s.split_once('-') {
+        Some((version, pre)) => {
+            validate_identifiers(pre, true)
+                .with_context(|| format!("Invalid pre-release in '{}'", s))?;
+            (version, Some(pre.to_string()))
         }
-        if let Some(v) = s.strip_prefix('<') {
-            return Ok(VersionReq::LessThan(v.trim().parse()?));
+        None => (s, None),
+    };
+
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() > 3 {
+        return Err(anyhow!("'{}' has more than three components", s));
+    }
+    let mut numbers = Vec::new();
+    let mut wildcard = false;
+    for part in &parts {
+        if is_wildcard(part) {
+            wildcard = true;
+        } else if wildcard {
+            return Err(anyhow!("'{}' has a number after a wildcard", s));
+        } else {
+            numbers.push(parse_number(part).with_context(|| format!("Invalid version '{}'", s))?);
         }
-        if let Some(v) = s.strip_prefix('=') {
-            return Ok(VersionReq::Exact(v.trim().parse()?));
+    }
+    if pre_release.is_some() && numbers.len() < 3 {
+        return Err(anyhow!("pre-release '{}' needs a full version", s));
+    }
+
+    let op = match op {
+        Op::Wildcard if !wildcard => Op::Exact,
+        _ => op,
+    };
+    let Some(&major) = numbers.first() else {
+        return match op {
+            Op::Greater | Op::Less => Err(anyhow!("'{}' can never match", s)),
+            _ => Ok(None),
+        };
+    };
+    Ok(Some(Comparator {
+        op,
+        major,
+        minor: numbers.get(1).copied(),
+        patch: numbers.get(2).copied(),
+        pre_release,
+    }))
+}
+
+/// Serialized as its string form, e.g. `">=1.2, <2.0.0"`
+impl Serialize for VersionReq {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        /// The single-comparator enum requirements used to be stored as
+        #[derive(Deserialize)]
+        enum Legacy {
+            Exact(Version),
+            GreaterThan(Version),
+            GreaterOrEqual(Version),
+            LessThan(Version),
+            LessOrEqual(Version),
+            Compatible(Version),
+            Any,
         }
-        if let Some(v) = s.strip_prefix('^') {
-            return Ok(VersionReq::Compatible(v.trim().parse()?));
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Legacy(Legacy),
+            Text(String),
         }
 
-        // Default to compatible
-        Ok(VersionReq::Compatible(s.parse()?))
+        let (op, version) = match Repr::deserialize(deserializer)? {
+            Repr::Text(text) => return text.parse().map_err(serde::de::Error::custom),
+            Repr::Legacy(Legacy::Any) => return Ok(Self::any()),
+            Repr::Legacy(Legacy::Exact(v)) => (Op::Exact, v),
+            Repr::Legacy(Legacy::GreaterThan(v)) => (Op::Greater, v),
+            Repr::Legacy(Legacy::GreaterOrEqual(v)) => (Op::GreaterEq, v),
+            Repr::Legacy(Legacy::LessThan(v)) => (Op::Less, v),
+            Repr::Legacy(Legacy::LessOrEqual(v)) => (Op::LessEq, v),
+            Repr::Legacy(Legacy::Compatible(v)) => (Op::Caret, v),
+        };
+        Ok(Self {
+            alternatives: vec![vec![Comparator {
+                op,
+                major: version.major,
+                minor: Some(version.minor),
+                patch: Some(version.patch),
+                pre_release: version.pre_release,
+            }]],
+        })
+    }
+}
+
+/// Serde helpers writing versions as their string form rather than
+/// `Version`'s fields
+pub(crate) mod version_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Version;
+
+    pub fn serialize<S: Serializer>(version: &Version, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(version)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Version, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -267,4 +748,173 @@ mod tests {
         let req = ">2.0.0".parse::<VersionReq>().unwrap();
         assert!(!v.satisfies(&req));
     }
+
+    fn matches(req: &str, version: &str) -> bool {
+        let req: VersionReq = req.parse().unwrap();
+        req.matches(&version.parse().unwrap())
+    }
+
+    #[test]
+    fn test_version_validation() {
+        for invalid in [
+            "1.2",
+            "1.2.3.4",
+            "01.2.3",
+            "1.2.3-",
+            "1.2.3-01",
+            "1.2.3+",
+            "1.2.3-a..b",
+            "1.2.3+a_b",
+            "+1.2.3",
+        ] {
+            assert!(
+                invalid.parse::<Version>().is_err(),
+                "{} should not parse",
+                invalid
+            );
+        }
+        assert!("1.2.3-0.alpha-1+exp.sha.5114f85".parse::<Version>().is_ok());
+        // Build metadata may have leading zeros
+        assert!("1.2.3+001".parse::<Version>().is_ok());
+    }
+
+    #[test]
+    fn test_prerelease_precedence() {
+        // Example ordering from the semver 2.0.0 spec
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            let (a, b) = (
+                pair[0].parse::<Version>().unwrap(),
+                pair[1].parse::<Version>().unwrap(),
+            );
+            assert!(a < b, "{} < {}", a, b);
+        }
+
+        // Build metadata doesn't affect precedence
+        let a = "1.0.0+build.1".parse::<Version>().unwrap();
+        let b = "1.0.0+build.2".parse::<Version>().unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_compound_and_union_requirements() {
+        assert!(matches(">=1.2, <2.0", "1.9.9"));
+        assert!(!matches(">=1.2, <2.0", "2.0.0"));
+        assert!(!matches(">=1.2, <2.0", "1.1.9"));
+        // npm style: space separated, with space after the operator
+        assert!(matches(">= 1.2.0 < 2.0.0", "1.5.0"));
+        assert!(!matches(">=1.2.0 <2.0.0", "2.1.0"));
+
+        assert!(matches("^1.0 || ^3.0", "3.4.0"));
+        assert!(matches("^1.0 || ^3.0", "1.4.0"));
+        assert!(!matches("^1.0 || ^3.0", "2.0.0"));
+    }
+
+    #[test]
+    fn test_caret_and_tilde_requirements() {
+        assert!(matches("^1.2.3", "1.9.0"));
+        assert!(!matches("^1.2.3", "1.2.2"));
+        assert!(matches("^0.2.3", "0.2.9"));
+        assert!(!matches("^0.2.3", "0.3.0"));
+        assert!(matches("^0.0.3", "0.0.3"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+        assert!(matches("^0", "0.9.0"));
+        // A bare version is a caret requirement
+        assert!(matches("1.2.3", "1.4.0"));
+
+        assert!(matches("~1.2.3", "1.2.9"));
+        assert!(!matches("~1.2.3", "1.3.0"));
+        assert!(matches("~1.2", "1.2.0"));
+        assert!(matches("~1", "1.9.0"));
+        assert!(!matches("~1", "2.0.0"));
+    }
+
+    #[test]
+    fn test_wildcard_and_hyphen_requirements() {
+        assert!(matches("*", "7.0.0"));
+        assert!(matches("", "7.0.0"));
+        assert!(matches("1.x", "1.9.3"));
+        assert!(!matches("1.x", "2.0.0"));
+        assert!(matches("1.2.*", "1.2.7"));
+        assert!(!matches("1.2.X", "1.3.0"));
+        assert!(matches(">=1.x", "1.0.0"));
+        assert!(matches("<=1.4", "1.4.9"));
+        assert!(!matches("<=1.4", "1.5.0"));
+
+        assert!(matches("1.2.3 - 2.3", "2.3.9"));
+        assert!(!matches("1.2.3 - 2.3", "2.4.0"));
+        assert!(!matches("1.2.3 - 2.3", "1.2.2"));
+        assert!(matches("1.2.3 - 2.3.4", "2.3.4"));
+
+        for invalid in [
+            "1.x.3",
+            ">*",
+            "1.2.3.4",
+            ">=",
+            "1.2 -",
+            "^1.2-beta",
+            "1.2.3 || ~x.1",
+            "~>1",
+        ] {
+            assert!(
+                invalid.parse::<VersionReq>().is_err(),
+                "{} should not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_prerelease_requirements() {
+        // Pre-releases only match when opted into on the same version
+        assert!(!matches(">=1.0.0", "1.1.0-beta"));
+        assert!(!matches("*", "1.0.0-alpha"));
+        assert!(matches(">=1.2.3-beta", "1.2.3-rc.1"));
+        assert!(!matches(">=1.2.3-beta", "1.2.3-alpha"));
+        assert!(!matches(">=1.2.3-beta", "1.3.0-alpha"));
+        assert!(matches(">=1.2.3-beta", "1.3.0"));
+        assert!(matches("^1.2.3-beta.2", "1.2.3-beta.11"));
+        assert!(matches("=1.2.3-rc.1", "1.2.3-rc.1+build.5"));
+        assert!(!matches("<1.2.3", "1.2.3-beta"));
+    }
+
+    #[test]
+    fn test_requirement_display_and_serde() {
+        for (input, display) in [
+            (">=1.2, <2.0", ">=1.2, <2.0"),
+            (">= 1.2.0 <2", ">=1.2.0, <2"),
+            ("~1.2 || 3.x", "~1.2 || 3.*"),
+            ("1.2.3 - 2", ">=1.2.3, <=2"),
+            ("*", "*"),
+            ("1.2.3", "^1.2.3"),
+        ] {
+            let req: VersionReq = input.parse().unwrap();
+            assert_eq!(req.to_string(), display);
+            assert_eq!(display.parse::<VersionReq>().unwrap(), req);
+        }
+
+        let req: VersionReq = ">=1.2, <2".parse().unwrap();
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(json, "\">=1.2, <2\"");
+        assert_eq!(serde_json::from_str::<VersionReq>(&json).unwrap(), req);
+
+        // Requirements stored by older registries
+        let legacy =
+            r#"{"Compatible":{"major":1,"minor":2,"patch":0,"pre_release":null,"build":null}}"#;
+        let req: VersionReq = serde_json::from_str(legacy).unwrap();
+        assert_eq!(req, "^1.2.0".parse().unwrap());
+        assert_eq!(
+            serde_json::from_str::<VersionReq>("\"Any\"").unwrap(),
+            VersionReq::any()
+        );
+    }
 }
@@ -163,10 +163,10 @@ mod api_tests {
         Ok(())
     }
     
-    #[test]
-    fn test_package_apis() -> Result<()> {
+    #[tokio::test]
+    async fn test_package_apis() -> Result<()> {
         // Test package search
-        let results = search_dx_package_registry("test")?;
+        let results = search_dx_package_registry("test").await?;
         assert!(results.is_empty()); // Empty in test mode
         
         // Test package listing
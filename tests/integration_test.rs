@@ -202,8 +202,8 @@ fn test_orchestrator_missing_dependency() -> Result<()> {
     Ok(())
 }
 
-#[test]
-fn test_version_snapshot_system() -> Result<()> {
+#[tokio::test]
+async fn test_version_snapshot_system() -> Result<()> {
     let temp_dir = TempDir::new()?;
     let mut manager = SnapshotManager::new(temp_dir.path())?;
 
@@ -219,7 +219,7 @@ fn test_version_snapshot_system() -> Result<()> {
         },
     );
 
-    let snapshot1 = manager.create_snapshot("Initial commit", tool_states.clone(), vec![])?;
+    let snapshot1 = manager.create_snapshot("Initial commit", tool_states.clone(), vec![]).await?;
 
     // Verify snapshot was created
     let loaded = manager.get_snapshot(&snapshot1)?;
@@ -227,7 +227,7 @@ fn test_version_snapshot_system() -> Result<()> {
     assert_eq!(loaded.unwrap().message, "Initial commit");
 
     // Create second snapshot
-    let snapshot2 = manager.create_snapshot("Second commit", tool_states, vec![])?;
+    let snapshot2 = manager.create_snapshot("Second commit", tool_states, vec![]).await?;
 
     // Verify history
     let history = manager.history(10)?;
@@ -238,25 +238,25 @@ fn test_version_snapshot_system() -> Result<()> {
     Ok(())
 }
 
-#[test]
-fn test_version_branching() -> Result<()> {
+#[tokio::test]
+async fn test_version_branching() -> Result<()> {
     let temp_dir = TempDir::new()?;
     let mut manager = SnapshotManager::new(temp_dir.path())?;
 
     // Create initial snapshot
-    manager.create_snapshot("Initial", std::collections::HashMap::new(), vec![])?;
+    manager.create_snapshot("Initial", std::collections::HashMap::new(), vec![]).await?;
 
     // Create and switch to feature branch
     manager.create_branch("feature")?;
-    manager.checkout_branch("feature")?;
+    manager.checkout_branch("feature").await?;
 
-    assert_eq!(manager.current_branch(), "feature");
+    assert_eq!(manager.current_branch(), Some("feature"));
 
     // Create commit on feature branch
-    manager.create_snapshot("Feature commit", std::collections::HashMap::new(), vec![])?;
+    manager.create_snapshot("Feature commit", std::collections::HashMap::new(), vec![]).await?;
 
     // Switch back to main
-    manager.checkout_branch("main")?;
+    manager.checkout_branch("main").await?;
 
     // List branches
     let branches = manager.list_branches()?;